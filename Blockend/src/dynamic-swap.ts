@@ -308,6 +308,7 @@ class DynamicSwapInterface {
       order.withdrawalStart,
       order.publicWithdrawalStart,
       order.cancellationStart,
+      order.publicCancellationStart,
       order.isPartialFillEnabled ? 0 : 0, // part index (0 for first part, whether partial or single)
       order.isPartialFillEnabled && order.partialFillManager ? order.partialFillManager.getPartsCount() : 1 // total parts
     );
@@ -528,6 +529,7 @@ class DynamicSwapInterface {
       order.withdrawalStart,
       order.publicWithdrawalStart,
       order.cancellationStart,
+      order.publicCancellationStart,
       order.isPartialFillEnabled ? 0 : 0, // part index (0 for first part, whether partial or single)
       order.isPartialFillEnabled && order.partialFillManager ? order.partialFillManager.getPartsCount() : 1 // total parts
     );
@@ -601,6 +603,7 @@ class DynamicSwapInterface {
     withdrawalStart: number,
     publicWithdrawalStart: number,
     cancellationStart: number,
+    publicCancellationStart: number,
     partIndex?: number,
     totalParts?: number
   ) {
@@ -633,7 +636,7 @@ class DynamicSwapInterface {
         command = `soroban contract invoke --id ${contractAddress} --source stellar-resolver --network testnet -- ${functionName} --creator ${creator} --hashed_secret ${hashedSecret.slice(2)} --recipient ${recipient} --token_amount ${amountInStroops} --withdrawal_start ${withdrawalStart} --public_withdrawal_start ${publicWithdrawalStart} --cancellation_start ${cancellationStart} --part_index ${actualPartIndex} --total_parts ${actualTotalParts}`;
       } else {
         // Regular function without partial fill parameters
-        command = `soroban contract invoke --id ${contractAddress} --source stellar-resolver --network testnet -- ${functionName} --creator ${creator} --hashed_secret ${hashedSecret.slice(2)} --recipient ${recipient} --token_amount ${amountInStroops} --withdrawal_start ${withdrawalStart} --public_withdrawal_start ${publicWithdrawalStart} --cancellation_start ${cancellationStart}`;
+        command = `soroban contract invoke --id ${contractAddress} --source stellar-resolver --network testnet -- ${functionName} --creator ${creator} --hashed_secret ${hashedSecret.slice(2)} --recipient ${recipient} --token_amount ${amountInStroops} --withdrawal_start ${withdrawalStart} --public_withdrawal_start ${publicWithdrawalStart} --cancellation_start ${cancellationStart} --public_cancellation_start ${publicCancellationStart}`;
       }
       
       const result = execSync(command, { encoding: 'utf8' });
@@ -771,9 +774,9 @@ class DynamicSwapInterface {
         // Format: --merkle_proof '[ "hex1", "hex2" ]' (with quotes around each element)
         const proofArray = merkleProof.map(p => `"${p.slice(2)}"`); // Remove 0x prefix and add quotes
         const proofStr = `[ ${proofArray.join(', ')} ]`;
-        command = `soroban contract invoke --id ${contractAddress} --source ${sourceKey} --network testnet -- ${methodName} --caller ${caller} --escrow_address ${escrowAddress} --secret ${secret.slice(2)} --merkle_proof '${proofStr}'`;
+        command = `soroban contract invoke --id ${contractAddress} --source ${sourceKey} --network testnet -- ${methodName} --caller ${caller} --escrow_id ${escrowAddress} --secret ${secret.slice(2)} --merkle_proof '${proofStr}'`;
       } else {
-        command = `soroban contract invoke --id ${contractAddress} --source ${sourceKey} --network testnet -- ${methodName} --caller ${caller} --escrow_address ${escrowAddress} --secret ${secret.slice(2)}`;
+        command = `soroban contract invoke --id ${contractAddress} --source ${sourceKey} --network testnet -- ${methodName} --caller ${caller} --escrow_id ${escrowAddress} --secret ${secret.slice(2)}`;
       }
       
       const result = execSync(command, { encoding: 'utf8' });
@@ -830,7 +833,7 @@ class DynamicSwapInterface {
       // Convert merkle proof to CLI format - Soroban CLI expects a specific format for Vec<BytesN<32>>
       // Each proof element should be passed as a separate argument
       const proofArgs = segment.proof.map(p => p.slice(2)).join(' ');
-      const command = `soroban contract invoke --id ${contractAddress} --source ${sourceKey} --network testnet -- ${methodName} --caller ${caller} --escrow_address ${escrowAddress} --secret ${segment.secret.slice(2)} --merkle_proof ${proofArgs}`;
+      const command = `soroban contract invoke --id ${contractAddress} --source ${sourceKey} --network testnet -- ${methodName} --caller ${caller} --escrow_id ${escrowAddress} --secret ${segment.secret.slice(2)} --merkle_proof ${proofArgs}`;
       
       
       const result = execSync(command, { encoding: 'utf8' });
@@ -2098,6 +2101,7 @@ class DynamicSwapInterface {
       order.withdrawalStart,
       order.publicWithdrawalStart,
       order.cancellationStart,
+      order.publicCancellationStart,
       segment.index, // part index
       order.partialFillManager!.getPartsCount() // total parts
     );
@@ -2285,6 +2289,7 @@ class DynamicSwapInterface {
       order.withdrawalStart,
       order.publicWithdrawalStart,
       order.cancellationStart,
+      order.publicCancellationStart,
       segment.index, // part index
       order.partialFillManager!.getPartsCount() // total parts
     );
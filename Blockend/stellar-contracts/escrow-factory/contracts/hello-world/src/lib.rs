@@ -1,15 +1,101 @@
 #![no_std]
+#![allow(clippy::too_many_arguments)]
 use soroban_sdk::{
-    contract, contractimpl, contracttype, token, Address, Bytes, BytesN, Env,
-    Vec, log,
+    contract, contractclient, contracterror, contractevent, contractimpl, contracttype, panic_with_error, symbol_short,
+    token, xdr::ToXdr, Address, Bytes, BytesN, Env, Executable, Symbol, Vec, log,
 };
 
-// Security deposit amount (0.1 XLM = 1,000,000 stroops)
-const DEPOSIT_AMOUNT: i128 = 1_000_000;
-const RESCUE_DELAY: u64 = 7 * 24 * 60 * 60; // 7 days in seconds
+const MAX_SECRET_HINT_LEN: u32 = 256; // bytes
+// Batch sizes are bounded by the per-transaction instruction budget (100M on mainnet)
+// Batch views only read storage, so 50 escrow ids fit comfortably
+const MAX_BATCH_SIZE: u32 = 50;
+// A batch withdrawal writes storage and makes token transfers per escrow, and its cost grows faster
+// than linearly: 20 escrows take ~27M instructions natively and 40 take ~80M, so 20 leaves headroom for
+// wasm execution. The LOP caps its batches at 20 for the same reason, as each item calls into this factory
+const MAX_WITHDRAW_BATCH_SIZE: u32 = 20;
+const DEFAULT_MAX_BATCH_PROOF_COST: u32 = 64; // hashes per multiproof batch withdrawal until the owner sets a ceiling
+const DEFAULT_EVENT_NAMESPACE: &str = "fusion"; // event namespace when none is set at initialize
+const PURGE_GRACE_PERIOD: u64 = 7 * 24 * 60 * 60; // seconds after the rescue window opens before anyone may purge
+const MAX_HEAD_STEPS: u64 = 32; // freed open-escrow slots one call may walk past, keeping settlement within read limits
+
+// Hook invoked atomically after an escrow's funds are released
+#[contractclient(name = "SettlementCallbackClient")]
+pub trait SettlementCallback {
+    fn on_settled(env: Env, escrow_id: BytesN<32>, recipient: Address, amount: i128);
+}
+
+// Hook on a contract recipient, called after a destination escrow pays it out
+#[contractclient(name = "TokensReceiverClient")]
+pub trait TokensReceiver {
+    fn on_tokens_received(env: Env, token: Address, amount: i128);
+}
+
+// Stable error codes for the common failure paths, so callers can match on them
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum EscrowError {
+    InvalidAmount = 1,
+    InvalidTimeWindows = 2,
+    AlreadyWithdrawn = 3,
+    AlreadyCancelled = 4,
+    WithdrawalNotStarted = 5,
+    InvalidSecret = 6,
+    InsufficientAllowance = 7,
+}
+
+// Emitted on every allowance change; topics ("Approval", namespace, owner, spender, token), data is the new allowance
+// The limit order protocol publishes the same event for its own allowances
+#[contractevent(topics = ["Approval"], data_format = "single-value")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Approval {
+    #[topic]
+    pub namespace: Symbol,
+    #[topic]
+    pub owner: Address,
+    #[topic]
+    pub spender: Address,
+    #[topic]
+    pub token: Address,
+    pub amount: i128,
+}
+
+// Emitted on every escrow lifecycle change; topics (kind, namespace, action) such as ("src_esc", "fusion", "created"),
+// data is a map of the escrow id, its parties, its amount and its part index
+#[contractevent(topics = [])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EscrowEvent {
+    #[topic]
+    pub kind: Symbol,
+    #[topic]
+    pub namespace: Symbol,
+    #[topic]
+    pub action: Symbol,
+    pub escrow_id: BytesN<32>,
+    pub creator: Address,
+    pub recipient: Address,
+    pub amount: i128,
+    pub part_index: u64,
+}
+
+// Emitted when the safe_exit recovery pool is funded; topics ("RecoveryPoolFunded", namespace, token), data is the amount added
+#[contractevent(topics = ["RecoveryPoolFunded"], data_format = "single-value")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecoveryPoolFunded {
+    #[topic]
+    pub namespace: Symbol,
+    #[topic]
+    pub token: Address,
+    pub amount: i128,
+}
 
-// Native XLM token address constant
-const NATIVE_XLM_ADDRESS: &str = "CAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAD2KM";
+// Hash function used for the hashlock preimage and merkle tree
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HashAlgo {
+    Sha256,
+    Keccak256,
+}
 
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -17,6 +103,7 @@ pub struct SourceEscrowData {
     pub creator: Address,
     pub recipient: Address,
     pub hashed_secret: BytesN<32>,
+    pub hash_algo: HashAlgo,
     pub token: Address,
     pub amount: i128,
     pub security_deposit: i128,
@@ -30,6 +117,15 @@ pub struct SourceEscrowData {
     pub part_index: u64,
     pub total_parts: u32,
     pub is_partial_fill: bool,
+    pub created_at: u64,
+    pub settlement_callback: Option<Address>,
+    pub allowance_consumed: i128, // factory allowance drawn from the buyer (creator), restored on cancel
+    pub gas_deposit: i128, // posted by the maker at creation; reimburses whoever completes the withdrawal, refunded otherwise
+    pub rescue_recipient: Option<Address>, // receives rescued principal instead of the recipient
+    pub proposed_cancellation_start: Option<u64>, // creator-proposed extension awaiting the recipient's approval
+    pub require_dual_auth: bool, // withdrawal needs both the creator's and the recipient's authorization
+    pub funded_via_allowance: bool, // principal pulled from the buyer with transfer_from by a third party (e.g. the LOP)
+    pub frozen: bool, // set by the owner; blocks withdrawal, cancellation, rescue and purging
 }
 
 #[contracttype]
@@ -38,18 +134,27 @@ pub struct DestinationEscrowData {
     pub creator: Address,
     pub recipient: Address,
     pub hashed_secret: BytesN<32>,
+    pub hash_algo: HashAlgo,
     pub token: Address,
     pub amount: i128,
     pub security_deposit: i128,
+    pub exclusive_withdrawal_start: u64, // creator-only window before withdrawal_start (0 = none)
     pub withdrawal_start: u64,
     pub public_withdrawal_start: u64,
     pub cancellation_start: u64,
+    pub public_cancellation_start: u64, // anyone may cancel from here on, collecting the security deposit
     pub funds_withdrawn: bool,
     pub cancelled: bool,
     // Partial fill support
     pub part_index: u64,
     pub total_parts: u32,
     pub is_partial_fill: bool,
+    pub created_at: u64,
+    pub settlement_callback: Option<Address>,
+    pub recipient_is_contract: bool, // recipient is a Wasm contract notified via on_tokens_received
+    pub require_dual_auth: bool, // withdrawal needs both the creator's and the recipient's authorization
+    pub auto_refund_deadline: u64, // anyone may refund the creator once this passes (0 = disabled)
+    pub frozen: bool, // set by the owner; blocks withdrawal, cancellation, rescue and purging
 }
 
 #[contracttype]
@@ -61,177 +166,2295 @@ pub struct TimeWindows {
     pub public_cancellation_start: u64,
 }
 
-#[contracttype]
-pub enum DataKey {
-    SourceEscrow(Address),
-    DestinationEscrow(Address),
-    UserEscrows(Address),
-    EscrowExists(Address),
-    NativeToken,
-    EscrowCounter,
-    // Add authorization storage - equivalent to EVM's allowances mapping
-    TokenAllowance(Address, Address), // (token_owner, spender) -> amount
-    // Partial fill tracking - equivalent to EVM mappings
-    PartialFillsUsed(BytesN<32>, u64), // (hashLock, index) -> bool
-    PartialFillsCount(BytesN<32>), // hashLock -> count
-}
+// A part's secret hash and merkle proof against the order root, checked when a partial escrow is created
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PartProof {
+    pub secret_hash: BytesN<32>,
+    pub merkle_proof: Vec<BytesN<32>>,
+}
+
+// What an escrow closed by safe_exit without a replacement still owes in its paused token, paid by claim_stranded_funds
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StrandedFunds {
+    pub token: Address,
+    pub creator: Address,
+    pub principal: i128,
+    pub deposit_funder: Address,
+    pub deposit: i128,
+}
+
+// Every configurable factory parameter, returned in one view by get_config
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FactoryConfig {
+    pub native_token: Address,
+    pub owner: Address,
+    pub event_namespace: Symbol,
+    pub hash_algo: HashAlgo,
+    pub deposit_amount: i128,
+    pub rescue_delay: u64,
+    pub deposit_guard_enabled: bool,
+    pub min_deposit_bps: u32,
+    pub cancellation_penalty_bps: u32,
+    pub keeper_share_bps: u32,
+    pub fee_collector: Address,
+    pub max_escrow_lifetime: u64,
+    pub max_escrows_per_user: u32,
+    pub max_batch_proof_cost: u32,
+    pub token_allowlist_enabled: bool,
+    pub winding_down: bool,
+    pub max_batch_size: u32,
+    pub max_secret_hint_len: u32,
+}
+
+#[contracttype]
+pub enum DataKey {
+    SourceEscrow(BytesN<32>),
+    DestinationEscrow(BytesN<32>),
+    UserEscrows(Address), // user -> Vec<BytesN<32>> of the escrow ids they created
+    EscrowExists(BytesN<32>),
+    NativeToken,
+    EscrowCounter,
+    Owner, // contract owner
+    EventNamespace, // namespace carried in event topics (defaults to DEFAULT_EVENT_NAMESPACE)
+    SecurityDeposit, // i128 - security deposit posted per escrow, set at initialize
+    RescueDelay, // u64 - seconds after cancellation before funds can be rescued, set at initialize
+    DepositGuardEnabled, // reject escrows whose amount is below the security deposit
+    DepositExempt(Address), // resolver -> exempt from destination security deposit
+    CancellationPenaltyBps, // share of the security deposit forfeited on source cancellation
+    MinDepositBps, // minimum security deposit as basis points of the escrowed amount
+    KeeperShareBps, // share of the security deposit paid to the keeper on public cancellation
+    FeeCollector, // receives the non-keeper share of public cancellation deposits
+    PrincipalRescued(BytesN<32>), // source escrow -> principal paid out by a rescue
+    DepositRescued(BytesN<32>), // source escrow -> security and gas deposits paid out by a rescue
+    MaxEscrowLifetime, // seconds after creation an escrow may be force-purged (0 = disabled)
+    MaxEscrowsPerUser, // cap on the length of a user's UserEscrows list (0 = unlimited)
+    MaxBatchProofCost, // cap on the estimated hashing cost of a multiproof batch withdrawal
+    RevealedHash(BytesN<32>), // hash(secret) -> revealed by a withdrawal on this chain
+    RevealedSecret(BytesN<32>), // escrow -> secret its withdrawal revealed
+    HashAlgo, // hash algorithm stamped on newly created escrows
+    SecretHint(BytesN<32>), // escrow -> opaque creator-supplied secret recovery hint
+    RecentEscrow(BytesN<32>), // hash of escrow fields -> ledger sequence it was created in (temporary)
+    TokenPaused(Address), // token -> blacklisted; escrows in it may only exit via safe_exit
+    RecoveryPool(Address), // replacement token -> amount available to safe_exit payouts
+    StrandedFunds(BytesN<32>), // escrow closed by safe_exit without a replacement -> StrandedFunds owed in its token
+    Winddown, // set once by begin_winddown; no new escrows may be created
+    OpenEscrowHead, // u64 - lowest slot that may still hold an open escrow
+    OpenEscrowTail, // u64 - next free slot in the open-escrow index
+    OpenEscrowCount, // u64 - escrows neither withdrawn nor cancelled
+    OpenEscrow(u64), // slot -> open escrow id; slots are handed out in creation order
+    OpenEscrowSlot(BytesN<32>), // open escrow id -> its slot in the index
+    EscrowFunded(BytesN<32>), // escrow -> tokens the factory actually received for it (principal, deposit, top-ups)
+    TokenLiability(Address), // token -> total the factory owes in it across unsettled escrows and the recovery pool
+    DepositFunder(BytesN<32>), // source escrow -> party that posted its security deposit (the resolver, not the buyer)
+    TokenAllowlistEnabled, // restrict escrows to allowlisted tokens
+    TokenAllowed(Address), // token -> accepted while the allowlist is enabled
+    TokenDeposit(Address), // non-native token -> security deposit charged in it; unset means escrows may not hold it
+    // Add authorization storage - equivalent to EVM's allowances mapping
+    TokenAllowance(Address, Address), // (token_owner, spender) -> amount
+    AllowanceExpiration(Address, Address), // (token_owner, spender) -> expiration timestamp
+    AllowanceConsumed(Address, Address), // (token_owner, spender) -> amount drawn since the last approval
+    AssetAllowance(Address, Address), // (token_owner, non-native token) -> amount the factory may pull in that token
+    ApprovedSpenders(Address), // token_owner -> spenders it has granted an allowance, in grant order
+    // Partial fill tracking - equivalent to EVM mappings
+    PartialFillsUsed(BytesN<32>, u64), // (hashLock, index) -> bool
+    PartialFillsCount(BytesN<32>), // hashLock -> count
+    PartSecretHash(BytesN<32>, u64), // (hashLock, index) -> hash of that part's secret
+}
+
+// Keys that no longer fit in DataKey, which is at the 50-variant limit for contract types
+#[contracttype]
+pub enum DataKeyExt {
+    FailedAttempts(BytesN<32>), // escrow -> wrong secrets submitted through attempt_withdraw_*
+    GasDeposit(Address, Address), // (maker, token) -> gas deposit each of the maker's source escrows takes from it
+}
+
+#[contract]
+pub struct HashLockedEscrowFactory;
+
+// Helper functions for partial fill support
+impl HashLockedEscrowFactory {
+    /// Hash data with the escrow's hash algorithm
+    fn hash_bytes(env: &Env, algo: &HashAlgo, data: &Bytes) -> BytesN<32> {
+        let hash = match algo {
+            HashAlgo::Sha256 => env.crypto().sha256(data),
+            HashAlgo::Keccak256 => env.crypto().keccak256(data),
+        };
+        BytesN::from_array(env, &hash.to_array())
+    }
+
+    /// Generate merkle leaf - equivalent to PartialFillHelper.generateLeaf in EVM
+    fn generate_leaf(env: &Env, algo: &HashAlgo, index: u64, secret_hash: &BytesN<32>) -> BytesN<32> {
+        // Pack index (8 bytes big endian) + secret hash (32 bytes) and hash with the escrow's algorithm
+        let mut packed = Bytes::new(env);
+        
+        // Convert index to 8-byte big endian representation
+        let index_bytes = index.to_be_bytes();
+        for byte in index_bytes.iter() {
+            packed.push_back(*byte);
+        }
+        
+        // Append secret hash bytes
+        for i in 0..32 {
+            packed.push_back(secret_hash.get(i).unwrap());
+        }
+        
+        Self::hash_bytes(env, algo, &packed)
+    }
+    
+    /// Verify merkle proof - equivalent to MerkleProof.verify in EVM
+    fn verify_merkle_proof(env: &Env, algo: &HashAlgo, proof: &Vec<BytesN<32>>, root: &BytesN<32>, leaf: &BytesN<32>) -> bool {
+        let mut computed_hash = leaf.clone();
+        
+        for i in 0..proof.len() {
+            let proof_element = proof.get(i).unwrap();
+            
+            // Sort hashes for consistent ordering (same as Solidity)
+            if computed_hash <= proof_element {
+                computed_hash = Self::hash_pair(env, algo, &computed_hash, &proof_element);
+            } else {
+                computed_hash = Self::hash_pair(env, algo, &proof_element, &computed_hash);
+            }
+        }
+        
+        computed_hash == *root
+    }
+    
+    /// Verify a merkle multiproof - equivalent to MerkleProof.multiProofVerify in EVM
+    /// Leaves must be given in tree order; each flag picks the next leaf/hash (true) or proof element (false)
+    fn verify_multiproof(
+        env: &Env,
+        algo: &HashAlgo,
+        proof: &Vec<BytesN<32>>,
+        proof_flags: &Vec<bool>,
+        root: &BytesN<32>,
+        leaves: &Vec<BytesN<32>>,
+    ) -> bool {
+        let total_hashes = proof_flags.len();
+        if leaves.len() + proof.len() != total_hashes + 1 {
+            return false;
+        }
+
+        let mut hashes: Vec<BytesN<32>> = Vec::new(env);
+        let mut leaf_pos = 0;
+        let mut hash_pos = 0;
+        let mut proof_pos = 0;
+        for i in 0..total_hashes {
+            let a = if leaf_pos < leaves.len() {
+                leaf_pos += 1;
+                leaves.get(leaf_pos - 1).unwrap()
+            } else {
+                hash_pos += 1;
+                hashes.get(hash_pos - 1).unwrap()
+            };
+            let b = if proof_flags.get(i).unwrap() {
+                if leaf_pos < leaves.len() {
+                    leaf_pos += 1;
+                    leaves.get(leaf_pos - 1).unwrap()
+                } else {
+                    hash_pos += 1;
+                    hashes.get(hash_pos - 1).unwrap()
+                }
+            } else {
+                proof_pos += 1;
+                proof.get(proof_pos - 1).unwrap()
+            };
+
+            // Sort hashes for consistent ordering (same as Solidity)
+            if a <= b {
+                hashes.push_back(Self::hash_pair(env, algo, &a, &b));
+            } else {
+                hashes.push_back(Self::hash_pair(env, algo, &b, &a));
+            }
+        }
+
+        if total_hashes > 0 {
+            proof_pos == proof.len() && hashes.get(total_hashes - 1).unwrap() == *root
+        } else if !leaves.is_empty() {
+            leaves.get(0).unwrap() == *root
+        } else {
+            proof.get(0).unwrap() == *root
+        }
+    }
+
+    /// Compute the merkle root of leaves given in tree order
+    /// Pairs are hashed sorted and an odd node is promoted unchanged, matching the off-chain MerkleTree
+    fn merkle_root(env: &Env, algo: &HashAlgo, leaves: &Vec<BytesN<32>>) -> BytesN<32> {
+        let mut level = leaves.clone();
+        while level.len() > 1 {
+            let mut next: Vec<BytesN<32>> = Vec::new(env);
+            let mut i = 0;
+            while i < level.len() {
+                let a = level.get(i).unwrap();
+                if i + 1 < level.len() {
+                    let b = level.get(i + 1).unwrap();
+                    if a <= b {
+                        next.push_back(Self::hash_pair(env, algo, &a, &b));
+                    } else {
+                        next.push_back(Self::hash_pair(env, algo, &b, &a));
+                    }
+                } else {
+                    next.push_back(a);
+                }
+                i += 2;
+            }
+            level = next;
+        }
+        level.get(0).unwrap()
+    }
+
+    /// Hash two elements together - helper for merkle proof verification
+    fn hash_pair(env: &Env, algo: &HashAlgo, a: &BytesN<32>, b: &BytesN<32>) -> BytesN<32> {
+        let mut concat = Bytes::new(env);
+        
+        // Append first hash
+        for i in 0..32 {
+            concat.push_back(a.get(i).unwrap());
+        }
+        
+        // Append second hash
+        for i in 0..32 {
+            concat.push_back(b.get(i).unwrap());
+        }
+        
+        Self::hash_bytes(env, algo, &concat)
+    }
+    
+}
+
+#[contractimpl]
+impl HashLockedEscrowFactory {
+    /// Initialize the factory with the native token address and owner
+    /// event_namespace is carried in every event's topics so indexers can tell deployments apart
+    pub fn initialize(env: Env, native_token: Address, owner: Address, event_namespace: Option<Symbol>, security_deposit: i128, rescue_delay: u64) {
+        // Initializing again would hand the owner role to whoever calls it
+        if env.storage().instance().has(&DataKey::Owner) {
+            panic!("Already initialized");
+        }
+        // A cheap view call catches a mistyped token address here instead of on the first transfer
+        if !matches!(token::Client::new(&env, &native_token).try_decimals(), Ok(Ok(_))) {
+            panic!("Invalid native token");
+        }
+        if security_deposit < 0 {
+            panic!("Invalid deposit");
+        }
+
+        env.storage().instance().set(&DataKey::NativeToken, &native_token);
+        env.storage().instance().set(&DataKey::EscrowCounter, &0u64);
+        env.storage().instance().set(&DataKey::Owner, &owner);
+        env.storage().instance().set(&DataKey::SecurityDeposit, &security_deposit);
+        env.storage().instance().set(&DataKey::RescueDelay, &rescue_delay);
+        if let Some(namespace) = event_namespace {
+            env.storage().instance().set(&DataKey::EventNamespace, &namespace);
+        }
+    }
+
+    /// Get the namespace carried in this deployment's event topics
+    pub fn get_event_namespace(env: Env) -> Symbol {
+        env.storage()
+            .instance()
+            .get(&DataKey::EventNamespace)
+            .unwrap_or_else(|| Symbol::new(&env, DEFAULT_EVENT_NAMESPACE))
+    }
+
+    /// Enable or disable the amount-below-deposit guard (owner only, enabled by default)
+    pub fn set_deposit_guard(env: Env, caller: Address, enabled: bool) {
+        Self::require_owner(&env, &caller);
+        env.storage().instance().set(&DataKey::DepositGuardEnabled, &enabled);
+
+        log!(&env, "DepositGuardUpdated: enabled={}", enabled);
+    }
+
+    /// Check if the amount-below-deposit guard is enabled
+    pub fn is_deposit_guard_enabled(env: Env) -> bool {
+        env.storage()
+            .instance()
+            .get(&DataKey::DepositGuardEnabled)
+            .unwrap_or(true)
+    }
+
+    /// Exempt (or un-exempt) a trusted resolver from posting destination security deposits (owner only)
+    pub fn set_deposit_exempt(env: Env, caller: Address, resolver: Address, exempt: bool) {
+        Self::require_owner(&env, &caller);
+        env.storage().persistent().set(&DataKey::DepositExempt(resolver.clone()), &exempt);
+
+        log!(&env, "DepositExemptUpdated: resolver={}, exempt={}", resolver, exempt);
+    }
+
+    /// Check if a resolver is exempt from destination security deposits
+    pub fn is_deposit_exempt(env: Env, resolver: Address) -> bool {
+        env.storage()
+            .persistent()
+            .get(&DataKey::DepositExempt(resolver))
+            .unwrap_or(false)
+    }
+
+    /// Require every new escrow's security deposit to be at least min_deposit_bps of its amount (owner only, 0 = off)
+    pub fn set_min_deposit_bps(env: Env, caller: Address, min_deposit_bps: u32) {
+        Self::require_owner(&env, &caller);
+        if min_deposit_bps > 10_000 {
+            panic!("Invalid deposit ratio");
+        }
+        env.storage().instance().set(&DataKey::MinDepositBps, &min_deposit_bps);
+
+        log!(&env, "MinDepositBpsUpdated: min_deposit_bps={}", min_deposit_bps);
+    }
+
+    /// Get the minimum security deposit in basis points of the escrowed amount
+    pub fn get_min_deposit_bps(env: Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::MinDepositBps)
+            .unwrap_or(0)
+    }
+
+    /// Total security deposit `creator` must pre-fund to fill `count` source (or destination) parts held in `token`
+    /// Each part's deposit is resolved as creation resolves it: the token's deposit, waived on destination
+    /// parts for exempt resolvers. With `part_amounts`, one per part, each part is also checked against the
+    /// minimum deposit ratio, so the total is only returned for fills creation would accept
+    pub fn total_deposit_for_parts(
+        env: Env,
+        creator: Address,
+        token: Address,
+        is_source: bool,
+        count: u32,
+        part_amounts: Option<Vec<i128>>,
+    ) -> i128 {
+        let part_deposit = Self::creator_deposit(&env, &creator, &token, is_source);
+        if let Some(amounts) = part_amounts {
+            if amounts.len() != count {
+                panic!("Part count mismatch");
+            }
+            for amount in amounts.iter() {
+                Self::check_min_deposit(&env, amount, part_deposit);
+            }
+        }
+        (count as i128).checked_mul(part_deposit).expect("Deposit total overflow")
+    }
+
+    /// Enable or disable the token allowlist (owner only, disabled by default)
+    pub fn set_token_allowlist_enabled(env: Env, caller: Address, enabled: bool) {
+        Self::require_owner(&env, &caller);
+        env.storage().instance().set(&DataKey::TokenAllowlistEnabled, &enabled);
+
+        log!(&env, "TokenAllowlistUpdated: enabled={}", enabled);
+    }
+
+    /// Check if the token allowlist is enabled
+    pub fn is_token_allowlist_enabled(env: Env) -> bool {
+        env.storage()
+            .instance()
+            .get(&DataKey::TokenAllowlistEnabled)
+            .unwrap_or(false)
+    }
+
+    /// Add a token to the allowlist (owner only)
+    pub fn allow_token(env: Env, caller: Address, token: Address) {
+        Self::require_owner(&env, &caller);
+        env.storage().persistent().set(&DataKey::TokenAllowed(token.clone()), &true);
+
+        log!(&env, "TokenAllowed: token={}", token);
+    }
+
+    /// Remove a token from the allowlist (owner only)
+    pub fn disallow_token(env: Env, caller: Address, token: Address) {
+        Self::require_owner(&env, &caller);
+        env.storage().persistent().remove(&DataKey::TokenAllowed(token.clone()));
+
+        log!(&env, "TokenDisallowed: token={}", token);
+    }
+
+    /// Check if a token is on the allowlist
+    pub fn is_token_allowed(env: Env, token: Address) -> bool {
+        env.storage()
+            .persistent()
+            .get(&DataKey::TokenAllowed(token))
+            .unwrap_or(false)
+    }
+
+    /// Accept a non-native token for escrows, charging the given security deposit in it (owner only)
+    /// Passing None stops new escrows from holding the token; the native token always uses the initialize deposit
+    pub fn set_token_deposit(env: Env, caller: Address, token: Address, deposit: Option<i128>) {
+        Self::require_owner(&env, &caller);
+        if token == Self::get_native_token(env.clone()) {
+            panic!("Native token uses the default deposit");
+        }
+
+        match deposit {
+            Some(amount) => {
+                if amount < 0 {
+                    panic_with_error!(&env, EscrowError::InvalidAmount);
+                }
+                env.storage().persistent().set(&DataKey::TokenDeposit(token.clone()), &amount);
+                log!(&env, "TokenDepositSet: token={}, deposit={}", token, amount);
+            }
+            None => {
+                env.storage().persistent().remove(&DataKey::TokenDeposit(token.clone()));
+                log!(&env, "TokenDepositCleared: token={}", token);
+            }
+        }
+    }
+
+    /// Get the security deposit charged on escrows held in a non-native token (None if the token is not accepted)
+    pub fn get_token_deposit(env: Env, token: Address) -> Option<i128> {
+        env.storage().persistent().get(&DataKey::TokenDeposit(token))
+    }
+
+    /// Set the hash algorithm used by escrows created from now on (owner only, SHA256 by default)
+    /// Applies to both the secret preimage check and merkle leaf/pair hashing
+    pub fn set_hash_algo(env: Env, caller: Address, algo: HashAlgo) {
+        Self::require_owner(&env, &caller);
+        env.storage().instance().set(&DataKey::HashAlgo, &algo);
+
+        log!(&env, "HashAlgoUpdated: algo={}", algo);
+    }
+
+    /// Get the hash algorithm stamped on newly created escrows
+    pub fn get_hash_algo(env: Env) -> HashAlgo {
+        env.storage()
+            .instance()
+            .get(&DataKey::HashAlgo)
+            .unwrap_or(HashAlgo::Sha256)
+    }
+
+    /// Approve factory to spend tokens (equivalent to ERC20 approve() in EVM)
+    /// This allows the factory to transfer tokens on behalf of the caller
+    pub fn approve(env: Env, caller: Address, amount: i128) {
+        caller.require_auth();
+        
+        // Store the allowance - factory can spend up to 'amount' tokens from caller
+        Self::set_allowance(&env, &caller, &env.current_contract_address(), amount);
+        // A plain approval never expires
+        env.storage().persistent().remove(
+            &DataKey::AllowanceExpiration(caller.clone(), env.current_contract_address())
+        );
+        // A new grant starts with nothing consumed
+        env.storage().persistent().remove(
+            &DataKey::AllowanceConsumed(caller.clone(), env.current_contract_address())
+        );
+        
+        log!(&env, "Approval: owner={}, spender={}, amount={}", 
+             caller, env.current_contract_address(), amount);
+    }
+
+    /// Approve factory to spend tokens until the given ledger timestamp
+    /// After expiration the allowance reads as zero and can be pruned by anyone
+    pub fn approve_with_expiration(env: Env, caller: Address, amount: i128, expiration: u64) {
+        caller.require_auth();
+
+        if expiration <= env.ledger().timestamp() {
+            panic!("Invalid expiration");
+        }
+
+        let spender = env.current_contract_address();
+        Self::set_allowance(&env, &caller, &spender, amount);
+        env.storage().persistent().set(&DataKey::AllowanceExpiration(caller.clone(), spender.clone()), &expiration);
+        env.storage().persistent().remove(&DataKey::AllowanceConsumed(caller.clone(), spender.clone()));
+
+        log!(&env, "Approval: owner={}, spender={}, amount={}, expiration={}", 
+             caller, spender, amount, expiration);
+    }
+
+    /// Raise a spender's allowance by added without overwriting it, avoiding the approve() front-running race
+    /// The grant's expiration and consumed amount are left as they are; an expired grant must be re-approved instead
+    pub fn increase_allowance(env: Env, caller: Address, spender: Address, added: i128) {
+        caller.require_auth();
+
+        if added < 0 {
+            panic_with_error!(&env, EscrowError::InvalidAmount);
+        }
+        if Self::is_allowance_expired(&env, &caller, &spender) {
+            panic!("Allowance expired");
+        }
+        let amount = Self::allowance(env.clone(), caller.clone(), spender.clone())
+            .checked_add(added)
+            .unwrap_or_else(|| panic_with_error!(&env, EscrowError::InvalidAmount));
+        Self::set_allowance(&env, &caller, &spender, amount);
+
+        log!(&env, "AllowanceIncreased: owner={}, spender={}, amount={}", caller, spender, amount);
+    }
+
+    /// Lower a spender's allowance by subtracted, saturating at zero
+    pub fn decrease_allowance(env: Env, caller: Address, spender: Address, subtracted: i128) {
+        caller.require_auth();
+
+        if subtracted < 0 {
+            panic_with_error!(&env, EscrowError::InvalidAmount);
+        }
+        let amount = (Self::allowance(env.clone(), caller.clone(), spender.clone()) - subtracted).max(0);
+        Self::set_allowance(&env, &caller, &spender, amount);
+
+        log!(&env, "AllowanceDecreased: owner={}, spender={}, amount={}", caller, spender, amount);
+    }
+
+    /// Approve factory to pull up to amount of a non-native token for source escrows held in it
+    /// Grants are kept per token, so an approval in one token never funds escrows in another
+    pub fn approve_token(env: Env, caller: Address, token: Address, amount: i128) {
+        caller.require_auth();
+
+        if amount < 0 {
+            panic_with_error!(&env, EscrowError::InvalidAmount);
+        }
+        if token == Self::get_native_token(env.clone()) {
+            panic!("Use approve for the native token");
+        }
+        Self::set_asset_allowance(&env, &caller, &token, amount);
+
+        log!(&env, "TokenApproval: owner={}, token={}, amount={}", caller, token, amount);
+    }
+
+    /// Get how much of token the factory may pull from owner; the native token reads the factory allowance
+    pub fn token_allowance(env: Env, owner: Address, token: Address) -> i128 {
+        if token == Self::get_native_token(env.clone()) {
+            return Self::allowance(env.clone(), owner, env.current_contract_address());
+        }
+        env.storage()
+            .persistent()
+            .get(&DataKey::AssetAllowance(owner, token))
+            .unwrap_or(0)
+    }
+
+    /// Get current allowance (equivalent to ERC20 allowance() in EVM)
+    pub fn allowance(env: Env, owner: Address, spender: Address) -> i128 {
+        if Self::is_allowance_expired(&env, &owner, &spender) {
+            return 0;
+        }
+        env.storage()
+            .persistent()
+            .get(&DataKey::TokenAllowance(owner, spender))
+            .unwrap_or(0)
+    }
+
+    /// Get how much of the current grant the spender has drawn; consumed + allowance is the amount approved
+    pub fn get_consumed(env: Env, owner: Address, spender: Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::AllowanceConsumed(owner, spender))
+            .unwrap_or(0)
+    }
+
+    /// Get allowance expiration timestamp (0 if the allowance never expires)
+    pub fn allowance_expiration(env: Env, owner: Address, spender: Address) -> u64 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::AllowanceExpiration(owner, spender))
+            .unwrap_or(0)
+    }
+
+    /// Remove an expired allowance entry to reclaim its storage rent (permissionless)
+    pub fn prune_expired_allowance(env: Env, owner: Address, spender: Address) {
+        if !Self::is_allowance_expired(&env, &owner, &spender) {
+            panic!("Allowance not expired");
+        }
+
+        env.storage().persistent().remove(&DataKey::TokenAllowance(owner.clone(), spender.clone()));
+        env.storage().persistent().remove(&DataKey::AllowanceExpiration(owner.clone(), spender.clone()));
+        env.storage().persistent().remove(&DataKey::AllowanceConsumed(owner.clone(), spender.clone()));
+        Self::untrack_spender(&env, &owner, &spender);
+        Self::publish_approval(&env, &owner, &spender, &Self::get_native_token(env.clone()), 0);
+
+        log!(&env, "AllowancePruned: owner={}, spender={}", owner, spender);
+    }
+
+    /// Revoke a spender's allowance entirely, clearing its expiration and consumed amount
+    pub fn revoke_allowance(env: Env, caller: Address, spender: Address) {
+        caller.require_auth();
+
+        env.storage().persistent().remove(&DataKey::TokenAllowance(caller.clone(), spender.clone()));
+        env.storage().persistent().remove(&DataKey::AllowanceExpiration(caller.clone(), spender.clone()));
+        env.storage().persistent().remove(&DataKey::AllowanceConsumed(caller.clone(), spender.clone()));
+        Self::untrack_spender(&env, &caller, &spender);
+        Self::publish_approval(&env, &caller, &spender, &Self::get_native_token(env.clone()), 0);
+
+        log!(&env, "AllowanceRevoked: owner={}, spender={}", caller, spender);
+    }
+
+    /// Get the spenders an owner has granted an allowance, so stale approvals can be audited and revoked
+    pub fn get_approved_spenders(env: Env, owner: Address) -> Vec<Address> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::ApprovedSpenders(owner))
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Pre-flight for create_src_escrow and create_src_escrow_with_token: runs the create-time checks without
+    /// changing state, using the same deposit and duplicate rules as creation
+    /// Returns (true, "ok") or (false, reason) for the first failing check, one of: invalid_amount,
+    /// winding_down, below_deposit, token_not_supported, amount_overflow, deposit_below_ratio, token_not_allowed,
+    /// paused, duplicate, user_escrow_limit, bad_windows, insufficient_allowance, insufficient_balance,
+    /// insufficient_deposit_balance
+    pub fn can_create_src_escrow(
+        env: Env,
+        creator: Address,
+        hashed_secret: BytesN<32>,
+        recipient: Address,
+        buyer: Address,
+        token: Address,
+        token_amount: i128,
+        windows: TimeWindows,
+    ) -> (bool, Symbol) {
+        let deposit = Self::token_deposit(&env, &token);
+        let min_deposit = Self::min_deposit_for(&env, token_amount);
+        let duplicate_key = Self::recent_escrow_key(&env, true, &hashed_secret, 0, token_amount, &buyer, &recipient);
+        // The buyer funds the principal and its gas deposit, the creator the security deposit
+        let buyer_owes = token_amount.saturating_add(Self::get_gas_deposit(env.clone(), buyer.clone(), token.clone()));
+        let creator_owes = deposit.unwrap_or(0).saturating_add(if creator == buyer { buyer_owes } else { 0 });
+        let token_client = token::Client::new(&env, &token);
+        let reason = if token_amount <= 0 {
+            "invalid_amount"
+        } else if Self::is_winddown(env.clone()) {
+            "winding_down"
+        } else if Self::is_below_deposit_guard(&env, token_amount) {
+            "below_deposit"
+        } else if deposit.is_none() {
+            "token_not_supported"
+        } else if min_deposit.is_none() {
+            "amount_overflow"
+        } else if deposit < min_deposit {
+            "deposit_below_ratio"
+        } else if Self::is_token_allowlist_enabled(env.clone()) && !Self::is_token_allowed(env.clone(), token.clone()) {
+            "token_not_allowed"
+        } else if Self::is_token_paused(env.clone(), token.clone()) {
+            "paused"
+        } else if Self::is_recent_duplicate(&env, &duplicate_key) {
+            "duplicate"
+        } else if Self::is_at_user_escrow_limit(&env, &Self::get_user_escrows(env.clone(), buyer.clone())) {
+            "user_escrow_limit"
+        } else if windows.public_withdrawal_start <= windows.withdrawal_start
+            || windows.cancellation_start <= windows.public_withdrawal_start
+            || windows.public_cancellation_start <= windows.cancellation_start
+        {
+            "bad_windows"
+        } else if Self::token_allowance(env.clone(), buyer.clone(), token.clone()) < token_amount {
+            "insufficient_allowance"
+        } else if token_client.balance(&buyer) < buyer_owes {
+            "insufficient_balance"
+        } else if token_client.balance(&creator) < creator_owes {
+            "insufficient_deposit_balance"
+        } else {
+            return (true, symbol_short!("ok"));
+        };
+        (false, Symbol::new(&env, reason))
+    }
+
+    /// Create a source escrow (equivalent to createSrcEscrow in EVM) - full fill only
+    /// This function requires authorization from both creator (resolver) and buyer
+    /// With require_dual_auth set, withdrawal also needs both the creator's and the recipient's authorization
+    pub fn create_src_escrow(
+        env: Env,
+        creator: Address,
+        hashed_secret: BytesN<32>,
+        recipient: Address,
+        buyer: Address,
+        token_amount: i128,
+        withdrawal_start: u64,
+        public_withdrawal_start: u64,
+        cancellation_start: u64,
+        public_cancellation_start: u64,
+        require_dual_auth: bool,
+    ) -> BytesN<32> {
+        let token = Self::get_native_token(env.clone());
+        let windows = TimeWindows { withdrawal_start, public_withdrawal_start, cancellation_start, public_cancellation_start };
+        Self::open_src_escrow(env, creator, hashed_secret, recipient, buyer, token, token_amount, windows, require_dual_auth)
+    }
+
+    /// Create a source escrow holding `token` instead of the native token - full fill only
+    /// Principal and security deposit are both taken in `token`, and withdrawals and refunds pay out in it
+    pub fn create_src_escrow_with_token(
+        env: Env,
+        creator: Address,
+        hashed_secret: BytesN<32>,
+        recipient: Address,
+        buyer: Address,
+        token: Address,
+        token_amount: i128,
+        windows: TimeWindows,
+        require_dual_auth: bool,
+    ) -> BytesN<32> {
+        Self::open_src_escrow(env, creator, hashed_secret, recipient, buyer, token, token_amount, windows, require_dual_auth)
+    }
+
+    /// Create a source escrow like create_src_escrow_with_token, storing an opaque secret recovery hint with it
+    /// Contract storage is publicly readable, so the hint should be encrypted client-side
+    pub fn create_src_escrow_with_hint(
+        env: Env,
+        creator: Address,
+        hashed_secret: BytesN<32>,
+        recipient: Address,
+        buyer: Address,
+        token: Address,
+        token_amount: i128,
+        windows: TimeWindows,
+        require_dual_auth: bool,
+        secret_hint: Bytes,
+    ) -> BytesN<32> {
+        Self::check_secret_hint(&secret_hint);
+        let escrow_id = Self::open_src_escrow(env.clone(), creator, hashed_secret, recipient, buyer, token, token_amount, windows, require_dual_auth);
+        env.storage().persistent().set(&DataKey::SecretHint(escrow_id.clone()), &secret_hint);
+        escrow_id
+    }
+
+    /// Create a destination escrow (equivalent to createDstEscrow in EVM) - full fill only
+    /// This function requires authorization from creator (resolver) for token transfer
+    /// With require_dual_auth set, withdrawal also needs both the creator's and the recipient's authorization
+    pub fn create_dst_escrow(
+        env: Env,
+        creator: Address,
+        hashed_secret: BytesN<32>,
+        recipient: Address,
+        token_amount: i128,
+        withdrawal_start: u64,
+        public_withdrawal_start: u64,
+        cancellation_start: u64,
+        public_cancellation_start: u64,
+        require_dual_auth: bool,
+    ) -> BytesN<32> {
+        let token = Self::get_native_token(env.clone());
+        Self::open_dst_escrow(env, creator, hashed_secret, recipient, token, token_amount, withdrawal_start, public_withdrawal_start, cancellation_start, public_cancellation_start, require_dual_auth)
+    }
+
+    /// Create a destination escrow holding `token` instead of the native token - full fill only
+    /// Principal and security deposit are both taken in `token`, and withdrawals and refunds pay out in it
+    pub fn create_dst_escrow_with_token(
+        env: Env,
+        creator: Address,
+        hashed_secret: BytesN<32>,
+        recipient: Address,
+        token: Address,
+        token_amount: i128,
+        withdrawal_start: u64,
+        public_withdrawal_start: u64,
+        cancellation_start: u64,
+        public_cancellation_start: u64,
+        require_dual_auth: bool,
+    ) -> BytesN<32> {
+        Self::open_dst_escrow(env, creator, hashed_secret, recipient, token, token_amount, withdrawal_start, public_withdrawal_start, cancellation_start, public_cancellation_start, require_dual_auth)
+    }
+
+    /// Create a destination escrow like create_dst_escrow_with_token, storing an opaque secret recovery hint with it
+    /// Contract storage is publicly readable, so the hint should be encrypted client-side
+    pub fn create_dst_escrow_with_hint(
+        env: Env,
+        creator: Address,
+        hashed_secret: BytesN<32>,
+        recipient: Address,
+        token: Address,
+        token_amount: i128,
+        windows: TimeWindows,
+        require_dual_auth: bool,
+        secret_hint: Bytes,
+    ) -> BytesN<32> {
+        Self::check_secret_hint(&secret_hint);
+        let TimeWindows { withdrawal_start, public_withdrawal_start, cancellation_start, public_cancellation_start } = windows;
+        let escrow_id = Self::open_dst_escrow(env.clone(), creator, hashed_secret, recipient, token, token_amount, withdrawal_start, public_withdrawal_start, cancellation_start, public_cancellation_start, require_dual_auth);
+        env.storage().persistent().set(&DataKey::SecretHint(escrow_id.clone()), &secret_hint);
+        escrow_id
+    }
+
+    /// Withdraw from source escrow (equivalent to SourceEscrow.withdraw in EVM)
+    pub fn withdraw_src_escrow(
+        env: Env,
+        caller: Address,
+        escrow_id: BytesN<32>,
+        secret: Bytes,
+    ) {
+        caller.require_auth();
+
+        Self::withdraw_src(env, caller, escrow_id, secret, false);
+    }
+
+    /// Withdraw from a source escrow, recording a wrong secret instead of reverting
+    /// Returns false and bumps get_failed_attempts on a wrong secret; every other failure still reverts
+    /// withdraw_src_escrow cannot count wrong secrets, as its revert rolls back the write
+    pub fn attempt_withdraw_src_escrow(env: Env, caller: Address, escrow_id: BytesN<32>, secret: Bytes) -> bool {
+        caller.require_auth();
+
+        Self::withdraw_src(env, caller, escrow_id, secret, true)
+    }
+
+    /// Top up a source escrow's amount before it is withdrawn (creator only)
+    pub fn top_up_src_escrow(env: Env, caller: Address, escrow_id: BytesN<32>, additional: i128) {
+        caller.require_auth();
+
+        if additional <= 0 {
+            panic_with_error!(&env, EscrowError::InvalidAmount);
+        }
+
+        let mut escrow_data: SourceEscrowData = env.storage()
+            .persistent()
+            .get(&DataKey::SourceEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_src_escrow(&env, &escrow_id));
+
+        if escrow_data.funds_withdrawn {
+            panic_with_error!(&env, EscrowError::AlreadyWithdrawn);
+        }
+        if escrow_data.cancelled {
+            panic_with_error!(&env, EscrowError::AlreadyCancelled);
+        }
+        if env.ledger().timestamp() >= escrow_data.cancellation_start {
+            panic!("Withdrawal ended");
+        }
+        if caller != escrow_data.creator {
+            panic!("Unauthorized");
+        }
+
+        // Increase escrow amount
+        escrow_data.amount += additional;
+        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
+
+        // Pull additional tokens from creator
+        Self::fund_escrow(&env, &escrow_id, &escrow_data.token, &caller, additional, false);
+
+        Self::publish_src_event(&env, symbol_short!("topped_up"), &escrow_id, &escrow_data);
+        log!(&env, "SourceEscrowToppedUp: creator={}, additional={}, amount={}", 
+             caller, additional, escrow_data.amount);
+    }
+
+    /// Set the gas deposit each of the caller's source escrows in `token` takes from it at creation (maker only)
+    /// Separate from the security deposit: it is pulled alongside the principal, paid to whichever resolver
+    /// completes the withdrawal, or refunded to the maker if the escrow is cancelled or rescued instead
+    pub fn set_gas_deposit(env: Env, caller: Address, token: Address, amount: i128) {
+        caller.require_auth();
+
+        if amount < 0 {
+            panic!("Invalid gas deposit");
+        }
+        env.storage().persistent().set(&DataKeyExt::GasDeposit(caller.clone(), token.clone()), &amount);
+
+        log!(&env, "GasDepositSet: maker={}, token={}, amount={}", caller, token, amount);
+    }
+
+    /// Get the gas deposit a maker's source escrows in `token` carry (0 by default)
+    pub fn get_gas_deposit(env: Env, maker: Address, token: Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKeyExt::GasDeposit(maker, token))
+            .unwrap_or(0)
+    }
+
+    /// Withdraw from source escrow with merkle proof (equivalent to SourceEscrow.withdrawWithProof in EVM)
+    pub fn withdraw_src_escrow_with_proof(
+        env: Env,
+        caller: Address,
+        escrow_id: BytesN<32>,
+        secret: Bytes,
+        merkle_proof: Vec<BytesN<32>>,
+    ) {
+        caller.require_auth();
+
+        let mut escrow_data: SourceEscrowData = env.storage()
+            .persistent()
+            .get(&DataKey::SourceEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_src_escrow(&env, &escrow_id));
+        Self::check_not_frozen(&env, &escrow_id);
+
+        // Validate escrow state (same validations as EVM)
+        if escrow_data.funds_withdrawn {
+            panic_with_error!(&env, EscrowError::AlreadyWithdrawn);
+        }
+        if escrow_data.cancelled {
+            panic_with_error!(&env, EscrowError::AlreadyCancelled);
+        }
+        Self::check_token_not_paused(&env, &escrow_data.token);
+
+        let current_time = env.ledger().timestamp();
+        if current_time < escrow_data.withdrawal_start {
+            panic_with_error!(&env, EscrowError::WithdrawalNotStarted);
+        }
+        if current_time >= escrow_data.cancellation_start {
+            panic!("Withdrawal ended");
+        }
+
+        // Must be partial fill to use this function
+        if !escrow_data.is_partial_fill {
+            panic!("Use withdraw() for complete fills");
+        }
+
+        // Check private window restriction (same as EVM)
+        if current_time < escrow_data.public_withdrawal_start && caller != escrow_data.recipient {
+            panic!("Private window only");
+        }
+        if escrow_data.require_dual_auth {
+            Self::require_dual_auth(&caller, &escrow_data.creator, &escrow_data.recipient);
+        }
+
+        // An empty proof would accept a leaf equal to the root, bypassing per-index binding
+        if merkle_proof.is_empty() {
+            panic!("Empty proof");
+        }
+
+        // Use hashedSecret directly as merkle root (no embedded parts count) - matches EVM
+        let merkle_root = escrow_data.hashed_secret.clone();
+
+        // Verify merkle proof; the leaf is always derived from the escrow's own part_index, never a
+        // caller-supplied index, so a proof for another part of the same root cannot withdraw this one
+        let secret_hash_bytes = Self::hash_bytes(&env, &escrow_data.hash_algo, &secret);
+        let leaf = Self::generate_leaf(&env, &escrow_data.hash_algo, escrow_data.part_index, &secret_hash_bytes);
+        
+        if !Self::verify_merkle_proof(&env, &escrow_data.hash_algo, &merkle_proof, &merkle_root, &leaf) {
+            panic!("Invalid merkle proof");
+        }
+        Self::mark_secret_revealed(&env, &escrow_id, &secret_hash_bytes, &secret);
+
+        // Mark as withdrawn
+        escrow_data.funds_withdrawn = true;
+        Self::untrack_open_escrow(&env, &escrow_id);
+        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
+
+        // Transfer funds, security deposit and the gas reimbursement to caller (resolver) in one transfer - matches EVM behavior
+        Self::pay_out(&env, &escrow_data.token, &caller, escrow_data.amount, &caller, escrow_data.security_deposit + escrow_data.gas_deposit);
+
+        Self::notify_settled(&env, &escrow_data.settlement_callback, &escrow_id, &caller, escrow_data.amount);
+
+        Self::publish_src_event(&env, symbol_short!("withdrawn"), &escrow_id, &escrow_data);
+        log!(&env, "SourceEscrowWithdrawalWithProof: caller={}, amount={}", caller, escrow_data.amount);
+    }
+
+    /// Verify a merkle multiproof against root using the factory's current hash algorithm
+    pub fn verify_merkle_multiproof(
+        env: Env,
+        proof: Vec<BytesN<32>>,
+        proof_flags: Vec<bool>,
+        root: BytesN<32>,
+        leaves: Vec<BytesN<32>>,
+    ) -> bool {
+        let algo = Self::get_hash_algo(env.clone());
+        Self::verify_multiproof(&env, &algo, &proof, &proof_flags, &root, &leaves)
+    }
+
+    /// Store the per-part secret hashes of a partial-fill order, in part order
+    /// Accepted only if the tree built from them has order_hash as its root, so anyone may submit them
+    pub fn set_order_secret_hashes(env: Env, order_hash: BytesN<32>, secret_hashes: Vec<BytesN<32>>) {
+        if secret_hashes.is_empty() {
+            panic!("Empty secret hashes");
+        }
+
+        let algo = Self::get_hash_algo(env.clone());
+        let mut leaves: Vec<BytesN<32>> = Vec::new(&env);
+        for i in 0..secret_hashes.len() {
+            leaves.push_back(Self::generate_leaf(&env, &algo, i as u64, &secret_hashes.get(i).unwrap()));
+        }
+        if Self::merkle_root(&env, &algo, &leaves) != order_hash {
+            panic!("Root mismatch");
+        }
+
+        for i in 0..secret_hashes.len() {
+            env.storage().persistent().set(
+                &DataKey::PartSecretHash(order_hash.clone(), i as u64),
+                &secret_hashes.get(i).unwrap(),
+            );
+        }
+
+        log!(&env, "OrderSecretHashesSet: order_hash={}, parts={}", order_hash, secret_hashes.len());
+    }
+
+    /// Get the merkle leaf of every part of an order from its stored secret hashes
+    /// Lets a resolver that lost its proof rebuild the tree and any proof off-chain
+    pub fn get_order_leaves(env: Env, order_hash: BytesN<32>, total_parts: u32) -> Vec<BytesN<32>> {
+        let algo = Self::get_hash_algo(env.clone());
+        let mut leaves: Vec<BytesN<32>> = Vec::new(&env);
+        for i in 0..total_parts as u64 {
+            let secret_hash: BytesN<32> = env
+                .storage()
+                .persistent()
+                .get(&DataKey::PartSecretHash(order_hash.clone(), i))
+                .unwrap_or_else(|| panic!("Secret hash unknown"));
+            leaves.push_back(Self::generate_leaf(&env, &algo, i, &secret_hash));
+        }
+        leaves
+    }
+
+    /// Withdraw several partial-fill source escrows of one order with a single merkle multiproof
+    /// Escrows must share the same root and be listed in the tree order of their leaves
+    /// At most MAX_WITHDRAW_BATCH_SIZE escrows may be withdrawn per call
+    pub fn withdraw_src_escrows_multiproof(
+        env: Env,
+        caller: Address,
+        escrow_ids: Vec<BytesN<32>>,
+        secrets: Vec<Bytes>,
+        proof: Vec<BytesN<32>>,
+        proof_flags: Vec<bool>,
+    ) {
+        if escrow_ids.len() > MAX_WITHDRAW_BATCH_SIZE {
+            panic!("Batch too large");
+        }
+        caller.require_auth();
+
+        if escrow_ids.is_empty() || escrow_ids.len() != secrets.len() {
+            panic!("Length mismatch");
+        }
+        // Without any hashing step a single leaf equal to the root would pass, bypassing per-index binding
+        if proof_flags.is_empty() {
+            panic!("Empty proof");
+        }
+        // Reject batches too costly to verify before any work is done, instead of running out of budget midway
+        if proof.len() + escrow_ids.len() > Self::get_max_batch_proof_cost(env.clone()) {
+            panic!("Batch proof cost too high");
+        }
+
+        let current_time = env.ledger().timestamp();
+        let mut escrows: Vec<SourceEscrowData> = Vec::new(&env);
+        let mut leaves: Vec<BytesN<32>> = Vec::new(&env);
+        for i in 0..escrow_ids.len() {
+            let escrow_id = escrow_ids.get(i).unwrap();
+            if escrow_ids.first_index_of(&escrow_id) != Some(i) {
+                panic!("Duplicate escrow");
+            }
+            let escrow_data: SourceEscrowData = env.storage()
+                .persistent()
+                .get(&DataKey::SourceEscrow(escrow_id.clone()))
+                .unwrap_or_else(|| Self::missing_src_escrow(&env, &escrow_id));
+            Self::check_not_frozen(&env, &escrow_id);
+
+            if escrow_data.funds_withdrawn {
+                panic_with_error!(&env, EscrowError::AlreadyWithdrawn);
+            }
+            if escrow_data.cancelled {
+                panic_with_error!(&env, EscrowError::AlreadyCancelled);
+            }
+            Self::check_token_not_paused(&env, &escrow_data.token);
+            if current_time < escrow_data.withdrawal_start {
+                panic_with_error!(&env, EscrowError::WithdrawalNotStarted);
+            }
+            if current_time >= escrow_data.cancellation_start {
+                panic!("Withdrawal ended");
+            }
+            if !escrow_data.is_partial_fill {
+                panic!("Use withdraw() for complete fills");
+            }
+            if current_time < escrow_data.public_withdrawal_start && caller != escrow_data.recipient {
+                panic!("Private window only");
+            }
+            if escrow_data.require_dual_auth {
+                Self::require_dual_auth(&caller, &escrow_data.creator, &escrow_data.recipient);
+            }
+
+            if let Some(first) = escrows.first() {
+                if escrow_data.hashed_secret != first.hashed_secret || escrow_data.hash_algo != first.hash_algo {
+                    panic!("Root mismatch");
+                }
+            }
+
+            let secret_hash_bytes = Self::hash_bytes(&env, &escrow_data.hash_algo, &secrets.get(i).unwrap());
+            leaves.push_back(Self::generate_leaf(&env, &escrow_data.hash_algo, escrow_data.part_index, &secret_hash_bytes));
+            escrows.push_back(escrow_data);
+        }
+
+        let first = escrows.first().unwrap();
+        if !Self::verify_multiproof(&env, &first.hash_algo, &proof, &proof_flags, &first.hashed_secret, &leaves) {
+            panic!("Invalid merkle proof");
+        }
+
+        for i in 0..escrow_ids.len() {
+            let escrow_id = escrow_ids.get(i).unwrap();
+            let mut escrow_data = escrows.get(i).unwrap();
+            let secret = secrets.get(i).unwrap();
+            let secret_hash_bytes = Self::hash_bytes(&env, &escrow_data.hash_algo, &secret);
+            Self::mark_secret_revealed(&env, &escrow_id, &secret_hash_bytes, &secret);
+
+            escrow_data.funds_withdrawn = true;
+            Self::untrack_open_escrow(&env, &escrow_id);
+            env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
+
+            // Principal, security deposit and gas deposit go to the caller, as in withdraw_src_escrow_with_proof
+            Self::pay_out(&env, &escrow_data.token, &caller, escrow_data.amount, &caller, escrow_data.security_deposit + escrow_data.gas_deposit);
+
+            Self::notify_settled(&env, &escrow_data.settlement_callback, &escrow_id, &caller, escrow_data.amount);
+
+            Self::publish_src_event(&env, symbol_short!("withdrawn"), &escrow_id, &escrow_data);
+            log!(&env, "SourceEscrowWithdrawalWithMultiproof: caller={}, escrow={}, amount={}", 
+                 caller, escrow_id, escrow_data.amount);
+        }
+    }
+
+    /// Withdraw from destination escrow (equivalent to DestinationEscrow.withdraw in EVM)
+    pub fn withdraw_dst_escrow(
+        env: Env,
+        caller: Address,
+        escrow_id: BytesN<32>,
+        secret: Bytes,
+    ) {
+        caller.require_auth();
+
+        Self::withdraw_dst(env, caller, escrow_id, secret, false);
+    }
+
+    /// Withdraw from a destination escrow, recording a wrong secret instead of reverting
+    /// Returns false and bumps get_failed_attempts on a wrong secret; every other failure still reverts
+    pub fn attempt_withdraw_dst_escrow(env: Env, caller: Address, escrow_id: BytesN<32>, secret: Bytes) -> bool {
+        caller.require_auth();
+
+        Self::withdraw_dst(env, caller, escrow_id, secret, true)
+    }
+
+    /// Get how many wrong secrets have been submitted for an escrow through the attempt_withdraw functions
+    /// Only those wrapper calls are counted: a wrong secret sent to withdraw_src_escrow, withdraw_dst_escrow or
+    /// the proof withdrawals reverts, and the revert rolls back any count, so this is a lower bound on guesses
+    pub fn get_failed_attempts(env: Env, escrow_id: BytesN<32>) -> u32 {
+        env.storage()
+            .persistent()
+            .get(&DataKeyExt::FailedAttempts(escrow_id))
+            .unwrap_or(0)
+    }
+
+    /// Withdraw from destination escrow with merkle proof (equivalent to DestinationEscrow.withdrawWithProof in EVM)
+    pub fn withdraw_dst_escrow_with_proof(
+        env: Env,
+        caller: Address,
+        escrow_id: BytesN<32>,
+        secret: Bytes,
+        merkle_proof: Vec<BytesN<32>>,
+    ) {
+        caller.require_auth();
+
+        let mut escrow_data: DestinationEscrowData = env.storage()
+            .persistent()
+            .get(&DataKey::DestinationEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_dst_escrow(&env, &escrow_id));
+        Self::check_not_frozen(&env, &escrow_id);
+
+        // Validate escrow state
+        if escrow_data.funds_withdrawn {
+            panic_with_error!(&env, EscrowError::AlreadyWithdrawn);
+        }
+        if escrow_data.cancelled {
+            panic_with_error!(&env, EscrowError::AlreadyCancelled);
+        }
+        Self::check_token_not_paused(&env, &escrow_data.token);
+
+        let current_time = env.ledger().timestamp();
+        if current_time < escrow_data.withdrawal_start {
+            // Creator (resolver) may act early during its exclusive window
+            if escrow_data.exclusive_withdrawal_start == 0 || current_time < escrow_data.exclusive_withdrawal_start {
+                panic_with_error!(&env, EscrowError::WithdrawalNotStarted);
+            }
+            if caller != escrow_data.creator {
+                panic!("Exclusive window only");
+            }
+        }
+        if current_time >= escrow_data.cancellation_start {
+            panic!("Withdrawal ended");
+        }
+
+        // Must be partial fill to use this function
+        if !escrow_data.is_partial_fill {
+            panic!("Use withdraw() for complete fills");
+        }
+
+        // Check private window - both recipient (buyer) and creator (resolver) can withdraw
+        if current_time < escrow_data.public_withdrawal_start
+            && caller != escrow_data.recipient
+            && caller != escrow_data.creator
+        {
+            panic!("Private window only");
+        }
+        if escrow_data.require_dual_auth {
+            Self::require_dual_auth(&caller, &escrow_data.creator, &escrow_data.recipient);
+        }
+
+        // An empty proof would accept a leaf equal to the root, bypassing per-index binding
+        if merkle_proof.is_empty() {
+            panic!("Empty proof");
+        }
+
+        // Use hashedSecret directly as merkle root (no embedded parts count) - matches EVM
+        let merkle_root = escrow_data.hashed_secret.clone();
+
+        // Verify merkle proof
+        let secret_hash_bytes = Self::hash_bytes(&env, &escrow_data.hash_algo, &secret);
+        let leaf = Self::generate_leaf(&env, &escrow_data.hash_algo, escrow_data.part_index, &secret_hash_bytes);
+        
+        if !Self::verify_merkle_proof(&env, &escrow_data.hash_algo, &merkle_proof, &merkle_root, &leaf) {
+            panic!("Invalid merkle proof");
+        }
+        Self::mark_secret_revealed(&env, &escrow_id, &secret_hash_bytes, &secret);
+
+        // Mark as withdrawn
+        escrow_data.funds_withdrawn = true;
+        Self::untrack_open_escrow(&env, &escrow_id);
+        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_id.clone()), &escrow_data);
+
+        // Transfer funds to recipient (buyer) regardless of who calls - matches EVM behavior
+        // and the security deposit to caller, in one transfer when the recipient withdraws itself
+        Self::pay_out(&env, &escrow_data.token, &escrow_data.recipient, escrow_data.amount, &caller, escrow_data.security_deposit);
+
+        Self::notify_recipient(&env, &escrow_data);
+        Self::notify_settled(&env, &escrow_data.settlement_callback, &escrow_id, &escrow_data.recipient, escrow_data.amount);
+
+        Self::publish_dst_event(&env, symbol_short!("withdrawn"), &escrow_id, &escrow_data);
+        log!(&env, "DestinationEscrowWithdrawalWithProof: caller={}, recipient={}, amount={}", 
+             caller, escrow_data.recipient, escrow_data.amount);
+    }
+
+    /// Set (or clear) the contract notified via on_settled when the escrow is withdrawn (creator only)
+    /// Must be set before withdrawal_start; a failing callback reverts the withdrawal, so the recipient
+    /// must co-authorize it or a creator could block the payout and reclaim the funds on cancellation
+    pub fn set_settlement_callback(env: Env, caller: Address, escrow_id: BytesN<32>, settlement_callback: Option<Address>) {
+        caller.require_auth();
+
+        let current_time = env.ledger().timestamp();
+        if let Some(mut escrow_data) = env.storage()
+            .persistent()
+            .get::<_, SourceEscrowData>(&DataKey::SourceEscrow(escrow_id.clone()))
+        {
+            if caller != escrow_data.creator {
+                panic!("Unauthorized");
+            }
+            if current_time >= escrow_data.withdrawal_start {
+                panic!("Withdrawal already started");
+            }
+            escrow_data.recipient.require_auth();
+            escrow_data.settlement_callback = settlement_callback.clone();
+            env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
+        } else {
+            let mut escrow_data = Self::get_dst_escrow(env.clone(), escrow_id.clone());
+            if caller != escrow_data.creator {
+                panic!("Unauthorized");
+            }
+            if current_time >= escrow_data.withdrawal_start {
+                panic!("Withdrawal already started");
+            }
+            escrow_data.recipient.require_auth();
+            escrow_data.settlement_callback = settlement_callback.clone();
+            env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_id.clone()), &escrow_data);
+        }
+
+        log!(&env, "SettlementCallbackSet: creator={}, callback={}", caller, settlement_callback);
+    }
+
+    /// Route a source escrow's rescued principal to a recovery address (None for the recipient)
+    /// Set by the creator before the withdrawal window opens; the principal would otherwise rescue to
+    /// the recipient, so the recipient must co-authorize the change
+    pub fn set_rescue_recipient(env: Env, caller: Address, escrow_id: BytesN<32>, rescue_recipient: Option<Address>) {
+        caller.require_auth();
+
+        let mut escrow_data: SourceEscrowData = env.storage()
+            .persistent()
+            .get(&DataKey::SourceEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_src_escrow(&env, &escrow_id));
+        if caller != escrow_data.creator {
+            panic!("Unauthorized");
+        }
+        if env.ledger().timestamp() >= escrow_data.withdrawal_start {
+            panic!("Withdrawal already started");
+        }
+        escrow_data.recipient.require_auth();
+        escrow_data.rescue_recipient = rescue_recipient.clone();
+        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
+
+        log!(&env, "RescueRecipientSet: creator={}, rescueRecipient={}", caller, rescue_recipient);
+    }
+
+    /// Propose moving a source escrow's cancellation_start later (creator only)
+    /// The window only moves once the recipient calls approve_extension; a new proposal replaces the pending one
+    pub fn propose_extension(env: Env, caller: Address, escrow_id: BytesN<32>, new_cancellation_start: u64) {
+        caller.require_auth();
+
+        let mut escrow_data = Self::get_extendable_src_escrow(&env, &escrow_id);
+        if caller != escrow_data.creator {
+            panic!("Unauthorized");
+        }
+        if new_cancellation_start <= escrow_data.cancellation_start {
+            panic!("Extension must be later");
+        }
+        escrow_data.proposed_cancellation_start = Some(new_cancellation_start);
+        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
+
+        log!(&env, "ExtensionProposed: escrow={}, cancellationStart={}", escrow_id, new_cancellation_start);
+    }
+
+    /// Approve the pending extension of a source escrow (recipient only)
+    /// public_cancellation_start moves by the same amount so the gap between the two windows is kept
+    pub fn approve_extension(env: Env, caller: Address, escrow_id: BytesN<32>) {
+        caller.require_auth();
+
+        let mut escrow_data = Self::get_extendable_src_escrow(&env, &escrow_id);
+        if caller != escrow_data.recipient {
+            panic!("Unauthorized");
+        }
+        let new_cancellation_start = escrow_data
+            .proposed_cancellation_start
+            .unwrap_or_else(|| panic!("No pending extension"));
+
+        escrow_data.public_cancellation_start += new_cancellation_start - escrow_data.cancellation_start;
+        escrow_data.cancellation_start = new_cancellation_start;
+        escrow_data.proposed_cancellation_start = None;
+        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
+
+        log!(&env, "ExtensionApproved: escrow={}, cancellationStart={}", escrow_id, new_cancellation_start);
+    }
+
+    /// Reserve a creator-only withdrawal window on a destination escrow before withdrawal_start
+    /// Gives the resolver first-mover rights; funds still go to the recipient
+    pub fn set_exclusive_withdrawal_start(env: Env, caller: Address, escrow_id: BytesN<32>, exclusive_withdrawal_start: u64) {
+        caller.require_auth();
+
+        let mut escrow_data: DestinationEscrowData = env.storage()
+            .persistent()
+            .get(&DataKey::DestinationEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_dst_escrow(&env, &escrow_id));
+
+        if caller != escrow_data.creator {
+            panic!("Unauthorized");
+        }
+        if escrow_data.funds_withdrawn {
+            panic_with_error!(&env, EscrowError::AlreadyWithdrawn);
+        }
+        if escrow_data.cancelled {
+            panic_with_error!(&env, EscrowError::AlreadyCancelled);
+        }
+
+        // Validate ordering: exclusive window must open in the future and before withdrawal_start
+        let current_time = env.ledger().timestamp();
+        if exclusive_withdrawal_start <= current_time
+            || exclusive_withdrawal_start >= escrow_data.withdrawal_start
+        {
+            panic_with_error!(&env, EscrowError::InvalidTimeWindows);
+        }
+
+        escrow_data.exclusive_withdrawal_start = exclusive_withdrawal_start;
+        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_id.clone()), &escrow_data);
+
+        log!(&env, "ExclusiveWithdrawalSet: creator={}, exclusive_withdrawal_start={}", 
+             caller, exclusive_withdrawal_start);
+    }
+
+    /// Opt a destination escrow into permissionless auto-refund once deadline passes (creator only, 0 disables)
+    /// The deadline may not precede cancellation_start, so it never cuts into the withdrawal windows
+    pub fn set_auto_refund_deadline(env: Env, caller: Address, escrow_id: BytesN<32>, deadline: u64) {
+        caller.require_auth();
+
+        let mut escrow_data: DestinationEscrowData = env.storage()
+            .persistent()
+            .get(&DataKey::DestinationEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_dst_escrow(&env, &escrow_id));
+
+        if caller != escrow_data.creator {
+            panic!("Unauthorized");
+        }
+        if escrow_data.funds_withdrawn {
+            panic_with_error!(&env, EscrowError::AlreadyWithdrawn);
+        }
+        if escrow_data.cancelled {
+            panic_with_error!(&env, EscrowError::AlreadyCancelled);
+        }
+        if deadline != 0 && deadline < escrow_data.cancellation_start {
+            panic_with_error!(&env, EscrowError::InvalidTimeWindows);
+        }
+
+        escrow_data.auto_refund_deadline = deadline;
+        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_id.clone()), &escrow_data);
+
+        log!(&env, "AutoRefundDeadlineSet: creator={}, escrow={}, deadline={}", caller, escrow_id, deadline);
+    }
+
+    /// Get the secret hint an escrow was created with (creator only)
+    pub fn get_secret_hint(env: Env, caller: Address, escrow_id: BytesN<32>) -> Option<Bytes> {
+        caller.require_auth();
+
+        if caller != Self::escrow_creator(&env, &escrow_id) {
+            panic!("Only creator");
+        }
+
+        env.storage().persistent().get(&DataKey::SecretHint(escrow_id))
+    }
+
+    /// Cancel source escrow (equivalent to SourceEscrow.cancel in EVM)
+    pub fn cancel_src_escrow(env: Env, caller: Address, escrow_id: BytesN<32>) {
+        caller.require_auth();
+
+        let mut escrow_data: SourceEscrowData = env.storage()
+            .persistent()
+            .get(&DataKey::SourceEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_src_escrow(&env, &escrow_id));
+        Self::check_not_frozen(&env, &escrow_id);
+
+        if escrow_data.funds_withdrawn {
+            panic_with_error!(&env, EscrowError::AlreadyWithdrawn);
+        }
+        if escrow_data.cancelled {
+            panic_with_error!(&env, EscrowError::AlreadyCancelled);
+        }
+
+        let current_time = env.ledger().timestamp();
+        if current_time < escrow_data.cancellation_start {
+            panic!("Cancellation not started");
+        }
+        if current_time >= escrow_data.public_cancellation_start {
+            panic!("Private cancellation ended");
+        }
+        if caller != escrow_data.creator {
+            panic!("Unauthorized");
+        }
+
+        // Mark as cancelled
+        escrow_data.cancelled = true;
+        Self::release_open_src_escrow(&env, &escrow_id, &escrow_data);
+        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
+
+        let (principal_refund, deposit_refund) = Self::cancel_refunds(&env, &escrow_data);
+        Self::restore_allowance(&env, &escrow_data);
+
+        // Return funds to creator
+        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, principal_refund, false);
+
+        // Return security deposit to whoever posted it, minus any penalty which goes to the recipient
+        Self::refund_deposit(&env, &escrow_data.token, &escrow_id, &escrow_data.creator, deposit_refund);
+        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.recipient, escrow_data.security_deposit - deposit_refund, false);
+
+        // Nobody completed the swap, so the gas deposit goes back to the creator
+        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.gas_deposit, false);
+
+        Self::publish_src_event(&env, symbol_short!("cancelled"), &escrow_id, &escrow_data);
+        log!(&env, "SourceEscrowCancelled: creator={}, amount={}", escrow_data.creator, escrow_data.amount);
+    }
+
+    /// Preview the (principal, deposit) refunded to the creator if the source escrow is cancelled
+    pub fn preview_cancel(env: Env, escrow_id: BytesN<32>) -> (i128, i128) {
+        let escrow_data = Self::get_src_escrow(env.clone(), escrow_id);
+        Self::cancel_refunds(&env, &escrow_data)
+    }
+
+    /// Set the share of the security deposit forfeited to the recipient on source cancellation (owner only)
+    pub fn set_cancellation_penalty(env: Env, caller: Address, penalty_bps: u32) {
+        Self::require_owner(&env, &caller);
+        if penalty_bps > 10_000 {
+            panic!("Invalid penalty");
+        }
+        env.storage().instance().set(&DataKey::CancellationPenaltyBps, &penalty_bps);
+
+        log!(&env, "CancellationPenaltyUpdated: penalty_bps={}", penalty_bps);
+    }
+
+    /// Get the cancellation penalty in basis points of the security deposit
+    pub fn get_cancellation_penalty(env: Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::CancellationPenaltyBps)
+            .unwrap_or(0)
+    }
+
+    /// Publicly cancel a source escrow once public_cancellation_start has passed (any keeper)
+    /// Principal returns to the creator; the security deposit is split between keeper and fee collector
+    pub fn public_cancel_src_escrow(env: Env, caller: Address, escrow_id: BytesN<32>) {
+        caller.require_auth();
+
+        let mut escrow_data: SourceEscrowData = env.storage()
+            .persistent()
+            .get(&DataKey::SourceEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_src_escrow(&env, &escrow_id));
+        Self::check_not_frozen(&env, &escrow_id);
+
+        if escrow_data.funds_withdrawn {
+            panic_with_error!(&env, EscrowError::AlreadyWithdrawn);
+        }
+        if escrow_data.cancelled {
+            panic_with_error!(&env, EscrowError::AlreadyCancelled);
+        }
+        if env.ledger().timestamp() < escrow_data.public_cancellation_start {
+            panic!("Public cancellation not started");
+        }
+
+        // Mark as cancelled
+        escrow_data.cancelled = true;
+        Self::release_open_src_escrow(&env, &escrow_id, &escrow_data);
+        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
+
+        // Return funds to creator
+        Self::restore_allowance(&env, &escrow_data);
+        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.amount, false);
+
+        // Split security deposit between keeper and fee collector
+        let keeper_share = escrow_data.security_deposit * Self::get_keeper_share(env.clone()) as i128 / 10_000;
+        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &caller, keeper_share, false);
+        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &Self::get_fee_collector(env.clone()), escrow_data.security_deposit - keeper_share, false);
+
+        // Nobody completed the swap, so the gas deposit goes back to the creator
+        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.gas_deposit, false);
+
+        Self::publish_src_event(&env, symbol_short!("cancelled"), &escrow_id, &escrow_data);
+        log!(&env, "SourceEscrowPublicCancelled: keeper={}, creator={}, amount={}", caller, escrow_data.creator, escrow_data.amount);
+    }
+
+    /// Set the keeper's share of the security deposit on public cancellation (owner only, 10000 by default)
+    pub fn set_keeper_share(env: Env, caller: Address, keeper_share_bps: u32) {
+        Self::require_owner(&env, &caller);
+        if keeper_share_bps > 10_000 {
+            panic!("Invalid keeper share");
+        }
+        env.storage().instance().set(&DataKey::KeeperShareBps, &keeper_share_bps);
+
+        log!(&env, "KeeperShareUpdated: keeper_share_bps={}", keeper_share_bps);
+    }
+
+    /// Get the keeper's share of the security deposit in basis points
+    pub fn get_keeper_share(env: Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::KeeperShareBps)
+            .unwrap_or(10_000)
+    }
+
+    /// Set the address receiving the remainder of public cancellation deposits (owner only)
+    pub fn set_fee_collector(env: Env, caller: Address, fee_collector: Address) {
+        Self::require_owner(&env, &caller);
+        env.storage().instance().set(&DataKey::FeeCollector, &fee_collector);
+
+        log!(&env, "FeeCollectorUpdated: fee_collector={}", fee_collector);
+    }
+
+    /// Get the fee collector (defaults to the owner)
+    pub fn get_fee_collector(env: Env) -> Address {
+        env.storage()
+            .instance()
+            .get(&DataKey::FeeCollector)
+            .unwrap_or_else(|| env.storage().instance().get(&DataKey::Owner).unwrap())
+    }
+
+    /// Cancel destination escrow (equivalent to DestinationEscrow.cancel in EVM)
+    /// Only the creator may cancel until public_cancellation_start; after that anyone may, collecting the security deposit
+    pub fn cancel_dst_escrow(env: Env, caller: Address, escrow_id: BytesN<32>) {
+        caller.require_auth();
+
+        let mut escrow_data: DestinationEscrowData = env.storage()
+            .persistent()
+            .get(&DataKey::DestinationEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_dst_escrow(&env, &escrow_id));
+        Self::check_not_frozen(&env, &escrow_id);
+
+        if escrow_data.funds_withdrawn {
+            panic_with_error!(&env, EscrowError::AlreadyWithdrawn);
+        }
+        if escrow_data.cancelled {
+            panic_with_error!(&env, EscrowError::AlreadyCancelled);
+        }
+
+        let current_time = env.ledger().timestamp();
+        if current_time < escrow_data.cancellation_start {
+            panic!("Cancellation not started");
+        }
+        if caller != escrow_data.creator && current_time < escrow_data.public_cancellation_start {
+            panic!("Unauthorized");
+        }
+
+        // Mark as cancelled
+        escrow_data.cancelled = true;
+        Self::untrack_open_escrow(&env, &escrow_id);
+        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_id.clone()), &escrow_data);
+
+        // Return funds to creator; the security deposit goes to whoever cancelled (the creator in the private window)
+        Self::pay_out(&env, &escrow_data.token, &escrow_data.creator, escrow_data.amount, &caller, escrow_data.security_deposit);
+
+        Self::publish_dst_event(&env, symbol_short!("cancelled"), &escrow_id, &escrow_data);
+        log!(&env, "DestinationEscrowCancelled: creator={}, amount={}", escrow_data.creator, escrow_data.amount);
+    }
+
+    /// Rescue funds from source escrow (equivalent to SourceEscrow.rescue in EVM)
+    /// Both halves are paid in one call here; use rescue_principal and rescue_deposit to claim them independently
+    pub fn rescue_src_escrow(env: Env, caller: Address, escrow_id: BytesN<32>) {
+        caller.require_auth();
+
+        let escrow_data = Self::load_rescuable_src_escrow(&env, &escrow_id);
+        if caller != escrow_data.recipient {
+            panic!("Unauthorized");
+        }
+
+        Self::rescue_src_principal(&env, &escrow_id, &escrow_data);
+        Self::rescue_src_deposit(&env, &escrow_id, &escrow_data);
+    }
+
+    /// Claim a rescuable source escrow's principal (recipient only), without touching the deposits
+    /// Paid to the rescue recipient, or the recipient by default; calling again once claimed does nothing
+    pub fn rescue_principal(env: Env, caller: Address, escrow_id: BytesN<32>) {
+        caller.require_auth();
+
+        let escrow_data = Self::load_rescuable_src_escrow(&env, &escrow_id);
+        if caller != escrow_data.recipient {
+            panic!("Unauthorized");
+        }
+
+        Self::rescue_src_principal(&env, &escrow_id, &escrow_data);
+    }
+
+    /// Claim a rescuable source escrow's security and gas deposits (creator or deposit funder), without touching the principal
+    /// The security deposit goes to whoever posted it and the gas deposit to the creator; calling again once claimed does nothing
+    pub fn rescue_deposit(env: Env, caller: Address, escrow_id: BytesN<32>) {
+        caller.require_auth();
+
+        let escrow_data = Self::load_rescuable_src_escrow(&env, &escrow_id);
+        if caller != escrow_data.creator && caller != Self::deposit_funder(&env, &escrow_id, &escrow_data.creator) {
+            panic!("Unauthorized");
+        }
+
+        Self::rescue_src_deposit(&env, &escrow_id, &escrow_data);
+    }
+
+    /// Return an unwithdrawn destination escrow's amount and deposit to its creator once its auto-refund deadline passes
+    /// Permissionless, so a keeper can settle it without waiting out the rescue delay
+    pub fn auto_refund_dst(env: Env, escrow_id: BytesN<32>) {
+        let mut escrow_data: DestinationEscrowData = env.storage()
+            .persistent()
+            .get(&DataKey::DestinationEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_dst_escrow(&env, &escrow_id));
+        Self::check_not_frozen(&env, &escrow_id);
+
+        if escrow_data.funds_withdrawn {
+            panic_with_error!(&env, EscrowError::AlreadyWithdrawn);
+        }
+        if escrow_data.cancelled {
+            panic_with_error!(&env, EscrowError::AlreadyCancelled);
+        }
+        if escrow_data.auto_refund_deadline == 0 || env.ledger().timestamp() < escrow_data.auto_refund_deadline {
+            panic!("Auto refund not available");
+        }
+
+        escrow_data.cancelled = true;
+        Self::untrack_open_escrow(&env, &escrow_id);
+        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_id.clone()), &escrow_data);
+
+        Self::pay_out(&env, &escrow_data.token, &escrow_data.creator, escrow_data.amount, &escrow_data.creator, escrow_data.security_deposit);
+
+        Self::publish_dst_event(&env, symbol_short!("refunded"), &escrow_id, &escrow_data);
+        log!(&env, "DestinationEscrowAutoRefunded: creator={}, amount={}", escrow_data.creator, escrow_data.amount);
+    }
+
+    /// Rescue funds from destination escrow (equivalent to DestinationEscrow.rescue in EVM)
+    pub fn rescue_dst_escrow(env: Env, caller: Address, escrow_id: BytesN<32>) {
+        caller.require_auth();
+
+        let mut escrow_data: DestinationEscrowData = env.storage()
+            .persistent()
+            .get(&DataKey::DestinationEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_dst_escrow(&env, &escrow_id));
+        Self::check_not_frozen(&env, &escrow_id);
+
+        if escrow_data.funds_withdrawn {
+            panic_with_error!(&env, EscrowError::AlreadyWithdrawn);
+        }
+        if escrow_data.cancelled {
+            panic_with_error!(&env, EscrowError::AlreadyCancelled);
+        }
+
+        let current_time = env.ledger().timestamp();
+        if current_time < escrow_data.public_cancellation_start.saturating_add(Self::get_rescue_delay(env.clone())) {
+            panic!("Rescue not available");
+        }
+        if caller != escrow_data.creator {
+            panic!("Unauthorized");
+        }
+
+        // Mark as withdrawn
+        escrow_data.funds_withdrawn = true;
+        Self::untrack_open_escrow(&env, &escrow_id);
+        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_id.clone()), &escrow_data);
+
+        // Transfer funds to creator
+        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.amount, false);
+
+        // Return security deposit to creator
+        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.security_deposit, false);
+
+        Self::publish_dst_event(&env, symbol_short!("rescued"), &escrow_id, &escrow_data);
+        log!(&env, "DestinationEscrowRescued: creator={}, amount={}", escrow_data.creator, escrow_data.amount);
+    }
+
+    /// Set the maximum escrow lifetime after which settled-or-abandoned escrows can be purged (owner only, 0 disables)
+    pub fn set_max_escrow_lifetime(env: Env, caller: Address, lifetime: u64) {
+        Self::require_owner(&env, &caller);
+        env.storage().instance().set(&DataKey::MaxEscrowLifetime, &lifetime);
+
+        log!(&env, "MaxEscrowLifetimeUpdated: lifetime={}", lifetime);
+    }
+
+    /// Get the maximum escrow lifetime (0 if disabled)
+    pub fn get_max_escrow_lifetime(env: Env) -> u64 {
+        env.storage()
+            .instance()
+            .get(&DataKey::MaxEscrowLifetime)
+            .unwrap_or(0)
+    }
+
+    /// Set the maximum number of escrows a user may hold in UserEscrows (owner only, 0 disables)
+    /// Purging finalized escrows frees capacity
+    pub fn set_max_escrows_per_user(env: Env, caller: Address, max_escrows: u32) {
+        Self::require_owner(&env, &caller);
+        env.storage().instance().set(&DataKey::MaxEscrowsPerUser, &max_escrows);
+
+        log!(&env, "MaxEscrowsPerUserUpdated: max={}", max_escrows);
+    }
+
+    /// Get the maximum number of escrows per user (0 if unlimited)
+    pub fn get_max_escrows_per_user(env: Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::MaxEscrowsPerUser)
+            .unwrap_or(0)
+    }
+
+    /// Set the ceiling on the estimated cost of a multiproof batch withdrawal (owner only)
+    /// The cost is one hash per proof element plus one per escrow leaf; batches above it revert up front
+    pub fn set_max_batch_proof_cost(env: Env, caller: Address, max_cost: u32) {
+        Self::require_owner(&env, &caller);
+        env.storage().instance().set(&DataKey::MaxBatchProofCost, &max_cost);
+
+        log!(&env, "MaxBatchProofCostUpdated: max={}", max_cost);
+    }
+
+    /// Get the ceiling on the estimated cost of a multiproof batch withdrawal
+    pub fn get_max_batch_proof_cost(env: Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::MaxBatchProofCost)
+            .unwrap_or(DEFAULT_MAX_BATCH_PROOF_COST)
+    }
+
+    /// Force-purge a source escrow past both its lifetime cap and rescue window (permissionless)
+    /// Any funds still held are returned (the security deposit to whoever posted it, the rest to the creator)
+    /// and the escrow's storage is removed; escrows in a paused token must go through safe_exit instead
+    /// The rescue parties keep the escrow to themselves for PURGE_GRACE_PERIOD after the rescue window opens
+    pub fn force_purge_src_escrow(env: Env, escrow_id: BytesN<32>) {
+        let escrow_data = Self::get_src_escrow(env.clone(), escrow_id.clone());
+        Self::check_not_frozen(&env, &escrow_id);
+        Self::check_purge_allowed(&env, escrow_data.created_at, escrow_data.public_cancellation_start);
+
+        // Escrows in a paused token can't pay out here; the owner exits them through safe_exit instead
+        if !escrow_data.funds_withdrawn && !escrow_data.cancelled {
+            Self::check_token_not_paused(&env, &escrow_data.token);
+            Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.amount + escrow_data.gas_deposit, false);
+            Self::refund_deposit(&env, &escrow_data.token, &escrow_id, &escrow_data.creator, escrow_data.security_deposit);
+            Self::release_open_src_escrow(&env, &escrow_id, &escrow_data);
+        } else if Self::is_rescue_started(&env, &escrow_id) {
+            Self::check_token_not_paused(&env, &escrow_data.token);
+            // Return whichever half of a partial rescue was never claimed
+            let mut unclaimed = 0;
+            if !env.storage().persistent().has(&DataKey::PrincipalRescued(escrow_id.clone())) {
+                unclaimed += escrow_data.amount;
+            }
+            if !env.storage().persistent().has(&DataKey::DepositRescued(escrow_id.clone())) {
+                unclaimed += escrow_data.gas_deposit;
+                Self::refund_deposit(&env, &escrow_data.token, &escrow_id, &escrow_data.creator, escrow_data.security_deposit);
+            }
+            Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, unclaimed, false);
+            env.storage().persistent().remove(&DataKey::PrincipalRescued(escrow_id.clone()));
+            env.storage().persistent().remove(&DataKey::DepositRescued(escrow_id.clone()));
+        }
+
+        env.storage().persistent().remove(&DataKey::SourceEscrow(escrow_id.clone()));
+        Self::remove_escrow_records(&env, &escrow_data.creator, &escrow_id);
+
+        Self::publish_src_event(&env, symbol_short!("purged"), &escrow_id, &escrow_data);
+        log!(&env, "SourceEscrowPurged: creator={}, amount={}", escrow_data.creator, escrow_data.amount);
+    }
+
+    /// Force-purge a destination escrow past both its lifetime cap and rescue window (permissionless)
+    /// Any funds still held are returned to the creator and the escrow's storage is removed
+    /// Escrows in a paused token must go through safe_exit instead
+    /// The creator keeps the escrow to itself for PURGE_GRACE_PERIOD after the rescue window opens
+    pub fn force_purge_dst_escrow(env: Env, escrow_id: BytesN<32>) {
+        let escrow_data = Self::get_dst_escrow(env.clone(), escrow_id.clone());
+        Self::check_not_frozen(&env, &escrow_id);
+        Self::check_purge_allowed(&env, escrow_data.created_at, escrow_data.public_cancellation_start);
+
+        if !escrow_data.funds_withdrawn && !escrow_data.cancelled {
+            Self::check_token_not_paused(&env, &escrow_data.token);
+            Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.amount + escrow_data.security_deposit, false);
+            Self::untrack_open_escrow(&env, &escrow_id);
+        }
+
+        env.storage().persistent().remove(&DataKey::DestinationEscrow(escrow_id.clone()));
+        Self::remove_escrow_records(&env, &escrow_data.creator, &escrow_id);
+
+        Self::publish_dst_event(&env, symbol_short!("purged"), &escrow_id, &escrow_data);
+        log!(&env, "DestinationEscrowPurged: creator={}, amount={}", escrow_data.creator, escrow_data.amount);
+    }
+
+    /// Mark an under-funded escrow invalid and refund whatever the factory actually received for it (owner only)
+    /// The refund is capped by the escrow's funding record; returns the total refunded to its funders
+    pub fn mark_escrow_invalid(env: Env, caller: Address, escrow_id: BytesN<32>) -> i128 {
+        Self::require_owner(&env, &caller);
+
+        let (creator, token, principal, deposit) = Self::close_escrow(&env, &escrow_id, symbol_short!("invalid"));
+
+        // Only refund what this escrow's own funding record shows was received, not the pooled balance
+        // The principal is transferred before the deposit, so a shortfall falls on the deposit first
+        let funded: i128 = env.storage()
+            .persistent()
+            .get(&DataKey::EscrowFunded(escrow_id.clone()))
+            .unwrap_or(0);
+        let principal_refund = principal.min(funded);
+        let deposit_refund = deposit.min(funded - principal_refund);
+
+        // Each share goes back to whoever funded it
+        Self::transfer_tokens(&env, &token, &env.current_contract_address(), &creator, principal_refund, false);
+        Self::refund_deposit(&env, &token, &escrow_id, &creator, deposit_refund);
+        // The escrow is closed, so the part of it that was never received is no longer owed either
+        Self::adjust_liability(&env, &token, -(principal + deposit - principal_refund - deposit_refund));
+
+        log!(&env, "EscrowMarkedInvalid: escrow={}, recorded={}, refunded={}", 
+             escrow_id, principal + deposit, principal_refund + deposit_refund);
+
+        principal_refund + deposit_refund
+    }
+
+    /// Permanently stop new escrow creation ahead of retiring the factory (owner only, one-way)
+    /// Withdrawals, cancellations and rescues keep working so existing escrows can settle
+    pub fn begin_winddown(env: Env, caller: Address) {
+        Self::require_owner(&env, &caller);
+        env.storage().instance().set(&DataKey::Winddown, &true);
+
+        log!(&env, "WinddownStarted: openEscrows={}", Self::get_open_escrows(env.clone()));
+    }
+
+    /// Check if the factory is winding down
+    pub fn is_winddown(env: Env) -> bool {
+        env.storage()
+            .instance()
+            .get(&DataKey::Winddown)
+            .unwrap_or(false)
+    }
+
+    /// Number of escrows not yet withdrawn or cancelled
+    pub fn get_open_escrows(env: Env) -> u64 {
+        env.storage().instance().get(&DataKey::OpenEscrowCount).unwrap_or(0)
+    }
+
+    /// Get the active escrow with the smallest created_at, for keepers to act on first
+    /// The open-escrow index is kept in creation order; this looks at most MAX_HEAD_STEPS slots past its head,
+    /// so if the head has fallen further behind it returns None until advance_oldest_escrow_head catches it up
+    pub fn get_oldest_active_escrow(env: Env) -> Option<BytesN<32>> {
+        let head: u64 = env.storage().instance().get(&DataKey::OpenEscrowHead).unwrap_or(0);
+        let tail: u64 = env.storage().instance().get(&DataKey::OpenEscrowTail).unwrap_or(0);
+        let end = tail.min(head.saturating_add(MAX_HEAD_STEPS));
+        (head..end).find_map(|slot| env.storage().persistent().get(&DataKey::OpenEscrow(slot)))
+    }
+
+    /// Move the open-escrow head past up to `max_steps` freed slots (permissionless); returns the new head
+    /// Settling the head escrow only walks MAX_HEAD_STEPS slots, so keepers call this to finish a longer walk
+    pub fn advance_oldest_escrow_head(env: Env, max_steps: u64) -> u64 {
+        Self::advance_open_escrow_head(&env, max_steps)
+    }
+
+    /// Check if the factory is winding down and every escrow has settled, so it can be abandoned
+    pub fn can_finalize_winddown(env: Env) -> bool {
+        Self::is_winddown(env.clone()) && Self::get_open_escrows(env) == 0
+    }
+
+    /// Freeze an escrow, blocking withdrawal, cancellation, rescue and purging until unfrozen (owner only)
+    /// Sets the frozen flag in the source or destination escrow's data
+    pub fn freeze_escrow(env: Env, caller: Address, escrow_id: BytesN<32>) {
+        Self::require_owner(&env, &caller);
+        Self::set_frozen(&env, &escrow_id, true);
+
+        log!(&env, "EscrowFrozen: escrow={}", escrow_id);
+    }
+
+    /// Lift a freeze placed by freeze_escrow (owner only)
+    pub fn unfreeze_escrow(env: Env, caller: Address, escrow_id: BytesN<32>) {
+        Self::require_owner(&env, &caller);
+        Self::set_frozen(&env, &escrow_id, false);
+
+        log!(&env, "EscrowUnfrozen: escrow={}", escrow_id);
+    }
+
+    /// Check if an escrow is frozen
+    pub fn is_escrow_frozen(env: Env, escrow_id: BytesN<32>) -> bool {
+        if let Some(escrow_data) = env.storage()
+            .persistent()
+            .get::<_, SourceEscrowData>(&DataKey::SourceEscrow(escrow_id.clone()))
+        {
+            return escrow_data.frozen;
+        }
+        env.storage()
+            .persistent()
+            .get::<_, DestinationEscrowData>(&DataKey::DestinationEscrow(escrow_id))
+            .is_some_and(|escrow_data| escrow_data.frozen)
+    }
+
+    /// Pause (or unpause) a token found to be malicious; its escrows can then only exit via safe_exit (owner only)
+    pub fn set_token_paused(env: Env, caller: Address, token: Address, paused: bool) {
+        Self::require_owner(&env, &caller);
+        env.storage().persistent().set(&DataKey::TokenPaused(token.clone()), &paused);
+
+        log!(&env, "TokenPausedUpdated: token={}, paused={}", token, paused);
+    }
+
+    /// Check if a token is paused
+    pub fn is_token_paused(env: Env, token: Address) -> bool {
+        env.storage()
+            .persistent()
+            .get(&DataKey::TokenPaused(token))
+            .unwrap_or(false)
+    }
+
+    /// Pay out an escrow left stranded by safe_exit without a replacement, once its token is unpaused
+    /// Anyone may call: the principal goes to the creator and the deposit to whoever posted it
+    pub fn claim_stranded_funds(env: Env, escrow_id: BytesN<32>) {
+        let stranded = Self::get_stranded_funds(env.clone(), escrow_id.clone())
+            .unwrap_or_else(|| panic!("No stranded funds"));
+        Self::check_token_not_paused(&env, &stranded.token);
+
+        env.storage().persistent().remove(&DataKey::StrandedFunds(escrow_id.clone()));
+        Self::pay_out(&env, &stranded.token, &stranded.creator, stranded.principal, &stranded.deposit_funder, stranded.deposit);
+
+        log!(&env, "StrandedFundsClaimed: escrow={}, principal={}, deposit={}", 
+             escrow_id, stranded.principal, stranded.deposit);
+    }
+
+    /// Get what an escrow closed by safe_exit without a replacement still owes (None once claimed or never stranded)
+    pub fn get_stranded_funds(env: Env, escrow_id: BytesN<32>) -> Option<StrandedFunds> {
+        env.storage().persistent().get(&DataKey::StrandedFunds(escrow_id))
+    }
+
+    /// Deposit replacement tokens into the recovery pool used by safe_exit
+    pub fn fund_recovery_pool(env: Env, caller: Address, token: Address, amount: i128) {
+        caller.require_auth();
+        if amount <= 0 {
+            panic_with_error!(&env, EscrowError::InvalidAmount);
+        }
+
+        Self::transfer_tokens(&env, &token, &caller, &env.current_contract_address(), amount, false);
+        let pool = Self::get_recovery_pool(env.clone(), token.clone());
+        env.storage().persistent().set(&DataKey::RecoveryPool(token.clone()), &(pool + amount));
+
+        RecoveryPoolFunded {
+            namespace: Self::get_event_namespace(env.clone()),
+            token: token.clone(),
+            amount,
+        }
+        .publish(&env);
+
+        log!(&env, "RecoveryPoolFunded: token={}, amount={}", token, amount);
+    }
+
+    /// Get the recovery pool balance for a replacement token
+    pub fn get_recovery_pool(env: Env, token: Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::RecoveryPool(token))
+            .unwrap_or(0)
+    }
+
+    /// Settle an escrow whose token is paused without calling that token (owner only)
+    /// The escrow is closed. With a replacement token, the recovery pool pays the principal to the creator and the
+    /// deposit to whoever posted it. With None nothing moves now: both stay in the paused token, recorded as
+    /// stranded funds that claim_stranded_funds pays to the same parties once the token is unpaused
+    pub fn safe_exit(env: Env, caller: Address, escrow_id: BytesN<32>, replacement_token: Option<Address>) {
+        Self::require_owner(&env, &caller);
+
+        let (creator, token, principal, deposit) = Self::close_escrow(&env, &escrow_id, symbol_short!("safe_exit"));
+        let recorded = principal + deposit;
+        if !Self::is_token_paused(env.clone(), token.clone()) {
+            panic!("Token not paused");
+        }
+
+        match replacement_token {
+            Some(replacement) => {
+                let pool = Self::get_recovery_pool(env.clone(), replacement.clone());
+                if pool < recorded {
+                    panic!("Insufficient recovery pool");
+                }
+                env.storage().persistent().set(&DataKey::RecoveryPool(replacement.clone()), &(pool - recorded));
+                Self::transfer_tokens(&env, &replacement, &env.current_contract_address(), &creator, principal, false);
+                Self::refund_deposit(&env, &replacement, &escrow_id, &creator, deposit);
+                // The pool paid in its place, so nothing is owed in the paused token any more
+                Self::adjust_liability(&env, &token, -recorded);
+            }
+            None => {
+                // Resolve the funder now, so a later purge of the escrow's records cannot redirect the deposit
+                let deposit_funder = Self::deposit_funder(&env, &escrow_id, &creator);
+                let stranded = StrandedFunds { token, creator: creator.clone(), principal, deposit_funder, deposit };
+                env.storage().persistent().set(&DataKey::StrandedFunds(escrow_id.clone()), &stranded);
+            }
+        }
+
+        log!(&env, "EscrowSafeExit: escrow={}, creator={}, amount={}", escrow_id, creator, recorded);
+    }
+
+    /// Get user escrows (equivalent to getUserEscrows in EVM)
+    pub fn get_user_escrows(env: Env, user: Address) -> Vec<BytesN<32>> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::UserEscrows(user))
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Get the token the factory escrows, as registered at initialize
+    pub fn get_native_token(env: Env) -> Address {
+        env.storage()
+            .instance()
+            .get(&DataKey::NativeToken)
+            .unwrap_or_else(|| panic!("Native token not set"))
+    }
+
+    /// Pair an amount in stroops with the token's decimals, so clients can render it in whole units
+    pub fn format_amount(env: Env, token: Address, amount: i128) -> (i128, u32) {
+        (amount, token::Client::new(&env, &token).decimals())
+    }
 
-#[contract]
-pub struct HashLockedEscrowFactory;
+    /// Get the security deposit configured at initialize
+    pub fn get_deposit_amount(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::SecurityDeposit).unwrap_or(0)
+    }
 
-// Helper functions for partial fill support
-impl HashLockedEscrowFactory {
-    /// Generate merkle leaf - equivalent to PartialFillHelper.generateLeaf in EVM
-    fn generate_leaf(env: &Env, index: u64, secret_hash: &BytesN<32>) -> BytesN<32> {
-        // Pack index (8 bytes big endian) + secret hash (32 bytes) and hash with SHA256
-        let mut packed = Bytes::new(env);
-        
-        // Convert index to 8-byte big endian representation
-        let index_bytes = index.to_be_bytes();
-        for byte in index_bytes.iter() {
-            packed.push_back(*byte);
+    /// Get the rescue delay configured at initialize
+    pub fn get_rescue_delay(env: Env) -> u64 {
+        env.storage().instance().get(&DataKey::RescueDelay).unwrap_or(0)
+    }
+
+    /// Get all configurable parameters and fixed limits in one call
+    pub fn get_config(env: Env) -> FactoryConfig {
+        FactoryConfig {
+            native_token: Self::get_native_token(env.clone()),
+            owner: env.storage().instance().get(&DataKey::Owner).unwrap(),
+            event_namespace: Self::get_event_namespace(env.clone()),
+            hash_algo: Self::get_hash_algo(env.clone()),
+            deposit_amount: Self::get_deposit_amount(env.clone()),
+            rescue_delay: Self::get_rescue_delay(env.clone()),
+            deposit_guard_enabled: Self::is_deposit_guard_enabled(env.clone()),
+            min_deposit_bps: Self::get_min_deposit_bps(env.clone()),
+            cancellation_penalty_bps: Self::get_cancellation_penalty(env.clone()),
+            keeper_share_bps: Self::get_keeper_share(env.clone()),
+            fee_collector: Self::get_fee_collector(env.clone()),
+            max_escrow_lifetime: Self::get_max_escrow_lifetime(env.clone()),
+            max_escrows_per_user: Self::get_max_escrows_per_user(env.clone()),
+            max_batch_proof_cost: Self::get_max_batch_proof_cost(env.clone()),
+            token_allowlist_enabled: Self::is_token_allowlist_enabled(env.clone()),
+            winding_down: Self::is_winddown(env),
+            max_batch_size: MAX_BATCH_SIZE,
+            max_secret_hint_len: MAX_SECRET_HINT_LEN,
         }
-        
-        // Append secret hash bytes
-        for i in 0..32 {
-            packed.push_back(secret_hash.get(i).unwrap());
+    }
+
+    /// Get source escrow details
+    pub fn get_src_escrow(env: Env, escrow_id: BytesN<32>) -> SourceEscrowData {
+        env.storage()
+            .persistent()
+            .get(&DataKey::SourceEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_src_escrow(&env, &escrow_id))
+    }
+
+    /// Security deposit a source escrow still holds; 0 once it has been withdrawn or cancelled
+    pub fn get_src_deposit_held(env: Env, escrow_id: BytesN<32>) -> i128 {
+        let escrow_data = Self::get_src_escrow(env, escrow_id);
+        if escrow_data.funds_withdrawn || escrow_data.cancelled {
+            0
+        } else {
+            escrow_data.security_deposit
         }
-        
-        // Hash the packed data with SHA256
-        let hash = env.crypto().sha256(&packed);
-        BytesN::from_array(env, &hash.to_array())
     }
-    
-    /// Verify merkle proof - equivalent to MerkleProof.verify in EVM
-    fn verify_merkle_proof(env: &Env, proof: &Vec<BytesN<32>>, root: &BytesN<32>, leaf: &BytesN<32>) -> bool {
-        let mut computed_hash = leaf.clone();
-        
-        for i in 0..proof.len() {
-            let proof_element = proof.get(i).unwrap();
-            
-            // Sort hashes for consistent ordering (same as Solidity)
-            if computed_hash <= proof_element {
-                computed_hash = Self::hash_pair(env, &computed_hash, &proof_element);
-            } else {
-                computed_hash = Self::hash_pair(env, &proof_element, &computed_hash);
+
+    /// What a resolver nets by withdrawing a source escrow: the principal plus the refunded security deposit
+    /// The factory charges no protocol fee, so nothing is deducted; any gas deposit is also paid to whoever
+    /// withdraws, so it is included as well
+    pub fn net_resolver_amount(env: Env, escrow_id: BytesN<32>) -> i128 {
+        let escrow_data = Self::get_src_escrow(env, escrow_id);
+        escrow_data.amount + escrow_data.security_deposit + escrow_data.gas_deposit
+    }
+
+    /// Get destination escrow details
+    pub fn get_dst_escrow(env: Env, escrow_id: BytesN<32>) -> DestinationEscrowData {
+        env.storage()
+            .persistent()
+            .get(&DataKey::DestinationEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_dst_escrow(&env, &escrow_id))
+    }
+
+    /// Get several source escrows in one call, in input order
+    /// Unknown ids are skipped when skip_unknown is set, otherwise they fail the call
+    pub fn get_src_escrows_batch(env: Env, escrow_ids: Vec<BytesN<32>>, skip_unknown: bool) -> Vec<SourceEscrowData> {
+        if escrow_ids.len() > MAX_BATCH_SIZE {
+            panic!("Batch too large");
+        }
+
+        let mut escrows = Vec::new(&env);
+        for escrow_id in escrow_ids.iter() {
+            match env.storage().persistent().get(&DataKey::SourceEscrow(escrow_id)) {
+                Some(escrow_data) => escrows.push_back(escrow_data),
+                None if skip_unknown => continue,
+                None => panic!("Invalid address"),
             }
         }
-        
-        computed_hash == *root
+        escrows
     }
-    
-    /// Hash two elements together - helper for merkle proof verification
-    fn hash_pair(env: &Env, a: &BytesN<32>, b: &BytesN<32>) -> BytesN<32> {
-        let mut concat = Bytes::new(env);
-        
-        // Append first hash
-        for i in 0..32 {
-            concat.push_back(a.get(i).unwrap());
+
+    /// Get several destination escrows in one call, in input order
+    /// Unknown ids are skipped when skip_unknown is set, otherwise they fail the call
+    pub fn get_dst_escrows_batch(env: Env, escrow_ids: Vec<BytesN<32>>, skip_unknown: bool) -> Vec<DestinationEscrowData> {
+        if escrow_ids.len() > MAX_BATCH_SIZE {
+            panic!("Batch too large");
         }
-        
-        // Append second hash
-        for i in 0..32 {
-            concat.push_back(b.get(i).unwrap());
+
+        let mut escrows = Vec::new(&env);
+        for escrow_id in escrow_ids.iter() {
+            match env.storage().persistent().get(&DataKey::DestinationEscrow(escrow_id)) {
+                Some(escrow_data) => escrows.push_back(escrow_data),
+                None if skip_unknown => continue,
+                None => panic!("Invalid address"),
+            }
         }
-        
-        // Hash the concatenated data with SHA256
-        let hash = env.crypto().sha256(&concat);
-        BytesN::from_array(env, &hash.to_array())
+        escrows
     }
-    
-}
 
-#[contractimpl]
-impl HashLockedEscrowFactory {
-    /// Initialize the factory with the native token address
-    pub fn initialize(env: Env, native_token: Address) {
-        env.storage().instance().set(&DataKey::NativeToken, &native_token);
-        env.storage().instance().set(&DataKey::EscrowCounter, &0u64);
+    /// Get destination escrow time windows in the same shape as the source side
+    pub fn get_dst_windows(env: Env, escrow_id: BytesN<32>) -> TimeWindows {
+        let escrow_data = Self::get_dst_escrow(env, escrow_id);
+        TimeWindows {
+            withdrawal_start: escrow_data.withdrawal_start,
+            public_withdrawal_start: escrow_data.public_withdrawal_start,
+            cancellation_start: escrow_data.cancellation_start,
+            public_cancellation_start: escrow_data.public_cancellation_start,
+        }
     }
 
-    /// Approve factory to spend tokens (equivalent to ERC20 approve() in EVM)
-    /// This allows the factory to transfer tokens on behalf of the caller
-    pub fn approve(env: Env, caller: Address, amount: i128) {
-        caller.require_auth();
-        
-        // Store the allowance - factory can spend up to 'amount' tokens from caller
-        env.storage().persistent().set(
-            &DataKey::TokenAllowance(caller.clone(), env.current_contract_address()),
-            &amount
-        );
-        
-        log!(&env, "Approval: owner={}, spender={}, amount={}", 
-             caller, env.current_contract_address(), amount);
+    /// Seconds until the escrow's withdrawal window opens (0 once open), for source or destination escrows
+    pub fn finality_remaining(env: Env, escrow_id: BytesN<32>) -> u64 {
+        let withdrawal_start = if let Some(escrow_data) = env.storage()
+            .persistent()
+            .get::<_, SourceEscrowData>(&DataKey::SourceEscrow(escrow_id.clone()))
+        {
+            escrow_data.withdrawal_start
+        } else {
+            Self::get_dst_escrow(env.clone(), escrow_id).withdrawal_start
+        };
+        withdrawal_start.saturating_sub(env.ledger().timestamp())
     }
 
-    /// Get current allowance (equivalent to ERC20 allowance() in EVM)
-    pub fn allowance(env: Env, owner: Address, spender: Address) -> i128 {
+    /// Seconds since the escrow was created, for source or destination escrows
+    pub fn get_escrow_age(env: Env, escrow_id: BytesN<32>) -> u64 {
+        let created_at = if let Some(escrow_data) = env.storage()
+            .persistent()
+            .get::<_, SourceEscrowData>(&DataKey::SourceEscrow(escrow_id.clone()))
+        {
+            escrow_data.created_at
+        } else {
+            Self::get_dst_escrow(env.clone(), escrow_id).created_at
+        };
+        env.ledger().timestamp().saturating_sub(created_at)
+    }
+
+    /// Check if the secret behind a hashlock has been revealed by any withdrawal on this chain
+    pub fn is_secret_revealed(env: Env, hashed_secret: BytesN<32>) -> bool {
         env.storage()
             .persistent()
-            .get(&DataKey::TokenAllowance(owner, spender))
+            .get(&DataKey::RevealedHash(hashed_secret))
+            .unwrap_or(false)
+    }
+
+    /// Get the secret revealed by withdrawing an escrow, so the counterparty can unlock the other chain
+    pub fn get_revealed_secret(env: Env, escrow_id: BytesN<32>) -> Option<Bytes> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::RevealedSecret(escrow_id))
+    }
+
+    /// Check if the id belongs to an escrow created by this factory
+    pub fn is_escrow_contract(env: Env, escrow_id: BytesN<32>) -> bool {
+        env.storage()
+            .persistent()
+            .get(&DataKey::EscrowExists(escrow_id))
+            .unwrap_or(false)
+    }
+
+    /// Get the total the factory owes in a token: every unsettled escrow and recovery pool balance held in it
+    pub fn get_token_liability(env: Env, token: Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::TokenLiability(token))
             .unwrap_or(0)
     }
 
-    /// Create a source escrow (equivalent to createSrcEscrow in EVM) - full fill only
-    /// This function requires authorization from both creator (resolver) and buyer
-    pub fn create_src_escrow(
+    /// Audit check: the escrow's tracked funding covers amount + security_deposit and the factory's
+    /// balance of its token covers everything owed in that token, not just this escrow. Settled escrows always pass
+    pub fn verify_escrow_solvency(env: Env, escrow_id: BytesN<32>) -> bool {
+        let (token, owed) = if let Some(escrow_data) = env.storage()
+            .persistent()
+            .get::<_, SourceEscrowData>(&DataKey::SourceEscrow(escrow_id.clone()))
+        {
+            let settled = escrow_data.funds_withdrawn || escrow_data.cancelled;
+            (escrow_data.token, if settled { 0 } else { escrow_data.amount + escrow_data.security_deposit + escrow_data.gas_deposit })
+        } else {
+            let escrow_data = Self::get_dst_escrow(env.clone(), escrow_id.clone());
+            let settled = escrow_data.funds_withdrawn || escrow_data.cancelled;
+            (escrow_data.token, if settled { 0 } else { escrow_data.amount + escrow_data.security_deposit })
+        };
+        if owed == 0 {
+            return true;
+        }
+
+        let funded: i128 = env.storage()
+            .persistent()
+            .get(&DataKey::EscrowFunded(escrow_id))
+            .unwrap_or(0);
+        let held = token::Client::new(&env, &token).balance(&env.current_contract_address());
+        funded >= owed && held >= Self::get_token_liability(env, token)
+    }
+
+    // Shared body of the full source escrow create functions, holding principal and deposit in `token`
+    fn open_src_escrow(
         env: Env,
         creator: Address,
         hashed_secret: BytesN<32>,
         recipient: Address,
         buyer: Address,
+        token: Address,
         token_amount: i128,
-        withdrawal_start: u64,
-        public_withdrawal_start: u64,
-        cancellation_start: u64,
-        public_cancellation_start: u64,
-    ) -> Address {
+        windows: TimeWindows,
+        require_dual_auth: bool,
+    ) -> BytesN<32> {
+        let TimeWindows { withdrawal_start, public_withdrawal_start, cancellation_start, public_cancellation_start } = windows;
         // Validate inputs (same as EVM contract)
         if token_amount <= 0 {
-            panic!("Invalid amount");
+            panic_with_error!(&env, EscrowError::InvalidAmount);
         }
+        Self::check_not_winding_down(&env);
+        Self::check_deposit_guard(&env, token_amount);
+        let security_deposit = Self::creator_deposit(&env, &creator, &token, true);
+        Self::check_min_deposit(&env, token_amount, security_deposit);
+        Self::check_token_allowed(&env, &token);
+        Self::check_token_not_paused(&env, &token);
+        Self::check_duplicate_escrow(&env, true, &hashed_secret, 0, token_amount, &buyer, &recipient);
 
         // Validate time windows (same as EVM contract)
         if public_withdrawal_start <= withdrawal_start
             || cancellation_start <= public_withdrawal_start
             || public_cancellation_start <= cancellation_start
         {
-            panic!("Invalid time windows");
+            panic_with_error!(&env, EscrowError::InvalidTimeWindows);
         }
 
         // Require authorization from creator (resolver) - equivalent to msg.sender check in EVM
         creator.require_auth();
         
         // Check allowance instead of requiring buyer auth - equivalent to EVM's transferFrom() pattern
-        let current_allowance = Self::allowance(env.clone(), buyer.clone(), env.current_contract_address());
+        // The allowance is keyed by token, so only a grant in the escrow's own token can fund it
+        let current_allowance = Self::token_allowance(env.clone(), buyer.clone(), token.clone());
         if current_allowance < token_amount {
-            panic!("Insufficient allowance");
+            panic_with_error!(&env, EscrowError::InsufficientAllowance);
         }
         
         // Reduce allowance - equivalent to EVM's transferFrom() reducing allowance
-        let new_allowance = current_allowance - token_amount;
-        env.storage().persistent().set(
-            &DataKey::TokenAllowance(buyer.clone(), env.current_contract_address()),
-            &new_allowance
-        );
+        if token == Self::get_native_token(env.clone()) {
+            Self::consume_allowance(&env, &buyer, &env.current_contract_address(), current_allowance, token_amount);
+        } else {
+            Self::set_asset_allowance(&env, &buyer, &token, current_allowance - token_amount);
+        }
 
-        // Generate unique escrow identifier using counter
-        let counter: u64 = env.storage().instance().get(&DataKey::EscrowCounter).unwrap_or(0);
-        let new_counter = counter + 1;
-        env.storage().instance().set(&DataKey::EscrowCounter, &new_counter);
-        
-        // Use the contract's own address as the escrow identifier
-        // Uniqueness is achieved through the counter and storage key system
-        let final_addr = env.current_contract_address();
+        // Each escrow is keyed by its own id, so escrows never overwrite one another
+        let escrow_id = Self::next_escrow_id(&env, &hashed_secret);
+        let funded_via_allowance = creator != buyer;
+        let gas_deposit = Self::get_gas_deposit(env.clone(), buyer.clone(), token.clone());
 
         // Create escrow data (full fill only - no partial fill logic)
         let escrow_data = SourceEscrowData {
             creator: buyer.clone(), // Use buyer as creator (matches EVM logic)
             recipient: recipient.clone(),
             hashed_secret: hashed_secret.clone(),
-            token: Self::get_native_token(&env),
+            hash_algo: Self::get_hash_algo(env.clone()),
+            token: token.clone(),
             amount: token_amount,
-            security_deposit: DEPOSIT_AMOUNT,
+            security_deposit,
             withdrawal_start,
             public_withdrawal_start,
             cancellation_start,
@@ -242,571 +2465,889 @@ impl HashLockedEscrowFactory {
             part_index: 0,
             total_parts: 1,
             is_partial_fill: false,
+            created_at: env.ledger().timestamp(),
+            settlement_callback: None,
+            allowance_consumed: token_amount,
+            gas_deposit,
+            rescue_recipient: None,
+            proposed_cancellation_start: None,
+            require_dual_auth,
+            funded_via_allowance,
+            frozen: false,
         };
 
         // Store escrow data
-        env.storage().persistent().set(&DataKey::SourceEscrow(final_addr.clone()), &escrow_data);
-        env.storage().persistent().set(&DataKey::EscrowExists(final_addr.clone()), &true);
+        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
+        env.storage().persistent().set(&DataKey::EscrowExists(escrow_id.clone()), &true);
+        env.storage().persistent().set(&DataKey::DepositFunder(escrow_id.clone()), &creator);
+        Self::track_open_escrow(&env, &escrow_id);
 
         // Update user escrows mapping
-        let mut user_escrows: Vec<Address> = env.storage()
-            .persistent()
-            .get(&DataKey::UserEscrows(buyer.clone()))
-            .unwrap_or(Vec::new(&env));
-        user_escrows.push_back(final_addr.clone());
-        env.storage().persistent().set(&DataKey::UserEscrows(buyer.clone()), &user_escrows);
+        Self::record_user_escrow(&env, &buyer, &escrow_id);
 
-        // Transfer tokens from buyer to this contract (equivalent to transferFrom in EVM)
-        let native_token = Self::get_native_token(&env);
-        Self::transfer_tokens(&env, &native_token, &buyer, &env.current_contract_address(), token_amount, true);
+        // Transfer tokens from buyer to this contract: via allowance (equivalent to transferFrom in EVM) when
+        // someone else creates the escrow, directly when the buyer creates it and has authorized this call itself
+        Self::fund_escrow(&env, &escrow_id, &token, &buyer, token_amount, funded_via_allowance);
+        // The maker's gas deposit comes from the buyer the same way
+        Self::fund_escrow(&env, &escrow_id, &token, &buyer, gas_deposit, funded_via_allowance);
 
         // Transfer security deposit from creator (resolver does this directly)
-        Self::transfer_tokens(&env, &native_token, &creator, &env.current_contract_address(), DEPOSIT_AMOUNT, false);
+        Self::fund_escrow(&env, &escrow_id, &token, &creator, security_deposit, false);
 
         // Log event (equivalent to SrcEscrowCreated event)
+        Self::publish_src_event(&env, symbol_short!("created"), &escrow_id, &escrow_data);
         log!(&env, "SrcEscrowCreated: creator={}, recipient={}, escrow={}, amount={}", 
-             buyer, recipient, final_addr, token_amount);
+             buyer, recipient, escrow_id, token_amount);
 
-        final_addr
+        escrow_id
     }
 
-    /// Create a destination escrow (equivalent to createDstEscrow in EVM) - full fill only
-    /// This function requires authorization from creator (resolver) for token transfer
-    pub fn create_dst_escrow(
+    // Shared body of the full destination escrow create functions, holding principal and deposit in `token`
+    fn open_dst_escrow(
         env: Env,
         creator: Address,
         hashed_secret: BytesN<32>,
         recipient: Address,
+        token: Address,
         token_amount: i128,
         withdrawal_start: u64,
         public_withdrawal_start: u64,
         cancellation_start: u64,
-    ) -> Address {
+        public_cancellation_start: u64,
+        require_dual_auth: bool,
+    ) -> BytesN<32> {
         // Validate inputs
         if token_amount <= 0 {
-            panic!("Invalid amount");
+            panic_with_error!(&env, EscrowError::InvalidAmount);
         }
+        Self::check_not_winding_down(&env);
+        Self::check_deposit_guard(&env, token_amount);
+        Self::check_token_allowed(&env, &token);
+        Self::check_token_not_paused(&env, &token);
+        Self::check_duplicate_escrow(&env, false, &hashed_secret, 0, token_amount, &creator, &recipient);
 
         // Validate time windows
         if public_withdrawal_start <= withdrawal_start
             || cancellation_start <= public_withdrawal_start
+            || public_cancellation_start <= cancellation_start
         {
-            panic!("Invalid time windows");
+            panic_with_error!(&env, EscrowError::InvalidTimeWindows);
         }
 
         // Require authorization from creator (resolver) - equivalent to msg.sender check in EVM
         creator.require_auth();
-        
-        // Require authorization from creator for token transfer - equivalent to approve() + transferFrom() in EVM
-        // In EVM, the resolver must have tokens and approve the factory
-        // In Soroban 23.0.0, the resolver must authorize this contract call
-        // Since creator.require_auth() is already called above, this covers the authorization
 
-        // Generate unique escrow identifier using counter
-        let counter: u64 = env.storage().instance().get(&DataKey::EscrowCounter).unwrap_or(0);
-        let new_counter = counter + 1;
-        env.storage().instance().set(&DataKey::EscrowCounter, &new_counter);
-        
-        // Use the contract's own address as the escrow identifier
-        // Uniqueness is achieved through the counter and storage key system
-        let final_addr = env.current_contract_address();
+        // Trusted resolvers may be exempt from posting the security deposit
+        let security_deposit = Self::creator_deposit(&env, &creator, &token, false);
+        Self::check_min_deposit(&env, token_amount, security_deposit);
+
+        // creator.require_auth() above authorizes both transfers out of the creator below:
+        // the principal and the security deposit, so the creator must hold amount + deposit
+
+        // Each escrow is keyed by its own id, so escrows never overwrite one another
+        let escrow_id = Self::next_escrow_id(&env, &hashed_secret);
 
         // Create escrow data (full fill only - no partial fill logic)
         let escrow_data = DestinationEscrowData {
             creator: creator.clone(),
             recipient: recipient.clone(),
             hashed_secret: hashed_secret.clone(),
-            token: Self::get_native_token(&env),
+            hash_algo: Self::get_hash_algo(env.clone()),
+            token: token.clone(),
             amount: token_amount,
-            security_deposit: DEPOSIT_AMOUNT,
+            security_deposit,
+            exclusive_withdrawal_start: 0,
             withdrawal_start,
             public_withdrawal_start,
             cancellation_start,
+            public_cancellation_start,
             funds_withdrawn: false,
             cancelled: false,
             // Default values for compatibility (full fill = part 0 of 1)
             part_index: 0,
             total_parts: 1,
             is_partial_fill: false,
+            created_at: env.ledger().timestamp(),
+            settlement_callback: None,
+            recipient_is_contract: Self::is_wasm_contract(&recipient),
+            auto_refund_deadline: 0,
+            frozen: false,
+            require_dual_auth,
         };
 
         // Store escrow data
-        env.storage().persistent().set(&DataKey::DestinationEscrow(final_addr.clone()), &escrow_data);
-        env.storage().persistent().set(&DataKey::EscrowExists(final_addr.clone()), &true);
+        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_id.clone()), &escrow_data);
+        env.storage().persistent().set(&DataKey::EscrowExists(escrow_id.clone()), &true);
+        Self::track_open_escrow(&env, &escrow_id);
 
         // Update user escrows mapping
-        let mut user_escrows: Vec<Address> = env.storage()
-            .persistent()
-            .get(&DataKey::UserEscrows(creator.clone()))
-            .unwrap_or(Vec::new(&env));
-        user_escrows.push_back(final_addr.clone());
-        env.storage().persistent().set(&DataKey::UserEscrows(creator.clone()), &user_escrows);
+        Self::record_user_escrow(&env, &creator, &escrow_id);
 
         // Transfer tokens from creator to this contract
-        let native_token = Self::get_native_token(&env);
-        Self::transfer_tokens(&env, &native_token, &creator, &env.current_contract_address(), token_amount, false);
+        Self::fund_escrow(&env, &escrow_id, &token, &creator, token_amount, false);
 
         // Transfer security deposit from creator
-        Self::transfer_tokens(&env, &native_token, &creator, &env.current_contract_address(), DEPOSIT_AMOUNT, false);
+        Self::fund_escrow(&env, &escrow_id, &token, &creator, security_deposit, false);
 
         // Log event
+        Self::publish_dst_event(&env, symbol_short!("created"), &escrow_id, &escrow_data);
         log!(&env, "DstEscrowCreated: creator={}, recipient={}, escrow={}, amount={}", 
-             creator, recipient, final_addr, token_amount);
+             creator, recipient, escrow_id, token_amount);
 
-        final_addr
+        escrow_id
     }
 
-    /// Withdraw from source escrow (equivalent to SourceEscrow.withdraw in EVM)
-    pub fn withdraw_src_escrow(
-        env: Env,
-        caller: Address,
-        escrow_address: Address,
-        secret: Bytes,
-    ) {
-        caller.require_auth();
-
+    // Shared body of withdraw_src_escrow and attempt_withdraw_src_escrow
+    // With record_failure set, a wrong secret is counted and returns false instead of reverting
+    fn withdraw_src(env: Env, caller: Address, escrow_id: BytesN<32>, secret: Bytes, record_failure: bool) -> bool {
         let mut escrow_data: SourceEscrowData = env.storage()
             .persistent()
-            .get(&DataKey::SourceEscrow(escrow_address.clone()))
-            .unwrap_or_else(|| panic!("Invalid address"));
+            .get(&DataKey::SourceEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_src_escrow(&env, &escrow_id));
+        Self::check_not_frozen(&env, &escrow_id);
 
         // Validate escrow state (same validations as EVM)
         if escrow_data.funds_withdrawn {
-            panic!("Already withdrawn");
+            panic_with_error!(&env, EscrowError::AlreadyWithdrawn);
         }
         if escrow_data.cancelled {
-            panic!("Already cancelled");
+            panic_with_error!(&env, EscrowError::AlreadyCancelled);
         }
+        Self::check_token_not_paused(&env, &escrow_data.token);
 
         let current_time = env.ledger().timestamp();
         if current_time < escrow_data.withdrawal_start {
-            panic!("Withdrawal not started");
+            panic_with_error!(&env, EscrowError::WithdrawalNotStarted);
         }
         if current_time >= escrow_data.cancellation_start {
             panic!("Withdrawal ended");
         }
 
         // Check private window restriction (same as EVM)
-        if current_time < escrow_data.public_withdrawal_start {
-            if caller != escrow_data.recipient {
-                panic!("Private window only");
-            }
+        if current_time < escrow_data.public_withdrawal_start && caller != escrow_data.recipient {
+            panic!("Private window only");
+        }
+        if escrow_data.require_dual_auth {
+            Self::require_dual_auth(&caller, &escrow_data.creator, &escrow_data.recipient);
         }
 
-        // Verify secret using SHA256 (same as EVM after our modification)
-        let computed_hash = env.crypto().sha256(&secret);
-        let computed_bytes = BytesN::from_array(&env, &computed_hash.to_array());
+        // Verify secret using the escrow's hash algorithm (SHA256 by default, same as EVM)
+        let computed_bytes = Self::hash_bytes(&env, &escrow_data.hash_algo, &secret);
         if computed_bytes != escrow_data.hashed_secret {
-            panic!("Invalid secret");
+            if !record_failure {
+                panic_with_error!(&env, EscrowError::InvalidSecret);
+            }
+            Self::record_failed_attempt(&env, &escrow_id);
+            return false;
         }
+        Self::mark_secret_revealed(&env, &escrow_id, &computed_bytes, &secret);
 
         // Mark as withdrawn
         escrow_data.funds_withdrawn = true;
-        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_address.clone()), &escrow_data);
+        Self::untrack_open_escrow(&env, &escrow_id);
+        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
 
-        // Transfer funds to caller (resolver) - matches EVM behavior
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &caller, escrow_data.amount, false);
+        // Transfer funds, security deposit and the gas reimbursement to caller (resolver) in one transfer - matches EVM behavior
+        Self::pay_out(&env, &escrow_data.token, &caller, escrow_data.amount, &caller, escrow_data.security_deposit + escrow_data.gas_deposit);
 
-        // Transfer security deposit to caller
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &caller, escrow_data.security_deposit, false);
+        Self::notify_settled(&env, &escrow_data.settlement_callback, &escrow_id, &caller, escrow_data.amount);
 
+        Self::publish_src_event(&env, symbol_short!("withdrawn"), &escrow_id, &escrow_data);
         log!(&env, "SourceEscrowWithdrawal: caller={}, amount={}", caller, escrow_data.amount);
+        true
     }
 
-    /// Withdraw from source escrow with merkle proof (equivalent to SourceEscrow.withdrawWithProof in EVM)
-    pub fn withdraw_src_escrow_with_proof(
-        env: Env,
-        caller: Address,
-        escrow_address: Address,
-        secret: Bytes,
-        merkle_proof: Vec<BytesN<32>>,
-    ) {
-        caller.require_auth();
-
-        let mut escrow_data: SourceEscrowData = env.storage()
+    // Shared body of withdraw_dst_escrow and attempt_withdraw_dst_escrow
+    fn withdraw_dst(env: Env, caller: Address, escrow_id: BytesN<32>, secret: Bytes, record_failure: bool) -> bool {
+        let mut escrow_data: DestinationEscrowData = env.storage()
             .persistent()
-            .get(&DataKey::SourceEscrow(escrow_address.clone()))
-            .unwrap_or_else(|| panic!("Invalid address"));
+            .get(&DataKey::DestinationEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_dst_escrow(&env, &escrow_id));
+        Self::check_not_frozen(&env, &escrow_id);
 
-        // Validate escrow state (same validations as EVM)
+        // Validate escrow state
         if escrow_data.funds_withdrawn {
-            panic!("Already withdrawn");
+            panic_with_error!(&env, EscrowError::AlreadyWithdrawn);
         }
         if escrow_data.cancelled {
-            panic!("Already cancelled");
+            panic_with_error!(&env, EscrowError::AlreadyCancelled);
         }
+        Self::check_token_not_paused(&env, &escrow_data.token);
 
         let current_time = env.ledger().timestamp();
         if current_time < escrow_data.withdrawal_start {
-            panic!("Withdrawal not started");
+            // Creator (resolver) may act early during its exclusive window
+            if escrow_data.exclusive_withdrawal_start == 0 || current_time < escrow_data.exclusive_withdrawal_start {
+                panic_with_error!(&env, EscrowError::WithdrawalNotStarted);
+            }
+            if caller != escrow_data.creator {
+                panic!("Exclusive window only");
+            }
         }
         if current_time >= escrow_data.cancellation_start {
             panic!("Withdrawal ended");
         }
 
-        // Must be partial fill to use this function
-        if !escrow_data.is_partial_fill {
-            panic!("Use withdraw() for complete fills");
+        // Check private window - both recipient (buyer) and creator (resolver) can withdraw
+        if current_time < escrow_data.public_withdrawal_start
+            && caller != escrow_data.recipient
+            && caller != escrow_data.creator
+        {
+            panic!("Private window only");
         }
-
-        // Check private window restriction (same as EVM)
-        if current_time < escrow_data.public_withdrawal_start {
-            if caller != escrow_data.recipient {
-                panic!("Private window only");
-            }
+        if escrow_data.require_dual_auth {
+            Self::require_dual_auth(&caller, &escrow_data.creator, &escrow_data.recipient);
         }
 
-        // Use hashedSecret directly as merkle root (no embedded parts count) - matches EVM
-        let merkle_root = escrow_data.hashed_secret.clone();
-
-        // Verify merkle proof
-        let secret_hash = env.crypto().sha256(&secret);
-        let secret_hash_bytes = BytesN::from_array(&env, &secret_hash.to_array());
-        let leaf = Self::generate_leaf(&env, escrow_data.part_index, &secret_hash_bytes);
-        
-        if !Self::verify_merkle_proof(&env, &merkle_proof, &merkle_root, &leaf) {
-            panic!("Invalid merkle proof");
+        // Verify secret using the escrow's hash algorithm
+        let computed_bytes = Self::hash_bytes(&env, &escrow_data.hash_algo, &secret);
+        if computed_bytes != escrow_data.hashed_secret {
+            if !record_failure {
+                panic_with_error!(&env, EscrowError::InvalidSecret);
+            }
+            Self::record_failed_attempt(&env, &escrow_id);
+            return false;
         }
+        Self::mark_secret_revealed(&env, &escrow_id, &computed_bytes, &secret);
 
         // Mark as withdrawn
         escrow_data.funds_withdrawn = true;
-        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_address.clone()), &escrow_data);
+        Self::untrack_open_escrow(&env, &escrow_id);
+        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_id.clone()), &escrow_data);
 
-        // Transfer funds to caller (resolver) - matches EVM behavior
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &caller, escrow_data.amount, false);
+        // Transfer funds to recipient (buyer) regardless of who calls - matches EVM behavior
+        // and the security deposit to caller, in one transfer when the recipient withdraws itself
+        Self::pay_out(&env, &escrow_data.token, &escrow_data.recipient, escrow_data.amount, &caller, escrow_data.security_deposit);
 
-        // Transfer security deposit to caller
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &caller, escrow_data.security_deposit, false);
+        Self::notify_recipient(&env, &escrow_data);
+        Self::notify_settled(&env, &escrow_data.settlement_callback, &escrow_id, &escrow_data.recipient, escrow_data.amount);
 
-        log!(&env, "SourceEscrowWithdrawalWithProof: caller={}, amount={}", caller, escrow_data.amount);
+        Self::publish_dst_event(&env, symbol_short!("withdrawn"), &escrow_id, &escrow_data);
+        log!(&env, "DestinationEscrowWithdrawal: caller={}, recipient={}, amount={}", 
+             caller, escrow_data.recipient, escrow_data.amount);
+        true
     }
 
-    /// Withdraw from destination escrow (equivalent to DestinationEscrow.withdraw in EVM)
-    pub fn withdraw_dst_escrow(
-        env: Env,
-        caller: Address,
-        escrow_address: Address,
-        secret: Bytes,
-    ) {
-        caller.require_auth();
+    // Count a wrong secret submitted for an escrow
+    fn record_failed_attempt(env: &Env, escrow_id: &BytesN<32>) {
+        let key = DataKeyExt::FailedAttempts(escrow_id.clone());
+        let attempts: u32 = env.storage().persistent().get(&key).unwrap_or(0);
+        env.storage().persistent().set(&key, &(attempts + 1));
 
-        let mut escrow_data: DestinationEscrowData = env.storage()
-            .persistent()
-            .get(&DataKey::DestinationEscrow(escrow_address.clone()))
-            .unwrap_or_else(|| panic!("Invalid address"));
+        log!(env, "FailedWithdrawalAttempt: escrow={}, attempts={}", escrow_id, attempts + 1);
+    }
 
-        // Validate escrow state
-        if escrow_data.funds_withdrawn {
-            panic!("Already withdrawn");
+    // Publish a lifecycle event for a source escrow
+    fn publish_src_event(env: &Env, action: Symbol, escrow_id: &BytesN<32>, escrow_data: &SourceEscrowData) {
+        EscrowEvent {
+            kind: symbol_short!("src_esc"),
+            namespace: Self::get_event_namespace(env.clone()),
+            action,
+            escrow_id: escrow_id.clone(),
+            creator: escrow_data.creator.clone(),
+            recipient: escrow_data.recipient.clone(),
+            amount: escrow_data.amount,
+            part_index: escrow_data.part_index,
         }
-        if escrow_data.cancelled {
-            panic!("Already cancelled");
+        .publish(env);
+    }
+
+    // Publish a lifecycle event for a destination escrow
+    fn publish_dst_event(env: &Env, action: Symbol, escrow_id: &BytesN<32>, escrow_data: &DestinationEscrowData) {
+        EscrowEvent {
+            kind: symbol_short!("dst_esc"),
+            namespace: Self::get_event_namespace(env.clone()),
+            action,
+            escrow_id: escrow_id.clone(),
+            creator: escrow_data.creator.clone(),
+            recipient: escrow_data.recipient.clone(),
+            amount: escrow_data.amount,
+            part_index: escrow_data.part_index,
         }
+        .publish(env);
+    }
 
-        let current_time = env.ledger().timestamp();
-        if current_time < escrow_data.withdrawal_start {
-            panic!("Withdrawal not started");
+    // Requires both parties of a dual-auth escrow to authorize; the caller has already authorized itself
+    fn require_dual_auth(caller: &Address, creator: &Address, recipient: &Address) {
+        if caller != recipient {
+            recipient.require_auth();
         }
-        if current_time >= escrow_data.cancellation_start {
-            panic!("Withdrawal ended");
+        if caller != creator && creator != recipient {
+            creator.require_auth();
         }
+    }
 
-        // Check private window - both recipient (buyer) and creator (resolver) can withdraw
-        if current_time < escrow_data.public_withdrawal_start {
-            if caller != escrow_data.recipient && caller != escrow_data.creator {
-                panic!("Private window only");
-            }
+    // Require that caller is the contract owner
+    fn require_owner(env: &Env, caller: &Address) {
+        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
+        if *caller != owner {
+            panic!("Only owner");
+        }
+        caller.require_auth();
+    }
+
+    // Reject escrows whose principal is smaller than the security deposit (likely a unit error)
+    fn check_deposit_guard(env: &Env, token_amount: i128) {
+        if Self::is_below_deposit_guard(env, token_amount) {
+            panic!("Amount below deposit");
         }
+    }
+
+    fn is_below_deposit_guard(env: &Env, token_amount: i128) -> bool {
+        token_amount < Self::get_deposit_amount(env.clone()) && Self::is_deposit_guard_enabled(env.clone())
+    }
+
+    // Reject escrows whose security deposit is below the owner-set fraction of the amount
+    fn check_min_deposit(env: &Env, token_amount: i128, security_deposit: i128) {
+        let min_deposit = Self::min_deposit_for(env, token_amount).unwrap_or_else(|| panic!("Amount too large"));
+        if security_deposit < min_deposit {
+            panic!("Deposit below minimum ratio");
+        }
+    }
+
+    // Smallest security deposit the owner-set ratio allows for token_amount; None if the product overflows
+    fn min_deposit_for(env: &Env, token_amount: i128) -> Option<i128> {
+        token_amount
+            .checked_mul(Self::get_min_deposit_bps(env.clone()) as i128)
+            .map(|scaled| scaled / 10_000)
+    }
+
+    // Invoke the escrow's settlement callback, if any
+    fn notify_settled(env: &Env, settlement_callback: &Option<Address>, escrow_id: &BytesN<32>, recipient: &Address, amount: i128) {
+        if let Some(callback) = settlement_callback {
+            SettlementCallbackClient::new(env, callback).on_settled(escrow_id, recipient, &amount);
+        }
+    }
+
+    // Whether the address is a deployed Wasm contract (accounts and the token's own contract are not)
+    fn is_wasm_contract(address: &Address) -> bool {
+        matches!(address.executable(), Some(Executable::Wasm(_)))
+    }
+
+    // Let a contract recipient credit a destination payout; a panicking hook reverts the withdrawal
+    fn notify_recipient(env: &Env, escrow_data: &DestinationEscrowData) {
+        if escrow_data.recipient_is_contract {
+            TokensReceiverClient::new(env, &escrow_data.recipient).on_tokens_received(&escrow_data.token, &escrow_data.amount);
+        }
+    }
 
-        // Verify secret using SHA256
-        let computed_hash = env.crypto().sha256(&secret);
-        let computed_bytes = BytesN::from_array(&env, &computed_hash.to_array());
-        if computed_bytes != escrow_data.hashed_secret {
-            panic!("Invalid secret");
+    // Mark an unsettled source or destination escrow cancelled and publish its lifecycle event under action
+    // Returns (creator, token, principal, deposit), where the principal includes anything else the creator posted
+    fn close_escrow(env: &Env, escrow_id: &BytesN<32>, action: Symbol) -> (Address, Address, i128, i128) {
+        if let Some(mut escrow_data) = env.storage()
+            .persistent()
+            .get::<_, SourceEscrowData>(&DataKey::SourceEscrow(escrow_id.clone()))
+        {
+            if escrow_data.funds_withdrawn {
+                panic_with_error!(env, EscrowError::AlreadyWithdrawn);
+            }
+            if escrow_data.cancelled {
+                panic_with_error!(env, EscrowError::AlreadyCancelled);
+            }
+            escrow_data.cancelled = true;
+            Self::release_open_src_escrow(env, escrow_id, &escrow_data);
+            env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
+            Self::publish_src_event(env, action, escrow_id, &escrow_data);
+            (escrow_data.creator, escrow_data.token, escrow_data.amount + escrow_data.gas_deposit, escrow_data.security_deposit)
+        } else {
+            let mut escrow_data = Self::get_dst_escrow(env.clone(), escrow_id.clone());
+            if escrow_data.funds_withdrawn {
+                panic_with_error!(env, EscrowError::AlreadyWithdrawn);
+            }
+            if escrow_data.cancelled {
+                panic_with_error!(env, EscrowError::AlreadyCancelled);
+            }
+            escrow_data.cancelled = true;
+            Self::untrack_open_escrow(env, escrow_id);
+            env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_id.clone()), &escrow_data);
+            Self::publish_dst_event(env, action, escrow_id, &escrow_data);
+            (escrow_data.creator, escrow_data.token, escrow_data.amount, escrow_data.security_deposit)
         }
+    }
 
-        // Mark as withdrawn
-        escrow_data.funds_withdrawn = true;
-        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_address.clone()), &escrow_data);
+    // Take a source escrow that is closing without a withdrawal out of the open index and free its part
+    // Shared by cancellation, close_escrow and force purges so no exit leaves partial-fill state behind
+    fn release_open_src_escrow(env: &Env, escrow_id: &BytesN<32>, escrow_data: &SourceEscrowData) {
+        Self::untrack_open_escrow(env, escrow_id);
+        Self::release_part(env, escrow_data);
+    }
 
-        // Transfer funds to recipient (buyer) regardless of who calls - matches EVM behavior
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.recipient, escrow_data.amount, false);
+    // Return a security deposit to the party that posted it; every exit refunding a deposit goes through here
+    fn refund_deposit(env: &Env, token: &Address, escrow_id: &BytesN<32>, creator: &Address, amount: i128) {
+        let funder = Self::deposit_funder(env, escrow_id, creator);
+        Self::transfer_tokens(env, token, &env.current_contract_address(), &funder, amount, false);
+    }
 
-        // Transfer security deposit to caller
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &caller, escrow_data.security_deposit, false);
+    // Party that posted an escrow's security deposit: recorded for source escrows, the creator otherwise
+    fn deposit_funder(env: &Env, escrow_id: &BytesN<32>, creator: &Address) -> Address {
+        env.storage()
+            .persistent()
+            .get(&DataKey::DepositFunder(escrow_id.clone()))
+            .unwrap_or(creator.clone())
+    }
 
-        log!(&env, "DestinationEscrowWithdrawal: caller={}, recipient={}, amount={}", 
-             caller, escrow_data.recipient, escrow_data.amount);
+    fn check_not_winding_down(env: &Env) {
+        if Self::is_winddown(env.clone()) {
+            panic!("Winding down");
+        }
     }
 
-    /// Withdraw from destination escrow with merkle proof (equivalent to DestinationEscrow.withdrawWithProof in EVM)
-    pub fn withdraw_dst_escrow_with_proof(
-        env: Env,
-        caller: Address,
-        escrow_address: Address,
-        secret: Bytes,
-        merkle_proof: Vec<BytesN<32>>,
-    ) {
-        caller.require_auth();
+    // Ledger time only moves forward, so handing out slots from the tail keeps the index sorted by created_at
+    fn track_open_escrow(env: &Env, escrow_id: &BytesN<32>) {
+        let tail: u64 = env.storage().instance().get(&DataKey::OpenEscrowTail).unwrap_or(0);
+        env.storage().persistent().set(&DataKey::OpenEscrow(tail), escrow_id);
+        env.storage().persistent().set(&DataKey::OpenEscrowSlot(escrow_id.clone()), &tail);
+        env.storage().instance().set(&DataKey::OpenEscrowTail, &(tail + 1));
+        env.storage().instance().set(&DataKey::OpenEscrowCount, &(Self::get_open_escrows(env.clone()) + 1));
+    }
 
-        let mut escrow_data: DestinationEscrowData = env.storage()
-            .persistent()
-            .get(&DataKey::DestinationEscrow(escrow_address.clone()))
-            .unwrap_or_else(|| panic!("Invalid address"));
+    // Free the escrow's slot; when it was the head, move the head past up to MAX_HEAD_STEPS freed slots
+    // Any longer run of freed slots is left to advance_oldest_escrow_head, so settling never exceeds read limits
+    fn untrack_open_escrow(env: &Env, escrow_id: &BytesN<32>) {
+        let slot: u64 = match env.storage().persistent().get(&DataKey::OpenEscrowSlot(escrow_id.clone())) {
+            Some(slot) => slot,
+            None => return,
+        };
+        env.storage().persistent().remove(&DataKey::OpenEscrow(slot));
+        env.storage().persistent().remove(&DataKey::OpenEscrowSlot(escrow_id.clone()));
+        env.storage().instance().set(&DataKey::OpenEscrowCount, &(Self::get_open_escrows(env.clone()) - 1));
 
-        // Validate escrow state
-        if escrow_data.funds_withdrawn {
-            panic!("Already withdrawn");
-        }
-        if escrow_data.cancelled {
-            panic!("Already cancelled");
+        let head: u64 = env.storage().instance().get(&DataKey::OpenEscrowHead).unwrap_or(0);
+        if slot == head {
+            Self::advance_open_escrow_head(env, MAX_HEAD_STEPS);
         }
+    }
 
-        let current_time = env.ledger().timestamp();
-        if current_time < escrow_data.withdrawal_start {
-            panic!("Withdrawal not started");
+    // Move the head past at most `max_steps` freed slots, stopping at the first live one
+    fn advance_open_escrow_head(env: &Env, max_steps: u64) -> u64 {
+        let mut head: u64 = env.storage().instance().get(&DataKey::OpenEscrowHead).unwrap_or(0);
+        let tail: u64 = env.storage().instance().get(&DataKey::OpenEscrowTail).unwrap_or(0);
+        let end = tail.min(head.saturating_add(max_steps));
+        while head < end && !env.storage().persistent().has(&DataKey::OpenEscrow(head)) {
+            head += 1;
         }
-        if current_time >= escrow_data.cancellation_start {
-            panic!("Withdrawal ended");
+        env.storage().instance().set(&DataKey::OpenEscrowHead, &head);
+        head
+    }
+
+    // Reject secret hints over the size bound
+    fn check_secret_hint(secret_hint: &Bytes) {
+        if secret_hint.len() > MAX_SECRET_HINT_LEN {
+            panic!("Hint too long");
         }
+    }
 
-        // Must be partial fill to use this function
-        if !escrow_data.is_partial_fill {
-            panic!("Use withdraw() for complete fills");
+    // Set the frozen flag in a source or destination escrow's data
+    fn set_frozen(env: &Env, escrow_id: &BytesN<32>, frozen: bool) {
+        let src_key = DataKey::SourceEscrow(escrow_id.clone());
+        if let Some(mut escrow_data) = env.storage().persistent().get::<_, SourceEscrowData>(&src_key) {
+            escrow_data.frozen = frozen;
+            env.storage().persistent().set(&src_key, &escrow_data);
+            return;
         }
+        let mut escrow_data = Self::get_dst_escrow(env.clone(), escrow_id.clone());
+        escrow_data.frozen = frozen;
+        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_id.clone()), &escrow_data);
+    }
 
-        // Check private window - both recipient (buyer) and creator (resolver) can withdraw
-        if current_time < escrow_data.public_withdrawal_start {
-            if caller != escrow_data.recipient && caller != escrow_data.creator {
-                panic!("Private window only");
-            }
+    // Reject exits from escrows frozen by the owner
+    fn check_not_frozen(env: &Env, escrow_id: &BytesN<32>) {
+        if Self::is_escrow_frozen(env.clone(), escrow_id.clone()) {
+            panic!("Frozen");
         }
+    }
 
-        // Use hashedSecret directly as merkle root (no embedded parts count) - matches EVM
-        let merkle_root = escrow_data.hashed_secret.clone();
+    // Reject operations on escrows whose token has been paused
+    fn check_token_not_paused(env: &Env, token: &Address) {
+        if Self::is_token_paused(env.clone(), token.clone()) {
+            panic!("Token paused");
+        }
+    }
 
-        // Verify merkle proof
-        let secret_hash = env.crypto().sha256(&secret);
-        let secret_hash_bytes = BytesN::from_array(&env, &secret_hash.to_array());
-        let leaf = Self::generate_leaf(&env, escrow_data.part_index, &secret_hash_bytes);
-        
-        if !Self::verify_merkle_proof(&env, &merkle_proof, &merkle_root, &leaf) {
-            panic!("Invalid merkle proof");
+    // Credit back the factory allowance a cancelled source escrow drew from its buyer
+    fn restore_allowance(env: &Env, escrow_data: &SourceEscrowData) {
+        if escrow_data.allowance_consumed == 0 {
+            return;
+        }
+        if escrow_data.token != Self::get_native_token(env.clone()) {
+            let current = Self::token_allowance(env.clone(), escrow_data.creator.clone(), escrow_data.token.clone());
+            Self::set_asset_allowance(env, &escrow_data.creator, &escrow_data.token, current + escrow_data.allowance_consumed);
+            return;
         }
+        let spender = env.current_contract_address();
+        let current: i128 = env.storage()
+            .persistent()
+            .get(&DataKey::TokenAllowance(escrow_data.creator.clone(), spender.clone()))
+            .unwrap_or(0);
+        Self::set_allowance(env, &escrow_data.creator, &spender, current + escrow_data.allowance_consumed);
 
-        // Mark as withdrawn
-        escrow_data.funds_withdrawn = true;
-        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_address.clone()), &escrow_data);
+        let consumed = Self::get_consumed(env.clone(), escrow_data.creator.clone(), spender.clone());
+        env.storage().persistent().set(
+            &DataKey::AllowanceConsumed(escrow_data.creator.clone(), spender),
+            &(consumed - escrow_data.allowance_consumed).max(0),
+        );
+    }
 
-        // Transfer funds to recipient (buyer) regardless of who calls - matches EVM behavior
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.recipient, escrow_data.amount, false);
+    // Security deposit for an escrow held in token, charged in that token
+    // The native token uses the initialize deposit; any other token must have one set by the owner
+    fn deposit_for(env: &Env, token: &Address) -> i128 {
+        Self::token_deposit(env, token).unwrap_or_else(|| panic!("Token not supported"))
+    }
 
-        // Transfer security deposit to caller
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &caller, escrow_data.security_deposit, false);
+    // Security deposit `creator` posts for an escrow in token; destination escrows waive it for exempt resolvers
+    fn creator_deposit(env: &Env, creator: &Address, token: &Address, is_source: bool) -> i128 {
+        if !is_source && Self::is_deposit_exempt(env.clone(), creator.clone()) {
+            return 0;
+        }
+        Self::deposit_for(env, token)
+    }
 
-        log!(&env, "DestinationEscrowWithdrawalWithProof: caller={}, recipient={}, amount={}", 
-             caller, escrow_data.recipient, escrow_data.amount);
+    // Security deposit for an escrow held in token, or None if escrows may not hold it
+    fn token_deposit(env: &Env, token: &Address) -> Option<i128> {
+        if *token == Self::get_native_token(env.clone()) {
+            return Some(Self::get_deposit_amount(env.clone()));
+        }
+        env.storage().persistent().get(&DataKey::TokenDeposit(token.clone()))
     }
 
-    /// Cancel source escrow (equivalent to SourceEscrow.cancel in EVM)
-    pub fn cancel_src_escrow(env: Env, caller: Address, escrow_address: Address) {
-        caller.require_auth();
+    // Free a cancelled partial fill's part so the same (hashLock, index) can be filled again
+    fn release_part(env: &Env, escrow_data: &SourceEscrowData) {
+        if !escrow_data.is_partial_fill {
+            return;
+        }
+        env.storage().persistent().remove(&DataKey::PartialFillsUsed(escrow_data.hashed_secret.clone(), escrow_data.part_index));
 
-        let mut escrow_data: SourceEscrowData = env.storage()
+        let current_count: u64 = env.storage()
             .persistent()
-            .get(&DataKey::SourceEscrow(escrow_address.clone()))
-            .unwrap_or_else(|| panic!("Invalid address"));
+            .get(&DataKey::PartialFillsCount(escrow_data.hashed_secret.clone()))
+            .unwrap_or(0);
+        env.storage().persistent().set(&DataKey::PartialFillsCount(escrow_data.hashed_secret.clone()), &current_count.saturating_sub(1));
+    }
 
-        if escrow_data.funds_withdrawn {
-            panic!("Already withdrawn");
+    // Draw amount from an allowance currently at current, recording it as consumed
+    fn consume_allowance(env: &Env, owner: &Address, spender: &Address, current: i128, amount: i128) {
+        Self::set_allowance(env, owner, spender, current - amount);
+        let consumed = Self::get_consumed(env.clone(), owner.clone(), spender.clone());
+        env.storage().persistent().set(&DataKey::AllowanceConsumed(owner.clone(), spender.clone()), &(consumed + amount));
+    }
+
+    // Every allowance write goes through here so indexers see an Approval event for it
+    fn set_allowance(env: &Env, owner: &Address, spender: &Address, amount: i128) {
+        env.storage().persistent().set(&DataKey::TokenAllowance(owner.clone(), spender.clone()), &amount);
+        Self::publish_approval(env, owner, spender, &Self::get_native_token(env.clone()), amount);
+
+        let mut spenders = Self::get_approved_spenders(env.clone(), owner.clone());
+        if amount > 0 && !spenders.contains(spender) {
+            spenders.push_back(spender.clone());
+            env.storage().persistent().set(&DataKey::ApprovedSpenders(owner.clone()), &spenders);
         }
-        if escrow_data.cancelled {
-            panic!("Already cancelled");
+    }
+
+    // Non-native grants go through here, so they publish the same Approval event as native allowances
+    fn set_asset_allowance(env: &Env, owner: &Address, token: &Address, amount: i128) {
+        env.storage().persistent().set(&DataKey::AssetAllowance(owner.clone(), token.clone()), &amount);
+        Self::publish_approval(env, owner, &env.current_contract_address(), token, amount);
+    }
+
+    fn publish_approval(env: &Env, owner: &Address, spender: &Address, token: &Address, amount: i128) {
+        Approval {
+            namespace: Self::get_event_namespace(env.clone()),
+            owner: owner.clone(),
+            spender: spender.clone(),
+            token: token.clone(),
+            amount,
         }
+        .publish(env);
+    }
 
-        let current_time = env.ledger().timestamp();
-        if current_time < escrow_data.cancellation_start {
-            panic!("Cancellation not started");
+    // Drop a spender from the owner's approved-spenders list
+    fn untrack_spender(env: &Env, owner: &Address, spender: &Address) {
+        let mut spenders = Self::get_approved_spenders(env.clone(), owner.clone());
+        if let Some(index) = spenders.first_index_of(spender) {
+            spenders.remove(index);
+            env.storage().persistent().set(&DataKey::ApprovedSpenders(owner.clone()), &spenders);
         }
-        if current_time >= escrow_data.public_cancellation_start {
-            panic!("Private cancellation ended");
+    }
+
+    // Creator of a source or destination escrow
+    fn escrow_creator(env: &Env, escrow_id: &BytesN<32>) -> Address {
+        if let Some(escrow_data) = env.storage()
+            .persistent()
+            .get::<_, SourceEscrowData>(&DataKey::SourceEscrow(escrow_id.clone()))
+        {
+            return escrow_data.creator;
         }
-        if caller != escrow_data.creator {
-            panic!("Unauthorized");
+        Self::get_dst_escrow(env.clone(), escrow_id.clone()).creator
+    }
+
+    // Reject an exact duplicate of an escrow created earlier in the same ledger
+    fn check_duplicate_escrow(
+        env: &Env,
+        is_source: bool,
+        hashed_secret: &BytesN<32>,
+        part_index: u64,
+        amount: i128,
+        creator: &Address,
+        recipient: &Address,
+    ) {
+        let key = Self::recent_escrow_key(env, is_source, hashed_secret, part_index, amount, creator, recipient);
+        if Self::is_recent_duplicate(env, &key) {
+            panic!("Duplicate escrow");
         }
+        env.storage().temporary().set(&key, &env.ledger().sequence());
+    }
 
-        // Mark as cancelled
-        escrow_data.cancelled = true;
-        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_address.clone()), &escrow_data);
+    fn is_recent_duplicate(env: &Env, key: &DataKey) -> bool {
+        let created_in: Option<u32> = env.storage().temporary().get(key);
+        created_in == Some(env.ledger().sequence())
+    }
 
-        // Return funds to creator
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.amount, false);
+    // Key remembering the ledger an escrow with these parameters was last created in
+    fn recent_escrow_key(
+        env: &Env,
+        is_source: bool,
+        hashed_secret: &BytesN<32>,
+        part_index: u64,
+        amount: i128,
+        creator: &Address,
+        recipient: &Address,
+    ) -> DataKey {
+        let mut packed = Bytes::new(env);
+        packed.push_back(is_source as u8);
+        packed.append(&Bytes::from_array(env, &hashed_secret.to_array()));
+        packed.extend_from_array(&part_index.to_be_bytes());
+        packed.extend_from_array(&amount.to_be_bytes());
+        packed.append(&creator.clone().to_xdr(env));
+        packed.append(&recipient.clone().to_xdr(env));
+        DataKey::RecentEscrow(BytesN::from_array(env, &env.crypto().sha256(&packed).to_array()))
+    }
 
-        // Return security deposit to creator
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.security_deposit, false);
+    // Reject tokens missing from the allowlist while it is enabled
+    fn check_token_allowed(env: &Env, token: &Address) {
+        if Self::is_token_allowlist_enabled(env.clone()) && !Self::is_token_allowed(env.clone(), token.clone()) {
+            panic!("Token not allowed");
+        }
+    }
 
-        log!(&env, "SourceEscrowCancelled: creator={}, amount={}", escrow_data.creator, escrow_data.amount);
+    // Draw the next escrow id: the counter and hashlock hashed together, unique per factory
+    fn next_escrow_id(env: &Env, hashed_secret: &BytesN<32>) -> BytesN<32> {
+        let counter: u64 = env.storage().instance().get(&DataKey::EscrowCounter).unwrap_or(0);
+        let new_counter = counter + 1;
+        env.storage().instance().set(&DataKey::EscrowCounter, &new_counter);
+
+        let mut packed = Bytes::from_array(env, &new_counter.to_be_bytes());
+        packed.append(&Bytes::from_array(env, &hashed_secret.to_array()));
+        env.crypto().sha256(&packed).into()
     }
 
-    /// Cancel destination escrow (equivalent to DestinationEscrow.cancel in EVM)
-    pub fn cancel_dst_escrow(env: Env, caller: Address, escrow_address: Address) {
-        caller.require_auth();
+    // Panic for an id holding no source escrow, naming the mistake if it is a destination escrow
+    fn missing_src_escrow(env: &Env, escrow_id: &BytesN<32>) -> ! {
+        if env.storage().persistent().has(&DataKey::DestinationEscrow(escrow_id.clone())) {
+            panic!("Wrong escrow kind: expected source");
+        }
+        panic!("Invalid address");
+    }
 
-        let mut escrow_data: DestinationEscrowData = env.storage()
-            .persistent()
-            .get(&DataKey::DestinationEscrow(escrow_address.clone()))
-            .unwrap_or_else(|| panic!("Invalid address"));
+    // Panic for an id holding no destination escrow, naming the mistake if it is a source escrow
+    fn missing_dst_escrow(env: &Env, escrow_id: &BytesN<32>) -> ! {
+        if env.storage().persistent().has(&DataKey::SourceEscrow(escrow_id.clone())) {
+            panic!("Wrong escrow kind: expected destination");
+        }
+        panic!("Invalid address");
+    }
 
+    // Load a source escrow whose cancellation window can still be extended
+    fn get_extendable_src_escrow(env: &Env, escrow_id: &BytesN<32>) -> SourceEscrowData {
+        let escrow_data: SourceEscrowData = env.storage()
+            .persistent()
+            .get(&DataKey::SourceEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_src_escrow(env, escrow_id));
         if escrow_data.funds_withdrawn {
-            panic!("Already withdrawn");
+            panic_with_error!(env, EscrowError::AlreadyWithdrawn);
         }
         if escrow_data.cancelled {
-            panic!("Already cancelled");
-        }
-
-        let current_time = env.ledger().timestamp();
-        if current_time < escrow_data.cancellation_start {
-            panic!("Cancellation not started");
+            panic_with_error!(env, EscrowError::AlreadyCancelled);
         }
-        if caller != escrow_data.creator {
-            panic!("Unauthorized");
+        if env.ledger().timestamp() >= escrow_data.cancellation_start {
+            panic!("Cancellation already started");
         }
-
-        // Mark as cancelled
-        escrow_data.cancelled = true;
-        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_address.clone()), &escrow_data);
-
-        // Return funds to creator
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.amount, false);
-
-        // Return security deposit to creator
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.security_deposit, false);
-
-        log!(&env, "DestinationEscrowCancelled: creator={}, amount={}", escrow_data.creator, escrow_data.amount);
+        escrow_data
     }
 
-    /// Rescue funds from source escrow (equivalent to SourceEscrow.rescue in EVM)
-    pub fn rescue_src_escrow(env: Env, caller: Address, escrow_address: Address) {
-        caller.require_auth();
+    // Record that the preimage of a hashlock has been revealed, keeping the secret under the escrow that revealed it
+    fn mark_secret_revealed(env: &Env, escrow_id: &BytesN<32>, secret_hash: &BytesN<32>, secret: &Bytes) {
+        env.storage().persistent().set(&DataKey::RevealedHash(secret_hash.clone()), &true);
+        env.storage().persistent().set(&DataKey::RevealedSecret(escrow_id.clone()), secret);
+    }
 
-        let mut escrow_data: SourceEscrowData = env.storage()
+    // Load a source escrow whose rescue window is open
+    // A rescue marks the escrow withdrawn, so that state is only accepted once one half has been rescued
+    fn load_rescuable_src_escrow(env: &Env, escrow_id: &BytesN<32>) -> SourceEscrowData {
+        let escrow_data: SourceEscrowData = env.storage()
             .persistent()
-            .get(&DataKey::SourceEscrow(escrow_address.clone()))
-            .unwrap_or_else(|| panic!("Invalid address"));
+            .get(&DataKey::SourceEscrow(escrow_id.clone()))
+            .unwrap_or_else(|| Self::missing_src_escrow(env, escrow_id));
+        Self::check_not_frozen(env, escrow_id);
 
-        if escrow_data.funds_withdrawn {
-            panic!("Already withdrawn");
+        if escrow_data.funds_withdrawn && !Self::is_rescue_started(env, escrow_id) {
+            panic_with_error!(env, EscrowError::AlreadyWithdrawn);
         }
         if escrow_data.cancelled {
-            panic!("Already cancelled");
+            panic_with_error!(env, EscrowError::AlreadyCancelled);
         }
-
-        let current_time = env.ledger().timestamp();
-        if current_time < escrow_data.public_cancellation_start + RESCUE_DELAY {
+        if env.ledger().timestamp() < escrow_data.public_cancellation_start.saturating_add(Self::get_rescue_delay(env.clone())) {
             panic!("Rescue not available");
         }
-        if caller != escrow_data.recipient {
-            panic!("Unauthorized");
-        }
+        escrow_data
+    }
 
-        // Mark as withdrawn
-        escrow_data.funds_withdrawn = true;
-        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_address.clone()), &escrow_data);
+    fn is_rescue_started(env: &Env, escrow_id: &BytesN<32>) -> bool {
+        env.storage().persistent().has(&DataKey::PrincipalRescued(escrow_id.clone()))
+            || env.storage().persistent().has(&DataKey::DepositRescued(escrow_id.clone()))
+    }
 
-        // Transfer funds to recipient
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.recipient, escrow_data.amount, false);
+    // Close a source escrow on the first half of its rescue
+    fn start_src_rescue(env: &Env, escrow_id: &BytesN<32>, escrow_data: &SourceEscrowData) {
+        if !escrow_data.funds_withdrawn {
+            let mut escrow_data = escrow_data.clone();
+            escrow_data.funds_withdrawn = true;
+            Self::untrack_open_escrow(env, escrow_id);
+            env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
+        }
+    }
 
-        // Return security deposit to creator
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.security_deposit, false);
+    fn rescue_src_principal(env: &Env, escrow_id: &BytesN<32>, escrow_data: &SourceEscrowData) {
+        if env.storage().persistent().has(&DataKey::PrincipalRescued(escrow_id.clone())) {
+            return;
+        }
+        Self::start_src_rescue(env, escrow_id, escrow_data);
+        env.storage().persistent().set(&DataKey::PrincipalRescued(escrow_id.clone()), &true);
 
-        log!(&env, "SourceEscrowRescued: recipient={}, amount={}", escrow_data.recipient, escrow_data.amount);
+        let rescue_to = escrow_data.rescue_recipient.clone().unwrap_or(escrow_data.recipient.clone());
+        Self::transfer_tokens(env, &escrow_data.token, &env.current_contract_address(), &rescue_to, escrow_data.amount, false);
+
+        Self::publish_src_event(env, symbol_short!("rescued"), escrow_id, escrow_data);
+        log!(env, "SourceEscrowRescued: recipient={}, amount={}", rescue_to, escrow_data.amount);
     }
 
-    /// Rescue funds from destination escrow (equivalent to DestinationEscrow.rescue in EVM)
-    pub fn rescue_dst_escrow(env: Env, caller: Address, escrow_address: Address) {
-        caller.require_auth();
+    fn rescue_src_deposit(env: &Env, escrow_id: &BytesN<32>, escrow_data: &SourceEscrowData) {
+        if env.storage().persistent().has(&DataKey::DepositRescued(escrow_id.clone())) {
+            return;
+        }
+        Self::start_src_rescue(env, escrow_id, escrow_data);
+        env.storage().persistent().set(&DataKey::DepositRescued(escrow_id.clone()), &true);
 
-        let mut escrow_data: DestinationEscrowData = env.storage()
-            .persistent()
-            .get(&DataKey::DestinationEscrow(escrow_address.clone()))
-            .unwrap_or_else(|| panic!("Invalid address"));
+        // The security deposit goes back to whoever posted it, the gas deposit to the creator
+        Self::refund_deposit(env, &escrow_data.token, escrow_id, &escrow_data.creator, escrow_data.security_deposit);
+        Self::transfer_tokens(env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.gas_deposit, false);
 
-        if escrow_data.funds_withdrawn {
-            panic!("Already withdrawn");
-        }
-        if escrow_data.cancelled {
-            panic!("Already cancelled");
+        Self::publish_src_event(env, Symbol::new(env, "deposit_rescued"), escrow_id, escrow_data);
+        log!(env, "SourceDepositRescued: creator={}, amount={}", escrow_data.creator, escrow_data.security_deposit + escrow_data.gas_deposit);
+    }
+
+    // Check the lifetime cap is set and both it and the rescue window (from rescue_base) plus its grace period have passed
+    // Saturating sums keep a huge configured delay meaning "never" instead of overflowing
+    fn check_purge_allowed(env: &Env, created_at: u64, rescue_base: u64) {
+        let lifetime = Self::get_max_escrow_lifetime(env.clone());
+        if lifetime == 0 {
+            panic!("Lifetime cap not set");
         }
 
         let current_time = env.ledger().timestamp();
-        if current_time < escrow_data.cancellation_start + RESCUE_DELAY {
+        if current_time < created_at.saturating_add(lifetime) {
+            panic!("Lifetime not exceeded");
+        }
+        if current_time < rescue_base.saturating_add(Self::get_rescue_delay(env.clone())) {
             panic!("Rescue not available");
         }
-        if caller != escrow_data.creator {
-            panic!("Unauthorized");
+        // A purge pays the creator, so it must not race a rescue party still entitled to claim first
+        if current_time < rescue_base.saturating_add(Self::get_rescue_delay(env.clone())).saturating_add(PURGE_GRACE_PERIOD) {
+            panic!("Rescue grace period");
         }
+    }
 
-        // Mark as withdrawn
-        escrow_data.funds_withdrawn = true;
-        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_address.clone()), &escrow_data);
-
-        // Transfer funds to creator
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.amount, false);
-
-        // Return security deposit to creator
-        Self::transfer_tokens(&env, &escrow_data.token, &env.current_contract_address(), &escrow_data.creator, escrow_data.security_deposit, false);
-
-        log!(&env, "DestinationEscrowRescued: creator={}, amount={}", escrow_data.creator, escrow_data.amount);
+    // Append a new escrow to its user's index, rejecting it if the user is at MaxEscrowsPerUser
+    fn record_user_escrow(env: &Env, user: &Address, escrow_id: &BytesN<32>) {
+        let mut user_escrows = Self::get_user_escrows(env.clone(), user.clone());
+        if Self::is_at_user_escrow_limit(env, &user_escrows) {
+            panic!("User escrow limit");
+        }
+        user_escrows.push_back(escrow_id.clone());
+        env.storage().persistent().set(&DataKey::UserEscrows(user.clone()), &user_escrows);
     }
 
-    /// Get user escrows (equivalent to getUserEscrows in EVM)
-    pub fn get_user_escrows(env: Env, user: Address) -> Vec<Address> {
-        env.storage()
-            .persistent()
-            .get(&DataKey::UserEscrows(user))
-            .unwrap_or(Vec::new(&env))
+    fn is_at_user_escrow_limit(env: &Env, user_escrows: &Vec<BytesN<32>>) -> bool {
+        let max_escrows = Self::get_max_escrows_per_user(env.clone());
+        max_escrows > 0 && user_escrows.len() >= max_escrows
     }
 
-    /// Get deposit amount constant
-    pub fn get_deposit_amount(env: Env) -> i128 {
-        DEPOSIT_AMOUNT
+    // Remove the existence flag and the user index entry for a purged escrow
+    fn remove_escrow_records(env: &Env, user: &Address, escrow_id: &BytesN<32>) {
+        env.storage().persistent().remove(&DataKey::EscrowExists(escrow_id.clone()));
+        env.storage().persistent().remove(&DataKey::EscrowFunded(escrow_id.clone()));
+        env.storage().persistent().remove(&DataKey::DepositFunder(escrow_id.clone()));
+        env.storage().persistent().remove(&DataKey::RevealedSecret(escrow_id.clone()));
+
+        let mut user_escrows = Self::get_user_escrows(env.clone(), user.clone());
+        if let Some(index) = user_escrows.first_index_of(escrow_id.clone()) {
+            user_escrows.remove(index);
+            env.storage().persistent().set(&DataKey::UserEscrows(user.clone()), &user_escrows);
+        }
     }
 
-    /// Get source escrow details
-    pub fn get_src_escrow(env: Env, escrow_address: Address) -> SourceEscrowData {
-        env.storage()
-            .persistent()
-            .get(&DataKey::SourceEscrow(escrow_address))
-            .unwrap_or_else(|| panic!("Invalid address"))
+    // Compute (principal, deposit) refunds for a source cancellation after the penalty
+    fn cancel_refunds(env: &Env, escrow_data: &SourceEscrowData) -> (i128, i128) {
+        let penalty_bps = Self::get_cancellation_penalty(env.clone()) as i128;
+        let penalty = escrow_data.security_deposit * penalty_bps / 10_000;
+        (escrow_data.amount, escrow_data.security_deposit - penalty)
     }
 
-    /// Get destination escrow details
-    pub fn get_dst_escrow(env: Env, escrow_address: Address) -> DestinationEscrowData {
-        env.storage()
+    // Check whether an allowance has an expiration that has already passed
+    fn is_allowance_expired(env: &Env, owner: &Address, spender: &Address) -> bool {
+        let expiration: u64 = env.storage()
             .persistent()
-            .get(&DataKey::DestinationEscrow(escrow_address))
-            .unwrap_or_else(|| panic!("Invalid address"))
+            .get(&DataKey::AllowanceExpiration(owner.clone(), spender.clone()))
+            .unwrap_or(0);
+        expiration != 0 && env.ledger().timestamp() >= expiration
     }
 
-    /// Check if address is an escrow contract
-    pub fn is_escrow_contract(env: Env, address: Address) -> bool {
-        env.storage()
-            .persistent()
-            .get(&DataKey::EscrowExists(address))
-            .unwrap_or(false)
+    // Collect an escrow's security deposit from the resolver that created it
+    // This is always a plain transfer covered by the creator's require_auth on the create call, never an
+    // allowance draw, so a creator funding the principal as well is charged amount + deposit in total
+    // Pull `amount` into the factory for an escrow and credit its funding record with what actually arrived,
+    // measured from the factory's balance, so a token that delivers short leaves the record short as well
+    fn fund_escrow(env: &Env, escrow_id: &BytesN<32>, token: &Address, from: &Address, amount: i128, use_allowance: bool) {
+        if amount == 0 {
+            return;
+        }
+        let token_client = token::Client::new(env, token);
+        let before = token_client.balance(&env.current_contract_address());
+        Self::transfer_tokens(env, token, from, &env.current_contract_address(), amount, use_allowance);
+        let received = token_client.balance(&env.current_contract_address()) - before;
+
+        let funded: i128 = env.storage().persistent().get(&DataKey::EscrowFunded(escrow_id.clone())).unwrap_or(0);
+        env.storage().persistent().set(&DataKey::EscrowFunded(escrow_id.clone()), &(funded + received));
     }
 
-    // Private helper function
-    fn get_native_token(env: &Env) -> Address {
-        env.storage()
-            .instance()
-            .get(&DataKey::NativeToken)
-            .unwrap_or_else(|| panic!("Native token not set"))
+    // Pay an escrow's principal and deposit out of the factory
+    // When both go to the same party they are combined into a single amount + deposit transfer
+    fn pay_out(env: &Env, token_address: &Address, principal_to: &Address, principal: i128, deposit_to: &Address, deposit: i128) {
+        if principal_to == deposit_to {
+            Self::transfer_tokens(env, token_address, &env.current_contract_address(), principal_to, principal + deposit, false);
+        } else {
+            Self::transfer_tokens(env, token_address, &env.current_contract_address(), principal_to, principal, false);
+            Self::transfer_tokens(env, token_address, &env.current_contract_address(), deposit_to, deposit, false);
+        }
     }
 
     // Helper function to handle native XLM and token transfers with proper authorization
     fn transfer_tokens(env: &Env, token_address: &Address, from: &Address, to: &Address, amount: i128, use_allowance: bool) {
-        let native_token = Self::get_native_token(env);
+        // Nothing to move (e.g. a waived security deposit)
+        if amount == 0 {
+            return;
+        }
+
+        let native_token = Self::get_native_token(env.clone());
         
         if token_address == &native_token {
             // For native XLM, use the token interface (Stellar treats XLM as a token contract)
@@ -837,32 +3378,54 @@ impl HashLockedEscrowFactory {
                 log!(env, "Token transferred directly: from={}, to={}, amount={}", from, to, amount);
             }
         }
+
+        // Everything the factory receives is owed back out, so its liability in the token follows its transfers
+        if *to == env.current_contract_address() {
+            Self::adjust_liability(env, token_address, amount);
+        } else if *from == env.current_contract_address() {
+            Self::adjust_liability(env, token_address, -amount);
+        }
+    }
+
+    fn adjust_liability(env: &Env, token: &Address, delta: i128) {
+        let liability = Self::get_token_liability(env.clone(), token.clone());
+        env.storage().persistent().set(&DataKey::TokenLiability(token.clone()), &(liability + delta).max(0));
     }
 
-    /// Create source escrow with partial fill support (separate function to avoid struct CLI issues)
+    /// Create source escrow with partial fill support, holding principal and deposit in `token`
+    /// With require_dual_auth set, withdrawal also needs both the creator's and the recipient's authorization
     pub fn create_src_escrow_partial(
         env: Env,
         creator: Address,
         hashed_secret: BytesN<32>,
         recipient: Address,
         buyer: Address,
+        token: Address,
         token_amount: i128,
-        withdrawal_start: u64,
-        public_withdrawal_start: u64,
-        cancellation_start: u64,
+        windows: TimeWindows,
         part_index: u64,
         total_parts: u32,
-    ) -> Address {
+        require_dual_auth: bool,
+    ) -> BytesN<32> {
+        let TimeWindows { withdrawal_start, public_withdrawal_start, cancellation_start, public_cancellation_start } = windows;
         // Validate inputs
         if token_amount <= 0 {
-            panic!("Invalid amount");
+            panic_with_error!(&env, EscrowError::InvalidAmount);
         }
+        Self::check_not_winding_down(&env);
+        Self::check_deposit_guard(&env, token_amount);
+        let security_deposit = Self::creator_deposit(&env, &creator, &token, true);
+        Self::check_min_deposit(&env, token_amount, security_deposit);
+        Self::check_token_allowed(&env, &token);
+        Self::check_token_not_paused(&env, &token);
+        Self::check_duplicate_escrow(&env, true, &hashed_secret, part_index, token_amount, &buyer, &recipient);
 
         // Validate time windows
         if public_withdrawal_start <= withdrawal_start
             || cancellation_start <= public_withdrawal_start
+            || public_cancellation_start <= cancellation_start
         {
-            panic!("Invalid time windows");
+            panic_with_error!(&env, EscrowError::InvalidTimeWindows);
         }
 
         // Check if this is a partial fill
@@ -896,8 +3459,8 @@ impl HashLockedEscrowFactory {
         
         // Check allowance - if creator is different from buyer, creator must have buyer's allowance
         let current_allowance = if creator == buyer {
-            // Direct case: buyer is creator, check factory allowance
-            Self::allowance(env.clone(), buyer.clone(), env.current_contract_address())
+            // Direct case: buyer is creator, check factory allowance in the escrow's token
+            Self::token_allowance(env.clone(), buyer.clone(), token.clone())
         } else {
             // LOP case: LOP is creator, check LOP's allowance from buyer
             // The LOP should have already checked its own allowance before calling factory
@@ -907,151 +3470,188 @@ impl HashLockedEscrowFactory {
         };
         
         if current_allowance < token_amount {
-            panic!("Insufficient allowance");
+            panic_with_error!(&env, EscrowError::InsufficientAllowance);
         }
         
         // Reduce allowance only if buyer is creator (direct case)
         if creator == buyer {
-            let new_allowance = current_allowance - token_amount;
-            env.storage().persistent().set(
-                &DataKey::TokenAllowance(buyer.clone(), env.current_contract_address()),
-                &new_allowance
-            );
+            if token == Self::get_native_token(env.clone()) {
+                Self::consume_allowance(&env, &buyer, &env.current_contract_address(), current_allowance, token_amount);
+            } else {
+                Self::set_asset_allowance(&env, &buyer, &token, current_allowance - token_amount);
+            }
         }
         // For LOP case, the LOP already reduced its own allowance
 
-        // Generate unique escrow identifier
-        let counter: u64 = env.storage().instance().get(&DataKey::EscrowCounter).unwrap_or(0);
-        let new_counter = counter + 1;
-        env.storage().instance().set(&DataKey::EscrowCounter, &new_counter);
-        
-        let final_addr = env.current_contract_address();
+        // Each escrow is keyed by its own id, so escrows never overwrite one another
+        let escrow_id = Self::next_escrow_id(&env, &hashed_secret);
 
         // Create escrow data
         let escrow_data = SourceEscrowData {
             creator: buyer.clone(),
             recipient: recipient.clone(),
             hashed_secret: hashed_secret.clone(),
-            token: Self::get_native_token(&env),
+            hash_algo: Self::get_hash_algo(env.clone()),
+            token: token.clone(),
             amount: token_amount,
-            security_deposit: DEPOSIT_AMOUNT,
+            security_deposit,
             withdrawal_start,
             public_withdrawal_start,
             cancellation_start,
-            public_cancellation_start: cancellation_start + 3600, // 1 hour after cancellation starts
+            public_cancellation_start,
             funds_withdrawn: false,
             cancelled: false,
             part_index,
             total_parts,
             is_partial_fill,
+            created_at: env.ledger().timestamp(),
+            settlement_callback: None,
+            allowance_consumed: if creator == buyer { token_amount } else { 0 },
+            gas_deposit: Self::get_gas_deposit(env.clone(), buyer.clone(), token.clone()),
+            rescue_recipient: None,
+            proposed_cancellation_start: None,
+            require_dual_auth,
+            funded_via_allowance: creator != buyer,
+            frozen: false,
         };
 
         // Store escrow data
-        env.storage().persistent().set(&DataKey::SourceEscrow(final_addr.clone()), &escrow_data);
-        env.storage().persistent().set(&DataKey::EscrowExists(final_addr.clone()), &true);
+        env.storage().persistent().set(&DataKey::SourceEscrow(escrow_id.clone()), &escrow_data);
+        env.storage().persistent().set(&DataKey::EscrowExists(escrow_id.clone()), &true);
+        env.storage().persistent().set(&DataKey::DepositFunder(escrow_id.clone()), &creator);
+        Self::track_open_escrow(&env, &escrow_id);
 
         // Update user escrows mapping
-        let mut user_escrows: Vec<Address> = env.storage()
-            .persistent()
-            .get(&DataKey::UserEscrows(buyer.clone()))
-            .unwrap_or(Vec::new(&env));
-        user_escrows.push_back(final_addr.clone());
-        env.storage().persistent().set(&DataKey::UserEscrows(buyer.clone()), &user_escrows);
+        Self::record_user_escrow(&env, &buyer, &escrow_id);
 
-        // Transfer tokens from buyer to escrow (using allowance pattern like EVM)
-        Self::transfer_tokens(&env, &escrow_data.token, &buyer, &final_addr, token_amount, true);
+        // Transfer tokens from buyer to escrow: via allowance (like EVM) when the LOP creates the escrow,
+        // directly when the buyer creates it and has authorized this call itself
+        Self::fund_escrow(&env, &escrow_id, &escrow_data.token, &buyer, token_amount, escrow_data.funded_via_allowance);
+        // The maker's gas deposit comes from the buyer the same way
+        Self::fund_escrow(&env, &escrow_id, &escrow_data.token, &buyer, escrow_data.gas_deposit, escrow_data.funded_via_allowance);
 
         // Security deposit from creator (resolver)
-        Self::transfer_tokens(&env, &escrow_data.token, &creator, &final_addr, DEPOSIT_AMOUNT, false);
+        Self::fund_escrow(&env, &escrow_id, &escrow_data.token, &creator, security_deposit, false);
 
+        Self::publish_src_event(&env, symbol_short!("created"), &escrow_id, &escrow_data);
         log!(&env, "SourceEscrowCreated: creator={}, recipient={}, amount={}, part_index={}, total_parts={}", 
              escrow_data.creator, escrow_data.recipient, escrow_data.amount, part_index, total_parts);
 
-        final_addr
+        escrow_id
     }
 
-    /// Create destination escrow with partial fill support (separate function to avoid struct CLI issues)
+    /// Create destination escrow with partial fill support, holding principal and deposit in `token`
+    /// With require_dual_auth set, withdrawal also needs both the creator's and the recipient's authorization
     pub fn create_dst_escrow_partial(
         env: Env,
         creator: Address,
         hashed_secret: BytesN<32>,
         recipient: Address,
+        token: Address,
         token_amount: i128,
-        withdrawal_start: u64,
-        public_withdrawal_start: u64,
-        cancellation_start: u64,
+        windows: TimeWindows,
         part_index: u64,
         total_parts: u32,
-    ) -> Address {
+        part_proof: Option<PartProof>,
+        require_dual_auth: bool,
+    ) -> BytesN<32> {
+        let TimeWindows { withdrawal_start, public_withdrawal_start, cancellation_start, public_cancellation_start } = windows;
         // Validate inputs
         if token_amount <= 0 {
-            panic!("Invalid amount");
+            panic_with_error!(&env, EscrowError::InvalidAmount);
         }
+        Self::check_not_winding_down(&env);
+        Self::check_deposit_guard(&env, token_amount);
+        Self::check_token_allowed(&env, &token);
+        Self::check_token_not_paused(&env, &token);
+        Self::check_duplicate_escrow(&env, false, &hashed_secret, part_index, token_amount, &creator, &recipient);
 
         // Validate time windows
         if public_withdrawal_start <= withdrawal_start
             || cancellation_start <= public_withdrawal_start
+            || public_cancellation_start <= cancellation_start
         {
-            panic!("Invalid time windows");
+            panic_with_error!(&env, EscrowError::InvalidTimeWindows);
         }
 
         // Check if this is a partial fill
         let is_partial_fill = total_parts > 1;
-        if is_partial_fill {
-            if part_index >= total_parts as u64 {
-                panic!("Invalid part index");
+        if is_partial_fill && part_index >= total_parts as u64 {
+            panic!("Invalid part index");
+        }
+
+        // Optionally prove part_index belongs to the root before any funds move
+        if let Some(part_proof) = part_proof {
+            if !is_partial_fill {
+                panic!("Not a partial fill");
+            }
+            if part_proof.merkle_proof.is_empty() {
+                panic!("Empty proof");
+            }
+            let algo = Self::get_hash_algo(env.clone());
+            let leaf = Self::generate_leaf(&env, &algo, part_index, &part_proof.secret_hash);
+            if !Self::verify_merkle_proof(&env, &algo, &part_proof.merkle_proof, &hashed_secret, &leaf) {
+                panic!("Invalid merkle proof");
             }
         }
 
         // Require authorization from creator
         creator.require_auth();
 
-        // Generate unique escrow identifier
-        let counter: u64 = env.storage().instance().get(&DataKey::EscrowCounter).unwrap_or(0);
-        let new_counter = counter + 1;
-        env.storage().instance().set(&DataKey::EscrowCounter, &new_counter);
-        
-        let final_addr = env.current_contract_address();
+        // Trusted resolvers may be exempt from posting the security deposit
+        let security_deposit = Self::creator_deposit(&env, &creator, &token, false);
+        Self::check_min_deposit(&env, token_amount, security_deposit);
+
+        // Each escrow is keyed by its own id, so escrows never overwrite one another
+        let escrow_id = Self::next_escrow_id(&env, &hashed_secret);
 
         // Create escrow data
         let escrow_data = DestinationEscrowData {
             creator: creator.clone(),
             recipient: recipient.clone(),
             hashed_secret: hashed_secret.clone(),
-            token: Self::get_native_token(&env),
+            hash_algo: Self::get_hash_algo(env.clone()),
+            token: token.clone(),
             amount: token_amount,
-            security_deposit: DEPOSIT_AMOUNT,
+            security_deposit,
+            exclusive_withdrawal_start: 0,
             withdrawal_start,
             public_withdrawal_start,
             cancellation_start,
+            public_cancellation_start,
             funds_withdrawn: false,
             cancelled: false,
             part_index,
             total_parts,
             is_partial_fill,
+            created_at: env.ledger().timestamp(),
+            settlement_callback: None,
+            recipient_is_contract: Self::is_wasm_contract(&recipient),
+            auto_refund_deadline: 0,
+            frozen: false,
+            require_dual_auth,
         };
 
         // Store escrow data
-        env.storage().persistent().set(&DataKey::DestinationEscrow(final_addr.clone()), &escrow_data);
-        env.storage().persistent().set(&DataKey::EscrowExists(final_addr.clone()), &true);
+        env.storage().persistent().set(&DataKey::DestinationEscrow(escrow_id.clone()), &escrow_data);
+        env.storage().persistent().set(&DataKey::EscrowExists(escrow_id.clone()), &true);
+        Self::track_open_escrow(&env, &escrow_id);
 
         // Update user escrows mapping
-        let mut user_escrows: Vec<Address> = env.storage()
-            .persistent()
-            .get(&DataKey::UserEscrows(creator.clone()))
-            .unwrap_or(Vec::new(&env));
-        user_escrows.push_back(final_addr.clone());
-        env.storage().persistent().set(&DataKey::UserEscrows(creator.clone()), &user_escrows);
+        Self::record_user_escrow(&env, &creator, &escrow_id);
 
         // Transfer tokens from creator to escrow
-        Self::transfer_tokens(&env, &escrow_data.token, &creator, &final_addr, token_amount, false);
+        Self::fund_escrow(&env, &escrow_id, &escrow_data.token, &creator, token_amount, false);
 
         // Security deposit from creator
-        Self::transfer_tokens(&env, &escrow_data.token, &creator, &final_addr, DEPOSIT_AMOUNT, false);
+        Self::fund_escrow(&env, &escrow_id, &escrow_data.token, &creator, security_deposit, false);
 
+        Self::publish_dst_event(&env, symbol_short!("created"), &escrow_id, &escrow_data);
         log!(&env, "DestinationEscrowCreated: creator={}, recipient={}, amount={}, part_index={}, total_parts={}", 
              escrow_data.creator, escrow_data.recipient, escrow_data.amount, part_index, total_parts);
 
-        final_addr
+        escrow_id
     }
 }
+
+mod test;
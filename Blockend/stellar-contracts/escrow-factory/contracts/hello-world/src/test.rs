@@ -1,21 +1,3947 @@
 #![cfg(test)]
+extern crate std;
 
 use super::*;
-use soroban_sdk::{vec, Env, String};
+use soroban_sdk::{
+    contract, contractimpl, symbol_short,
+    testutils::{Address as _, Events, Ledger, MockAuth, MockAuthInvoke},
+    token::{StellarAssetClient, TokenClient},
+    vec, Env, FromVal, IntoVal, Map, Symbol, Val,
+};
+
+// Mock settlement hook that records its last invocation, or panics when told to fail
+#[contract]
+pub struct MockSettlementCallback;
+
+#[contractimpl]
+impl MockSettlementCallback {
+    pub fn set_fail(env: Env, fail: bool) {
+        env.storage().instance().set(&symbol_short!("fail"), &fail);
+    }
+
+    pub fn on_settled(env: Env, escrow_id: BytesN<32>, recipient: Address, amount: i128) {
+        let fail: bool = env.storage().instance().get(&symbol_short!("fail")).unwrap_or(false);
+        if fail {
+            panic!("Callback failed");
+        }
+        env.storage().instance().set(&symbol_short!("last"), &(escrow_id, recipient, amount));
+    }
+
+    pub fn last_settled(env: Env) -> Option<(BytesN<32>, Address, i128)> {
+        env.storage().instance().get(&symbol_short!("last"))
+    }
+}
+
+// Mock contract recipient that records what it was credited, or panics when told to fail
+#[contract]
+pub struct MockTokensReceiver;
+
+#[contractimpl]
+impl MockTokensReceiver {
+    pub fn set_fail(env: Env, fail: bool) {
+        env.storage().instance().set(&symbol_short!("fail"), &fail);
+    }
+
+    pub fn on_tokens_received(env: Env, token: Address, amount: i128) {
+        let fail: bool = env.storage().instance().get(&symbol_short!("fail")).unwrap_or(false);
+        if fail {
+            panic!("Receiver failed");
+        }
+        env.storage().instance().set(&symbol_short!("last"), &(token, amount));
+    }
+
+    pub fn last_received(env: Env) -> Option<(Address, i128)> {
+        env.storage().instance().get(&symbol_short!("last"))
+    }
+}
+
+// Mock token that only reports its decimals
+#[contract]
+pub struct MockDecimalsToken;
+
+#[contractimpl]
+impl MockDecimalsToken {
+    pub fn decimals(_env: Env) -> u32 {
+        7
+    }
+}
+
+// Mock token that burns a tenth of every transfer, so the receiver gets less than was sent
+#[contract]
+pub struct MockFeeToken;
+
+#[contractimpl]
+impl MockFeeToken {
+    pub fn mint(env: Env, to: Address, amount: i128) {
+        let balance = Self::balance(env.clone(), to.clone());
+        env.storage().persistent().set(&to, &(balance + amount));
+    }
+
+    pub fn balance(env: Env, id: Address) -> i128 {
+        env.storage().persistent().get(&id).unwrap_or(0)
+    }
+
+    pub fn transfer(env: Env, from: Address, to: Address, amount: i128) {
+        from.require_auth();
+        Self::move_balance(&env, from, to, amount);
+    }
+
+    pub fn transfer_from(env: Env, _spender: Address, from: Address, to: Address, amount: i128) {
+        Self::move_balance(&env, from, to, amount);
+    }
+
+    pub fn decimals(_env: Env) -> u32 {
+        7
+    }
+
+    fn move_balance(env: &Env, from: Address, to: Address, amount: i128) {
+        let from_balance = Self::balance(env.clone(), from.clone());
+        env.storage().persistent().set(&from, &(from_balance - amount));
+        Self::mint(env.clone(), to, amount - amount / 10);
+    }
+}
+
+// Security deposit the test factory is initialized with (0.1 XLM)
+const DEPOSIT_AMOUNT: i128 = 1_000_000;
+// Rescue delay the test factory is initialized with (7 days)
+const RESCUE_DELAY: u64 = 7 * 24 * 60 * 60;
+
+struct Setup<'a> {
+    env: Env,
+    owner: Address,
+    factory: HashLockedEscrowFactoryClient<'a>,
+    token: Address,
+}
+
+fn setup<'a>() -> Setup<'a> {
+    setup_with_config(DEPOSIT_AMOUNT, RESCUE_DELAY)
+}
+
+fn setup_with_config<'a>(security_deposit: i128, rescue_delay: u64) -> Setup<'a> {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::generate(&env);
+    let owner = Address::generate(&env);
+    let token_id = env.register_stellar_asset_contract_v2(admin).address();
+    let factory_id = env.register(HashLockedEscrowFactory, ());
+
+    let factory = HashLockedEscrowFactoryClient::new(&env, &factory_id);
+    factory.initialize(&token_id, &owner, &None, &security_deposit, &rescue_delay);
+
+    Setup { env, owner, factory, token: token_id }
+}
+
+// Result of a try_ call that failed with the given error code
+fn escrow_error<T>(error: EscrowError) -> Result<T, Result<soroban_sdk::Error, soroban_sdk::InvokeError>> {
+    Err(Ok(error.into()))
+}
+
+fn set_time(env: &Env, timestamp: u64) {
+    env.ledger().with_mut(|l| l.timestamp = timestamp);
+}
+
+fn mint(s: &Setup, to: &Address, amount: i128) {
+    StellarAssetClient::new(&s.env, &s.token).mint(to, &amount);
+}
+
+fn balance(s: &Setup, of: &Address) -> i128 {
+    TokenClient::new(&s.env, &s.token).balance(of)
+}
+
+fn hashed_secret(env: &Env, secret: &Bytes) -> BytesN<32> {
+    BytesN::from_array(env, &env.crypto().sha256(secret).to_array())
+}
+
+fn create_src(s: &Setup, creator: &Address, buyer: &Address, recipient: &Address, secret: &Bytes, amount: i128) -> BytesN<32> {
+    // The buyer also funds whatever gas deposit it has set
+    let pulled = amount + s.factory.get_gas_deposit(buyer, &s.token);
+    mint(s, buyer, pulled);
+    mint(s, creator, DEPOSIT_AMOUNT);
+    TokenClient::new(&s.env, &s.token).approve(buyer, &s.factory.address, &pulled, &1_000);
+    s.factory.approve(buyer, &amount);
+    s.factory.create_src_escrow(
+        creator,
+        &hashed_secret(&s.env, secret),
+        recipient,
+        buyer,
+        &amount,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    )
+}
+
+fn create_src_partial(
+    s: &Setup,
+    buyer: &Address,
+    recipient: &Address,
+    root: &BytesN<32>,
+    amount: i128,
+    part_index: u64,
+    total_parts: u32,
+) -> BytesN<32> {
+    mint(s, buyer, amount + DEPOSIT_AMOUNT);
+    TokenClient::new(&s.env, &s.token).approve(buyer, &s.factory.address, &amount, &1_000);
+    s.factory.approve(buyer, &amount);
+    s.factory.create_src_escrow_partial(
+        buyer,
+        root,
+        recipient,
+        buyer,
+        &s.token,
+        &amount,
+        &src_windows(),
+        &part_index,
+        &total_parts,
+        &false,
+    )
+}
+
+// Build a two-leaf merkle tree over parts 0 and 1, returning (root, leaf0, leaf1)
+fn two_leaf_tree(env: &Env, secret0: &Bytes, secret1: &Bytes) -> (BytesN<32>, BytesN<32>, BytesN<32>) {
+    let algo = HashAlgo::Sha256;
+    let leaf0 = HashLockedEscrowFactory::generate_leaf(env, &algo, 0, &hashed_secret(env, secret0));
+    let leaf1 = HashLockedEscrowFactory::generate_leaf(env, &algo, 1, &hashed_secret(env, secret1));
+    let root = if leaf0 <= leaf1 {
+        HashLockedEscrowFactory::hash_pair(env, &algo, &leaf0, &leaf1)
+    } else {
+        HashLockedEscrowFactory::hash_pair(env, &algo, &leaf1, &leaf0)
+    };
+    (root, leaf0, leaf1)
+}
+
+fn create_dst(s: &Setup, creator: &Address, recipient: &Address, secret: &Bytes, amount: i128) -> BytesN<32> {
+    mint(s, creator, amount + DEPOSIT_AMOUNT);
+    s.factory.create_dst_escrow(
+        creator,
+        &hashed_secret(&s.env, secret),
+        recipient,
+        &amount,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    )
+}
+
+#[test]
+fn test_prune_expired_allowance() {
+    let s = setup();
+    let owner = Address::generate(&s.env);
+    let spender = s.factory.address.clone();
+
+    set_time(&s.env, 1_000);
+    s.factory.approve_with_expiration(&owner, &500, &2_000);
+    assert_eq!(s.factory.allowance(&owner, &spender), 500);
+
+    set_time(&s.env, 2_000);
+    assert_eq!(s.factory.allowance(&owner, &spender), 0);
+
+    s.factory.prune_expired_allowance(&owner, &spender);
+    assert_eq!(s.factory.allowance_expiration(&owner, &spender), 0);
+    s.env.as_contract(&s.factory.address, || {
+        assert!(!s.env.storage().persistent().has(&DataKey::TokenAllowance(owner.clone(), spender.clone())));
+    });
+}
+
+#[test]
+#[should_panic(expected = "Allowance not expired")]
+fn test_prune_live_allowance_reverts() {
+    let s = setup();
+    let owner = Address::generate(&s.env);
+
+    set_time(&s.env, 1_000);
+    s.factory.approve_with_expiration(&owner, &500, &2_000);
+    s.factory.prune_expired_allowance(&owner, &s.factory.address);
+}
+
+#[test]
+#[should_panic(expected = "Allowance not expired")]
+fn test_prune_non_expiring_allowance_reverts() {
+    let s = setup();
+    let owner = Address::generate(&s.env);
+
+    s.factory.approve(&owner, &500);
+    s.factory.prune_expired_allowance(&owner, &s.factory.address);
+}
+
+#[test]
+fn test_get_dst_windows() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    let escrow_data = s.factory.get_dst_escrow(&escrow);
+    let windows = s.factory.get_dst_windows(&escrow);
+
+    assert_eq!(windows.withdrawal_start, escrow_data.withdrawal_start);
+    assert_eq!(windows.public_withdrawal_start, escrow_data.public_withdrawal_start);
+    assert_eq!(windows.cancellation_start, escrow_data.cancellation_start);
+    assert_eq!(windows.public_cancellation_start, 4_000);
+}
+
+#[test]
+fn test_create_above_deposit_succeeds() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, DEPOSIT_AMOUNT);
+    assert_eq!(s.factory.get_src_escrow(&escrow).amount, DEPOSIT_AMOUNT);
+}
+
+#[test]
+#[should_panic(expected = "Amount below deposit")]
+fn test_create_src_below_deposit_reverts() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    create_src(&s, &creator, &buyer, &recipient, &secret, DEPOSIT_AMOUNT - 1);
+}
+
+#[test]
+#[should_panic(expected = "Amount below deposit")]
+fn test_create_dst_below_deposit_reverts() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    create_dst(&s, &creator, &recipient, &secret, DEPOSIT_AMOUNT - 1);
+}
+
+#[test]
+fn test_deposit_guard_can_be_disabled() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    s.factory.set_deposit_guard(&s.owner, &false);
+    assert!(!s.factory.is_deposit_guard_enabled());
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10);
+    assert_eq!(s.factory.get_dst_escrow(&escrow).amount, 10);
+}
+
+#[test]
+#[should_panic(expected = "Only owner")]
+fn test_deposit_guard_owner_only() {
+    let s = setup();
+    s.factory.set_deposit_guard(&Address::generate(&s.env), &false);
+}
+
+#[test]
+fn test_top_up_src_escrow_increases_payout() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    mint(&s, &buyer, 5_000_000);
+    s.factory.top_up_src_escrow(&buyer, &escrow, &5_000_000);
+    assert_eq!(s.factory.get_src_escrow(&escrow).amount, 15_000_000);
+    assert_eq!(balance(&s, &buyer), 0);
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+    assert_eq!(balance(&s, &recipient), 15_000_000 + DEPOSIT_AMOUNT);
+}
+
+#[test]
+#[should_panic(expected = "Unauthorized")]
+fn test_top_up_src_escrow_creator_only() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    mint(&s, &recipient, 1_000);
+    s.factory.top_up_src_escrow(&recipient, &escrow, &1_000);
+}
+
+#[test]
+fn test_top_up_after_withdrawal_reverts() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+
+    mint(&s, &buyer, 1_000);
+    assert_eq!(s.factory.try_top_up_src_escrow(&buyer, &escrow, &1_000), escrow_error(EscrowError::AlreadyWithdrawn));
+}
+
+#[test]
+#[should_panic(expected = "Empty proof")]
+fn test_empty_proof_rejected_even_when_leaf_equals_root() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    // Root crafted to equal the leaf, so an empty proof would otherwise verify
+    let leaf = HashLockedEscrowFactory::generate_leaf(&s.env, &HashAlgo::Sha256, 1, &hashed_secret(&s.env, &secret));
+    let escrow = create_src_partial(&s, &buyer, &recipient, &leaf, 10_000_000, 1, 4);
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow_with_proof(&recipient, &escrow, &secret, &Vec::new(&s.env));
+}
+
+#[test]
+fn test_withdraw_with_valid_proof() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret0 = Bytes::from_slice(&s.env, b"secret0");
+    let secret1 = Bytes::from_slice(&s.env, b"secret1");
+
+    let (root, leaf0, _) = two_leaf_tree(&s.env, &secret0, &secret1);
+    let escrow = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 1, 2);
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow_with_proof(&recipient, &escrow, &secret1, &vec![&s.env, leaf0]);
+    assert!(s.factory.get_src_escrow(&escrow).funds_withdrawn);
+}
+
+#[test]
+fn test_exclusive_window_creator_only() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    s.factory.set_exclusive_withdrawal_start(&creator, &escrow, &500);
+
+    // Recipient cannot act during the exclusive window
+    set_time(&s.env, 600);
+    assert!(s.factory.try_withdraw_dst_escrow(&recipient, &escrow, &secret).is_err());
+
+    // Creator can, and funds still go to the recipient
+    s.factory.withdraw_dst_escrow(&creator, &escrow, &secret);
+    assert_eq!(balance(&s, &recipient), 10_000_000);
+    assert_eq!(balance(&s, &creator), DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_recipient_joins_at_withdrawal_start() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    s.factory.set_exclusive_withdrawal_start(&creator, &escrow, &500);
+
+    set_time(&s.env, 1_000);
+    s.factory.withdraw_dst_escrow(&recipient, &escrow, &secret);
+    assert_eq!(balance(&s, &recipient), 10_000_000 + DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_no_exclusive_window_by_default() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    set_time(&s.env, 600);
+    assert_eq!(s.factory.try_withdraw_dst_escrow(&creator, &escrow, &secret), escrow_error(EscrowError::WithdrawalNotStarted));
+}
+
+#[test]
+fn test_exclusive_window_must_precede_withdrawal_start() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    assert_eq!(
+        s.factory.try_set_exclusive_withdrawal_start(&creator, &escrow, &1_000),
+        escrow_error(EscrowError::InvalidTimeWindows)
+    );
+}
+
+#[test]
+fn test_deposit_exempt_creator_posts_no_deposit() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    s.factory.set_deposit_exempt(&s.owner, &creator, &true);
+    assert!(s.factory.is_deposit_exempt(&creator));
+
+    mint(&s, &creator, 10_000_000);
+    let escrow = s.factory.create_dst_escrow(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    );
+    assert_eq!(s.factory.get_dst_escrow(&escrow).security_deposit, 0);
+    assert_eq!(balance(&s, &creator), 0);
+    assert_eq!(balance(&s, &s.factory.address), 10_000_000);
+
+    // Zero deposit is handled on withdrawal
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_dst_escrow(&recipient, &escrow, &secret);
+    assert_eq!(balance(&s, &recipient), 10_000_000);
+}
+
+#[test]
+fn test_deposit_exempt_cancel_with_zero_deposit() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    s.factory.set_deposit_exempt(&s.owner, &creator, &true);
+    mint(&s, &creator, 10_000_000);
+    let escrow = s.factory.create_dst_escrow(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    );
+
+    set_time(&s.env, 3_000);
+    s.factory.cancel_dst_escrow(&creator, &escrow);
+    assert_eq!(balance(&s, &creator), 10_000_000);
+}
+
+#[test]
+fn test_non_exempt_creator_posts_deposit() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    assert_eq!(s.factory.get_dst_escrow(&escrow).security_deposit, DEPOSIT_AMOUNT);
+    assert_eq!(balance(&s, &creator), 0);
+    assert_eq!(balance(&s, &s.factory.address), 10_000_000 + DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_preview_cancel_matches_refund_without_penalty() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    let (principal, deposit) = s.factory.preview_cancel(&escrow);
+    assert_eq!((principal, deposit), (10_000_000, DEPOSIT_AMOUNT));
+
+    set_time(&s.env, 3_000);
+    s.factory.cancel_src_escrow(&buyer, &escrow);
+    assert_eq!(balance(&s, &buyer), principal);
+    assert_eq!(balance(&s, &creator), deposit);
+    assert_eq!(balance(&s, &recipient), 0);
+}
+
+#[test]
+fn test_preview_cancel_matches_refund_with_penalty() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    s.factory.set_cancellation_penalty(&s.owner, &2_500);
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    let (principal, deposit) = s.factory.preview_cancel(&escrow);
+    assert_eq!((principal, deposit), (10_000_000, DEPOSIT_AMOUNT * 3 / 4));
+
+    // Preview does not mutate state
+    assert!(!s.factory.get_src_escrow(&escrow).cancelled);
+
+    set_time(&s.env, 3_000);
+    s.factory.cancel_src_escrow(&buyer, &escrow);
+    assert_eq!(balance(&s, &buyer), principal);
+    assert_eq!(balance(&s, &creator), deposit);
+    assert_eq!(balance(&s, &recipient), DEPOSIT_AMOUNT / 4);
+}
+
+#[test]
+#[should_panic(expected = "Invalid penalty")]
+fn test_cancellation_penalty_bounded() {
+    let s = setup();
+    s.factory.set_cancellation_penalty(&s.owner, &10_001);
+}
+
+#[test]
+fn test_force_purge_returns_funds_and_removes_storage() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    set_time(&s.env, 100);
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    assert_eq!(s.factory.get_dst_escrow(&escrow).created_at, 100);
+
+    let lifetime = 30 * 24 * 60 * 60;
+    s.factory.set_max_escrow_lifetime(&s.owner, &lifetime);
+
+    // Past the rescue window but not the lifetime cap
+    set_time(&s.env, 4_000 + RESCUE_DELAY);
+    assert!(s.factory.try_force_purge_dst_escrow(&escrow).is_err());
+
+    set_time(&s.env, 100 + lifetime);
+    s.factory.force_purge_dst_escrow(&escrow);
+
+    assert_eq!(balance(&s, &creator), 10_000_000 + DEPOSIT_AMOUNT);
+    assert!(s.factory.try_get_dst_escrow(&escrow).is_err());
+    assert!(!s.factory.is_escrow_contract(&escrow));
+    assert_eq!(s.factory.get_user_escrows(&creator).len(), 0);
+}
+
+#[test]
+fn test_force_purge_frozen_escrow_reverts() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+
+    let src = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"src"), 10_000_000);
+    let dst = create_dst(&s, &creator, &recipient, &Bytes::from_slice(&s.env, b"dst"), 10_000_000);
+    s.factory.freeze_escrow(&s.owner, &src);
+    s.factory.freeze_escrow(&s.owner, &dst);
+
+    let lifetime = 30 * 24 * 60 * 60;
+    s.factory.set_max_escrow_lifetime(&s.owner, &lifetime);
+    set_time(&s.env, lifetime + 4_000 + RESCUE_DELAY);
+
+    assert!(s.factory.try_force_purge_src_escrow(&src).is_err());
+    assert!(s.factory.try_force_purge_dst_escrow(&dst).is_err());
+    assert!(s.factory.is_escrow_contract(&src));
+    assert!(s.factory.is_escrow_contract(&dst));
+
+    // Once unfrozen the purge goes through
+    s.factory.unfreeze_escrow(&s.owner, &dst);
+    s.factory.force_purge_dst_escrow(&dst);
+    assert!(!s.factory.is_escrow_contract(&dst));
+}
+
+#[test]
+fn test_force_purge_settled_escrow_moves_no_funds() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+
+    s.factory.set_max_escrow_lifetime(&s.owner, &1);
+    set_time(&s.env, 4_000 + RESCUE_DELAY + PURGE_GRACE_PERIOD);
+    s.factory.force_purge_src_escrow(&escrow);
+
+    assert_eq!(balance(&s, &buyer), 0);
+    assert!(s.factory.try_get_src_escrow(&escrow).is_err());
+}
+
+#[test]
+fn test_max_escrows_per_user() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    s.factory.set_max_escrows_per_user(&s.owner, &2);
+    s.factory.set_max_escrow_lifetime(&s.owner, &1);
+
+    let escrow = create_dst(&s, &creator, &recipient, &Bytes::from_slice(&s.env, b"one"), 10_000_000);
+    create_dst(&s, &creator, &recipient, &Bytes::from_slice(&s.env, b"two"), 10_000_000);
+    assert_eq!(s.factory.get_user_escrows(&creator).len(), 2);
+
+    mint(&s, &creator, 10_000_000 + DEPOSIT_AMOUNT);
+    let result = s.factory.try_create_dst_escrow(
+        &creator,
+        &hashed_secret(&s.env, &Bytes::from_slice(&s.env, b"three")),
+        &recipient,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    );
+    assert!(result.is_err());
+    assert_create_reason(&s, &creator, &creator, &recipient, 10_000_000, &src_windows(), "user_escrow_limit");
+
+    // Purging an escrow past its lifetime cap frees a slot
+    set_time(&s.env, 4_000 + RESCUE_DELAY + PURGE_GRACE_PERIOD);
+    s.factory.force_purge_dst_escrow(&escrow);
+    assert_eq!(s.factory.get_user_escrows(&creator).len(), 1);
+    create_dst(&s, &creator, &recipient, &Bytes::from_slice(&s.env, b"three"), 10_000_000);
+    assert_eq!(s.factory.get_user_escrows(&creator).len(), 2);
+}
+
+#[test]
+fn test_force_purge_waits_out_rescue_grace_period() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"secret"), 10_000_000);
+    s.factory.set_max_escrow_lifetime(&s.owner, &1);
+
+    // Once the rescue window opens the recipient gets first claim; a purge would hand the principal to the buyer
+    set_time(&s.env, 4_000 + RESCUE_DELAY + PURGE_GRACE_PERIOD - 1);
+    assert!(s.factory.try_force_purge_src_escrow(&escrow).is_err());
+    s.factory.rescue_principal(&recipient, &escrow);
+    assert_eq!(balance(&s, &recipient), 10_000_000);
+
+    // After the grace period anyone may purge what is left
+    set_time(&s.env, 4_000 + RESCUE_DELAY + PURGE_GRACE_PERIOD);
+    s.factory.force_purge_src_escrow(&escrow);
+    assert!(s.factory.try_get_src_escrow(&escrow).is_err());
+    assert_eq!(balance(&s, &recipient), 10_000_000);
+    // The unclaimed security deposit goes back to the resolver that posted it, not the buyer
+    assert_eq!(balance(&s, &creator), DEPOSIT_AMOUNT);
+    assert_eq!(balance(&s, &buyer), 0);
+}
+
+#[test]
+fn test_force_purge_unsettled_src_refunds_deposit_to_funder() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    s.factory.set_gas_deposit(&buyer, &s.token, &500);
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"secret"), 10_000_000);
+    s.factory.set_max_escrow_lifetime(&s.owner, &1);
+
+    set_time(&s.env, 4_000 + RESCUE_DELAY + PURGE_GRACE_PERIOD);
+    s.factory.force_purge_src_escrow(&escrow);
+    assert_eq!(balance(&s, &buyer), 10_000_000 + 500);
+    assert_eq!(balance(&s, &creator), DEPOSIT_AMOUNT);
+    assert_eq!(balance(&s, &s.factory.address), 0);
+}
+
+#[test]
+fn test_force_purge_paused_token_goes_through_safe_exit() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let src = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"src"), 10_000_000);
+    let dst = create_dst(&s, &creator, &recipient, &Bytes::from_slice(&s.env, b"dst"), 10_000_000);
+    s.factory.set_max_escrow_lifetime(&s.owner, &1);
+    s.factory.set_token_paused(&s.owner, &s.token, &true);
+
+    set_time(&s.env, 4_000 + RESCUE_DELAY + PURGE_GRACE_PERIOD);
+    assert!(s.factory.try_force_purge_src_escrow(&src).is_err());
+    assert!(s.factory.try_force_purge_dst_escrow(&dst).is_err());
+
+    // Once safe_exit has recorded the stranded funds, the purge only clears storage
+    s.factory.safe_exit(&s.owner, &src, &None);
+    s.factory.force_purge_src_escrow(&src);
+    assert!(!s.factory.is_escrow_contract(&src));
+    assert_eq!(s.factory.get_stranded_funds(&src).unwrap().deposit_funder, creator);
+    assert_eq!(balance(&s, &s.factory.address), 2 * (10_000_000 + DEPOSIT_AMOUNT));
+}
+
+#[test]
+fn test_force_purge_frees_partial_fill_part() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let (root, _, _) = two_leaf_tree(&s.env, &Bytes::from_slice(&s.env, b"secret0"), &Bytes::from_slice(&s.env, b"secret1"));
+    let escrow = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 0, 2);
+    s.factory.set_max_escrow_lifetime(&s.owner, &1);
+
+    set_time(&s.env, 4_000 + RESCUE_DELAY + PURGE_GRACE_PERIOD);
+    s.factory.force_purge_src_escrow(&escrow);
+
+    // The purged part can be filled again, just as after a cancellation
+    set_time(&s.env, 0);
+    s.env.ledger().with_mut(|l| l.sequence_number += 1);
+    let refill = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 0, 2);
+    assert_ne!(refill, escrow);
+}
+
+#[test]
+fn test_deposit_funder_may_rescue_deposit() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"secret"), 10_000_000);
+
+    set_time(&s.env, 4_000 + RESCUE_DELAY);
+    assert!(s.factory.try_rescue_deposit(&Address::generate(&s.env), &escrow).is_err());
+    s.factory.rescue_deposit(&creator, &escrow);
+    assert_eq!(balance(&s, &creator), DEPOSIT_AMOUNT);
+    assert_eq!(balance(&s, &buyer), 0);
+}
+
+#[test]
+#[should_panic(expected = "Lifetime cap not set")]
+fn test_force_purge_requires_lifetime_cap() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    set_time(&s.env, 4_000 + RESCUE_DELAY);
+    s.factory.force_purge_dst_escrow(&escrow);
+}
+
+// Number of token transfers made by the last invocation
+fn transfer_count(s: &Setup) -> usize {
+    s.env.events().all().iter()
+        .filter(|(contract, topics, _)| {
+            *contract == s.token && Symbol::from_val(&s.env, &topics.get(0).unwrap()) == symbol_short!("transfer")
+        })
+        .count()
+}
+
+#[test]
+fn test_withdraw_combines_transfers_to_one_party() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+    assert_eq!(transfer_count(&s), 1);
+    assert_eq!(balance(&s, &recipient), 10_000_000 + DEPOSIT_AMOUNT);
+
+    // The destination recipient withdrawing itself receives amount and deposit together
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    s.factory.withdraw_dst_escrow(&recipient, &escrow, &secret);
+    assert_eq!(transfer_count(&s), 1);
+    assert_eq!(balance(&s, &recipient), 2 * (10_000_000 + DEPOSIT_AMOUNT));
+}
+
+#[test]
+fn test_withdraw_splits_transfers_between_parties() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let resolver = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    set_time(&s.env, 2_500);
+    s.factory.withdraw_dst_escrow(&resolver, &escrow, &secret);
+    assert_eq!(transfer_count(&s), 2);
+    assert_eq!(balance(&s, &recipient), 10_000_000);
+    assert_eq!(balance(&s, &resolver), DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_is_secret_revealed_after_withdrawal() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let hash = hashed_secret(&s.env, &secret);
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    assert!(!s.factory.is_secret_revealed(&hash));
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_dst_escrow(&recipient, &escrow, &secret);
+    assert!(s.factory.is_secret_revealed(&hash));
+}
+
+#[test]
+fn test_is_secret_revealed_after_proof_withdrawal() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret0 = Bytes::from_slice(&s.env, b"secret0");
+    let secret1 = Bytes::from_slice(&s.env, b"secret1");
+
+    let (root, leaf0, _) = two_leaf_tree(&s.env, &secret0, &secret1);
+    let escrow = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 1, 2);
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow_with_proof(&recipient, &escrow, &secret1, &vec![&s.env, leaf0]);
+
+    assert!(s.factory.is_secret_revealed(&hashed_secret(&s.env, &secret1)));
+    assert!(!s.factory.is_secret_revealed(&hashed_secret(&s.env, &secret0)));
+}
+
+#[test]
+#[should_panic(expected = "Token not allowed")]
+fn test_allowlist_rejects_unlisted_token() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    s.factory.set_token_allowlist_enabled(&s.owner, &true);
+    create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+}
+
+#[test]
+fn test_allowlist_accepts_listed_token() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    s.factory.set_token_allowlist_enabled(&s.owner, &true);
+    s.factory.allow_token(&s.owner, &s.token);
+    assert!(s.factory.is_token_allowed(&s.token));
+
+    create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+
+    // Delisting blocks further creates
+    s.factory.disallow_token(&s.owner, &s.token);
+    mint(&s, &creator, 10_000_000 + DEPOSIT_AMOUNT);
+    let result = s.factory.try_create_dst_escrow(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    );
+    assert!(result.is_err());
+}
+
+// Record an escrow as having received only `funded`, as if part of its funding never landed
+fn set_escrow_funded(s: &Setup, escrow: &BytesN<32>, funded: i128) {
+    s.env.as_contract(&s.factory.address, || {
+        s.env.storage().persistent().set(&DataKey::EscrowFunded(escrow.clone()), &funded);
+    });
+}
+
+#[test]
+fn test_mark_escrow_invalid_refunds_only_received() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    // Part of the security deposit never arrived
+    let shortfall = 400_000;
+    set_escrow_funded(&s, &escrow, 10_000_000 + DEPOSIT_AMOUNT - shortfall);
+
+    let refunded = s.factory.mark_escrow_invalid(&s.owner, &escrow);
+    assert_eq!(refunded, 10_000_000 + DEPOSIT_AMOUNT - shortfall);
+    // Source escrows record the buyer as their creator; the deposit goes back to the resolver that posted it
+    assert_eq!(balance(&s, &buyer), 10_000_000);
+    assert_eq!(balance(&s, &creator), DEPOSIT_AMOUNT - shortfall);
+    assert_eq!(balance(&s, &s.factory.address), shortfall);
+    assert!(s.factory.get_src_escrow(&escrow).cancelled);
+}
+
+#[test]
+fn test_mark_escrow_invalid_principal_shortfall() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    set_escrow_funded(&s, &escrow, 6_000_000);
+
+    let refunded = s.factory.mark_escrow_invalid(&s.owner, &escrow);
+    assert_eq!(refunded, 6_000_000);
+    assert_eq!(balance(&s, &buyer), 6_000_000);
+    assert_eq!(balance(&s, &creator), 0);
+}
+
+#[test]
+fn test_mark_escrow_invalid_ignores_other_escrows_funds() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+
+    let other = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"other"), 10_000_000);
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"secret"), 10_000_000);
+    set_escrow_funded(&s, &escrow, 0);
+
+    // The factory holds plenty for the other escrow, but none of it is attributable to this one
+    assert_eq!(s.factory.mark_escrow_invalid(&s.owner, &escrow), 0);
+    assert_eq!(balance(&s, &s.factory.address), 2 * (10_000_000 + DEPOSIT_AMOUNT));
+    assert!(s.factory.verify_escrow_solvency(&other));
+}
+
+#[test]
+fn test_mark_escrow_invalid_caps_refund_at_tokens_received() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let owner = Address::generate(&env);
+    let creator = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let token_id = env.register(MockFeeToken, ());
+    let token = MockFeeTokenClient::new(&env, &token_id);
+    let factory = HashLockedEscrowFactoryClient::new(&env, &env.register(HashLockedEscrowFactory, ()));
+    factory.initialize(&token_id, &owner, &None, &DEPOSIT_AMOUNT, &RESCUE_DELAY);
+
+    token.mint(&creator, &(10_000_000 + DEPOSIT_AMOUNT));
+    let escrow = factory.create_dst_escrow(
+        &creator,
+        &hashed_secret(&env, &Bytes::from_slice(&env, b"secret")),
+        &recipient,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    );
+
+    // The token kept a tenth of both the principal and the deposit on the way in
+    let received = (10_000_000 + DEPOSIT_AMOUNT) * 9 / 10;
+    assert_eq!(token.balance(&factory.address), received);
+
+    // Only what actually arrived is refunded, so the factory is never asked to pay out more than it holds
+    assert_eq!(factory.mark_escrow_invalid(&owner, &escrow), received);
+    assert_eq!(token.balance(&factory.address), 0);
+}
+
+#[test]
+fn test_mark_escrow_invalid_fully_funded_refunds_all() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+
+    let refunded = s.factory.mark_escrow_invalid(&s.owner, &escrow);
+    assert_eq!(refunded, 10_000_000 + DEPOSIT_AMOUNT);
+    assert_eq!(balance(&s, &creator), 10_000_000 + DEPOSIT_AMOUNT);
+    assert!(s.factory.get_dst_escrow(&escrow).cancelled);
+}
+
+#[test]
+#[should_panic(expected = "Only owner")]
+fn test_mark_escrow_invalid_owner_only() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    s.factory.mark_escrow_invalid(&creator, &escrow);
+}
+
+// Build a keccak two-leaf tree independently of the contract, as an EVM tool would, returning (root, leaf0)
+fn keccak_two_leaf_tree(env: &Env, secret0: &Bytes, secret1: &Bytes) -> (BytesN<32>, BytesN<32>) {
+    let keccak = |data: &Bytes| Bytes::from_array(env, &env.crypto().keccak256(data).to_array());
+    let leaf = |index: u64, secret: &Bytes| {
+        let mut packed = Bytes::from_array(env, &index.to_be_bytes());
+        packed.append(&keccak(secret));
+        keccak(&packed)
+    };
+
+    let leaf0 = leaf(0, secret0);
+    let leaf1 = leaf(1, secret1);
+    let (lo, hi) = if leaf0 <= leaf1 { (&leaf0, &leaf1) } else { (&leaf1, &leaf0) };
+    let mut pair = lo.clone();
+    pair.append(hi);
+
+    let root = BytesN::from_array(env, &env.crypto().keccak256(&pair).to_array());
+    (root, leaf0.try_into().unwrap())
+}
+
+#[test]
+fn test_withdraw_with_keccak_merkle_proof() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret0 = Bytes::from_slice(&s.env, b"secret0");
+    let secret1 = Bytes::from_slice(&s.env, b"secret1");
+
+    let (root, leaf0) = keccak_two_leaf_tree(&s.env, &secret0, &secret1);
+
+    s.factory.set_hash_algo(&s.owner, &HashAlgo::Keccak256);
+    let escrow = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 1, 2);
+    assert_eq!(s.factory.get_src_escrow(&escrow).hash_algo, HashAlgo::Keccak256);
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow_with_proof(&recipient, &escrow, &secret1, &vec![&s.env, leaf0]);
+    assert!(s.factory.get_src_escrow(&escrow).funds_withdrawn);
+}
+
+#[test]
+#[should_panic(expected = "Invalid merkle proof")]
+fn test_sha256_escrow_rejects_keccak_tree() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret0 = Bytes::from_slice(&s.env, b"secret0");
+    let secret1 = Bytes::from_slice(&s.env, b"secret1");
+
+    let (root, leaf0) = keccak_two_leaf_tree(&s.env, &secret0, &secret1);
+
+    let escrow = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 1, 2);
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow_with_proof(&recipient, &escrow, &secret1, &vec![&s.env, leaf0]);
+}
+
+#[test]
+fn test_finality_remaining_counts_down() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 0);
+    assert_eq!(s.factory.finality_remaining(&escrow), 1_000);
+    set_time(&s.env, 400);
+    assert_eq!(s.factory.finality_remaining(&escrow), 600);
+    set_time(&s.env, 999);
+    assert_eq!(s.factory.finality_remaining(&escrow), 1);
+    set_time(&s.env, 1_000);
+    assert_eq!(s.factory.finality_remaining(&escrow), 0);
+    set_time(&s.env, 5_000);
+    assert_eq!(s.factory.finality_remaining(&escrow), 0);
+}
+
+#[test]
+fn test_finality_remaining_for_src_escrow() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 250);
+    assert_eq!(s.factory.finality_remaining(&escrow), 750);
+    set_time(&s.env, 1_000);
+    assert_eq!(s.factory.finality_remaining(&escrow), 0);
+}
+
+fn create_dst_with_hint(s: &Setup, creator: &Address, recipient: &Address, secret: &Bytes, hint: &Bytes) -> BytesN<32> {
+    mint(s, creator, 10_000_000 + DEPOSIT_AMOUNT);
+    s.factory.create_dst_escrow_with_hint(
+        creator,
+        &hashed_secret(&s.env, secret),
+        recipient,
+        &s.token,
+        &10_000_000,
+        &src_windows(),
+        &false,
+        hint,
+    )
+}
+
+#[test]
+fn test_secret_hint_round_trip() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let hint = Bytes::from_slice(&s.env, b"encrypted-hint");
+
+    // Escrows created without a hint have none
+    let plain = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    assert_eq!(s.factory.get_secret_hint(&creator, &plain), None);
+
+    let dst = create_dst_with_hint(&s, &creator, &recipient, &Bytes::from_slice(&s.env, b"other"), &hint);
+    assert_eq!(s.factory.get_secret_hint(&creator, &dst), Some(hint.clone()));
+
+    mint(&s, &buyer, 10_000_000);
+    mint(&s, &creator, DEPOSIT_AMOUNT);
+    TokenClient::new(&s.env, &s.token).approve(&buyer, &s.factory.address, &10_000_000, &1_000);
+    s.factory.approve(&buyer, &10_000_000);
+    let src = s.factory.create_src_escrow_with_hint(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &buyer,
+        &s.token,
+        &10_000_000,
+        &src_windows(),
+        &false,
+        &hint,
+    );
+    // A source escrow records the buyer as its creator
+    assert_eq!(s.factory.get_secret_hint(&buyer, &src), Some(hint));
+}
+
+#[test]
+#[should_panic(expected = "Only creator")]
+fn test_secret_hint_hidden_from_non_creator() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst_with_hint(&s, &creator, &recipient, &secret, &Bytes::from_slice(&s.env, b"encrypted-hint"));
+    s.factory.get_secret_hint(&recipient, &escrow);
+}
+
+#[test]
+fn test_secret_hint_size_bounded() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    // An oversized hint rejects the whole creation
+    let hint = Bytes::from_array(&s.env, &[7u8; MAX_SECRET_HINT_LEN as usize + 1]);
+    mint(&s, &creator, 10_000_000 + DEPOSIT_AMOUNT);
+    let result = s.factory.try_create_dst_escrow_with_hint(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &s.token,
+        &10_000_000,
+        &src_windows(),
+        &false,
+        &hint,
+    );
+    assert!(result.is_err());
+    assert_eq!(s.factory.get_user_escrows(&creator).len(), 0);
+}
+
+#[test]
+#[should_panic(expected = "Duplicate escrow")]
+fn test_duplicate_escrow_in_same_ledger_reverts() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+}
+
+#[test]
+fn test_identical_escrow_allowed_in_later_ledger() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    // A different amount is not a duplicate
+    create_dst(&s, &creator, &recipient, &secret, 20_000_000);
+
+    s.env.ledger().with_mut(|l| l.sequence_number += 1);
+    create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+}
+
+#[test]
+fn test_settlement_callback_invoked_on_withdrawal() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let callback = MockSettlementCallbackClient::new(&s.env, &s.env.register(MockSettlementCallback, ()));
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    s.factory.set_settlement_callback(&creator, &escrow, &Some(callback.address.clone()));
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_dst_escrow(&recipient, &escrow, &secret);
+    assert_eq!(callback.last_settled(), Some((escrow, recipient, 10_000_000)));
+}
+
+#[test]
+fn test_failing_settlement_callback_reverts_withdrawal() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let callback = MockSettlementCallbackClient::new(&s.env, &s.env.register(MockSettlementCallback, ()));
+    callback.set_fail(&true);
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    s.factory.set_settlement_callback(&creator, &escrow, &Some(callback.address.clone()));
+
+    set_time(&s.env, 1_500);
+    assert!(s.factory.try_withdraw_dst_escrow(&recipient, &escrow, &secret).is_err());
+    assert!(!s.factory.get_dst_escrow(&escrow).funds_withdrawn);
+    assert_eq!(balance(&s, &recipient), 0);
+}
+
+#[test]
+fn test_settlement_callback_requires_recipient_auth() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let callback = s.env.register(MockSettlementCallback, ());
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    let invoke = MockAuthInvoke {
+        contract: &s.factory.address,
+        fn_name: "set_settlement_callback",
+        args: (creator.clone(), escrow.clone(), Some(callback.clone())).into_val(&s.env),
+        sub_invokes: &[],
+    };
+
+    // The creator alone cannot gate the recipient's payout on a callback
+    s.env.mock_auths(&[MockAuth { address: &creator, invoke: &invoke }]);
+    assert!(s.factory.try_set_settlement_callback(&creator, &escrow, &Some(callback.clone())).is_err());
+
+    s.env.mock_auths(&[
+        MockAuth { address: &creator, invoke: &invoke },
+        MockAuth { address: &recipient, invoke: &invoke },
+    ]);
+    s.factory.set_settlement_callback(&creator, &escrow, &Some(callback.clone()));
+    assert_eq!(s.factory.get_dst_escrow(&escrow).settlement_callback, Some(callback));
+}
+
+#[test]
+#[should_panic(expected = "Withdrawal already started")]
+fn test_settlement_callback_locked_once_withdrawal_starts() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    set_time(&s.env, 1_000);
+    s.factory.set_settlement_callback(&creator, &escrow, &Some(Address::generate(&s.env)));
+}
+
+#[test]
+fn test_public_cancel_pays_whole_deposit_to_keeper_by_default() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let keeper = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 4_000);
+    s.factory.public_cancel_src_escrow(&keeper, &escrow);
+    assert_eq!(balance(&s, &keeper), DEPOSIT_AMOUNT);
+    // Source escrows record the buyer as their creator
+    assert_eq!(balance(&s, &buyer), 10_000_000);
+    assert!(s.factory.get_src_escrow(&escrow).cancelled);
+}
+
+#[test]
+fn test_public_cancel_splits_deposit() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let keeper = Address::generate(&s.env);
+    let collector = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    s.factory.set_keeper_share(&s.owner, &5_000);
+    s.factory.set_fee_collector(&s.owner, &collector);
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 4_000);
+    s.factory.public_cancel_src_escrow(&keeper, &escrow);
+    assert_eq!(balance(&s, &keeper), DEPOSIT_AMOUNT / 2);
+    assert_eq!(balance(&s, &collector), DEPOSIT_AMOUNT / 2);
+    assert_eq!(balance(&s, &buyer), 10_000_000);
+}
+
+#[test]
+#[should_panic(expected = "Public cancellation not started")]
+fn test_public_cancel_before_window_reverts() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 3_999);
+    s.factory.public_cancel_src_escrow(&Address::generate(&s.env), &escrow);
+}
+
+#[test]
+fn test_cancel_restores_buyer_allowance() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    assert_eq!(s.factory.allowance(&buyer, &s.factory.address), 0);
+    assert_eq!(s.factory.get_src_escrow(&escrow).allowance_consumed, 10_000_000);
+
+    // Source escrows record the buyer as their creator
+    set_time(&s.env, 3_000);
+    s.factory.cancel_src_escrow(&buyer, &escrow);
+    assert_eq!(s.factory.allowance(&buyer, &s.factory.address), 10_000_000);
+}
+
+#[test]
+fn test_public_cancel_restores_partial_buyer_allowance() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret0 = Bytes::from_slice(&s.env, b"secret0");
+    let secret1 = Bytes::from_slice(&s.env, b"secret1");
+
+    let (root, _, _) = two_leaf_tree(&s.env, &secret0, &secret1);
+    let escrow = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 0, 2);
+    assert_eq!(s.factory.allowance(&buyer, &s.factory.address), 0);
+
+    set_time(&s.env, s.factory.get_src_escrow(&escrow).public_cancellation_start);
+    s.factory.public_cancel_src_escrow(&Address::generate(&s.env), &escrow);
+    assert_eq!(s.factory.allowance(&buyer, &s.factory.address), 10_000_000);
+}
+
+#[test]
+fn test_cancelled_part_can_be_refilled() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret0 = Bytes::from_slice(&s.env, b"secret0");
+    let secret1 = Bytes::from_slice(&s.env, b"secret1");
+
+    let (root, _, _) = two_leaf_tree(&s.env, &secret0, &secret1);
+    let escrow = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 0, 2);
+
+    // A live part cannot be filled twice
+    s.env.ledger().with_mut(|l| l.sequence_number += 1);
+    mint(&s, &buyer, 10_000_000 + DEPOSIT_AMOUNT);
+    s.factory.approve(&buyer, &10_000_000);
+    assert!(s.factory
+        .try_create_src_escrow_partial(&buyer, &root, &recipient, &buyer, &s.token, &10_000_000, &src_windows(), &0, &2, &false)
+        .is_err());
+
+    // Once cancelled, the limit order protocol can reactivate the part and fill the same (hashLock, index) again
+    set_time(&s.env, 3_000);
+    s.factory.cancel_src_escrow(&buyer, &escrow);
+
+    set_time(&s.env, 0);
+    s.env.ledger().with_mut(|l| l.sequence_number += 1);
+    let refill = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 0, 2);
+    assert_ne!(refill, escrow);
+    assert!(!s.factory.get_src_escrow(&refill).cancelled);
+}
+
+#[test]
+fn test_safe_exit_pays_from_recovery_pool_without_touching_paused_token() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let replacement = s.env.register_stellar_asset_contract_v2(Address::generate(&s.env)).address();
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    s.factory.set_token_paused(&s.owner, &s.token, &true);
+
+    // Normal withdrawal is blocked
+    set_time(&s.env, 1_500);
+    assert!(s.factory.try_withdraw_dst_escrow(&recipient, &escrow, &secret).is_err());
+
+    StellarAssetClient::new(&s.env, &replacement).mint(&s.owner, &20_000_000);
+    s.factory.fund_recovery_pool(&s.owner, &replacement, &20_000_000);
+
+    s.factory.safe_exit(&s.owner, &escrow, &Some(replacement.clone()));
+    assert!(s.factory.get_dst_escrow(&escrow).cancelled);
+    assert_eq!(TokenClient::new(&s.env, &replacement).balance(&creator), 10_000_000 + DEPOSIT_AMOUNT);
+    assert_eq!(s.factory.get_recovery_pool(&replacement), 20_000_000 - 10_000_000 - DEPOSIT_AMOUNT);
+    // Paused token balances are untouched
+    assert_eq!(balance(&s, &s.factory.address), 10_000_000 + DEPOSIT_AMOUNT);
+    assert_eq!(balance(&s, &creator), 0);
+}
+
+#[test]
+fn test_safe_exit_without_replacement_only_settles() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    s.factory.set_token_paused(&s.owner, &s.token, &true);
+
+    s.factory.safe_exit(&s.owner, &escrow, &None);
+    assert!(s.factory.get_dst_escrow(&escrow).cancelled);
+    assert_eq!(balance(&s, &s.factory.address), 10_000_000 + DEPOSIT_AMOUNT);
+    assert_eq!(s.factory.get_stranded_funds(&escrow).unwrap().principal, 10_000_000);
+}
+
+#[test]
+fn test_stranded_funds_claimable_once_unpaused() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"secret"), 10_000_000);
+    s.factory.set_token_paused(&s.owner, &s.token, &true);
+    s.factory.safe_exit(&s.owner, &escrow, &None);
+
+    // Claims go through the token, so they wait until it is unpaused
+    assert!(s.factory.try_claim_stranded_funds(&escrow).is_err());
+    s.factory.set_token_paused(&s.owner, &s.token, &false);
+
+    // The principal goes back to the buyer and the deposit to the resolver that posted it
+    s.factory.claim_stranded_funds(&escrow);
+    assert_eq!(balance(&s, &buyer), 10_000_000);
+    assert_eq!(balance(&s, &creator), DEPOSIT_AMOUNT);
+    assert_eq!(s.factory.get_stranded_funds(&escrow), None);
+    assert!(s.factory.try_claim_stranded_funds(&escrow).is_err());
+}
+
+#[test]
+fn test_safe_exit_returns_deposit_to_its_funder() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let replacement = s.env.register_stellar_asset_contract_v2(Address::generate(&s.env)).address();
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"secret"), 10_000_000);
+    s.factory.set_token_paused(&s.owner, &s.token, &true);
+
+    StellarAssetClient::new(&s.env, &replacement).mint(&s.owner, &20_000_000);
+    s.factory.fund_recovery_pool(&s.owner, &replacement, &20_000_000);
+    s.factory.safe_exit(&s.owner, &escrow, &Some(replacement.clone()));
+
+    let replacement_client = TokenClient::new(&s.env, &replacement);
+    assert_eq!(replacement_client.balance(&buyer), 10_000_000);
+    assert_eq!(replacement_client.balance(&creator), DEPOSIT_AMOUNT);
+    assert_eq!(s.factory.get_stranded_funds(&escrow), None);
+    // The recovery pool settled the escrow, so the paused token no longer owes it
+    assert_eq!(s.factory.get_token_liability(&s.token), 0);
+    assert_eq!(s.factory.get_token_liability(&replacement), 20_000_000 - 10_000_000 - DEPOSIT_AMOUNT);
+}
+
+#[test]
+#[should_panic(expected = "Token not paused")]
+fn test_safe_exit_requires_paused_token() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    s.factory.safe_exit(&s.owner, &escrow, &None);
+}
+
+#[test]
+fn test_total_deposit_for_parts_sums_part_deposits() {
+    let s = setup();
+    let resolver = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let root = BytesN::from_array(&s.env, &[7u8; 32]);
+
+    let escrow = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 0, 4);
+    let part_deposit = s.factory.get_src_escrow(&escrow).security_deposit;
+
+    assert_eq!(s.factory.total_deposit_for_parts(&resolver, &s.token, &true, &0, &None), 0);
+    assert_eq!(s.factory.total_deposit_for_parts(&resolver, &s.token, &true, &1, &None), part_deposit);
+    assert_eq!(s.factory.total_deposit_for_parts(&resolver, &s.token, &true, &4, &None), 4 * part_deposit);
+
+    // Per-token deposits and destination exemptions resolve as they do at creation
+    let usdc = s.env.register_stellar_asset_contract_v2(Address::generate(&s.env)).address();
+    s.factory.set_token_deposit(&s.owner, &usdc, &Some(300));
+    assert_eq!(s.factory.total_deposit_for_parts(&resolver, &usdc, &true, &4, &None), 1_200);
+    s.factory.set_deposit_exempt(&s.owner, &resolver, &true);
+    assert_eq!(s.factory.total_deposit_for_parts(&resolver, &usdc, &false, &4, &None), 0);
+    assert_eq!(s.factory.total_deposit_for_parts(&resolver, &usdc, &true, &4, &None), 1_200);
+}
+
+#[test]
+fn test_total_deposit_for_parts_checks_scaled_part_amounts() {
+    let s = setup();
+    let resolver = Address::generate(&s.env);
+    s.factory.set_min_deposit_bps(&s.owner, &1_000);
+
+    // Every part's amount must keep the deposit at or above 10% of it, as creation requires
+    let parts = vec![&s.env, 5_000_000, 10_000_000, 2_500_000];
+    assert_eq!(s.factory.total_deposit_for_parts(&resolver, &s.token, &true, &3, &Some(parts.clone())), 3 * DEPOSIT_AMOUNT);
+
+    let oversized = vec![&s.env, 5_000_000, 10_000_010];
+    assert!(s.factory.try_total_deposit_for_parts(&resolver, &s.token, &true, &2, &Some(oversized)).is_err());
+    assert!(s.factory.try_total_deposit_for_parts(&resolver, &s.token, &true, &2, &Some(parts)).is_err());
+}
+
+#[test]
+fn test_frozen_src_escrow_blocks_all_exits() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    assert!(!s.factory.get_src_escrow(&escrow).frozen);
+    s.factory.freeze_escrow(&s.owner, &escrow);
+    assert!(s.factory.is_escrow_frozen(&escrow));
+    assert!(s.factory.get_src_escrow(&escrow).frozen);
+
+    set_time(&s.env, 1_500);
+    assert!(s.factory.try_withdraw_src_escrow(&recipient, &escrow, &secret).is_err());
+    set_time(&s.env, 3_500);
+    assert!(s.factory.try_cancel_src_escrow(&creator, &escrow).is_err());
+    set_time(&s.env, 4_500);
+    assert!(s.factory.try_public_cancel_src_escrow(&creator, &escrow).is_err());
+    set_time(&s.env, 4_000 + RESCUE_DELAY);
+    assert!(s.factory.try_rescue_src_escrow(&recipient, &escrow).is_err());
+
+    s.factory.unfreeze_escrow(&s.owner, &escrow);
+    assert!(!s.factory.is_escrow_frozen(&escrow));
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+    assert_eq!(balance(&s, &recipient), 10_000_000 + DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_frozen_dst_escrow_blocks_all_exits() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+
+    s.factory.freeze_escrow(&s.owner, &escrow);
+    assert!(s.factory.get_dst_escrow(&escrow).frozen);
+    set_time(&s.env, 1_500);
+    assert!(s.factory.try_withdraw_dst_escrow(&recipient, &escrow, &secret).is_err());
+    set_time(&s.env, 3_500);
+    assert!(s.factory.try_cancel_dst_escrow(&creator, &escrow).is_err());
+    set_time(&s.env, 4_000 + RESCUE_DELAY);
+    assert!(s.factory.try_rescue_dst_escrow(&creator, &escrow).is_err());
+
+    s.factory.unfreeze_escrow(&s.owner, &escrow);
+    assert!(!s.factory.get_dst_escrow(&escrow).frozen);
+    set_time(&s.env, 3_500);
+    s.factory.cancel_dst_escrow(&creator, &escrow);
+    assert_eq!(balance(&s, &creator), 10_000_000 + DEPOSIT_AMOUNT);
+}
+
+#[test]
+#[should_panic(expected = "Only owner")]
+fn test_freeze_escrow_owner_only() {
+    let s = setup();
+    s.factory.freeze_escrow(&Address::generate(&s.env), &BytesN::from_array(&s.env, &[1u8; 32]));
+}
+
+#[test]
+fn test_freeze_unknown_escrow_reverts() {
+    let s = setup();
+    assert!(s.factory.try_freeze_escrow(&s.owner, &BytesN::from_array(&s.env, &[1u8; 32])).is_err());
+}
+
+// Latest Approval event published by the factory, as (owner, spender, new allowance)
+fn last_approval(s: &Setup) -> (Address, Address, i128) {
+    let (_, topics, data) = s.env.events().all().iter()
+        .filter(|(contract, topics, _)| {
+            *contract == s.factory.address
+                && Symbol::from_val(&s.env, &topics.get(0).unwrap()) == symbol_short!("Approval")
+        })
+        .last()
+        .unwrap();
+    (
+        Address::from_val(&s.env, &topics.get(2).unwrap()),
+        Address::from_val(&s.env, &topics.get(3).unwrap()),
+        i128::from_val(&s.env, &data),
+    )
+}
+
+#[test]
+fn test_approval_events_track_allowance() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    s.factory.approve(&buyer, &30_000_000);
+    assert_eq!(last_approval(&s), (buyer.clone(), s.factory.address.clone(), 30_000_000));
+
+    // Creating the escrow draws the allowance down and reports the remainder
+    mint(&s, &buyer, 10_000_000);
+    mint(&s, &creator, DEPOSIT_AMOUNT);
+    TokenClient::new(&s.env, &s.token).approve(&buyer, &s.factory.address, &10_000_000, &1_000);
+    s.factory.create_src_escrow(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &buyer,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    );
+    assert_eq!(last_approval(&s), (buyer.clone(), s.factory.address.clone(), 20_000_000));
+}
+
+#[test]
+fn test_verify_escrow_solvency() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    assert!(s.factory.verify_escrow_solvency(&escrow));
+
+    // Drain part of the factory's balance behind the escrow's back
+    TokenClient::new(&s.env, &s.token).transfer(&s.factory.address, Address::generate(&s.env), &1);
+    assert!(!s.factory.verify_escrow_solvency(&escrow));
+}
+
+#[test]
+fn test_solvency_counts_every_escrow_in_the_token() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let first = create_dst(&s, &creator, &recipient, &Bytes::from_slice(&s.env, b"first"), 10_000_000);
+    let second = create_dst(&s, &creator, &recipient, &Bytes::from_slice(&s.env, b"second"), 10_000_000);
+    assert_eq!(s.factory.get_token_liability(&s.token), 2 * (10_000_000 + DEPOSIT_AMOUNT));
+
+    // The factory still holds enough for either escrow alone, but not for both
+    TokenClient::new(&s.env, &s.token).transfer(&s.factory.address, Address::generate(&s.env), &10_000_000);
+    assert!(!s.factory.verify_escrow_solvency(&first));
+    assert!(!s.factory.verify_escrow_solvency(&second));
+}
+
+#[test]
+fn test_settled_escrow_is_solvent() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_dst_escrow(&recipient, &escrow, &secret);
+    assert!(s.factory.verify_escrow_solvency(&escrow));
+}
+
+#[test]
+fn test_winddown_keeps_exits_open_until_settled() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    assert_eq!(s.factory.get_open_escrows(), 1);
+
+    s.factory.begin_winddown(&s.owner);
+    assert!(s.factory.is_winddown());
+    assert!(!s.factory.can_finalize_winddown());
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_dst_escrow(&recipient, &escrow, &secret);
+    assert_eq!(s.factory.get_open_escrows(), 0);
+    assert!(s.factory.can_finalize_winddown());
+}
+
+#[test]
+#[should_panic(expected = "Winding down")]
+fn test_winddown_blocks_creates() {
+    let s = setup();
+    s.factory.begin_winddown(&s.owner);
+    create_dst(&s, &Address::generate(&s.env), &Address::generate(&s.env), &Bytes::from_slice(&s.env, b"secret"), 10_000_000);
+}
+
+#[test]
+fn test_get_oldest_active_escrow() {
+    let s = setup();
+    assert_eq!(s.factory.get_oldest_active_escrow(), None);
+
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    set_time(&s.env, 100);
+    let src = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    set_time(&s.env, 200);
+    let dst = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    assert_eq!(s.factory.get_open_escrows(), 2);
+    assert_eq!(s.factory.get_oldest_active_escrow(), Some(src.clone()));
+
+    // Settling the oldest moves the next one to the front
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow(&recipient, &src, &secret);
+    assert_eq!(s.factory.get_oldest_active_escrow(), Some(dst.clone()));
+
+    s.factory.withdraw_dst_escrow(&recipient, &dst, &secret);
+    assert_eq!(s.factory.get_oldest_active_escrow(), None);
+}
+
+#[test]
+fn test_oldest_active_escrow_skips_settled_slots() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let first_secret = Bytes::from_slice(&s.env, b"first");
+    let middle_secret = Bytes::from_slice(&s.env, b"middle");
+    let first = create_dst(&s, &creator, &recipient, &first_secret, 10_000_000);
+    let middle = create_dst(&s, &creator, &recipient, &middle_secret, 10_000_000);
+    let last = create_dst(&s, &creator, &recipient, &Bytes::from_slice(&s.env, b"last"), 10_000_000);
+
+    // Settling a slot behind the head leaves the head where it is
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_dst_escrow(&recipient, &middle, &middle_secret);
+    assert_eq!(s.factory.get_open_escrows(), 2);
+    assert_eq!(s.factory.get_oldest_active_escrow(), Some(first.clone()));
+
+    // Settling the head moves it past the freed slot to the next open escrow
+    s.factory.withdraw_dst_escrow(&recipient, &first, &first_secret);
+    assert_eq!(s.factory.get_open_escrows(), 1);
+    assert_eq!(s.factory.get_oldest_active_escrow(), Some(last));
+}
+
+#[test]
+fn test_settling_head_after_many_out_of_order_settlements() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let mut escrows = std::vec::Vec::new();
+    for i in 0..41u8 {
+        escrows.push(create_dst(&s, &creator, &recipient, &Bytes::from_array(&s.env, &[b's', i]), 10_000_000));
+    }
+
+    // Everything but the oldest and the newest settles first, newest to oldest
+    set_time(&s.env, 3_000);
+    for escrow in escrows[1..40].iter().rev() {
+        s.factory.cancel_dst_escrow(&creator, escrow);
+    }
+    assert_eq!(s.factory.get_oldest_active_escrow(), Some(escrows[0].clone()));
+
+    // Settling the head walks a bounded number of freed slots, and the view looks ahead past the rest
+    s.factory.cancel_dst_escrow(&creator, &escrows[0]);
+    assert_eq!(s.factory.get_open_escrows(), 1);
+    assert_eq!(s.factory.get_oldest_active_escrow(), Some(escrows[40].clone()));
+
+    // A keeper finishes the walk
+    assert_eq!(s.factory.advance_oldest_escrow_head(&100), 40);
+    assert_eq!(s.factory.advance_oldest_escrow_head(&100), 40);
+    assert_eq!(s.factory.get_oldest_active_escrow(), Some(escrows[40].clone()));
+}
+
+#[test]
+fn test_gas_deposit_pays_completer() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let resolver = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    s.factory.set_gas_deposit(&buyer, &s.token, &500);
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    assert_eq!(s.factory.get_src_escrow(&escrow).gas_deposit, 500);
+    assert!(s.factory.verify_escrow_solvency(&escrow));
+
+    // Any resolver completing in the public window collects principal, security deposit and gas deposit
+    set_time(&s.env, 2_500);
+    s.factory.withdraw_src_escrow(&resolver, &escrow, &secret);
+    assert_eq!(balance(&s, &resolver), 10_000_000 + DEPOSIT_AMOUNT + 500);
+}
+
+#[test]
+fn test_gas_deposit_taken_from_maker_at_creation() {
+    let s = setup();
+    let resolver = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let (root, _, _) = two_leaf_tree(&s.env, &Bytes::from_slice(&s.env, b"s0"), &Bytes::from_slice(&s.env, b"s1"));
+    assert!(s.factory.try_set_gas_deposit(&buyer, &s.token, &-1).is_err());
+
+    // The maker sets its gas deposit up front; a fill on its behalf pulls it along with the principal
+    s.factory.set_gas_deposit(&buyer, &s.token, &500);
+    mint(&s, &buyer, 10_000_000 + 500);
+    mint(&s, &resolver, DEPOSIT_AMOUNT);
+    TokenClient::new(&s.env, &s.token).approve(&buyer, &s.factory.address, &(10_000_000 + 500), &1_000);
+    let escrow = s.factory.create_src_escrow_partial(
+        &resolver,
+        &root,
+        &recipient,
+        &buyer,
+        &s.token,
+        &10_000_000,
+        &src_windows(),
+        &0,
+        &2,
+        &false,
+    );
+    assert_eq!(s.factory.get_src_escrow(&escrow).gas_deposit, 500);
+    assert_eq!(balance(&s, &buyer), 0);
+    assert_eq!(balance(&s, &resolver), 0);
+    assert_eq!(balance(&s, &s.factory.address), 10_000_000 + 500 + DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_gas_deposit_refunded_on_cancel() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    s.factory.set_gas_deposit(&buyer, &s.token, &500);
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    s.factory.set_cancellation_penalty(&s.owner, &5_000);
+
+    // The security deposit follows the cancellation penalty; the gas deposit returns in full
+    set_time(&s.env, 3_500);
+    s.factory.cancel_src_escrow(&buyer, &escrow);
+    assert_eq!(balance(&s, &buyer), 10_000_000 + 500);
+    assert_eq!(balance(&s, &creator), DEPOSIT_AMOUNT / 2);
+    assert_eq!(balance(&s, &recipient), DEPOSIT_AMOUNT / 2);
+}
+
+fn src_windows() -> TimeWindows {
+    TimeWindows {
+        withdrawal_start: 1_000,
+        public_withdrawal_start: 2_000,
+        cancellation_start: 3_000,
+        public_cancellation_start: 4_000,
+    }
+}
+
+// can_create_src_escrow for a native-token escrow under the shared test secret
+fn can_create(s: &Setup, creator: &Address, buyer: &Address, recipient: &Address, amount: i128, windows: &TimeWindows) -> (bool, Symbol) {
+    let hashed = hashed_secret(&s.env, &Bytes::from_slice(&s.env, b"secret"));
+    s.factory.can_create_src_escrow(creator, &hashed, recipient, buyer, &s.token, &amount, windows)
+}
+
+fn assert_create_reason(
+    s: &Setup,
+    creator: &Address,
+    buyer: &Address,
+    recipient: &Address,
+    amount: i128,
+    windows: &TimeWindows,
+    reason: &str,
+) {
+    assert_eq!(can_create(s, creator, buyer, recipient, amount, windows), (false, Symbol::new(&s.env, reason)));
+}
+
+#[test]
+fn test_can_create_src_escrow_reasons() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let windows = src_windows();
+    let reason = |amount: i128, windows: &TimeWindows, reason: &str| {
+        assert_create_reason(&s, &creator, &buyer, &recipient, amount, windows, reason)
+    };
+
+    reason(0, &windows, "invalid_amount");
+    reason(DEPOSIT_AMOUNT - 1, &windows, "below_deposit");
+
+    let mut bad_windows = src_windows();
+    bad_windows.cancellation_start = bad_windows.public_withdrawal_start;
+    reason(10_000_000, &bad_windows, "bad_windows");
+
+    reason(10_000_000, &windows, "insufficient_allowance");
+    s.factory.approve(&buyer, &10_000_000);
+    reason(10_000_000, &windows, "insufficient_balance");
+    mint(&s, &buyer, 10_000_000);
+    reason(10_000_000, &windows, "insufficient_deposit_balance");
+
+    // The buyer's gas deposit is part of what it must hold
+    mint(&s, &creator, DEPOSIT_AMOUNT);
+    s.factory.set_gas_deposit(&buyer, &s.token, &500);
+    reason(10_000_000, &windows, "insufficient_balance");
+    s.factory.set_gas_deposit(&buyer, &s.token, &0);
+    assert_eq!(can_create(&s, &creator, &buyer, &recipient, 10_000_000, &windows), (true, symbol_short!("ok")));
+
+    s.factory.set_min_deposit_bps(&s.owner, &500);
+    reason(30_000_000, &windows, "deposit_below_ratio");
+    reason(i128::MAX, &windows, "amount_overflow");
+    s.factory.set_min_deposit_bps(&s.owner, &0);
+
+    s.factory.set_token_allowlist_enabled(&s.owner, &true);
+    reason(10_000_000, &windows, "token_not_allowed");
+    s.factory.set_token_allowlist_enabled(&s.owner, &false);
+
+    s.factory.set_token_paused(&s.owner, &s.token, &true);
+    reason(10_000_000, &windows, "paused");
+    s.factory.set_token_paused(&s.owner, &s.token, &false);
+
+    s.factory.begin_winddown(&s.owner);
+    reason(10_000_000, &windows, "winding_down");
+}
+
+#[test]
+fn test_can_create_src_escrow_matches_create_rules() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+
+    // A token without an owner-set deposit cannot be escrowed
+    let usdc = s.env.register_stellar_asset_contract_v2(Address::generate(&s.env)).address();
+    let hashed = hashed_secret(&s.env, &Bytes::from_slice(&s.env, b"secret"));
+    assert_eq!(
+        s.factory.can_create_src_escrow(&creator, &hashed, &recipient, &buyer, &usdc, &10_000_000, &src_windows()),
+        (false, Symbol::new(&s.env, "token_not_supported"))
+    );
+
+    // The same escrow a second time in one ledger is a duplicate
+    create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"secret"), 10_000_000);
+    assert_create_reason(&s, &creator, &buyer, &recipient, 10_000_000, &src_windows(), "duplicate");
+}
+
+// Build a four-leaf merkle tree over parts 0..4, returning (root, leaves, [node01, node23])
+fn four_leaf_tree(env: &Env, secrets: &[Bytes; 4]) -> (BytesN<32>, [BytesN<32>; 4], [BytesN<32>; 2]) {
+    let algo = HashAlgo::Sha256;
+    let leaves: [BytesN<32>; 4] = core::array::from_fn(|i| {
+        HashLockedEscrowFactory::generate_leaf(env, &algo, i as u64, &hashed_secret(env, &secrets[i]))
+    });
+    let pair = |a: &BytesN<32>, b: &BytesN<32>| {
+        if a <= b {
+            HashLockedEscrowFactory::hash_pair(env, &algo, a, b)
+        } else {
+            HashLockedEscrowFactory::hash_pair(env, &algo, b, a)
+        }
+    };
+    let nodes = [pair(&leaves[0], &leaves[1]), pair(&leaves[2], &leaves[3])];
+    (pair(&nodes[0], &nodes[1]), leaves, nodes)
+}
+
+#[test]
+fn test_verify_merkle_multiproof() {
+    let s = setup();
+    let secrets: [Bytes; 4] = core::array::from_fn(|i| Bytes::from_array(&s.env, &[b's', i as u8]));
+    let (root, leaves, _) = four_leaf_tree(&s.env, &secrets);
+
+    // Prove leaves 0 and 2 together: each is paired with its sibling, then the two nodes with each other
+    let proof = vec![&s.env, leaves[1].clone(), leaves[3].clone()];
+    let flags = vec![&s.env, false, false, true];
+    let proven = vec![&s.env, leaves[0].clone(), leaves[2].clone()];
+    assert!(s.factory.verify_merkle_multiproof(&proof, &flags, &root, &proven));
+
+    let wrong = vec![&s.env, leaves[0].clone(), leaves[1].clone()];
+    assert!(!s.factory.verify_merkle_multiproof(&proof, &flags, &root, &wrong));
+    let short_flags = vec![&s.env, false, false];
+    assert!(!s.factory.verify_merkle_multiproof(&proof, &short_flags, &root, &proven));
+}
+
+#[test]
+fn test_get_order_leaves() {
+    let s = setup();
+    let secrets: [Bytes; 4] = core::array::from_fn(|i| Bytes::from_array(&s.env, &[b's', i as u8]));
+    let (root, leaves, nodes) = four_leaf_tree(&s.env, &secrets);
+    let mut secret_hashes = Vec::new(&s.env);
+    for secret in secrets.iter() {
+        secret_hashes.push_back(hashed_secret(&s.env, secret));
+    }
+
+    s.factory.set_order_secret_hashes(&root, &secret_hashes);
+    let returned = s.factory.get_order_leaves(&root, &4);
+    assert_eq!(returned, vec![&s.env, leaves[0].clone(), leaves[1].clone(), leaves[2].clone(), leaves[3].clone()]);
+
+    // A proof rebuilt from the returned leaves verifies against the order's root
+    let proof = vec![&s.env, returned.get(2).unwrap(), nodes[0].clone()];
+    assert!(HashLockedEscrowFactory::verify_merkle_proof(&s.env, &HashAlgo::Sha256, &proof, &root, &returned.get(3).unwrap()));
+}
+
+#[test]
+#[should_panic(expected = "Root mismatch")]
+fn test_set_order_secret_hashes_wrong_root() {
+    let s = setup();
+    let secrets: [Bytes; 4] = core::array::from_fn(|i| Bytes::from_array(&s.env, &[b's', i as u8]));
+    let (root, _, _) = four_leaf_tree(&s.env, &secrets);
+    let mut secret_hashes = Vec::new(&s.env);
+    for secret in secrets.iter().rev() {
+        secret_hashes.push_back(hashed_secret(&s.env, secret));
+    }
+
+    s.factory.set_order_secret_hashes(&root, &secret_hashes);
+}
+
+#[test]
+fn test_withdraw_src_escrows_multiproof() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secrets: [Bytes; 4] = core::array::from_fn(|i| Bytes::from_array(&s.env, &[b's', i as u8]));
+    let (root, leaves, nodes) = four_leaf_tree(&s.env, &secrets);
+    let escrow = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 2, 4);
+
+    set_time(&s.env, 1_500);
+    let proof = vec![&s.env, leaves[3].clone(), nodes[0].clone()];
+    let flags = vec![&s.env, false, false];
+    let escrows = vec![&s.env, escrow.clone()];
+
+    let wrong_secret = vec![&s.env, secrets[3].clone()];
+    assert!(s.factory.try_withdraw_src_escrows_multiproof(&recipient, &escrows, &wrong_secret, &proof, &flags).is_err());
+    let duplicated = vec![&s.env, escrow.clone(), escrow.clone()];
+    let both = vec![&s.env, secrets[2].clone(), secrets[2].clone()];
+    assert!(s.factory.try_withdraw_src_escrows_multiproof(&recipient, &duplicated, &both, &proof, &flags).is_err());
+
+    s.factory.withdraw_src_escrows_multiproof(&recipient, &escrows, &vec![&s.env, secrets[2].clone()], &proof, &flags);
+    assert!(s.factory.get_src_escrow(&escrow).funds_withdrawn);
+    assert_eq!(balance(&s, &recipient), 10_000_000 + DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_withdraw_two_src_escrows_multiproof() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secrets: [Bytes; 4] = core::array::from_fn(|i| Bytes::from_array(&s.env, &[b's', i as u8]));
+    let (root, leaves, _) = four_leaf_tree(&s.env, &secrets);
+    let escrow0 = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 0, 4);
+    let escrow2 = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 2, 4);
+
+    // Parts 0 and 2 are each paired with their sibling, then the two nodes with each other
+    set_time(&s.env, 1_500);
+    let proof = vec![&s.env, leaves[1].clone(), leaves[3].clone()];
+    let flags = vec![&s.env, false, false, true];
+    s.factory.withdraw_src_escrows_multiproof(
+        &recipient,
+        &vec![&s.env, escrow0.clone(), escrow2.clone()],
+        &vec![&s.env, secrets[0].clone(), secrets[2].clone()],
+        &proof,
+        &flags,
+    );
+    assert!(s.factory.get_src_escrow(&escrow0).funds_withdrawn);
+    assert!(s.factory.get_src_escrow(&escrow2).funds_withdrawn);
+    assert_eq!(balance(&s, &recipient), 2 * (10_000_000 + DEPOSIT_AMOUNT));
+}
+
+// Build a merkle tree over `leaves` (a power of two in length) and a multiproof for the first `count`
+// of them, returning (root, proof, flags)
+fn prefix_multiproof(env: &Env, leaves: &[BytesN<32>], count: usize) -> (BytesN<32>, Vec<BytesN<32>>, Vec<bool>) {
+    let algo = HashAlgo::Sha256;
+    let mut level = leaves.to_vec();
+    let mut known: std::vec::Vec<bool> = (0..leaves.len()).map(|i| i < count).collect();
+    let mut proof = Vec::new(env);
+    let mut flags = Vec::new(env);
+    while level.len() > 1 {
+        let mut next = std::vec::Vec::new();
+        let mut next_known = std::vec::Vec::new();
+        for k in 0..level.len() / 2 {
+            let (left, right) = (&level[2 * k], &level[2 * k + 1]);
+            match (known[2 * k], known[2 * k + 1]) {
+                (true, true) => flags.push_back(true),
+                (true, false) => {
+                    flags.push_back(false);
+                    proof.push_back(right.clone());
+                }
+                (false, true) => {
+                    flags.push_back(false);
+                    proof.push_back(left.clone());
+                }
+                (false, false) => {}
+            }
+            next.push(if left <= right {
+                HashLockedEscrowFactory::hash_pair(env, &algo, left, right)
+            } else {
+                HashLockedEscrowFactory::hash_pair(env, &algo, right, left)
+            });
+            next_known.push(known[2 * k] || known[2 * k + 1]);
+        }
+        level = next;
+        known = next_known;
+    }
+    (level[0].clone(), proof, flags)
+}
+
+#[test]
+fn test_withdraw_src_escrows_multiproof_at_max_batch_size() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secrets: std::vec::Vec<Bytes> = (0..32u8).map(|i| Bytes::from_array(&s.env, &[b's', i])).collect();
+    let leaves: std::vec::Vec<BytesN<32>> = (0..32)
+        .map(|i| HashLockedEscrowFactory::generate_leaf(&s.env, &HashAlgo::Sha256, i as u64, &hashed_secret(&s.env, &secrets[i])))
+        .collect();
+    let (root, proof, flags) = prefix_multiproof(&s.env, &leaves, MAX_WITHDRAW_BATCH_SIZE as usize);
+
+    let mut escrow_ids = Vec::new(&s.env);
+    let mut batch_secrets = Vec::new(&s.env);
+    for i in 0..MAX_WITHDRAW_BATCH_SIZE {
+        escrow_ids.push_back(create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, i as u64, 32));
+        batch_secrets.push_back(secrets[i as usize].clone());
+    }
+
+    // A full batch fits the default per-transaction budget
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrows_multiproof(&recipient, &escrow_ids, &batch_secrets, &proof, &flags);
+    for escrow_id in escrow_ids.iter() {
+        assert!(s.factory.get_src_escrow(&escrow_id).funds_withdrawn);
+    }
+    assert_eq!(balance(&s, &recipient), MAX_WITHDRAW_BATCH_SIZE as i128 * (10_000_000 + DEPOSIT_AMOUNT));
+}
+
+#[test]
+#[should_panic(expected = "Batch too large")]
+fn test_withdraw_src_escrows_multiproof_over_max_batch_size_reverts() {
+    let s = setup();
+    let mut escrow_ids = Vec::new(&s.env);
+    let mut batch_secrets = Vec::new(&s.env);
+    for i in 0..=MAX_WITHDRAW_BATCH_SIZE {
+        escrow_ids.push_back(BytesN::from_array(&s.env, &[i as u8; 32]));
+        batch_secrets.push_back(Bytes::from_slice(&s.env, b"s"));
+    }
+    s.factory.withdraw_src_escrows_multiproof(
+        &Address::generate(&s.env),
+        &escrow_ids,
+        &batch_secrets,
+        &vec![&s.env, BytesN::from_array(&s.env, &[0u8; 32])],
+        &vec![&s.env, false],
+    );
+}
+
+#[test]
+fn test_get_native_token() {
+    let s = setup();
+    assert_eq!(s.factory.get_native_token(), s.token);
+
+    let uninitialized = HashLockedEscrowFactoryClient::new(&s.env, &s.env.register(HashLockedEscrowFactory, ()));
+    assert!(uninitialized.try_get_native_token().is_err());
+}
+
+#[test]
+fn test_contract_recipient_notified_on_dst_withdrawal() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let receiver = MockTokensReceiverClient::new(&s.env, &s.env.register(MockTokensReceiver, ()));
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_dst(&s, &creator, &receiver.address, &secret, 10_000_000);
+    assert!(s.factory.get_dst_escrow(&escrow).recipient_is_contract);
+
+    // A panicking hook reverts the whole withdrawal
+    set_time(&s.env, 1_500);
+    receiver.set_fail(&true);
+    assert!(s.factory.try_withdraw_dst_escrow(&receiver.address, &escrow, &secret).is_err());
+    assert!(!s.factory.get_dst_escrow(&escrow).funds_withdrawn);
+
+    receiver.set_fail(&false);
+    s.factory.withdraw_dst_escrow(&receiver.address, &escrow, &secret);
+    assert_eq!(receiver.last_received(), Some((s.token.clone(), 10_000_000)));
+    assert_eq!(balance(&s, &receiver.address), 10_000_000 + DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_account_recipient_skips_tokens_received_hook() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    assert!(!s.factory.get_dst_escrow(&escrow).recipient_is_contract);
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_dst_escrow(&recipient, &escrow, &secret);
+    assert_eq!(balance(&s, &recipient), 10_000_000 + DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_get_consumed() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let spender = s.factory.address.clone();
+    let (root, _, _) = two_leaf_tree(&s.env, &Bytes::from_slice(&s.env, b"s0"), &Bytes::from_slice(&s.env, b"s1"));
+
+    // Grant more than the escrow draws so part of the approval remains
+    mint(&s, &buyer, 10_000_000 + DEPOSIT_AMOUNT);
+    TokenClient::new(&s.env, &s.token).approve(&buyer, &s.factory.address, &10_000_000, &1_000);
+    s.factory.approve(&buyer, &25_000_000);
+    assert_eq!(s.factory.get_consumed(&buyer, &spender), 0);
+    s.factory.create_src_escrow_partial(&buyer, &root, &recipient, &buyer, &s.token, &10_000_000, &src_windows(), &0, &2, &false);
+
+    assert_eq!(s.factory.get_consumed(&buyer, &spender), 10_000_000);
+    assert_eq!(s.factory.get_consumed(&buyer, &spender) + s.factory.allowance(&buyer, &spender), 25_000_000);
+}
+
+#[test]
+fn test_min_deposit_bps() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    assert!(s.factory.try_set_min_deposit_bps(&s.owner, &10_001).is_err());
+
+    // 10% of 10_000_000 is exactly the flat deposit, so an escrow at the ratio is accepted
+    s.factory.set_min_deposit_bps(&s.owner, &1_000);
+    assert_eq!(s.factory.get_min_deposit_bps(), 1_000);
+    create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    // Above 10 XLM the flat deposit falls below the ratio
+    let other = Bytes::from_slice(&s.env, b"other");
+    mint(&s, &creator, 10_000_010 + DEPOSIT_AMOUNT);
+    let result = s.factory.try_create_dst_escrow(
+        &creator,
+        &hashed_secret(&s.env, &other),
+        &recipient,
+        &10_000_010,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    );
+    assert!(result.is_err());
+
+    // Exempt resolvers post no deposit, so any non-zero ratio rejects them
+    s.factory.set_deposit_exempt(&s.owner, &creator, &true);
+    let result = s.factory.try_create_dst_escrow(
+        &creator,
+        &hashed_secret(&s.env, &other),
+        &recipient,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_get_escrow_age() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    set_time(&s.env, 100);
+    let src = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    assert_eq!(s.factory.get_escrow_age(&src), 0);
+    set_time(&s.env, 350);
+    assert_eq!(s.factory.get_escrow_age(&src), 250);
+    set_time(&s.env, 1_100);
+    assert_eq!(s.factory.get_escrow_age(&src), 1_000);
+}
+
+#[test]
+fn test_get_escrow_age_dst() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    set_time(&s.env, 200);
+    let dst = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    set_time(&s.env, 260);
+    assert_eq!(s.factory.get_escrow_age(&dst), 60);
+
+    assert!(s.factory.try_get_escrow_age(&BytesN::from_array(&s.env, &[1u8; 32])).is_err());
+}
+
+#[test]
+fn test_self_swap_partial_nets_to_zero() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let secret0 = Bytes::from_slice(&s.env, b"s0");
+    let (root, _, leaf1) = two_leaf_tree(&s.env, &secret0, &Bytes::from_slice(&s.env, b"s1"));
+
+    // The maker creates, funds and receives its own escrow, posting the security deposit itself
+    let escrow = create_src_partial(&s, &maker, &maker, &root, 10_000_000, 0, 2);
+    assert_eq!(balance(&s, &maker), 0);
+    assert_eq!(s.factory.allowance(&maker, &s.factory.address), 0);
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow_with_proof(&maker, &escrow, &secret0, &vec![&s.env, leaf1]);
+    assert_eq!(balance(&s, &maker), 10_000_000 + DEPOSIT_AMOUNT);
+    assert_eq!(balance(&s, &s.factory.address), 0);
+}
+
+#[test]
+fn test_self_swap_with_resolver_deposit() {
+    let s = setup();
+    let resolver = Address::generate(&s.env);
+    let maker = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_src(&s, &resolver, &maker, &maker, &secret, 10_000_000);
+    assert_eq!(balance(&s, &maker), 0);
+
+    // The maker withdraws its own principal plus the resolver's deposit, like any withdrawing recipient
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow(&maker, &escrow, &secret);
+    assert_eq!(balance(&s, &maker), 10_000_000 + DEPOSIT_AMOUNT);
+    assert_eq!(balance(&s, &resolver), 0);
+    assert_eq!(balance(&s, &s.factory.address), 0);
+}
+
+#[test]
+fn test_approved_spenders() {
+    let s = setup();
+    let owner = Address::generate(&s.env);
+    let spender = s.factory.address.clone();
+    assert_eq!(s.factory.get_approved_spenders(&owner).len(), 0);
+
+    // Re-approving the same spender does not list it twice
+    s.factory.approve(&owner, &1_000);
+    s.factory.approve_with_expiration(&owner, &2_000, &5_000);
+    assert_eq!(s.factory.get_approved_spenders(&owner), vec![&s.env, spender.clone()]);
+
+    // A zero approval grants nothing, so it does not list a spender
+    let other_owner = Address::generate(&s.env);
+    s.factory.approve(&other_owner, &0);
+    assert_eq!(s.factory.get_approved_spenders(&other_owner).len(), 0);
+
+    s.factory.revoke_allowance(&owner, &spender);
+    assert_eq!(last_approval(&s), (owner.clone(), spender.clone(), 0));
+    assert_eq!(s.factory.get_approved_spenders(&owner).len(), 0);
+    assert_eq!(s.factory.allowance(&owner, &spender), 0);
+    assert_eq!(s.factory.allowance_expiration(&owner, &spender), 0);
+
+    // Revoking a spender that was never approved is a no-op for the list
+    s.factory.revoke_allowance(&owner, &Address::generate(&s.env));
+    assert_eq!(s.factory.get_approved_spenders(&owner).len(), 0);
+}
+
+#[test]
+fn test_rescue_routes_to_rescue_recipient() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let recovery = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    // Only the escrow's creator (the buyer) may set it
+    assert!(s.factory.try_set_rescue_recipient(&recipient, &escrow, &Some(recovery.clone())).is_err());
+    s.factory.set_rescue_recipient(&buyer, &escrow, &Some(recovery.clone()));
+    assert_eq!(s.factory.get_src_escrow(&escrow).rescue_recipient, Some(recovery.clone()));
+
+    set_time(&s.env, 4_000 + RESCUE_DELAY);
+    s.factory.rescue_src_escrow(&recipient, &escrow);
+    assert_eq!(balance(&s, &recovery), 10_000_000);
+    assert_eq!(balance(&s, &recipient), 0);
+    assert_eq!(balance(&s, &creator), DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_rescue_recipient_needs_recipient_auth() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let recovery = Address::generate(&s.env);
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"secret"), 10_000_000);
+
+    // The creator alone cannot divert the recipient's rescued principal
+    let args: Vec<Val> = (buyer.clone(), escrow.clone(), Some(recovery.clone())).into_val(&s.env);
+    mock_factory_auths(&s, "set_rescue_recipient", args.clone(), &[&buyer]);
+    assert!(s.factory.try_set_rescue_recipient(&buyer, &escrow, &Some(recovery.clone())).is_err());
+    assert_eq!(s.factory.get_src_escrow(&escrow).rescue_recipient, None);
+
+    mock_factory_auths(&s, "set_rescue_recipient", args, &[&buyer, &recipient]);
+    s.factory.set_rescue_recipient(&buyer, &escrow, &Some(recovery.clone()));
+    assert_eq!(s.factory.get_src_escrow(&escrow).rescue_recipient, Some(recovery));
+}
+
+#[test]
+fn test_rescue_defaults_to_recipient() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    // The rescue recipient is fixed once the withdrawal window opens
+    set_time(&s.env, 1_000);
+    assert!(s.factory.try_set_rescue_recipient(&buyer, &escrow, &Some(Address::generate(&s.env))).is_err());
+
+    set_time(&s.env, 4_000 + RESCUE_DELAY);
+    s.factory.rescue_src_escrow(&recipient, &escrow);
+    assert_eq!(balance(&s, &recipient), 10_000_000);
+}
+
+#[test]
+fn test_rescue_principal_and_deposit_claimed_separately() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 4_000 + RESCUE_DELAY - 1);
+    assert!(s.factory.try_rescue_principal(&recipient, &escrow).is_err());
+    set_time(&s.env, 4_000 + RESCUE_DELAY);
+    assert!(s.factory.try_rescue_principal(&buyer, &escrow).is_err());
+    assert!(s.factory.try_rescue_deposit(&recipient, &escrow).is_err());
+
+    // Each party claims its half on its own, and a repeat claim pays nothing
+    s.factory.rescue_principal(&recipient, &escrow);
+    s.factory.rescue_principal(&recipient, &escrow);
+    assert_eq!(balance(&s, &recipient), 10_000_000);
+    assert_eq!(balance(&s, &buyer), 0);
+    assert!(s.factory.get_src_escrow(&escrow).funds_withdrawn);
+
+    s.factory.rescue_deposit(&buyer, &escrow);
+    s.factory.rescue_deposit(&buyer, &escrow);
+    assert_eq!(balance(&s, &creator), DEPOSIT_AMOUNT);
+    assert_eq!(balance(&s, &buyer), 0);
+    assert_eq!(balance(&s, &recipient), 10_000_000);
+    assert_eq!(balance(&s, &s.factory.address), 0);
+}
+
+#[test]
+#[should_panic(expected = "Rescue not available")]
+fn test_max_rescue_delay_never_opens_rescue() {
+    let s = setup_with_config(DEPOSIT_AMOUNT, u64::MAX);
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"secret"), 10_000_000);
+
+    // public_cancellation_start + rescue_delay would overflow; it saturates instead
+    set_time(&s.env, 5_000);
+    s.factory.rescue_principal(&recipient, &escrow);
+}
+
+#[test]
+fn test_rescue_deposit_before_principal() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 4_000 + RESCUE_DELAY);
+    s.factory.rescue_deposit(&buyer, &escrow);
+    assert_eq!(balance(&s, &creator), DEPOSIT_AMOUNT);
+    s.factory.rescue_src_escrow(&recipient, &escrow);
+    assert_eq!(balance(&s, &recipient), 10_000_000);
+    assert_eq!(balance(&s, &creator), DEPOSIT_AMOUNT);
+
+}
+
+#[test]
+fn test_rescue_deposit_after_withdrawal_reverts() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    // A normally withdrawn escrow has nothing left to rescue
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+    set_time(&s.env, 4_000 + RESCUE_DELAY);
+    assert!(s.factory.try_rescue_deposit(&buyer, &escrow).is_err());
+    assert!(s.factory.try_rescue_principal(&recipient, &escrow).is_err());
+}
+
+#[test]
+fn test_extension_requires_both_parties() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    // A proposal alone leaves the windows untouched
+    s.factory.propose_extension(&buyer, &escrow, &5_000);
+    let escrow_data = s.factory.get_src_escrow(&escrow);
+    assert_eq!(escrow_data.proposed_cancellation_start, Some(5_000));
+    assert_eq!((escrow_data.cancellation_start, escrow_data.public_cancellation_start), (3_000, 4_000));
+
+    s.factory.approve_extension(&recipient, &escrow);
+    let escrow_data = s.factory.get_src_escrow(&escrow);
+    assert_eq!(escrow_data.proposed_cancellation_start, None);
+    assert_eq!((escrow_data.cancellation_start, escrow_data.public_cancellation_start), (5_000, 6_000));
+
+    // The recipient can still withdraw where the escrow would otherwise have been cancellable
+    set_time(&s.env, 3_500);
+    assert!(s.factory.try_cancel_src_escrow(&buyer, &escrow).is_err());
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+}
+
+#[test]
+fn test_extension_is_not_unilateral() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    assert!(s.factory.try_approve_extension(&recipient, &escrow).is_err());
+    assert!(s.factory.try_propose_extension(&recipient, &escrow, &5_000).is_err());
+    s.factory.propose_extension(&buyer, &escrow, &5_000);
+    assert!(s.factory.try_approve_extension(&buyer, &escrow).is_err());
+
+    // The unapproved proposal has no effect once cancellation opens
+    set_time(&s.env, 3_000);
+    assert!(s.factory.try_approve_extension(&recipient, &escrow).is_err());
+    s.factory.cancel_src_escrow(&buyer, &escrow);
+    assert!(s.factory.get_src_escrow(&escrow).cancelled);
+}
+
+#[test]
+fn test_get_escrows_batch() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let src = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    let src2 = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"secret2"), 11_000_000);
+    let src3 = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"secret3"), 12_000_000);
+    let dst = create_dst(&s, &creator, &recipient, &secret, 20_000_000);
+    let unknown = BytesN::from_array(&s.env, &[1u8; 32]);
+
+    let src_batch = s.factory.get_src_escrows_batch(&vec![&s.env, src.clone(), unknown.clone(), src3.clone(), src2.clone()], &true);
+    assert_eq!(
+        src_batch,
+        vec![&s.env, s.factory.get_src_escrow(&src), s.factory.get_src_escrow(&src3), s.factory.get_src_escrow(&src2)]
+    );
+    assert_eq!(src_batch.get(1).unwrap().amount, 12_000_000);
+    let dst_batch = s.factory.get_dst_escrows_batch(&vec![&s.env, unknown.clone(), dst.clone()], &true);
+    assert_eq!(dst_batch, vec![&s.env, s.factory.get_dst_escrow(&dst)]);
+
+    // Without skipping, an unknown id fails the whole call
+    assert!(s.factory.try_get_src_escrows_batch(&vec![&s.env, src.clone(), unknown.clone()], &false).is_err());
+    assert!(s.factory.try_get_dst_escrows_batch(&vec![&s.env, unknown], &false).is_err());
+
+    let mut too_many = Vec::new(&s.env);
+    for _ in 0..=MAX_BATCH_SIZE {
+        too_many.push_back(src.clone());
+    }
+    assert!(s.factory.try_get_src_escrows_batch(&too_many, &true).is_err());
+}
+
+#[test]
+fn test_proof_for_other_part_rejected() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret0 = Bytes::from_slice(&s.env, b"s0");
+    let secret1 = Bytes::from_slice(&s.env, b"s1");
+    let (root, leaf0, leaf1) = two_leaf_tree(&s.env, &secret0, &secret1);
+    let escrow = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 1, 2);
+
+    // Part 0's secret and proof are valid for the root, but this escrow's leaf is bound to part 1
+    set_time(&s.env, 1_500);
+    assert!(s.factory.try_withdraw_src_escrow_with_proof(&recipient, &escrow, &secret0, &vec![&s.env, leaf1]).is_err());
+    assert!(!s.factory.get_src_escrow(&escrow).funds_withdrawn);
+
+    s.factory.withdraw_src_escrow_with_proof(&recipient, &escrow, &secret1, &vec![&s.env, leaf0]);
+    assert!(s.factory.get_src_escrow(&escrow).funds_withdrawn);
+}
+
+// Namespace topic of the factory's most recent Approval event
+fn last_approval_namespace(env: &Env, factory: &Address) -> Symbol {
+    let (_, topics, _) = env.events().all().iter()
+        .filter(|(contract, _, _)| contract == factory)
+        .last()
+        .unwrap();
+    Symbol::from_val(env, &topics.get(1).unwrap())
+}
+
+#[test]
+fn test_initialize_validates_native_token() {
+    let s = setup();
+    assert_eq!(s.factory.get_native_token(), s.token);
+
+    // Neither a plain account nor a contract without the token interface is accepted
+    let factory = HashLockedEscrowFactoryClient::new(&s.env, &s.env.register(HashLockedEscrowFactory, ()));
+    assert!(factory.try_initialize(&Address::generate(&s.env), &s.owner, &None, &DEPOSIT_AMOUNT, &RESCUE_DELAY).is_err());
+    let not_a_token = s.env.register(MockTokensReceiver, ());
+    assert!(factory.try_initialize(&not_a_token, &s.owner, &None, &DEPOSIT_AMOUNT, &RESCUE_DELAY).is_err());
+    assert!(factory.try_get_native_token().is_err());
+}
+
+#[test]
+fn test_event_namespace() {
+    let s = setup();
+    let owner = Address::generate(&s.env);
+
+    s.factory.approve(&owner, &1_000);
+    assert_eq!(last_approval_namespace(&s.env, &s.factory.address), Symbol::new(&s.env, "fusion"));
+    assert_eq!(s.factory.get_event_namespace(), Symbol::new(&s.env, "fusion"));
+
+    // A second deployment with its own namespace is distinguishable by topics alone
+    let other = HashLockedEscrowFactoryClient::new(&s.env, &s.env.register(HashLockedEscrowFactory, ()));
+    other.initialize(&s.token, &s.owner, &Some(symbol_short!("testnet2")), &DEPOSIT_AMOUNT, &RESCUE_DELAY);
+    other.approve(&owner, &1_000);
+    assert_eq!(last_approval_namespace(&s.env, &other.address), symbol_short!("testnet2"));
+}
+
+#[test]
+fn test_get_config() {
+    let s = setup();
+    let config = s.factory.get_config();
+    assert_eq!(config.native_token, s.token);
+    assert_eq!(config.owner, s.owner);
+    assert_eq!(config.fee_collector, s.owner);
+    assert_eq!(config.deposit_amount, s.factory.get_deposit_amount());
+    assert_eq!((config.rescue_delay, config.max_batch_size, config.max_secret_hint_len), (RESCUE_DELAY, MAX_BATCH_SIZE, MAX_SECRET_HINT_LEN));
+
+    let fee_collector = Address::generate(&s.env);
+    s.factory.set_hash_algo(&s.owner, &HashAlgo::Keccak256);
+    s.factory.set_deposit_guard(&s.owner, &true);
+    s.factory.set_min_deposit_bps(&s.owner, &250);
+    s.factory.set_cancellation_penalty(&s.owner, &1_000);
+    s.factory.set_keeper_share(&s.owner, &2_000);
+    s.factory.set_fee_collector(&s.owner, &fee_collector);
+    s.factory.set_max_escrow_lifetime(&s.owner, &86_400);
+    s.factory.set_max_escrows_per_user(&s.owner, &5);
+    s.factory.set_max_batch_proof_cost(&s.owner, &16);
+    s.factory.set_token_allowlist_enabled(&s.owner, &true);
+    s.factory.begin_winddown(&s.owner);
+
+    assert_eq!(
+        s.factory.get_config(),
+        FactoryConfig {
+            native_token: s.token.clone(),
+            owner: s.owner.clone(),
+            event_namespace: s.factory.get_event_namespace(),
+            hash_algo: HashAlgo::Keccak256,
+            deposit_amount: DEPOSIT_AMOUNT,
+            rescue_delay: RESCUE_DELAY,
+            deposit_guard_enabled: true,
+            min_deposit_bps: 250,
+            cancellation_penalty_bps: 1_000,
+            keeper_share_bps: 2_000,
+            fee_collector,
+            max_escrow_lifetime: 86_400,
+            max_escrows_per_user: 5,
+            max_batch_proof_cost: 16,
+            token_allowlist_enabled: true,
+            winding_down: true,
+            max_batch_size: MAX_BATCH_SIZE,
+            max_secret_hint_len: MAX_SECRET_HINT_LEN,
+        }
+    );
+}
+
+fn create_dst_partial_with_proof(s: &Setup, creator: &Address, root: &BytesN<32>, part_index: u64, part_proof: &Option<PartProof>) -> Result<BytesN<32>, ()> {
+    mint(s, creator, 10_000_000 + DEPOSIT_AMOUNT);
+    s.factory
+        .try_create_dst_escrow_partial(
+            creator,
+            root,
+            &Address::generate(&s.env),
+            &s.token,
+            &10_000_000,
+            &src_windows(),
+            &part_index,
+            &4,
+            part_proof,
+            &false,
+        )
+        .map(|escrow| escrow.unwrap())
+        .map_err(|_| ())
+}
+
+#[test]
+fn test_create_dst_partial_with_valid_proof() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let secrets: [Bytes; 4] = core::array::from_fn(|i| Bytes::from_array(&s.env, &[b's', i as u8]));
+    let (root, leaves, nodes) = four_leaf_tree(&s.env, &secrets);
+
+    let part_proof = PartProof {
+        secret_hash: hashed_secret(&s.env, &secrets[2]),
+        merkle_proof: vec![&s.env, leaves[3].clone(), nodes[0].clone()],
+    };
+    let escrow = create_dst_partial_with_proof(&s, &creator, &root, 2, &Some(part_proof)).unwrap();
+    assert_eq!(s.factory.get_dst_escrow(&escrow).part_index, 2);
+}
+
+#[test]
+fn test_create_dst_partial_with_invalid_proof() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let secrets: [Bytes; 4] = core::array::from_fn(|i| Bytes::from_array(&s.env, &[b's', i as u8]));
+    let (root, leaves, nodes) = four_leaf_tree(&s.env, &secrets);
+
+    // Part 2's proof does not place a leaf at index 1, and an empty proof is never accepted
+    let part_proof = PartProof {
+        secret_hash: hashed_secret(&s.env, &secrets[2]),
+        merkle_proof: vec![&s.env, leaves[3].clone(), nodes[0].clone()],
+    };
+    assert!(create_dst_partial_with_proof(&s, &creator, &root, 1, &Some(part_proof)).is_err());
+    let empty = PartProof { secret_hash: hashed_secret(&s.env, &secrets[1]), merkle_proof: Vec::new(&s.env) };
+    assert!(create_dst_partial_with_proof(&s, &creator, &root, 1, &Some(empty)).is_err());
+    assert!(s.factory.get_user_escrows(&creator).is_empty());
+
+    // Without a proof the part is created unchecked, as before
+    create_dst_partial_with_proof(&s, &creator, &root, 1, &None).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Wrong escrow kind: expected source")]
+fn test_withdraw_src_rejects_dst_escrow() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 2_000);
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+}
 
 #[test]
-fn test() {
-    let env = Env::default();
-    let contract_id = env.register(Contract, ());
-    let client = ContractClient::new(&env, &contract_id);
+#[should_panic(expected = "Wrong escrow kind: expected destination")]
+fn test_withdraw_dst_rejects_src_escrow() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_src(&s, &buyer, &buyer, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 2_000);
+    s.factory.withdraw_dst_escrow(&recipient, &escrow, &secret);
+}
+
+#[test]
+fn test_format_amount_reports_token_decimals() {
+    let s = setup();
+    let token = s.env.register(MockDecimalsToken, ());
+
+    assert_eq!(s.factory.format_amount(&token, &12_345_678), (12_345_678, 7));
+    assert_eq!(s.factory.format_amount(&s.token, &DEPOSIT_AMOUNT), (DEPOSIT_AMOUNT, 7));
+}
+
+#[test]
+fn test_escrows_have_distinct_ids() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secrets: [Bytes; 3] = core::array::from_fn(|i| Bytes::from_array(&s.env, &[b's', i as u8]));
+    let escrows: [BytesN<32>; 3] =
+        core::array::from_fn(|i| create_src(&s, &creator, &buyer, &recipient, &secrets[i], 10_000_000 * (i as i128 + 1)));
+
+    // Each escrow keeps its own data instead of overwriting the previous one
+    assert_ne!(escrows[0], escrows[1]);
+    assert_ne!(escrows[1], escrows[2]);
+    assert_ne!(escrows[0], escrows[2]);
+    for (i, escrow) in escrows.iter().enumerate() {
+        let escrow_data = s.factory.get_src_escrow(escrow);
+        assert_eq!(escrow_data.amount, 10_000_000 * (i as i128 + 1));
+        assert_eq!(escrow_data.hashed_secret, hashed_secret(&s.env, &secrets[i]));
+    }
+    assert_eq!(s.factory.get_user_escrows(&buyer), vec![&s.env, escrows[0].clone(), escrows[1].clone(), escrows[2].clone()]);
+
+    // Settling one leaves the others open
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow(&recipient, &escrows[1], &secrets[1]);
+    assert!(!s.factory.get_src_escrow(&escrows[0]).funds_withdrawn);
+    assert!(s.factory.get_src_escrow(&escrows[1]).funds_withdrawn);
+    assert!(!s.factory.get_src_escrow(&escrows[2]).funds_withdrawn);
+}
+
+// Authorize a withdrawal by `caller` with only the given signers, instead of mocking every auth
+fn mock_withdraw_auths(s: &Setup, fn_name: &str, caller: &Address, escrow: &BytesN<32>, secret: &Bytes, signers: &[&Address]) {
+    mock_factory_auths(s, fn_name, (caller.clone(), escrow.clone(), secret.clone()).into_val(&s.env), signers);
+}
+
+// Authorize a factory call with the given arguments by only the given signers
+fn mock_factory_auths(s: &Setup, fn_name: &str, args: Vec<Val>, signers: &[&Address]) {
+    let invoke = MockAuthInvoke {
+        contract: &s.factory.address,
+        fn_name,
+        args,
+        sub_invokes: &[],
+    };
+    let auths: std::vec::Vec<MockAuth> = signers.iter().map(|address| MockAuth { address, invoke: &invoke }).collect();
+    s.env.mock_auths(&auths);
+}
+
+#[test]
+fn test_dual_auth_src_withdrawal_needs_both_parties() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    mint(&s, &buyer, 10_000_000 + DEPOSIT_AMOUNT);
+    TokenClient::new(&s.env, &s.token).approve(&buyer, &s.factory.address, &10_000_000, &1_000);
+    s.factory.approve(&buyer, &10_000_000);
+    let escrow = s.factory.create_src_escrow(
+        &buyer,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &buyer,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &true,
+    );
+    assert!(s.factory.get_src_escrow(&escrow).require_dual_auth);
+
+    set_time(&s.env, 1_000);
+    mock_withdraw_auths(&s, "withdraw_src_escrow", &recipient, &escrow, &secret, &[&recipient]);
+    assert!(s.factory.try_withdraw_src_escrow(&recipient, &escrow, &secret).is_err());
+
+    mock_withdraw_auths(&s, "withdraw_src_escrow", &recipient, &escrow, &secret, &[&recipient, &buyer]);
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+    assert!(s.factory.get_src_escrow(&escrow).funds_withdrawn);
+}
+
+#[test]
+fn test_dual_auth_dst_withdrawal_needs_both_parties() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    mint(&s, &creator, 10_000_000 + DEPOSIT_AMOUNT);
+    let escrow = s.factory.create_dst_escrow(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &true,
+    );
+
+    set_time(&s.env, 1_000);
+    mock_withdraw_auths(&s, "withdraw_dst_escrow", &creator, &escrow, &secret, &[&creator]);
+    assert!(s.factory.try_withdraw_dst_escrow(&creator, &escrow, &secret).is_err());
+
+    mock_withdraw_auths(&s, "withdraw_dst_escrow", &creator, &escrow, &secret, &[&creator, &recipient]);
+    s.factory.withdraw_dst_escrow(&creator, &escrow, &secret);
+    assert_eq!(balance(&s, &recipient), 10_000_000);
+}
+
+#[test]
+fn test_dual_auth_src_proof_withdrawals_need_both_parties() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secrets: [Bytes; 4] = core::array::from_fn(|i| Bytes::from_array(&s.env, &[b's', i as u8]));
+    let (root, leaves, nodes) = four_leaf_tree(&s.env, &secrets);
+    mint(&s, &buyer, 10_000_000 + DEPOSIT_AMOUNT);
+    TokenClient::new(&s.env, &s.token).approve(&buyer, &s.factory.address, &10_000_000, &1_000);
+    s.factory.approve(&buyer, &10_000_000);
+    let escrow = s.factory.create_src_escrow_partial(&buyer, &root, &recipient, &buyer, &s.token, &10_000_000, &src_windows(), &2, &4, &true);
+    assert!(s.factory.get_src_escrow(&escrow).require_dual_auth);
+
+    set_time(&s.env, 1_000);
+    let proof = vec![&s.env, leaves[3].clone(), nodes[0].clone()];
+    let proof_args: Vec<Val> = (recipient.clone(), escrow.clone(), secrets[2].clone(), proof.clone()).into_val(&s.env);
+    mock_factory_auths(&s, "withdraw_src_escrow_with_proof", proof_args.clone(), &[&recipient]);
+    assert!(s.factory.try_withdraw_src_escrow_with_proof(&recipient, &escrow, &secrets[2], &proof).is_err());
+
+    // The multiproof batch checks every escrow it settles
+    let escrows = vec![&s.env, escrow.clone()];
+    let batch_secrets = vec![&s.env, secrets[2].clone()];
+    let flags = vec![&s.env, false, false];
+    let batch_args: Vec<Val> = (recipient.clone(), escrows.clone(), batch_secrets.clone(), proof.clone(), flags.clone()).into_val(&s.env);
+    mock_factory_auths(&s, "withdraw_src_escrows_multiproof", batch_args, &[&recipient]);
+    assert!(s.factory.try_withdraw_src_escrows_multiproof(&recipient, &escrows, &batch_secrets, &proof, &flags).is_err());
+
+    mock_factory_auths(&s, "withdraw_src_escrow_with_proof", proof_args, &[&recipient, &buyer]);
+    s.factory.withdraw_src_escrow_with_proof(&recipient, &escrow, &secrets[2], &proof);
+    assert!(s.factory.get_src_escrow(&escrow).funds_withdrawn);
+}
+
+#[test]
+fn test_dual_auth_dst_proof_withdrawal_needs_both_parties() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secrets: [Bytes; 4] = core::array::from_fn(|i| Bytes::from_array(&s.env, &[b's', i as u8]));
+    let (root, leaves, nodes) = four_leaf_tree(&s.env, &secrets);
+    mint(&s, &creator, 10_000_000 + DEPOSIT_AMOUNT);
+    let escrow = s.factory.create_dst_escrow_partial(&creator, &root, &recipient, &s.token, &10_000_000, &src_windows(), &2, &4, &None, &true);
+
+    set_time(&s.env, 1_000);
+    let proof = vec![&s.env, leaves[3].clone(), nodes[0].clone()];
+    let args: Vec<Val> = (creator.clone(), escrow.clone(), secrets[2].clone(), proof.clone()).into_val(&s.env);
+    mock_factory_auths(&s, "withdraw_dst_escrow_with_proof", args.clone(), &[&creator]);
+    assert!(s.factory.try_withdraw_dst_escrow_with_proof(&creator, &escrow, &secrets[2], &proof).is_err());
+
+    mock_factory_auths(&s, "withdraw_dst_escrow_with_proof", args, &[&creator, &recipient]);
+    s.factory.withdraw_dst_escrow_with_proof(&creator, &escrow, &secrets[2], &proof);
+    assert_eq!(balance(&s, &recipient), 10_000_000);
+}
+
+// Latest escrow lifecycle event published by the factory, as ((kind, action), data map)
+// Every escrow event carries the factory's namespace between its kind and action topics
+fn last_escrow_event(s: &Setup) -> ((Symbol, Symbol), Map<Symbol, Val>) {
+    let (_, topics, data) = s.env.events().all().iter()
+        .filter(|(contract, topics, _)| {
+            let kind = Symbol::from_val(&s.env, &topics.get(0).unwrap());
+            *contract == s.factory.address && (kind == symbol_short!("src_esc") || kind == symbol_short!("dst_esc"))
+        })
+        .last()
+        .unwrap();
+    assert_eq!(Symbol::from_val(&s.env, &topics.get(1).unwrap()), s.factory.get_event_namespace());
+    (
+        (Symbol::from_val(&s.env, &topics.get(0).unwrap()), Symbol::from_val(&s.env, &topics.get(2).unwrap())),
+        Map::from_val(&s.env, &data),
+    )
+}
+
+fn assert_escrow_event(
+    s: &Setup,
+    topics: (Symbol, Symbol),
+    escrow: &BytesN<32>,
+    creator: &Address,
+    recipient: &Address,
+    amount: i128,
+    part_index: u64,
+) {
+    let (event_topics, data) = last_escrow_event(s);
+    assert_eq!(event_topics, topics);
+    assert_eq!(BytesN::<32>::from_val(&s.env, &data.get(Symbol::new(&s.env, "escrow_id")).unwrap()), *escrow);
+    assert_eq!(Address::from_val(&s.env, &data.get(symbol_short!("creator")).unwrap()), *creator);
+    assert_eq!(Address::from_val(&s.env, &data.get(symbol_short!("recipient")).unwrap()), *recipient);
+    assert_eq!(i128::from_val(&s.env, &data.get(symbol_short!("amount")).unwrap()), amount);
+    assert_eq!(u64::from_val(&s.env, &data.get(Symbol::new(&s.env, "part_index")).unwrap()), part_index);
+}
+
+#[test]
+fn test_src_escrow_lifecycle_events() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_src(&s, &buyer, &buyer, &recipient, &secret, 10_000_000);
+    assert_escrow_event(&s, (symbol_short!("src_esc"), symbol_short!("created")), &escrow, &buyer, &recipient, 10_000_000, 0);
+
+    set_time(&s.env, 1_000);
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+    assert_escrow_event(&s, (symbol_short!("src_esc"), symbol_short!("withdrawn")), &escrow, &buyer, &recipient, 10_000_000, 0);
+}
+
+#[test]
+fn test_dst_escrow_lifecycle_events() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    assert_escrow_event(&s, (symbol_short!("dst_esc"), symbol_short!("created")), &escrow, &creator, &recipient, 10_000_000, 0);
+
+    set_time(&s.env, 3_000);
+    s.factory.cancel_dst_escrow(&creator, &escrow);
+    assert_escrow_event(&s, (symbol_short!("dst_esc"), symbol_short!("cancelled")), &escrow, &creator, &recipient, 10_000_000, 0);
+}
+
+#[test]
+fn test_partial_escrow_events_carry_part_index() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret1 = Bytes::from_slice(&s.env, b"s1");
+    let (root, leaf0, _) = two_leaf_tree(&s.env, &Bytes::from_slice(&s.env, b"s0"), &secret1);
+    let escrow = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 1, 2);
+    assert_escrow_event(&s, (symbol_short!("src_esc"), symbol_short!("created")), &escrow, &buyer, &recipient, 10_000_000, 1);
+
+    set_time(&s.env, 1_000);
+    s.factory.withdraw_src_escrow_with_proof(&recipient, &escrow, &secret1, &vec![&s.env, leaf0]);
+    assert_escrow_event(&s, (symbol_short!("src_esc"), symbol_short!("withdrawn")), &escrow, &buyer, &recipient, 10_000_000, 1);
+}
+
+#[test]
+fn test_admin_and_deposit_paths_publish_escrow_events() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let invalid = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"invalid"), 10_000_000);
+    let rescued = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"rescued"), 10_000_000);
+    let exited = create_dst(&s, &creator, &recipient, &Bytes::from_slice(&s.env, b"exited"), 10_000_000);
+
+    s.factory.mark_escrow_invalid(&s.owner, &invalid);
+    assert_escrow_event(&s, (symbol_short!("src_esc"), symbol_short!("invalid")), &invalid, &buyer, &recipient, 10_000_000, 0);
+
+    set_time(&s.env, 4_000 + RESCUE_DELAY);
+    s.factory.rescue_deposit(&buyer, &rescued);
+    assert_escrow_event(&s, (symbol_short!("src_esc"), Symbol::new(&s.env, "deposit_rescued")), &rescued, &buyer, &recipient, 10_000_000, 0);
+
+    s.factory.set_token_paused(&s.owner, &s.token, &true);
+    s.factory.safe_exit(&s.owner, &exited, &None);
+    assert_escrow_event(&s, (symbol_short!("dst_esc"), symbol_short!("safe_exit")), &exited, &creator, &recipient, 10_000_000, 0);
+}
+
+#[test]
+fn test_fund_recovery_pool_publishes_event() {
+    let s = setup();
+    let replacement = s.env.register_stellar_asset_contract_v2(Address::generate(&s.env)).address();
+    StellarAssetClient::new(&s.env, &replacement).mint(&s.owner, &20_000_000);
+    s.factory.fund_recovery_pool(&s.owner, &replacement, &20_000_000);
+
+    let (_, topics, data) = s.env.events().all().iter()
+        .filter(|(contract, _, _)| *contract == s.factory.address)
+        .last()
+        .unwrap();
+    assert_eq!(Symbol::from_val(&s.env, &topics.get(0).unwrap()), Symbol::new(&s.env, "RecoveryPoolFunded"));
+    assert_eq!(Symbol::from_val(&s.env, &topics.get(1).unwrap()), s.factory.get_event_namespace());
+    assert_eq!(Address::from_val(&s.env, &topics.get(2).unwrap()), replacement);
+    assert_eq!(i128::from_val(&s.env, &data), 20_000_000);
+}
+
+#[test]
+fn test_get_src_deposit_held() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let withdrawn = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    let cancelled = create_src(&s, &creator, &buyer, &recipient, &Bytes::from_slice(&s.env, b"other"), 10_000_000);
+    assert_eq!(s.factory.get_src_deposit_held(&withdrawn), DEPOSIT_AMOUNT);
+
+    set_time(&s.env, 1_000);
+    s.factory.withdraw_src_escrow(&recipient, &withdrawn, &secret);
+    assert_eq!(s.factory.get_src_deposit_held(&withdrawn), 0);
+
+    set_time(&s.env, 3_000);
+    s.factory.cancel_src_escrow(&buyer, &cancelled);
+    assert_eq!(s.factory.get_src_deposit_held(&cancelled), 0);
+}
+
+#[test]
+fn test_create_error_codes() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let hashed = hashed_secret(&s.env, &Bytes::from_slice(&s.env, b"secret"));
+
+    let result = s.factory.try_create_dst_escrow(&creator, &hashed, &recipient, &0, &1_000, &2_000, &3_000, &4_000, &false);
+    assert_eq!(result, escrow_error(EscrowError::InvalidAmount));
+
+    let result = s.factory.try_create_dst_escrow(&creator, &hashed, &recipient, &10_000_000, &2_000, &1_000, &3_000, &4_000, &false);
+    assert_eq!(result, escrow_error(EscrowError::InvalidTimeWindows));
+
+    // The buyer never approved the factory
+    let result = s.factory.try_create_src_escrow(
+        &creator,
+        &hashed,
+        &recipient,
+        &buyer,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    );
+    assert_eq!(result, escrow_error(EscrowError::InsufficientAllowance));
+}
+
+#[test]
+fn test_withdraw_error_codes() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 500);
+    let result = s.factory.try_withdraw_src_escrow(&recipient, &escrow, &secret);
+    assert_eq!(result, escrow_error(EscrowError::WithdrawalNotStarted));
+
+    set_time(&s.env, 1_000);
+    let result = s.factory.try_withdraw_src_escrow(&recipient, &escrow, &Bytes::from_slice(&s.env, b"wrong"));
+    assert_eq!(result, escrow_error(EscrowError::InvalidSecret));
+
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+    let result = s.factory.try_withdraw_src_escrow(&recipient, &escrow, &secret);
+    assert_eq!(result, escrow_error(EscrowError::AlreadyWithdrawn));
+}
+
+#[test]
+fn test_withdraw_cancelled_escrow_error_code() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 3_000);
+    s.factory.cancel_dst_escrow(&creator, &escrow);
+    let result = s.factory.try_withdraw_dst_escrow(&creator, &escrow, &secret);
+    assert_eq!(result, escrow_error(EscrowError::AlreadyCancelled));
+}
+
+#[test]
+fn test_custom_security_deposit() {
+    let deposit = 2_500_000;
+    let s = setup_with_config(deposit, RESCUE_DELAY);
+    assert_eq!(s.factory.get_deposit_amount(), deposit);
+    assert_eq!(s.factory.get_config().deposit_amount, deposit);
+
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    // The creator posts the configured deposit, which the withdrawing recipient collects
+    mint(&s, &creator, deposit - DEPOSIT_AMOUNT);
+    let src = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    assert_eq!(s.factory.get_src_escrow(&src).security_deposit, deposit);
+    set_time(&s.env, 1_000);
+    s.factory.withdraw_src_escrow(&recipient, &src, &secret);
+    assert_eq!(balance(&s, &recipient), 10_000_000 + deposit);
+
+    // A cancelled destination escrow refunds the configured deposit to its creator
+    mint(&s, &creator, deposit - DEPOSIT_AMOUNT);
+    let dst = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    assert_eq!(s.factory.get_dst_escrow(&dst).security_deposit, deposit);
+    set_time(&s.env, 3_000);
+    s.factory.cancel_dst_escrow(&creator, &dst);
+    assert_eq!(balance(&s, &creator), 10_000_000 + deposit);
+}
+
+#[test]
+fn test_initialize_rejects_negative_deposit() {
+    let s = setup();
+    let factory = HashLockedEscrowFactoryClient::new(&s.env, &s.env.register(HashLockedEscrowFactory, ()));
+    assert!(factory.try_initialize(&s.token, &s.owner, &None, &-1, &RESCUE_DELAY).is_err());
+}
+
+#[test]
+fn test_funded_via_allowance_flag() {
+    let s = setup();
+    let resolver = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let (root, _, _) = two_leaf_tree(&s.env, &Bytes::from_slice(&s.env, b"s0"), &Bytes::from_slice(&s.env, b"s1"));
+    let token = TokenClient::new(&s.env, &s.token);
+
+    // A partial escrow created on the buyer's behalf, as the LOP does, pulls the buyer's tokens via allowance
+    mint(&s, &buyer, 10_000_000);
+    mint(&s, &resolver, DEPOSIT_AMOUNT);
+    token.approve(&buyer, &s.factory.address, &10_000_000, &1_000);
+    let on_behalf = s.factory.create_src_escrow_partial(
+        &resolver,
+        &root,
+        &recipient,
+        &buyer,
+        &s.token,
+        &10_000_000,
+        &src_windows(),
+        &0,
+        &2,
+        &false,
+    );
+    assert!(s.factory.get_src_escrow(&on_behalf).funded_via_allowance);
+    assert_eq!(token.allowance(&buyer, &s.factory.address), 0);
+
+    // A buyer creating its own escrow transfers the tokens directly, leaving its token allowance untouched
+    let own = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 1, 2);
+    assert!(!s.factory.get_src_escrow(&own).funded_via_allowance);
+    assert_eq!(token.allowance(&buyer, &s.factory.address), 10_000_000);
+    assert_eq!(balance(&s, &s.factory.address), 2 * (10_000_000 + DEPOSIT_AMOUNT));
+}
+
+#[test]
+fn test_custom_rescue_delay() {
+    let s = setup_with_config(DEPOSIT_AMOUNT, 60);
+    assert_eq!(s.factory.get_rescue_delay(), 60);
+    assert_eq!(s.factory.get_config().rescue_delay, 60);
+
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let src = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    let dst = create_dst(&s, &creator, &recipient, &Bytes::from_slice(&s.env, b"other"), 10_000_000);
+
+    // Both sides count the rescue delay from public_cancellation_start (4_000)
+    set_time(&s.env, 4_059);
+    assert!(s.factory.try_rescue_dst_escrow(&creator, &dst).is_err());
+    assert!(s.factory.try_rescue_src_escrow(&recipient, &src).is_err());
+    set_time(&s.env, 4_060);
+    s.factory.rescue_dst_escrow(&creator, &dst);
+    s.factory.rescue_src_escrow(&recipient, &src);
+    assert_eq!(balance(&s, &recipient), 10_000_000);
+}
+
+#[test]
+fn test_multiproof_batch_cost_ceiling() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secrets: [Bytes; 4] = core::array::from_fn(|i| Bytes::from_array(&s.env, &[b's', i as u8]));
+    let (root, leaves, _) = four_leaf_tree(&s.env, &secrets);
+    let escrow0 = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 0, 4);
+    let escrow2 = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 2, 4);
+    assert_eq!(s.factory.get_max_batch_proof_cost(), DEFAULT_MAX_BATCH_PROOF_COST);
+
+    // Two proof elements and two leaves cost 4
+    set_time(&s.env, 1_500);
+    let escrow_ids = vec![&s.env, escrow0.clone(), escrow2.clone()];
+    let batch_secrets = vec![&s.env, secrets[0].clone(), secrets[2].clone()];
+    let proof = vec![&s.env, leaves[1].clone(), leaves[3].clone()];
+    let flags = vec![&s.env, false, false, true];
+    s.factory.set_max_batch_proof_cost(&s.owner, &3);
+    let result = s.factory.try_withdraw_src_escrows_multiproof(&recipient, &escrow_ids, &batch_secrets, &proof, &flags);
+    assert!(result.is_err());
+
+    s.factory.set_max_batch_proof_cost(&s.owner, &4);
+    s.factory.withdraw_src_escrows_multiproof(&recipient, &escrow_ids, &batch_secrets, &proof, &flags);
+    assert!(s.factory.get_src_escrow(&escrow0).funds_withdrawn);
+    assert!(s.factory.get_src_escrow(&escrow2).funds_withdrawn);
+}
+
+#[test]
+#[should_panic(expected = "Batch proof cost too high")]
+fn test_multiproof_batch_above_cost_ceiling_reverts() {
+    let s = setup();
+    let recipient = Address::generate(&s.env);
+    s.factory.set_max_batch_proof_cost(&s.owner, &1);
+    let id = BytesN::from_array(&s.env, &[1u8; 32]);
+    s.factory.withdraw_src_escrows_multiproof(
+        &recipient,
+        &vec![&s.env, id],
+        &vec![&s.env, Bytes::from_slice(&s.env, b"s")],
+        &vec![&s.env, BytesN::from_array(&s.env, &[2u8; 32])],
+        &vec![&s.env, false],
+    );
+}
+
+#[test]
+fn test_src_escrow_with_token_settles_in_that_token() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let usdc = s.env.register_stellar_asset_contract_v2(Address::generate(&s.env)).address();
+    let usdc_client = TokenClient::new(&s.env, &usdc);
+
+    StellarAssetClient::new(&s.env, &usdc).mint(&buyer, &10_000_000);
+    StellarAssetClient::new(&s.env, &usdc).mint(&creator, &DEPOSIT_AMOUNT);
+    usdc_client.approve(&buyer, &s.factory.address, &10_000_000, &1_000);
+    s.factory.set_token_deposit(&s.owner, &usdc, &Some(DEPOSIT_AMOUNT));
+    s.factory.approve_token(&buyer, &usdc, &10_000_000);
+    let escrow = s.factory.create_src_escrow_with_token(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &buyer,
+        &usdc,
+        &10_000_000,
+        &src_windows(),
+        &false,
+    );
+    assert_eq!(s.factory.get_src_escrow(&escrow).token, usdc);
+    assert_eq!(usdc_client.balance(&s.factory.address), 10_000_000 + DEPOSIT_AMOUNT);
+    assert_eq!(balance(&s, &s.factory.address), 0);
+    assert_eq!(s.factory.token_allowance(&buyer, &usdc), 0);
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+    assert_eq!(usdc_client.balance(&recipient), 10_000_000 + DEPOSIT_AMOUNT);
+    assert_eq!(usdc_client.balance(&s.factory.address), 0);
+    assert_eq!(balance(&s, &recipient), 0);
+}
+
+// Token topic of the factory's most recent Approval event
+fn last_approval_token(s: &Setup) -> Address {
+    let (_, topics, _) = s.env.events().all().iter()
+        .filter(|(contract, topics, _)| {
+            *contract == s.factory.address
+                && Symbol::from_val(&s.env, &topics.get(0).unwrap()) == symbol_short!("Approval")
+        })
+        .last()
+        .unwrap();
+    Address::from_val(&s.env, &topics.get(4).unwrap())
+}
+
+#[test]
+fn test_asset_allowance_writes_publish_approval() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let usdc = s.env.register_stellar_asset_contract_v2(Address::generate(&s.env)).address();
+
+    s.factory.approve(&buyer, &5);
+    assert_eq!(last_approval(&s), (buyer.clone(), s.factory.address.clone(), 5));
+    assert_eq!(last_approval_token(&s), s.token);
+
+    StellarAssetClient::new(&s.env, &usdc).mint(&buyer, &10_000_000);
+    StellarAssetClient::new(&s.env, &usdc).mint(&creator, &DEPOSIT_AMOUNT);
+    TokenClient::new(&s.env, &usdc).approve(&buyer, &s.factory.address, &10_000_000, &1_000);
+    s.factory.set_token_deposit(&s.owner, &usdc, &Some(DEPOSIT_AMOUNT));
+    s.factory.approve_token(&buyer, &usdc, &10_000_000);
+    assert_eq!(last_approval(&s), (buyer.clone(), s.factory.address.clone(), 10_000_000));
+    assert_eq!(last_approval_token(&s), usdc);
 
-    let words = client.hello(&String::from_str(&env, "Dev"));
+    // Drawing the grant down and restoring it on cancellation publish the same event
+    let escrow = s.factory.create_src_escrow_with_token(
+        &creator,
+        &hashed_secret(&s.env, &Bytes::from_slice(&s.env, b"secret")),
+        &recipient,
+        &buyer,
+        &usdc,
+        &10_000_000,
+        &src_windows(),
+        &false,
+    );
+    assert_eq!(last_approval(&s), (buyer.clone(), s.factory.address.clone(), 0));
+    assert_eq!(last_approval_token(&s), usdc);
+
+    set_time(&s.env, 3_500);
+    s.factory.cancel_src_escrow(&buyer, &escrow);
+    assert_eq!(last_approval(&s), (buyer.clone(), s.factory.address.clone(), 10_000_000));
+    assert_eq!(last_approval_token(&s), usdc);
+}
+
+#[test]
+fn test_dst_escrow_with_token_refunds_in_that_token() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let usdc = s.env.register_stellar_asset_contract_v2(Address::generate(&s.env)).address();
+    let usdc_client = TokenClient::new(&s.env, &usdc);
+
+    StellarAssetClient::new(&s.env, &usdc).mint(&creator, &(10_000_000 + DEPOSIT_AMOUNT));
+    s.factory.set_token_deposit(&s.owner, &usdc, &Some(DEPOSIT_AMOUNT));
+    let escrow = s.factory.create_dst_escrow_with_token(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &usdc,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    );
+    assert_eq!(s.factory.get_dst_escrow(&escrow).token, usdc);
+    assert_eq!(usdc_client.balance(&creator), 0);
+
+    set_time(&s.env, 3_000);
+    s.factory.cancel_dst_escrow(&creator, &escrow);
+    assert_eq!(usdc_client.balance(&creator), 10_000_000 + DEPOSIT_AMOUNT);
+    assert_eq!(balance(&s, &creator), 0);
+}
+
+#[test]
+fn test_partial_escrows_with_token_settle_in_that_token() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let resolver = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secrets: [Bytes; 4] = core::array::from_fn(|i| Bytes::from_array(&s.env, &[b's', i as u8]));
+    let (root, leaves, nodes) = four_leaf_tree(&s.env, &secrets);
+    let usdc = s.env.register_stellar_asset_contract_v2(Address::generate(&s.env)).address();
+    let usdc_client = TokenClient::new(&s.env, &usdc);
+
+    StellarAssetClient::new(&s.env, &usdc).mint(&buyer, &(10_000_000 + DEPOSIT_AMOUNT));
+    StellarAssetClient::new(&s.env, &usdc).mint(&resolver, &(10_000_000 + DEPOSIT_AMOUNT));
+    usdc_client.approve(&buyer, &s.factory.address, &10_000_000, &1_000);
+    s.factory.set_token_deposit(&s.owner, &usdc, &Some(DEPOSIT_AMOUNT));
+    s.factory.approve_token(&buyer, &usdc, &10_000_000);
+
+    let src = s.factory.create_src_escrow_partial(&buyer, &root, &recipient, &buyer, &usdc, &10_000_000, &src_windows(), &2, &4, &false);
+    let dst = s.factory.create_dst_escrow_partial(&resolver, &root, &buyer, &usdc, &10_000_000, &src_windows(), &2, &4, &None, &false);
+    assert_eq!(s.factory.get_src_escrow(&src).token, usdc);
+    assert_eq!(s.factory.get_dst_escrow(&dst).token, usdc);
+    assert_eq!(s.factory.token_allowance(&buyer, &usdc), 0);
+    assert_eq!(usdc_client.balance(&s.factory.address), 2 * (10_000_000 + DEPOSIT_AMOUNT));
+    assert_eq!(balance(&s, &s.factory.address), 0);
+
+    set_time(&s.env, 1_500);
+    let proof = vec![&s.env, leaves[3].clone(), nodes[0].clone()];
+    s.factory.withdraw_src_escrow_with_proof(&recipient, &src, &secrets[2], &proof);
+    s.factory.withdraw_dst_escrow_with_proof(&resolver, &dst, &secrets[2], &proof);
+    assert_eq!(usdc_client.balance(&recipient), 10_000_000 + DEPOSIT_AMOUNT);
+    assert_eq!(usdc_client.balance(&buyer), 10_000_000);
+    assert_eq!(usdc_client.balance(&resolver), DEPOSIT_AMOUNT);
+    assert_eq!(usdc_client.balance(&s.factory.address), 0);
+    assert_eq!(balance(&s, &recipient), 0);
+}
+
+#[test]
+#[should_panic(expected = "Token not supported")]
+fn test_create_with_unsupported_token_reverts() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let junk = s.env.register_stellar_asset_contract_v2(Address::generate(&s.env)).address();
+
+    StellarAssetClient::new(&s.env, &junk).mint(&creator, &(10_000_000 + DEPOSIT_AMOUNT));
+    s.factory.create_dst_escrow_with_token(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &junk,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    );
+}
+
+#[test]
+fn test_native_allowance_does_not_fund_other_token() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let usdc = s.env.register_stellar_asset_contract_v2(Address::generate(&s.env)).address();
+
+    StellarAssetClient::new(&s.env, &usdc).mint(&buyer, &10_000_000);
+    StellarAssetClient::new(&s.env, &usdc).mint(&creator, &DEPOSIT_AMOUNT);
+    TokenClient::new(&s.env, &usdc).approve(&buyer, &s.factory.address, &10_000_000, &1_000);
+    s.factory.set_token_deposit(&s.owner, &usdc, &Some(DEPOSIT_AMOUNT));
+    s.factory.approve(&buyer, &10_000_000);
+    assert_eq!(s.factory.token_allowance(&buyer, &usdc), 0);
+
+    let result = s.factory.try_create_src_escrow_with_token(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &buyer,
+        &usdc,
+        &10_000_000,
+        &src_windows(),
+        &false,
+    );
+    assert!(result.is_err());
+    assert_eq!(s.factory.allowance(&buyer, &s.factory.address), 10_000_000);
+}
+
+#[test]
+fn test_create_with_native_token_matches_create() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    mint(&s, &creator, 10_000_000 + DEPOSIT_AMOUNT);
+    let escrow = s.factory.create_dst_escrow_with_token(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &s.token,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    );
+    assert_eq!(s.factory.get_dst_escrow(&escrow).token, s.token);
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_dst_escrow(&recipient, &escrow, &secret);
+    assert_eq!(balance(&s, &recipient), 10_000_000 + DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_increase_allowance_from_zero() {
+    let s = setup();
+    let owner = Address::generate(&s.env);
+    let spender = s.factory.address.clone();
+
+    s.factory.increase_allowance(&owner, &spender, &500);
+    assert_eq!(s.factory.allowance(&owner, &spender), 500);
+    s.factory.increase_allowance(&owner, &spender, &300);
+    assert_eq!(s.factory.allowance(&owner, &spender), 800);
+    assert_eq!(s.factory.get_approved_spenders(&owner), vec![&s.env, spender]);
+}
+
+#[test]
+fn test_increase_allowance_rejects_expired_grant_and_overflow() {
+    let s = setup();
+    let owner = Address::generate(&s.env);
+    let spender = s.factory.address.clone();
+
+    // Raising an expired grant would leave it reading zero, so the owner has to approve again
+    s.factory.approve_with_expiration(&owner, &500, &100);
+    set_time(&s.env, 100);
+    assert!(s.factory.try_increase_allowance(&owner, &spender, &300).is_err());
+    assert_eq!(s.factory.allowance(&owner, &spender), 0);
+
+    s.factory.approve(&owner, &i128::MAX);
+    assert_eq!(
+        s.factory.try_increase_allowance(&owner, &spender, &1),
+        escrow_error(EscrowError::InvalidAmount)
+    );
+}
+
+#[test]
+fn test_decrease_allowance_saturates_at_zero() {
+    let s = setup();
+    let owner = Address::generate(&s.env);
+    let spender = s.factory.address.clone();
+
+    s.factory.approve(&owner, &500);
+    s.factory.decrease_allowance(&owner, &spender, &200);
+    assert_eq!(s.factory.allowance(&owner, &spender), 300);
+    s.factory.decrease_allowance(&owner, &spender, &1_000);
+    assert_eq!(s.factory.allowance(&owner, &spender), 0);
+
+    assert_eq!(
+        s.factory.try_increase_allowance(&owner, &spender, &-1),
+        escrow_error(EscrowError::InvalidAmount)
+    );
+}
+
+#[test]
+fn test_create_src_draws_down_adjusted_allowance() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    mint(&s, &buyer, 10_000_000);
+    mint(&s, &creator, DEPOSIT_AMOUNT);
+    TokenClient::new(&s.env, &s.token).approve(&buyer, &s.factory.address, &10_000_000, &1_000);
+    s.factory.increase_allowance(&buyer, &s.factory.address, &15_000_000);
+    s.factory.decrease_allowance(&buyer, &s.factory.address, &3_000_000);
+
+    s.factory.create_src_escrow(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &buyer,
+        &10_000_000,
+        &1_000,
+        &2_000,
+        &3_000,
+        &4_000,
+        &false,
+    );
+    assert_eq!(s.factory.allowance(&buyer, &s.factory.address), 2_000_000);
+    assert_eq!(s.factory.get_consumed(&buyer, &s.factory.address), 10_000_000);
+}
+
+#[test]
+fn test_auto_refund_dst_after_deadline() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    s.factory.set_auto_refund_deadline(&creator, &escrow, &5_000);
+    assert_eq!(s.factory.get_dst_escrow(&escrow).auto_refund_deadline, 5_000);
+
+    set_time(&s.env, 5_000);
+    s.factory.auto_refund_dst(&escrow);
+    assert_escrow_event(&s, (symbol_short!("dst_esc"), symbol_short!("refunded")), &escrow, &creator, &recipient, 10_000_000, 0);
+    assert!(s.factory.get_dst_escrow(&escrow).cancelled);
+    assert_eq!(balance(&s, &creator), 10_000_000 + DEPOSIT_AMOUNT);
+    assert_eq!(balance(&s, &s.factory.address), 0);
+}
+
+#[test]
+#[should_panic(expected = "Auto refund not available")]
+fn test_auto_refund_dst_before_deadline() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    s.factory.set_auto_refund_deadline(&creator, &escrow, &5_000);
+
+    set_time(&s.env, 4_999);
+    s.factory.auto_refund_dst(&escrow);
+}
+
+#[test]
+#[should_panic(expected = "Auto refund not available")]
+fn test_auto_refund_dst_requires_opt_in() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    set_time(&s.env, 1_000_000);
+    s.factory.auto_refund_dst(&escrow);
+}
+
+#[test]
+fn test_auto_refund_dst_rejects_withdrawn_escrow_and_early_deadline() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
     assert_eq!(
-        words,
-        vec![
-            &env,
-            String::from_str(&env, "Hello"),
-            String::from_str(&env, "Dev"),
-        ]
+        s.factory.try_set_auto_refund_deadline(&creator, &escrow, &2_999),
+        escrow_error(EscrowError::InvalidTimeWindows)
+    );
+    s.factory.set_auto_refund_deadline(&creator, &escrow, &3_000);
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_dst_escrow(&recipient, &escrow, &secret);
+    set_time(&s.env, 3_000);
+    assert_eq!(s.factory.try_auto_refund_dst(&escrow), escrow_error(EscrowError::AlreadyWithdrawn));
+}
+
+#[test]
+fn test_dst_cancellation_private_then_public() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let keeper = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+
+    // Private cancellation window: only the creator
+    set_time(&s.env, 3_000);
+    assert!(s.factory.try_cancel_dst_escrow(&keeper, &escrow).is_err());
+
+    // Public cancellation window: anyone, and the keeper collects the deposit
+    set_time(&s.env, 4_000);
+    s.factory.cancel_dst_escrow(&keeper, &escrow);
+    assert!(s.factory.get_dst_escrow(&escrow).cancelled);
+    assert_eq!(balance(&s, &creator), 10_000_000);
+    assert_eq!(balance(&s, &keeper), DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_create_dst_rejects_public_cancellation_before_cancellation() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let hashed = hashed_secret(&s.env, &Bytes::from_slice(&s.env, b"secret"));
+
+    mint(&s, &creator, 10_000_000 + DEPOSIT_AMOUNT);
+    let result = s.factory.try_create_dst_escrow(&creator, &hashed, &recipient, &10_000_000, &1_000, &2_000, &3_000, &3_000, &false);
+    assert_eq!(result, escrow_error(EscrowError::InvalidTimeWindows));
+}
+
+#[test]
+fn test_create_dst_partial_uses_given_public_cancellation_start() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let hashed = hashed_secret(&s.env, &Bytes::from_slice(&s.env, b"secret"));
+
+    mint(&s, &creator, 10_000_000 + DEPOSIT_AMOUNT);
+    let windows = TimeWindows { public_cancellation_start: 9_000, ..src_windows() };
+    let escrow = s.factory.create_dst_escrow_partial(&creator, &hashed, &recipient, &s.token, &10_000_000, &windows, &1, &4, &None, &false);
+    assert_eq!(s.factory.get_dst_escrow(&escrow).public_cancellation_start, 9_000);
+
+    let windows = TimeWindows { public_cancellation_start: 2_500, ..src_windows() };
+    let result = s.factory.try_create_dst_escrow_partial(&creator, &hashed, &recipient, &s.token, &10_000_000, &windows, &2, &4, &None, &false);
+    assert_eq!(result, escrow_error(EscrowError::InvalidTimeWindows));
+}
+
+#[test]
+fn test_create_dst_charges_amount_plus_deposit() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    mint(&s, &creator, 5_000);
+    create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+    assert_eq!(balance(&s, &creator), 5_000);
+    assert_eq!(balance(&s, &s.factory.address), 10_000_000 + DEPOSIT_AMOUNT);
+
+    mint(&s, &creator, 10_000_000 + DEPOSIT_AMOUNT);
+    s.factory.create_dst_escrow_partial(
+        &creator,
+        &hashed_secret(&s.env, &secret),
+        &recipient,
+        &s.token,
+        &10_000_000,
+        &src_windows(),
+        &1,
+        &4,
+        &None,
+        &false,
     );
+    assert_eq!(balance(&s, &creator), 5_000);
+    assert_eq!(balance(&s, &s.factory.address), 2 * (10_000_000 + DEPOSIT_AMOUNT));
+}
+
+#[test]
+fn test_withdraw_stores_revealed_secret() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"a 32-byte swap secret, revealed!");
+
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    assert_eq!(s.factory.get_revealed_secret(&escrow), None);
+
+    set_time(&s.env, 1_000);
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+    let revealed = s.factory.get_revealed_secret(&escrow).unwrap();
+    assert_eq!(revealed.to_buffer::<64>().as_slice(), b"a 32-byte swap secret, revealed!");
+}
+
+#[test]
+#[should_panic(expected = "Already initialized")]
+fn test_initialize_twice_reverts() {
+    let s = setup();
+    let attacker = Address::generate(&s.env);
+    s.factory.initialize(&s.token, &attacker, &None, &0, &0);
+}
+
+#[test]
+fn test_failed_src_withdraw_attempts_are_counted() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 1_500);
+    for attempt in 1..=3u32 {
+        let guess = Bytes::from_array(&s.env, &[b'g', attempt as u8]);
+        assert!(!s.factory.attempt_withdraw_src_escrow(&recipient, &escrow, &guess));
+        assert_eq!(s.factory.get_failed_attempts(&escrow), attempt);
+    }
+    assert!(!s.factory.get_src_escrow(&escrow).funds_withdrawn);
+
+    // A reverting withdraw with a wrong secret leaves no count behind
+    assert!(s.factory.try_withdraw_src_escrow(&recipient, &escrow, &Bytes::from_slice(&s.env, b"wrong")).is_err());
+    assert_eq!(s.factory.get_failed_attempts(&escrow), 3);
+
+    assert!(s.factory.attempt_withdraw_src_escrow(&recipient, &escrow, &secret));
+    assert!(s.factory.get_src_escrow(&escrow).funds_withdrawn);
+    assert_eq!(balance(&s, &recipient), 10_000_000 + DEPOSIT_AMOUNT);
+    assert_eq!(s.factory.get_failed_attempts(&escrow), 3);
+}
+
+#[test]
+fn test_failed_dst_withdraw_attempts_are_counted() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_dst(&s, &creator, &recipient, &secret, 10_000_000);
+
+    set_time(&s.env, 1_500);
+    assert!(!s.factory.attempt_withdraw_dst_escrow(&creator, &escrow, &Bytes::from_slice(&s.env, b"guess1")));
+    assert!(!s.factory.attempt_withdraw_dst_escrow(&creator, &escrow, &Bytes::from_slice(&s.env, b"guess2")));
+    assert_eq!(s.factory.get_failed_attempts(&escrow), 2);
+
+    assert!(s.factory.attempt_withdraw_dst_escrow(&creator, &escrow, &secret));
+    assert!(s.factory.get_dst_escrow(&escrow).funds_withdrawn);
+    assert_eq!(balance(&s, &recipient), 10_000_000);
+}
+
+#[test]
+fn test_attempt_withdraw_still_reverts_on_other_failures() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+
+    // Before the withdrawal window opens the attempt reverts and is not counted
+    set_time(&s.env, 500);
+    let result = s.factory.try_attempt_withdraw_src_escrow(&recipient, &escrow, &Bytes::from_slice(&s.env, b"guess"));
+    assert_eq!(result, escrow_error(EscrowError::WithdrawalNotStarted));
+    assert_eq!(s.factory.get_failed_attempts(&escrow), 0);
+}
+
+#[test]
+fn test_net_resolver_amount() {
+    let s = setup();
+    let creator = Address::generate(&s.env);
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    s.factory.set_gas_deposit(&buyer, &s.token, &500);
+    let escrow = create_src(&s, &creator, &buyer, &recipient, &secret, 10_000_000);
+    assert_eq!(s.factory.net_resolver_amount(&escrow), 10_000_000 + DEPOSIT_AMOUNT + 500);
+
+    set_time(&s.env, 1_500);
+    s.factory.withdraw_src_escrow(&recipient, &escrow, &secret);
+    assert_eq!(balance(&s, &recipient), s.factory.net_resolver_amount(&escrow));
+    assert_eq!(balance(&s, &s.factory.address), 0);
+}
+
+#[test]
+fn test_net_resolver_amount_matches_proof_withdrawal() {
+    let s = setup();
+    let buyer = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    let secrets: [Bytes; 4] = core::array::from_fn(|i| Bytes::from_array(&s.env, &[b's', i as u8]));
+    let (root, leaves, nodes) = four_leaf_tree(&s.env, &secrets);
+
+    let escrow = create_src_partial(&s, &buyer, &recipient, &root, 10_000_000, 2, 4);
+    assert_eq!(s.factory.net_resolver_amount(&escrow), 10_000_000 + DEPOSIT_AMOUNT);
+
+    set_time(&s.env, 1_500);
+    let proof = vec![&s.env, leaves[3].clone(), nodes[0].clone()];
+    s.factory.withdraw_src_escrow_with_proof(&recipient, &escrow, &secrets[2], &proof);
+    assert_eq!(balance(&s, &recipient), 10_000_000 + DEPOSIT_AMOUNT);
 }
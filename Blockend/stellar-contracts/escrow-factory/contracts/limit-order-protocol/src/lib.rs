@@ -1,6 +1,8 @@
 #![no_std]
+#![allow(clippy::too_many_arguments)]
 use soroban_sdk::{
-    contract, contractimpl, contracttype, contractclient, Address, BytesN, Env, Vec, log,
+    contract, contractevent, contractimpl, contracttype, contractclient, crypto::Hash, xdr::ToXdr, Address, Bytes,
+    BytesN, Env, Symbol, Vec, log,
 };
 
 // EscrowFactory client interface
@@ -12,94 +14,1053 @@ pub trait EscrowFactoryTrait {
         hashed_secret: BytesN<32>,
         recipient: Address,
         buyer: Address,
+        token: Address,
         token_amount: i128,
-        withdrawal_start: u64,
-        public_withdrawal_start: u64,
-        cancellation_start: u64,
+        windows: TimeWindows,
         part_index: u64,
         total_parts: u32,
-    ) -> Address;
+        require_dual_auth: bool,
+    ) -> BytesN<32>;
     
-    fn get_user_escrows(env: Env, user: Address) -> Vec<Address>;
+    fn create_dst_escrow(
+        env: Env,
+        creator: Address,
+        hashed_secret: BytesN<32>,
+        recipient: Address,
+        token_amount: i128,
+        withdrawal_start: u64,
+        public_withdrawal_start: u64,
+        cancellation_start: u64,
+        public_cancellation_start: u64,
+        require_dual_auth: bool,
+    ) -> BytesN<32>;
+
+    fn get_user_escrows(env: Env, user: Address) -> Vec<BytesN<32>>;
     fn get_deposit_amount(env: Env) -> i128;
-    fn cancel_src_escrow(env: Env, caller: Address, escrow_address: Address);
+    fn get_native_token(env: Env) -> Address;
+    fn cancel_src_escrow(env: Env, caller: Address, escrow_id: BytesN<32>);
+    fn public_cancel_src_escrow(env: Env, caller: Address, escrow_id: BytesN<32>);
+    fn get_src_deposit_held(env: Env, escrow_id: BytesN<32>) -> i128;
+}
+
+// Maker-registered contract notified after each of the maker's order parts is filled
+#[contractclient(name = "FillHookClient")]
+pub trait FillHook {
+    fn on_order_filled(env: Env, order_hash: BytesN<32>, part_index: u64, escrow_id: BytesN<32>);
+}
+
+// Emitted on every allowance change, the same event the EscrowFactory publishes for its allowances
+// Topics ("Approval", namespace, owner, spender, token), data is the new allowance
+#[contractevent(topics = ["Approval"], data_format = "single-value")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Approval {
+    #[topic]
+    pub namespace: Symbol,
+    #[topic]
+    pub owner: Address,
+    #[topic]
+    pub spender: Address,
+    #[topic]
+    pub token: Address,
+    pub amount: i128,
+}
+
+// Escrow time windows (matching the EscrowFactory)
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TimeWindows {
+    pub withdrawal_start: u64,
+    pub public_withdrawal_start: u64,
+    pub cancellation_start: u64,
+    pub public_cancellation_start: u64,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FilledOrder {
+    pub order_hash: BytesN<32>,
+    pub maker: Address,
+    pub taker: Address,
+    pub recipient: Address,
+    pub escrow_id: BytesN<32>,
+    pub part_index: u64,
+    pub total_parts: u32,
+    pub is_active: bool,
+    pub amount: i128, // tokens escrowed for this part
+    pub grant_epoch: u64, // maker's approval count when filled; the allowance is only restored under the same grant
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderParams {
+    pub token_amount: i128,
+    pub hashed_secret: BytesN<32>,
+    pub withdrawal_start: u64,
+    pub public_withdrawal_start: u64,
+    pub cancellation_start: u64,
+    pub part_index: u64,
+    pub total_parts: u32,
+}
+
+// Full-fill order posted by its maker for peer-to-peer matching
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PostedOrder {
+    pub maker: Address,
+    pub recipient: Address,
+    pub token_amount: i128,
+    pub hashed_secret: BytesN<32>,
+    pub withdrawal_start: u64,
+    pub public_withdrawal_start: u64,
+    pub cancellation_start: u64, // used when matched as the destination leg
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderStats {
+    pub total_parts_filled: u64,
+    pub total_parts_cancelled: u64,
+    pub first_fill_time: u64,
+    pub last_fill_time: u64,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderProgress {
+    pub filled_count: u64,
+    pub remaining_count: u64,
+    pub filled_indices: Vec<u64>,
+    pub remaining_indices: Vec<u64>,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PartReservation {
+    pub reserver: Address,
+    pub expires_at: u64,
+}
+
+// Order signed on an EVM chain, mirroring the 1inch LOP v4 Order struct
+// uint256 amounts are carried as u128; addresses are raw 20-byte EVM addresses
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EvmOrder {
+    pub salt: BytesN<32>,
+    pub maker: BytesN<20>,
+    pub receiver: BytesN<20>,
+    pub maker_asset: BytesN<20>,
+    pub taker_asset: BytesN<20>,
+    pub making_amount: u128,
+    pub taking_amount: u128,
+    pub maker_traits: BytesN<32>,
+}
+
+#[contracttype]
+pub enum DataKey {
+    // Order tracking - non-sequential support
+    FilledOrders(BytesN<32>), // orderHash -> Vec<FilledOrder>
+    PartsFilled(BytesN<32>, u64), // (orderHash, partIndex) -> bool
+    FilledSegmentsCount(BytesN<32>), // orderHash -> count
+    UserFilledOrders(Address), // user -> Vec<orderHash>
+    TakerFilledOrder(Address, BytesN<32>), // (taker, orderHash) -> taker has filled a part of the order
+    TakerFilledCount(Address), // taker -> number of distinct orders it has filled parts of
+    TakerFilledOrderAt(Address, u32), // (taker, position) -> orderHash, in first-fill order
+    EscrowFactory, // factory contract address
+    Owner, // contract owner
+    // Add authorization storage - equivalent to EVM's allowances mapping
+    TokenAllowance(Address, Address), // (token_owner, spender) -> amount
+    AllowanceConsumed(Address, Address), // (token_owner, spender) -> amount drawn since the last approval
+    AllowanceEpoch(Address), // token_owner -> number of approvals it has granted the LOP
+    OrderStats(BytesN<32>), // orderHash -> OrderStats
+    PartReservation(BytesN<32>, u64), // (orderHash, partIndex) -> PartReservation (temporary)
+    ReservedParts(BytesN<32>, Address), // (orderHash, reserver) -> part indices it has reserved (temporary)
+    FillHook(Address), // maker -> contract notified on fills
+    OrderTotalParts(BytesN<32>), // orderHash -> total_parts fixed by the first fill
+    DomainSeparator, // EIP-712 domain separator of the EVM-side protocol
+    EvmSigner(Address), // maker -> EVM address allowed to sign orders on its behalf
+    MaxFills(BytesN<32>), // orderHash -> cap on the number of parts that may be filled
+    EventNamespace, // namespace carried in event topics (defaults to DEFAULT_EVENT_NAMESPACE)
+    PostedOrder(BytesN<32>), // orderHash -> PostedOrder awaiting a peer-to-peer match
+    DepositExposure, // i128 - security deposit the LOP has posted into source escrows not yet released
+    SrcEscrowDeposit(BytesN<32>), // source escrow created by a fill -> deposit the LOP posted, until released
+    NewOrdersPaused, // bool - blocks new orders while orders already posted or filled stay fillable
 }
 
-#[contracttype]
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct FilledOrder {
-    pub order_hash: BytesN<32>,
-    pub maker: Address,
-    pub recipient: Address,
-    pub escrow_address: Address,
-    pub part_index: u64,
-    pub total_parts: u32,
-    pub is_active: bool,
-}
+// EIP-712 type string of EvmOrder
+const EVM_ORDER_TYPE: &[u8] = b"Order(uint256 salt,address maker,address receiver,address makerAsset,address takerAsset,uint256 makingAmount,uint256 takingAmount,uint256 makerTraits)";
+
+// Approximate ledger close time, used to size reservation TTLs
+const LEDGER_SECONDS: u64 = 5;
+// Longest a part may be held by reserve_part; keeps one taker from locking a part indefinitely
+const MAX_RESERVATION_TTL: u64 = 3600;
+// Most live reservations one taker may hold on a single order
+const MAX_RESERVATIONS_PER_RESERVER: u32 = 5;
+// Largest input accepted by batch functions; each item costs a cross-contract call into the factory,
+// so this matches the factory's withdrawal batch cap rather than its larger cap for read-only views
+const MAX_BATCH_SIZE: u32 = 20;
+// Largest part count get_order_progress will enumerate
+const MAX_PROGRESS_PARTS: u32 = 100;
+// Delay after withdrawal_start at which a filled source escrow becomes cancellable
+const SRC_CANCELLATION_DELAY: u64 = 86400;
+// Delay after cancellation_start at which anyone may cancel an escrow this contract creates
+const SRC_PUBLIC_CANCELLATION_DELAY: u64 = 3600;
+// Event namespace when none is set at initialize
+const DEFAULT_EVENT_NAMESPACE: &str = "fusion";
+
+#[contract]
+pub struct SimpleLimitOrderProtocol;
+
+#[contractimpl]
+impl SimpleLimitOrderProtocol {
+    /// Initialize the protocol with the escrow factory address and owner
+    /// event_namespace is carried in every event's topics so indexers can tell deployments apart
+    pub fn initialize(env: Env, escrow_factory: Address, owner: Address, event_namespace: Option<Symbol>) {
+        // Initializing again would let anyone take over the owner role and repoint the factory
+        if env.storage().instance().has(&DataKey::Owner) {
+            panic!("Already initialized");
+        }
+        env.storage().instance().set(&DataKey::EscrowFactory, &escrow_factory);
+        env.storage().instance().set(&DataKey::Owner, &owner);
+        if let Some(namespace) = event_namespace {
+            env.storage().instance().set(&DataKey::EventNamespace, &namespace);
+        }
+    }
+
+    /// Get the namespace carried in this deployment's event topics
+    pub fn get_event_namespace(env: Env) -> Symbol {
+        env.storage()
+            .instance()
+            .get(&DataKey::EventNamespace)
+            .unwrap_or_else(|| Symbol::new(&env, DEFAULT_EVENT_NAMESPACE))
+    }
+
+    /// Approve LOP to spend tokens (equivalent to ERC20 approve() in EVM)
+    /// This allows the LOP to transfer tokens on behalf of the caller
+    pub fn approve(env: Env, caller: Address, amount: i128) {
+        caller.require_auth();
+        
+        // Store the allowance - LOP can spend up to 'amount' tokens from caller
+        Self::set_allowance(&env, &caller, &env.current_contract_address(), amount);
+        // A new grant starts with nothing consumed
+        env.storage().persistent().remove(
+            &DataKey::AllowanceConsumed(caller.clone(), env.current_contract_address())
+        );
+        let epoch = Self::allowance_epoch(&env, &caller);
+        env.storage().persistent().set(&DataKey::AllowanceEpoch(caller.clone()), &(epoch + 1));
+        
+        log!(&env, "LOP Approval: owner={}, spender={}, amount={}", 
+             caller, env.current_contract_address(), amount);
+    }
+
+    /// Get current allowance (equivalent to ERC20 allowance() in EVM)
+    pub fn allowance(env: Env, owner: Address, spender: Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::TokenAllowance(owner, spender))
+            .unwrap_or(0)
+    }
+
+    /// Get how much of the current grant the spender has drawn; consumed + allowance is the amount approved
+    pub fn get_consumed(env: Env, owner: Address, spender: Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::AllowanceConsumed(owner, spender))
+            .unwrap_or(0)
+    }
+
+    /// Reserve an unfilled part so only the reserver can fill it for the next `ttl` seconds
+    /// Closes the race between checking `is_part_available` and filling the part
+    /// `ttl` is capped at MAX_RESERVATION_TTL, and a taker may hold at most
+    /// MAX_RESERVATIONS_PER_RESERVER live reservations per order
+    pub fn reserve_part(env: Env, caller: Address, order_hash: BytesN<32>, part_index: u64, ttl: u64) {
+        caller.require_auth();
+
+        if ttl == 0 {
+            panic!("TTL must be > 0");
+        }
+        if ttl > MAX_RESERVATION_TTL {
+            panic!("TTL too long");
+        }
+        if !Self::is_part_available(env.clone(), order_hash.clone(), part_index) {
+            panic!("Part already filled");
+        }
+        if let Some(reserver) = Self::get_part_reservation(env.clone(), order_hash.clone(), part_index) {
+            if reserver != caller {
+                panic!("Part reserved");
+            }
+        }
+
+        // Cap the live reservations the caller holds on this order; expired, filled or
+        // released parts drop out of the list here, and re-reserving a part does not count twice
+        let parts_key = DataKey::ReservedParts(order_hash.clone(), caller.clone());
+        let held: Vec<u64> = env.storage().temporary().get(&parts_key).unwrap_or(Vec::new(&env));
+        let mut reserved = Vec::new(&env);
+        for index in held.iter() {
+            if index != part_index
+                && Self::get_part_reservation(env.clone(), order_hash.clone(), index) == Some(caller.clone())
+            {
+                reserved.push_back(index);
+            }
+        }
+        if reserved.len() >= MAX_RESERVATIONS_PER_RESERVER {
+            panic!("Too many reservations");
+        }
+        reserved.push_back(part_index);
+
+        let expires_at = env.ledger().timestamp().checked_add(ttl).expect("TTL too long");
+        let reservation = PartReservation {
+            reserver: caller.clone(),
+            expires_at,
+        };
+        let key = DataKey::PartReservation(order_hash.clone(), part_index);
+        let ledgers = u32::try_from(ttl / LEDGER_SECONDS + 1).expect("TTL too long");
+        env.storage().temporary().set(&key, &reservation);
+        env.storage().temporary().extend_ttl(&key, ledgers, ledgers);
+
+        // The list must outlive every reservation in it
+        let max_ledgers = (MAX_RESERVATION_TTL / LEDGER_SECONDS + 1) as u32;
+        env.storage().temporary().set(&parts_key, &reserved);
+        env.storage().temporary().extend_ttl(&parts_key, max_ledgers, max_ledgers);
+
+        log!(&env, "PartReserved: orderHash={}, partIndex={}, reserver={}, expiresAt={}", 
+             order_hash, part_index, caller, reservation.expires_at);
+    }
+
+    /// Get the current reserver of a part, if the reservation has not expired
+    pub fn get_part_reservation(env: Env, order_hash: BytesN<32>, part_index: u64) -> Option<Address> {
+        let reservation: Option<PartReservation> = env.storage()
+            .temporary()
+            .get(&DataKey::PartReservation(order_hash, part_index));
+        match reservation {
+            Some(r) if r.expires_at > env.ledger().timestamp() => Some(r.reserver),
+            _ => None,
+        }
+    }
+
+    /// Register (or clear) a hook notified via on_order_filled whenever one of the maker's orders is filled
+    pub fn set_fill_hook(env: Env, maker: Address, fill_hook: Option<Address>) {
+        maker.require_auth();
+
+        match &fill_hook {
+            Some(hook) => env.storage().persistent().set(&DataKey::FillHook(maker.clone()), hook),
+            None => env.storage().persistent().remove(&DataKey::FillHook(maker.clone())),
+        }
+
+        log!(&env, "FillHookSet: maker={}, hook={}", maker, fill_hook);
+    }
+
+    /// Get the fill hook registered by a maker
+    pub fn get_fill_hook(env: Env, maker: Address) -> Option<Address> {
+        env.storage().persistent().get(&DataKey::FillHook(maker))
+    }
+
+    /// Cap (or uncap with None) how many parts of the maker's order may be filled, regardless of total_parts
+    /// The order is given by its fields so the cap is bound to the maker through compute_order_hash
+    pub fn set_max_fills(
+        env: Env,
+        maker: Address,
+        recipient: Address,
+        token_amount: i128,
+        hashed_secret: BytesN<32>,
+        total_parts: u32,
+        nonce: u64,
+        max_fills: Option<u32>,
+    ) {
+        maker.require_auth();
+
+        let order_hash = Self::compute_order_hash(
+            env.clone(),
+            maker.clone(),
+            recipient,
+            token_amount,
+            hashed_secret,
+            total_parts,
+            nonce,
+        );
+        match max_fills {
+            Some(max) => env.storage().persistent().set(&DataKey::MaxFills(order_hash.clone()), &max),
+            None => env.storage().persistent().remove(&DataKey::MaxFills(order_hash.clone())),
+        }
+
+        log!(&env, "MaxFillsSet: orderHash={}, maker={}, maxFills={}", order_hash, maker, max_fills);
+    }
+
+    /// Get the fill cap of an order, if the maker set one
+    pub fn get_max_fills(env: Env, order_hash: BytesN<32>) -> Option<u32> {
+        env.storage().persistent().get(&DataKey::MaxFills(order_hash))
+    }
+
+    /// Canonical order hash: sha256(maker || recipient || token_amount || hashed_secret || total_parts || nonce)
+    /// Addresses are XDR-encoded; integers are big endian (i128: 16 bytes, u32: 4 bytes, u64: 8 bytes)
+    pub fn compute_order_hash(
+        env: Env,
+        maker: Address,
+        recipient: Address,
+        token_amount: i128,
+        hashed_secret: BytesN<32>,
+        total_parts: u32,
+        nonce: u64,
+    ) -> BytesN<32> {
+        let mut packed = Bytes::new(&env);
+        packed.append(&maker.to_xdr(&env));
+        packed.append(&recipient.to_xdr(&env));
+        packed.extend_from_array(&token_amount.to_be_bytes());
+        packed.extend_from_array(&hashed_secret.to_array());
+        packed.extend_from_array(&total_parts.to_be_bytes());
+        packed.extend_from_array(&nonce.to_be_bytes());
+        env.crypto().sha256(&packed).into()
+    }
+
+    /// Stop accepting new orders (owner only): post_order and first fills revert, while orders
+    /// already posted or partly filled can still be matched and filled so in-flight swaps complete
+    pub fn pause_new_orders(env: Env, caller: Address) {
+        Self::set_new_orders_paused(&env, &caller, true);
+    }
+
+    /// Accept new orders again (owner only)
+    pub fn resume_new_orders(env: Env, caller: Address) {
+        Self::set_new_orders_paused(&env, &caller, false);
+    }
+
+    /// Whether new orders are currently paused
+    pub fn is_new_orders_paused(env: Env) -> bool {
+        env.storage().instance().get(&DataKey::NewOrdersPaused).unwrap_or(false)
+    }
+
+    /// Set the EIP-712 domain separator signed orders are verified against (owner only)
+    pub fn set_domain_separator(env: Env, caller: Address, domain_separator: BytesN<32>) {
+        caller.require_auth();
+
+        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
+        if caller != owner {
+            panic!("Only owner can set domain");
+        }
+        env.storage().instance().set(&DataKey::DomainSeparator, &domain_separator);
+
+        log!(&env, "DomainSeparatorUpdated: domainSeparator={}", domain_separator);
+    }
+
+    /// Get the EIP-712 domain separator, if set
+    pub fn get_domain_separator(env: Env) -> Option<BytesN<32>> {
+        env.storage().instance().get(&DataKey::DomainSeparator)
+    }
+
+    /// Link the maker to the EVM address that signs its orders (None to unlink)
+    pub fn register_evm_signer(env: Env, maker: Address, evm_signer: Option<BytesN<20>>) {
+        maker.require_auth();
+
+        match &evm_signer {
+            Some(signer) => env.storage().persistent().set(&DataKey::EvmSigner(maker.clone()), signer),
+            None => env.storage().persistent().remove(&DataKey::EvmSigner(maker.clone())),
+        }
+
+        log!(&env, "EvmSignerUpdated: maker={}, signer={}", maker, evm_signer);
+    }
+
+    /// Get the EVM address registered to sign the maker's orders
+    pub fn get_evm_signer(env: Env, maker: Address) -> Option<BytesN<20>> {
+        env.storage().persistent().get(&DataKey::EvmSigner(maker))
+    }
+
+    /// EIP-712 digest of an EVM order: keccak256(0x1901 || domain_separator || hashStruct(order))
+    /// Matches eth_signTypedData_v4, so a signature from an EVM wallet verifies against it
+    pub fn compute_typed_order_digest(env: Env, domain_separator: BytesN<32>, order: EvmOrder) -> BytesN<32> {
+        Self::typed_order_digest(&env, &domain_separator, &order).into()
+    }
+
+    /// keccak256(hashed_secret || recipient XDR): the Stellar-side fill parameters a signed order commits to
+    /// Like a 1inch order's extension hash, its low 160 bits go in the low 160 bits of the order's salt
+    pub fn compute_fill_extension_hash(env: Env, hashed_secret: BytesN<32>, recipient: Address) -> BytesN<32> {
+        Self::fill_extension_hash(&env, &hashed_secret, &recipient).into()
+    }
+
+    /// Fill an order by creating an escrow - supports non-sequential partial fills  
+    /// The order hash is derived from the order fields with compute_order_hash, so it always matches the canonical form
+    pub fn fill_order(
+        env: Env,
+        taker: Address,
+        maker: Address,
+        recipient: Address,
+        token_amount: i128,
+        hashed_secret: BytesN<32>,
+        withdrawal_start: u64,
+        public_withdrawal_start: u64,
+        part_index: u64,
+        total_parts: u32,
+        nonce: u64,
+    ) -> BytesN<32> {
+        // Validate inputs
+        if total_parts == 0 {
+            panic!("Total parts must be > 0");
+        }
+        let order_hash = Self::compute_order_hash(
+            env.clone(),
+            maker.clone(),
+            recipient.clone(),
+            token_amount,
+            hashed_secret.clone(),
+            total_parts,
+            nonce,
+        );
+        Self::check_order_accepted(&env, &order_hash);
+        Self::fill_part(
+            env,
+            order_hash,
+            taker,
+            maker,
+            recipient,
+            token_amount,
+            hashed_secret,
+            withdrawal_start,
+            public_withdrawal_start,
+            part_index,
+            total_parts,
+        )
+    }
+
+    /// Fill part of an order signed on an EVM chain (EIP-712), identified by its typed-data digest
+    /// The signature (r || s || v) must recover to order.maker, and maker must have registered that
+    /// EVM address with register_evm_signer. Each part escrows making_amount / total_parts
+    /// hashed_secret and recipient must match the commitment in the order's salt (compute_fill_extension_hash)
+    pub fn fill_order_signed(
+        env: Env,
+        taker: Address,
+        maker: Address,
+        recipient: Address,
+        order: EvmOrder,
+        signature: BytesN<65>,
+        hashed_secret: BytesN<32>,
+        withdrawal_start: u64,
+        public_withdrawal_start: u64,
+        part_index: u64,
+        total_parts: u32,
+    ) -> BytesN<32> {
+        if total_parts == 0 {
+            panic!("Total parts must be > 0");
+        }
+        if Self::get_evm_signer(env.clone(), maker.clone()) != Some(order.maker.clone()) {
+            panic!("Signer not registered");
+        }
+
+        let domain_separator = Self::get_domain_separator(env.clone())
+            .unwrap_or_else(|| panic!("Domain separator not set"));
+        let digest = Self::typed_order_digest(&env, &domain_separator, &order);
+        if Self::recover_evm_signer(&env, &digest, &signature) != order.maker {
+            panic!("Invalid signature");
+        }
+
+        // The taker supplies hashed_secret and recipient, so they are only trusted if the maker signed them
+        let extension_hash = Self::fill_extension_hash(&env, &hashed_secret, &recipient).to_array();
+        if order.salt.to_array()[12..] != extension_hash[12..] {
+            panic!("Extension mismatch");
+        }
+
+        if !order.making_amount.is_multiple_of(total_parts as u128) {
+            panic!("Amount not divisible by parts");
+        }
+        let token_amount = i128::try_from(order.making_amount / total_parts as u128)
+            .unwrap_or_else(|_| panic!("Amount too large"));
+        Self::check_order_accepted(&env, &digest.to_bytes());
+
+        Self::fill_part(
+            env,
+            digest.into(),
+            taker,
+            maker,
+            recipient,
+            token_amount,
+            hashed_secret,
+            withdrawal_start,
+            public_withdrawal_start,
+            part_index,
+            total_parts,
+        )
+    }
+
+    /// Post a full-fill order for peer-to-peer matching with match_orders; returns its order hash
+    /// The maker's authorization here is its consent to any compatible match
+    pub fn post_order(
+        env: Env,
+        maker: Address,
+        recipient: Address,
+        token_amount: i128,
+        hashed_secret: BytesN<32>,
+        withdrawal_start: u64,
+        public_withdrawal_start: u64,
+        cancellation_start: u64,
+        nonce: u64,
+    ) -> BytesN<32> {
+        if token_amount <= 0 {
+            panic!("Token amount must be > 0");
+        }
+        if Self::is_new_orders_paused(env.clone()) {
+            panic!("New orders paused");
+        }
+        maker.require_auth();
+
+        let order_hash = Self::compute_order_hash(
+            env.clone(),
+            maker.clone(),
+            recipient.clone(),
+            token_amount,
+            hashed_secret.clone(),
+            1,
+            nonce,
+        );
+        let order = PostedOrder {
+            maker: maker.clone(),
+            recipient,
+            token_amount,
+            hashed_secret,
+            withdrawal_start,
+            public_withdrawal_start,
+            cancellation_start,
+        };
+        env.storage().persistent().set(&DataKey::PostedOrder(order_hash.clone()), &order);
+
+        log!(&env, "OrderPosted: orderHash={}, maker={}, amount={}", order_hash, maker, token_amount);
+
+        order_hash
+    }
+
+    /// Get a posted order that has not been matched yet
+    pub fn get_posted_order(env: Env, order_hash: BytesN<32>) -> Option<PostedOrder> {
+        env.storage().persistent().get(&DataKey::PostedOrder(order_hash))
+    }
+
+    /// Match two posted orders without a resolver: the source order is filled from its maker's allowance
+    /// and the destination maker funds the opposite escrow; returns (source escrow, destination escrow)
+    /// Each order must pay the other maker, share the hashlock and amount, and the destination leg must
+    /// become cancellable before the source leg does. Both makers must authorize the match: the source maker
+    /// directly, the destination maker as the source fill's taker and as creator of the destination escrow
+    /// Not blocked by pause_new_orders: both orders were posted before the pause, which treats them as in flight
+    pub fn match_orders(env: Env, src_order_hash: BytesN<32>, dst_order_hash: BytesN<32>) -> (BytesN<32>, BytesN<32>) {
+        let src = Self::get_posted_order(env.clone(), src_order_hash.clone())
+            .unwrap_or_else(|| panic!("Order not posted"));
+        let dst = Self::get_posted_order(env.clone(), dst_order_hash.clone())
+            .unwrap_or_else(|| panic!("Order not posted"));
+        // The source maker authorizes here; the destination maker's authorization is required by fill_part
+        // (as taker) and by the factory's create_dst_escrow (as creator)
+        src.maker.require_auth();
+
+        if src.recipient != dst.maker || dst.recipient != src.maker {
+            panic!("Counterparty mismatch");
+        }
+        if src.hashed_secret != dst.hashed_secret {
+            panic!("Hashlock mismatch");
+        }
+        if src.token_amount != dst.token_amount {
+            panic!("Amount mismatch");
+        }
+        // Both legs' later windows are derived from the posted timestamps, so overflow is rejected up front
+        let src_cancellation_start = src.withdrawal_start
+            .checked_add(SRC_CANCELLATION_DELAY)
+            .expect("Time window overflow");
+        let dst_public_cancellation_start = dst.cancellation_start
+            .checked_add(SRC_PUBLIC_CANCELLATION_DELAY)
+            .expect("Time window overflow");
+        if dst.cancellation_start >= src_cancellation_start {
+            panic!("Timing mismatch");
+        }
+
+        // Both orders leave the book before the legs are created
+        env.storage().persistent().remove(&DataKey::PostedOrder(src_order_hash.clone()));
+        env.storage().persistent().remove(&DataKey::PostedOrder(dst_order_hash.clone()));
+
+        let src_escrow = Self::fill_part(
+            env.clone(),
+            src_order_hash.clone(),
+            dst.maker.clone(),
+            src.maker.clone(),
+            src.recipient.clone(),
+            src.token_amount,
+            src.hashed_secret.clone(),
+            src.withdrawal_start,
+            src.public_withdrawal_start,
+            0,
+            1,
+        );
+
+        let factory_address: Address = env.storage().instance().get(&DataKey::EscrowFactory).unwrap();
+        let factory_client = EscrowFactoryTraitClient::new(&env, &factory_address);
+        let dst_escrow = factory_client.create_dst_escrow(
+            &dst.maker,
+            &dst.hashed_secret,
+            &dst.recipient,
+            &dst.token_amount,
+            &dst.withdrawal_start,
+            &dst.public_withdrawal_start,
+            &dst.cancellation_start,
+            &dst_public_cancellation_start,
+            &false,
+        );
+
+        log!(&env, "OrdersMatched: srcOrderHash={}, dstOrderHash={}, srcEscrow={}, dstEscrow={}", 
+             src_order_hash, dst_order_hash, src_escrow, dst_escrow);
+
+        (src_escrow, dst_escrow)
+    }
+
+    /// Cancel a specific order part by calling the escrow's cancel function
+    pub fn cancel_order(env: Env, caller: Address, order_hash: BytesN<32>, part_index: u64) {
+        caller.require_auth();
+        Self::cancel_part(&env, &caller, &order_hash, part_index, false);
+    }
+
+    /// Cancel a filled part as the taker that filled it, e.g. a resolver unwinding its own swap
+    /// The escrow is cancelled through the factory's public path, so this only succeeds once its
+    /// public_cancellation_start has passed; the principal still returns to the maker
+    pub fn cancel_order_as_taker(env: Env, caller: Address, order_hash: BytesN<32>, part_index: u64) {
+        caller.require_auth();
+        Self::cancel_part(&env, &caller, &order_hash, part_index, true);
+    }
+
+    /// Cancel several parts of an order in one call
+    /// Parts that are unfilled, already cancelled or whose escrow can no longer be cancelled
+    /// (e.g. already withdrawn) are skipped; returns per-part success
+    /// At most MAX_BATCH_SIZE parts may be passed per call
+    pub fn cancel_order_parts(env: Env, caller: Address, order_hash: BytesN<32>, part_indices: Vec<u64>) -> Vec<bool> {
+        if part_indices.len() > MAX_BATCH_SIZE {
+            panic!("Batch too large");
+        }
+        caller.require_auth();
+
+        let mut filled_orders: Vec<FilledOrder> = env.storage()
+            .persistent()
+            .get(&DataKey::FilledOrders(order_hash.clone()))
+            .unwrap_or(Vec::new(&env));
+        if filled_orders.is_empty() {
+            panic!("Order not found");
+        }
+
+        // All parts of an order share the maker, so ownership is verified once
+        if filled_orders.get(0).unwrap().maker != caller {
+            panic!("Only maker can cancel");
+        }
+
+        let factory_address: Address = env.storage()
+            .instance()
+            .get(&DataKey::EscrowFactory)
+            .unwrap();
+        let factory_client = EscrowFactoryTraitClient::new(&env, &factory_address);
+
+        let mut results = Vec::new(&env);
+        let mut cancelled_count: u64 = 0;
+        for part_index in part_indices.iter() {
+            let mut cancelled = false;
+            for i in 0..filled_orders.len() {
+                let mut order = filled_orders.get(i).unwrap();
+                if order.part_index == part_index && order.is_active {
+                    if factory_client.try_cancel_src_escrow(&caller, &order.escrow_id).is_ok() {
+                        Self::settle_cancelled_part(&env, &mut order);
+                        filled_orders.set(i, order);
+                        cancelled = true;
+                        cancelled_count += 1;
+                    }
+                    break;
+                }
+            }
+            results.push_back(cancelled);
+        }
+
+        env.storage().persistent().set(&DataKey::FilledOrders(order_hash.clone()), &filled_orders);
+
+        // Update order statistics
+        let mut stats = Self::get_order_stats(env.clone(), order_hash.clone());
+        stats.total_parts_cancelled += cancelled_count;
+        env.storage().persistent().set(&DataKey::OrderStats(order_hash.clone()), &stats);
+
+        log!(&env, "OrderPartsCancelled: orderHash={}, maker={}, cancelled={}", 
+             order_hash, caller, cancelled_count);
+
+        results
+    }
+
+    /// Make a cancelled part of an order fillable again
+    /// A part is only marked inactive once its escrow was cancelled, so a withdrawn part can't be reactivated
+    /// The factory frees a cancelled part's (hashLock, index) slot, so the refill creates a fresh escrow
+    pub fn reactivate_part(env: Env, caller: Address, order_hash: BytesN<32>, part_index: u64) {
+        caller.require_auth();
+
+        let mut filled_orders: Vec<FilledOrder> = env.storage()
+            .persistent()
+            .get(&DataKey::FilledOrders(order_hash.clone()))
+            .unwrap_or(Vec::new(&env));
+
+        let mut found = false;
+        for i in 0..filled_orders.len() {
+            let order = filled_orders.get(i).unwrap();
+            if order.part_index == part_index {
+                if order.maker != caller {
+                    panic!("Only maker can reactivate");
+                }
+                if order.is_active {
+                    panic!("Part not cancelled");
+                }
+
+                // Drop the cancelled record so the refill is tracked as the part's only entry
+                filled_orders.remove(i);
+                found = true;
+                break;
+            }
+        }
+
+        if !found {
+            panic!("Part not found");
+        }
+
+        env.storage().persistent().set(&DataKey::FilledOrders(order_hash.clone()), &filled_orders);
+        env.storage().persistent().set(&DataKey::PartsFilled(order_hash.clone(), part_index), &false);
+
+        let filled_count: u64 = env.storage()
+            .persistent()
+            .get(&DataKey::FilledSegmentsCount(order_hash.clone()))
+            .unwrap_or(0);
+        env.storage().persistent().set(&DataKey::FilledSegmentsCount(order_hash.clone()), &filled_count.saturating_sub(1));
+
+        // Back the part out of the stats; the refill counts it again. Its amount left get_order_vwap with the record
+        let mut stats = Self::get_order_stats(env.clone(), order_hash.clone());
+        stats.total_parts_filled = stats.total_parts_filled.saturating_sub(1);
+        stats.total_parts_cancelled = stats.total_parts_cancelled.saturating_sub(1);
+        env.storage().persistent().set(&DataKey::OrderStats(order_hash.clone()), &stats);
+
+        log!(&env, "PartReactivated: orderHash={}, maker={}, partIndex={}", 
+             order_hash, caller, part_index);
+    }
+
+    /// Get all filled order parts
+    pub fn get_order(env: Env, order_hash: BytesN<32>) -> Vec<FilledOrder> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::FilledOrders(order_hash))
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Get aggregated fill/cancel statistics for an order
+    pub fn get_order_stats(env: Env, order_hash: BytesN<32>) -> OrderStats {
+        env.storage()
+            .persistent()
+            .get(&DataKey::OrderStats(order_hash))
+            .unwrap_or(OrderStats {
+                total_parts_filled: 0,
+                total_parts_cancelled: 0,
+                first_fill_time: 0,
+                last_fill_time: 0,
+            })
+    }
+
+    /// Get specific filled order part
+    pub fn get_order_part(env: Env, order_hash: BytesN<32>, part_index: u64) -> FilledOrder {
+        let part_filled: bool = env.storage()
+            .persistent()
+            .get(&DataKey::PartsFilled(order_hash.clone(), part_index))
+            .unwrap_or(false);
+        if !part_filled {
+            panic!("Part not filled");
+        }
+
+        let filled_orders: Vec<FilledOrder> = env.storage()
+            .persistent()
+            .get(&DataKey::FilledOrders(order_hash))
+            .unwrap_or(Vec::new(&env));
+
+        for i in 0..filled_orders.len() {
+            let order = filled_orders.get(i).unwrap();
+            if order.part_index == part_index {
+                return order;
+            }
+        }
+        panic!("Part not found");
+    }
+
+    /// Get remaining segments for an order
+    pub fn get_remaining_segments(env: Env, order_hash: BytesN<32>, total_parts: u32) -> u64 {
+        Self::check_total_parts(&env, &order_hash, total_parts);
+        if total_parts <= 1 {
+            // Complete fill - check if filled
+            let part_filled: bool = env.storage()
+                .persistent()
+                .get(&DataKey::PartsFilled(order_hash, 0))
+                .unwrap_or(false);
+            if part_filled { 0 } else { 1 }
+        } else {
+            // Partial fill - calculate remaining segments
+            let filled_count: u64 = env.storage()
+                .persistent()
+                .get(&DataKey::FilledSegmentsCount(order_hash))
+                .unwrap_or(0);
+            (total_parts as u64) - filled_count
+        }
+    }
+
+    /// Check if a specific part index is available for partial fill
+    pub fn is_part_available(env: Env, order_hash: BytesN<32>, part_index: u64) -> bool {
+        let part_filled: bool = env.storage()
+            .persistent()
+            .get(&DataKey::PartsFilled(order_hash, part_index))
+            .unwrap_or(false);
+        !part_filled
+    }
+
+    /// Get all available part indices for an order
+    pub fn get_available_part_indices(env: Env, order_hash: BytesN<32>, total_parts: u32) -> Vec<u64> {
+        Self::check_total_parts(&env, &order_hash, total_parts);
+        let mut available_indices = Vec::new(&env);
+        
+        for i in 0..(total_parts as u64) {
+            let part_filled: bool = env.storage()
+                .persistent()
+                .get(&DataKey::PartsFilled(order_hash.clone(), i))
+                .unwrap_or(false);
+            if !part_filled {
+                available_indices.push_back(i);
+            }
+        }
+        
+        available_indices
+    }
+
+    /// Get filled and remaining part indices of an order in one call (at most MAX_PROGRESS_PARTS parts)
+    pub fn get_order_progress(env: Env, order_hash: BytesN<32>, total_parts: u32) -> OrderProgress {
+        if total_parts > MAX_PROGRESS_PARTS {
+            panic!("Too many parts");
+        }
+        Self::check_total_parts(&env, &order_hash, total_parts);
 
-#[contracttype]
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct OrderParams {
-    pub token_amount: i128,
-    pub hashed_secret: BytesN<32>,
-    pub withdrawal_start: u64,
-    pub public_withdrawal_start: u64,
-    pub cancellation_start: u64,
-    pub part_index: u64,
-    pub total_parts: u32,
-}
+        let mut filled_indices = Vec::new(&env);
+        let mut remaining_indices = Vec::new(&env);
+        for i in 0..(total_parts as u64) {
+            if Self::is_part_available(env.clone(), order_hash.clone(), i) {
+                remaining_indices.push_back(i);
+            } else {
+                filled_indices.push_back(i);
+            }
+        }
 
-#[contracttype]
-pub enum DataKey {
-    // Order tracking - non-sequential support
-    FilledOrders(BytesN<32>), // orderHash -> Vec<FilledOrder>
-    PartsFilled(BytesN<32>, u64), // (orderHash, partIndex) -> bool
-    FilledSegmentsCount(BytesN<32>), // orderHash -> count
-    UserFilledOrders(Address), // user -> Vec<orderHash>
-    EscrowFactory, // factory contract address
-    Owner, // contract owner
-    // Add authorization storage - equivalent to EVM's allowances mapping
-    TokenAllowance(Address, Address), // (token_owner, spender) -> amount
-}
+        OrderProgress {
+            filled_count: filled_indices.len() as u64,
+            remaining_count: remaining_indices.len() as u64,
+            filled_indices,
+            remaining_indices,
+        }
+    }
 
-#[contract]
-pub struct SimpleLimitOrderProtocol;
+    /// Get the part count recorded by an order's first fill, if it has been filled
+    pub fn get_order_total_parts(env: Env, order_hash: BytesN<32>) -> Option<u32> {
+        env.storage().persistent().get(&DataKey::OrderTotalParts(order_hash))
+    }
 
-#[contractimpl]
-impl SimpleLimitOrderProtocol {
-    /// Initialize the protocol with the escrow factory address and owner
-    pub fn initialize(env: Env, escrow_factory: Address, owner: Address) {
-        env.storage().instance().set(&DataKey::EscrowFactory, &escrow_factory);
-        env.storage().instance().set(&DataKey::Owner, &owner);
+    /// Check if the order can still be filled to completion (at most MAX_PROGRESS_PARTS parts)
+    /// True only if every part is either filled and active or still available; a cancelled part
+    /// stays marked as filled until the maker reactivates it, and makes this false meanwhile
+    pub fn can_complete_order(env: Env, order_hash: BytesN<32>, total_parts: u32) -> bool {
+        if total_parts > MAX_PROGRESS_PARTS {
+            panic!("Too many parts");
+        }
+        Self::check_total_parts(&env, &order_hash, total_parts);
+
+        let active_parts = Self::get_order(env.clone(), order_hash.clone())
+            .iter()
+            .filter(|part| part.is_active)
+            .count() as u32;
+        let available_parts = Self::get_available_part_indices(env, order_hash, total_parts).len();
+        active_parts + available_parts == total_parts
     }
 
-    /// Approve LOP to spend tokens (equivalent to ERC20 approve() in EVM)
-    /// This allows the LOP to transfer tokens on behalf of the caller
-    pub fn approve(env: Env, caller: Address, amount: i128) {
-        caller.require_auth();
-        
-        // Store the allowance - LOP can spend up to 'amount' tokens from caller
-        env.storage().persistent().set(
-            &DataKey::TokenAllowance(caller.clone(), env.current_contract_address()),
-            &amount
-        );
-        
-        log!(&env, "LOP Approval: owner={}, spender={}, amount={}", 
-             caller, env.current_contract_address(), amount);
+    /// Amount-weighted average of the amounts of an order's active parts: sum(a * a) / sum(a)
+    /// Cancelled parts are skipped; returns 0 if no part is active
+    pub fn get_order_vwap(env: Env, order_hash: BytesN<32>) -> i128 {
+        let mut weighted_sum: i128 = 0;
+        let mut total_amount: i128 = 0;
+        for part in Self::get_order(env, order_hash).iter() {
+            if part.is_active {
+                weighted_sum += part.amount * part.amount;
+                total_amount += part.amount;
+            }
+        }
+        if total_amount == 0 { 0 } else { weighted_sum / total_amount }
     }
 
-    /// Get current allowance (equivalent to ERC20 allowance() in EVM)
-    pub fn allowance(env: Env, owner: Address, spender: Address) -> i128 {
+    /// Total security deposit the LOP has posted into source escrows that are still active
+    /// Cancelling through the LOP releases a fill's deposit at once; escrows settled directly on the factory
+    /// count until someone calls release_settled_escrow for them
+    pub fn get_lop_deposit_exposure(env: Env) -> i128 {
+        env.storage().persistent().get(&DataKey::DepositExposure).unwrap_or(0)
+    }
+
+    /// Release a settled fill's deposit from the LOP's exposure (permissionless)
+    /// Reverts unless the factory reports the escrow no longer holds a deposit
+    pub fn release_settled_escrow(env: Env, escrow_id: BytesN<32>) {
+        if !env.storage().persistent().has(&DataKey::SrcEscrowDeposit(escrow_id.clone())) {
+            panic!("Escrow not tracked");
+        }
+        let factory_address: Address = env.storage().instance().get(&DataKey::EscrowFactory).unwrap();
+        let factory_client = EscrowFactoryTraitClient::new(&env, &factory_address);
+        if factory_client.get_src_deposit_held(&escrow_id) > 0 {
+            panic!("Escrow still open");
+        }
+
+        Self::untrack_src_escrow(&env, &escrow_id);
+
+        log!(&env, "SettledEscrowReleased: escrow={}", escrow_id);
+    }
+
+    /// Get all filled orders for a user
+    pub fn get_user_filled_orders(env: Env, user: Address) -> Vec<BytesN<32>> {
         env.storage()
             .persistent()
-            .get(&DataKey::TokenAllowance(owner, spender))
+            .get(&DataKey::UserFilledOrders(user))
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Get the orders a taker (resolver) has filled at least one part of, paginated in first-fill order
+    pub fn get_taker_filled_orders(env: Env, taker: Address, start: u32, limit: u32) -> Vec<BytesN<32>> {
+        let count = Self::get_taker_filled_count(env.clone(), taker.clone());
+        let mut page = Vec::new(&env);
+
+        let end = start.saturating_add(limit).min(count);
+        for i in start..end {
+            page.push_back(env.storage().persistent().get(&DataKey::TakerFilledOrderAt(taker.clone(), i)).unwrap());
+        }
+
+        page
+    }
+
+    /// Get how many distinct orders a taker has filled parts of
+    pub fn get_taker_filled_count(env: Env, taker: Address) -> u32 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::TakerFilledCount(taker))
             .unwrap_or(0)
     }
 
-    /// Fill an order by creating an escrow - supports non-sequential partial fills  
-    pub fn fill_order(
+    /// Check whether a taker has filled at least one part of an order
+    pub fn has_taker_filled(env: Env, taker: Address, order_hash: BytesN<32>) -> bool {
+        env.storage()
+            .persistent()
+            .has(&DataKey::TakerFilledOrder(taker, order_hash))
+    }
+
+    /// Emergency function to rescue XLM stuck in contract
+    pub fn rescue_xlm(env: Env, caller: Address, to: Address) {
+        caller.require_auth();
+        
+        // Check if caller is owner
+        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
+        if caller != owner {
+            panic!("Only owner can rescue");
+        }
+
+        // In Stellar, we can't directly send native balance like EVM
+        // This would need to be implemented based on the specific token contract
+        log!(&env, "XLM rescue requested: to={}", to);
+    }
+
+    // Shared fill path once the order hash is known
+    fn fill_part(
         env: Env,
         order_hash: BytesN<32>,
+        taker: Address,
         maker: Address,
         recipient: Address,
         token_amount: i128,
@@ -108,17 +1069,21 @@ impl SimpleLimitOrderProtocol {
         public_withdrawal_start: u64,
         part_index: u64,
         total_parts: u32,
-    ) -> Address {
-        // Validate inputs
-        if total_parts == 0 {
-            panic!("Total parts must be > 0");
-        }
+    ) -> BytesN<32> {
         if part_index >= total_parts as u64 {
             panic!("Invalid part index");
         }
         if token_amount <= 0 {
             panic!("Token amount must be > 0");
         }
+        taker.require_auth();
+
+        // Respect any live reservation held by another taker
+        if let Some(reserver) = Self::get_part_reservation(env.clone(), order_hash.clone(), part_index) {
+            if reserver != taker {
+                panic!("Part reserved");
+            }
+        }
 
         // Check if this part is already filled
         let part_filled: bool = env.storage()
@@ -129,7 +1094,19 @@ impl SimpleLimitOrderProtocol {
             panic!("Part already filled");
         }
 
-        let is_partial_fill = total_parts > 1;
+        // Every fill of an order must agree on its part count
+        Self::check_total_parts(&env, &order_hash, total_parts);
+
+        // Respect the maker's cap on the number of fills
+        let current_count: u64 = env.storage()
+            .persistent()
+            .get(&DataKey::FilledSegmentsCount(order_hash.clone()))
+            .unwrap_or(0);
+        if let Some(max_fills) = Self::get_max_fills(env.clone(), order_hash.clone()) {
+            if current_count >= max_fills as u64 {
+                panic!("Max fills reached");
+            }
+        }
 
         // Check allowance - LOP must be approved to spend maker's tokens
         let current_allowance = Self::allowance(env.clone(), maker.clone(), env.current_contract_address());
@@ -139,9 +1116,11 @@ impl SimpleLimitOrderProtocol {
         
         // Reduce allowance - equivalent to EVM's transferFrom() reducing allowance
         let new_allowance = current_allowance - token_amount;
+        Self::set_allowance(&env, &maker, &env.current_contract_address(), new_allowance);
+        let consumed = Self::get_consumed(env.clone(), maker.clone(), env.current_contract_address());
         env.storage().persistent().set(
-            &DataKey::TokenAllowance(maker.clone(), env.current_contract_address()),
-            &new_allowance
+            &DataKey::AllowanceConsumed(maker.clone(), env.current_contract_address()),
+            &(consumed + token_amount),
         );
 
         // Get factory address and create escrow directly
@@ -149,28 +1128,47 @@ impl SimpleLimitOrderProtocol {
         let factory_client = EscrowFactoryTraitClient::new(&env, &factory_address);
         
         // Create escrow using factory client - matches exact factory signature
-        let escrow_address = factory_client.create_src_escrow_partial(
+        let cancellation_start = withdrawal_start
+            .checked_add(SRC_CANCELLATION_DELAY) // 24 hours after withdrawal
+            .expect("Time window overflow");
+        let windows = TimeWindows {
+            withdrawal_start,
+            public_withdrawal_start,
+            cancellation_start,
+            public_cancellation_start: cancellation_start
+                .checked_add(SRC_PUBLIC_CANCELLATION_DELAY)
+                .expect("Time window overflow"),
+        };
+        let escrow_id = factory_client.create_src_escrow_partial(
             &env.current_contract_address(), // creator (LOP)
             &hashed_secret,
             &recipient,
             &maker,        // buyer (the one who approved LOP)
+            &factory_client.get_native_token(), // the LOP's allowances are in the native token
             &token_amount,
-            &withdrawal_start,
-            &public_withdrawal_start,
-            &(withdrawal_start + 86400), // cancellation_start (24 hours after withdrawal)
+            &windows,
             &part_index,
             &total_parts,
+            &false,
         );
 
+        let deposit = factory_client.get_src_deposit_held(&escrow_id);
+        env.storage().persistent().set(&DataKey::SrcEscrowDeposit(escrow_id.clone()), &deposit);
+        let exposure = Self::get_lop_deposit_exposure(env.clone());
+        env.storage().persistent().set(&DataKey::DepositExposure, &(exposure + deposit));
+
         // Track the filled order part
         let filled_order = FilledOrder {
             order_hash: order_hash.clone(),
             maker: maker.clone(),
+            taker: taker.clone(),
             recipient: recipient.clone(),
-            escrow_address: escrow_address.clone(),
+            escrow_id: escrow_id.clone(),
             part_index,
             total_parts,
             is_active: true,
+            amount: token_amount,
+            grant_epoch: Self::allowance_epoch(&env, &maker),
         };
 
         // Add to filled orders array
@@ -181,18 +1179,28 @@ impl SimpleLimitOrderProtocol {
         filled_orders.push_back(filled_order);
         env.storage().persistent().set(&DataKey::FilledOrders(order_hash.clone()), &filled_orders);
 
-        // Mark this part as filled
+        // Mark this part as filled and release any reservation
         env.storage().persistent().set(&DataKey::PartsFilled(order_hash.clone(), part_index), &true);
+        env.storage().persistent().set(&DataKey::OrderTotalParts(order_hash.clone()), &total_parts);
+        env.storage().temporary().remove(&DataKey::PartReservation(order_hash.clone(), part_index));
 
         // Update filled segments count
-        let current_count: u64 = env.storage()
-            .persistent()
-            .get(&DataKey::FilledSegmentsCount(order_hash.clone()))
-            .unwrap_or(0);
         env.storage().persistent().set(&DataKey::FilledSegmentsCount(order_hash.clone()), &(current_count + 1));
 
+        // Update order statistics
+        // Stats are written on the first fill and kept through reactivations, so they mark a first fill
+        let first_fill = !env.storage().persistent().has(&DataKey::OrderStats(order_hash.clone()));
+        let mut stats = Self::get_order_stats(env.clone(), order_hash.clone());
+        let now = env.ledger().timestamp();
+        if first_fill {
+            stats.first_fill_time = now;
+        }
+        stats.total_parts_filled += 1;
+        stats.last_fill_time = now;
+        env.storage().persistent().set(&DataKey::OrderStats(order_hash.clone()), &stats);
+
         // Add to user's orders if first fill
-        if current_count == 0 {
+        if first_fill {
             let mut user_orders: Vec<BytesN<32>> = env.storage()
                 .persistent()
                 .get(&DataKey::UserFilledOrders(maker.clone()))
@@ -201,18 +1209,101 @@ impl SimpleLimitOrderProtocol {
             env.storage().persistent().set(&DataKey::UserFilledOrders(maker.clone()), &user_orders);
         }
 
-        log!(&env, "OrderFilled: orderHash={}, taker={}, partIndex={}, escrowAddress={}", 
-             order_hash, env.current_contract_address(), part_index, escrow_address);
-        log!(&env, "EscrowCreated: orderHash={}, escrowAddress={}, hashedSecret={}, partIndex={}", 
-             order_hash, escrow_address, hashed_secret, part_index);
+        // Index the order under the taker the first time it fills a part of it
+        if !Self::has_taker_filled(env.clone(), taker.clone(), order_hash.clone()) {
+            let count = Self::get_taker_filled_count(env.clone(), taker.clone());
+            env.storage().persistent().set(&DataKey::TakerFilledOrder(taker.clone(), order_hash.clone()), &true);
+            env.storage().persistent().set(&DataKey::TakerFilledOrderAt(taker.clone(), count), &order_hash);
+            env.storage().persistent().set(&DataKey::TakerFilledCount(taker.clone()), &(count + 1));
+        }
+
+        // Notify the maker's hook on a best-effort basis so a failing hook cannot block fills
+        if let Some(hook) = Self::get_fill_hook(env.clone(), maker.clone()) {
+            let hook_client = FillHookClient::new(&env, &hook);
+            if hook_client.try_on_order_filled(&order_hash, &part_index, &escrow_id).is_err() {
+                log!(&env, "FillHookFailed: orderHash={}, hook={}", order_hash, hook);
+            }
+        }
+
+        log!(&env, "OrderFilled: orderHash={}, taker={}, partIndex={}, escrowId={}", 
+             order_hash, taker, part_index, escrow_id);
+        log!(&env, "EscrowCreated: orderHash={}, escrowId={}, hashedSecret={}, partIndex={}", 
+             order_hash, escrow_id, hashed_secret, part_index);
 
-        escrow_address
+        escrow_id
     }
 
-    /// Cancel a specific order part by calling the escrow's cancel function
-    pub fn cancel_order(env: Env, caller: Address, order_hash: BytesN<32>, part_index: u64) {
-        caller.require_auth();
+    fn typed_order_digest(env: &Env, domain_separator: &BytesN<32>, order: &EvmOrder) -> Hash<32> {
+        let type_hash = env.crypto().keccak256(&Bytes::from_slice(env, EVM_ORDER_TYPE));
+
+        // hashStruct: every field is a 32-byte word; addresses are left-padded with zeros
+        let mut encoded = Bytes::from_array(env, &type_hash.to_array());
+        encoded.extend_from_array(&order.salt.to_array());
+        for address in [&order.maker, &order.receiver, &order.maker_asset, &order.taker_asset] {
+            encoded.extend_from_array(&[0u8; 12]);
+            encoded.extend_from_array(&address.to_array());
+        }
+        for amount in [order.making_amount, order.taking_amount] {
+            encoded.extend_from_array(&[0u8; 16]);
+            encoded.extend_from_array(&amount.to_be_bytes());
+        }
+        encoded.extend_from_array(&order.maker_traits.to_array());
+        let struct_hash = env.crypto().keccak256(&encoded);
+
+        Self::hash_typed_data(env, domain_separator, &struct_hash.to_array())
+    }
+
+    fn fill_extension_hash(env: &Env, hashed_secret: &BytesN<32>, recipient: &Address) -> Hash<32> {
+        let mut extension = Bytes::from_array(env, &hashed_secret.to_array());
+        extension.append(&recipient.clone().to_xdr(env));
+        env.crypto().keccak256(&extension)
+    }
+
+    fn hash_typed_data(env: &Env, domain_separator: &BytesN<32>, struct_hash: &[u8; 32]) -> Hash<32> {
+        let mut packed = Bytes::from_array(env, &[0x19, 0x01]);
+        packed.extend_from_array(&domain_separator.to_array());
+        packed.extend_from_array(struct_hash);
+        env.crypto().keccak256(&packed)
+    }
+
+    // Recover the EVM address behind an Ethereum-style (r || s || v) signature; v may be 27/28 or 0/1
+    fn recover_evm_signer(env: &Env, digest: &Hash<32>, signature: &BytesN<65>) -> BytesN<20> {
+        let signature = signature.to_array();
+        let mut rs = [0u8; 64];
+        rs.copy_from_slice(&signature[..64]);
+        let v = signature[64];
+        let recovery_id = if v >= 27 { v - 27 } else { v };
+        if recovery_id > 1 {
+            panic!("Invalid signature");
+        }
+
+        let public_key = env.crypto()
+            .secp256k1_recover(digest, &BytesN::from_array(env, &rs), recovery_id as u32)
+            .to_array();
+        let key_hash = env.crypto().keccak256(&Bytes::from_slice(env, &public_key[1..])).to_array();
+        let mut evm_address = [0u8; 20];
+        evm_address.copy_from_slice(&key_hash[12..]);
+        BytesN::from_array(env, &evm_address)
+    }
+
+    // Every allowance write goes through here so indexers see an Approval event for it
+    fn set_allowance(env: &Env, owner: &Address, spender: &Address, amount: i128) {
+        env.storage().persistent().set(&DataKey::TokenAllowance(owner.clone(), spender.clone()), &amount);
+
+        // The LOP's allowances are in the factory's native token
+        let factory_address: Address = env.storage().instance().get(&DataKey::EscrowFactory).unwrap();
+        Approval {
+            namespace: Self::get_event_namespace(env.clone()),
+            owner: owner.clone(),
+            spender: spender.clone(),
+            token: EscrowFactoryTraitClient::new(env, &factory_address).get_native_token(),
+            amount,
+        }
+        .publish(env);
+    }
 
+    // Cancel a filled part's escrow as its maker, or as its taker when as_taker is set
+    fn cancel_part(env: &Env, caller: &Address, order_hash: &BytesN<32>, part_index: u64, as_taker: bool) {
         // Check if part is filled
         let part_filled: bool = env.storage()
             .persistent()
@@ -226,7 +1317,7 @@ impl SimpleLimitOrderProtocol {
         let mut filled_orders: Vec<FilledOrder> = env.storage()
             .persistent()
             .get(&DataKey::FilledOrders(order_hash.clone()))
-            .unwrap_or(Vec::new(&env));
+            .unwrap_or(Vec::new(env));
 
         let mut found = false;
         for i in 0..filled_orders.len() {
@@ -235,9 +1326,6 @@ impl SimpleLimitOrderProtocol {
                 if !order.is_active {
                     panic!("Part already cancelled");
                 }
-                if order.maker != caller {
-                    panic!("Only maker can cancel");
-                }
 
                 // Call escrow factory's cancel function
                 let factory_address: Address = env.storage()
@@ -245,10 +1333,21 @@ impl SimpleLimitOrderProtocol {
                     .get(&DataKey::EscrowFactory)
                     .unwrap();
                 
-                let factory_client = EscrowFactoryTraitClient::new(&env, &factory_address);
-                factory_client.cancel_src_escrow(&caller, &order.escrow_address);
-                
-                order.is_active = false;
+                let factory_client = EscrowFactoryTraitClient::new(env, &factory_address);
+                if as_taker {
+                    if order.taker != *caller {
+                        panic!("Only taker can cancel");
+                    }
+                    // Only the maker created the escrow, so the taker goes through public cancellation;
+                    // the keeper share of the deposit comes back to the LOP, which posted it
+                    factory_client.public_cancel_src_escrow(&env.current_contract_address(), &order.escrow_id);
+                } else {
+                    if order.maker != *caller {
+                        panic!("Only maker can cancel");
+                    }
+                    factory_client.cancel_src_escrow(caller, &order.escrow_id);
+                }
+                Self::settle_cancelled_part(env, &mut order);
                 filled_orders.set(i, order);
                 found = true;
                 break;
@@ -261,108 +1360,91 @@ impl SimpleLimitOrderProtocol {
 
         env.storage().persistent().set(&DataKey::FilledOrders(order_hash.clone()), &filled_orders);
 
-        log!(&env, "OrderCancelled: orderHash={}, maker={}, partIndex={}", 
-             order_hash, caller, part_index);
+        // Update order statistics
+        let mut stats = Self::get_order_stats(env.clone(), order_hash.clone());
+        stats.total_parts_cancelled += 1;
+        env.storage().persistent().set(&DataKey::OrderStats(order_hash.clone()), &stats);
+
+        log!(env, "OrderCancelled: orderHash={}, caller={}, partIndex={}", 
+             order_hash.clone(), caller.clone(), part_index);
     }
 
-    /// Get all filled order parts
-    pub fn get_order(env: Env, order_hash: BytesN<32>) -> Vec<FilledOrder> {
+    // LOP-side bookkeeping once a part's escrow has been cancelled on the factory, shared by every cancel path
+    // The factory refunds the principal to the maker, so the allowance the fill drew is credited back,
+    // and the deposit the LOP posted is released from its exposure
+    fn settle_cancelled_part(env: &Env, order: &mut FilledOrder) {
+        Self::restore_allowance(env, order);
+        Self::untrack_src_escrow(env, &order.escrow_id);
+        order.is_active = false;
+    }
+
+    fn allowance_epoch(env: &Env, owner: &Address) -> u64 {
         env.storage()
             .persistent()
-            .get(&DataKey::FilledOrders(order_hash))
-            .unwrap_or(Vec::new(&env))
+            .get(&DataKey::AllowanceEpoch(owner.clone()))
+            .unwrap_or(0)
     }
 
-    /// Get specific filled order part
-    pub fn get_order_part(env: Env, order_hash: BytesN<32>, part_index: u64) -> FilledOrder {
-        let part_filled: bool = env.storage()
-            .persistent()
-            .get(&DataKey::PartsFilled(order_hash.clone(), part_index))
-            .unwrap_or(false);
-        if !part_filled {
-            panic!("Part not filled");
+    // Credit back the LOP allowance a cancelled fill drew from its maker
+    // Skipped if the maker has approved since the fill: the new grant (or revocation) replaced the one it drew from
+    fn restore_allowance(env: &Env, order: &FilledOrder) {
+        if order.grant_epoch != Self::allowance_epoch(env, &order.maker) {
+            return;
         }
+        let spender = env.current_contract_address();
+        let current = Self::allowance(env.clone(), order.maker.clone(), spender.clone());
+        Self::set_allowance(env, &order.maker, &spender, current + order.amount);
 
-        let filled_orders: Vec<FilledOrder> = env.storage()
-            .persistent()
-            .get(&DataKey::FilledOrders(order_hash))
-            .unwrap_or(Vec::new(&env));
-
-        for i in 0..filled_orders.len() {
-            let order = filled_orders.get(i).unwrap();
-            if order.part_index == part_index {
-                return order;
-            }
-        }
-        panic!("Part not found");
+        let consumed = Self::get_consumed(env.clone(), order.maker.clone(), spender.clone());
+        env.storage().persistent().set(&DataKey::AllowanceConsumed(order.maker.clone(), spender), &(consumed - order.amount).max(0));
     }
 
-    /// Get remaining segments for an order
-    pub fn get_remaining_segments(env: Env, order_hash: BytesN<32>, total_parts: u32) -> u64 {
-        if total_parts <= 1 {
-            // Complete fill - check if filled
-            let part_filled: bool = env.storage()
-                .persistent()
-                .get(&DataKey::PartsFilled(order_hash, 0))
-                .unwrap_or(false);
-            return if part_filled { 0 } else { 1 };
-        } else {
-            // Partial fill - calculate remaining segments
-            let filled_count: u64 = env.storage()
-                .persistent()
-                .get(&DataKey::FilledSegmentsCount(order_hash))
-                .unwrap_or(0);
-            return (total_parts as u64) - filled_count;
+    fn set_new_orders_paused(env: &Env, caller: &Address, paused: bool) {
+        caller.require_auth();
+
+        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
+        if *caller != owner {
+            panic!("Only owner can pause");
         }
-    }
+        env.storage().instance().set(&DataKey::NewOrdersPaused, &paused);
 
-    /// Check if a specific part index is available for partial fill
-    pub fn is_part_available(env: Env, order_hash: BytesN<32>, part_index: u64) -> bool {
-        let part_filled: bool = env.storage()
-            .persistent()
-            .get(&DataKey::PartsFilled(order_hash, part_index))
-            .unwrap_or(false);
-        !part_filled
+        log!(env, "NewOrdersPausedUpdated: paused={}", paused);
     }
 
-    /// Get all available part indices for an order
-    pub fn get_available_part_indices(env: Env, order_hash: BytesN<32>, total_parts: u32) -> Vec<u64> {
-        let mut available_indices = Vec::new(&env);
-        
-        for i in 0..(total_parts as u64) {
-            let part_filled: bool = env.storage()
-                .persistent()
-                .get(&DataKey::PartsFilled(order_hash.clone(), i))
-                .unwrap_or(false);
-            if !part_filled {
-                available_indices.push_back(i);
-            }
+    // While new orders are paused, only orders already posted or partly filled may be filled
+    fn check_order_accepted(env: &Env, order_hash: &BytesN<32>) {
+        if !Self::is_new_orders_paused(env.clone()) {
+            return;
+        }
+        let known = env.storage().persistent().has(&DataKey::OrderTotalParts(order_hash.clone()))
+            || env.storage().persistent().has(&DataKey::PostedOrder(order_hash.clone()));
+        if !known {
+            panic!("New orders paused");
         }
-        
-        available_indices
     }
 
-    /// Get all filled orders for a user
-    pub fn get_user_filled_orders(env: Env, user: Address) -> Vec<BytesN<32>> {
-        env.storage()
-            .persistent()
-            .get(&DataKey::UserFilledOrders(user))
-            .unwrap_or(Vec::new(&env))
+    // Release a settled fill's deposit from the exposure counter; a no-op if already released
+    fn untrack_src_escrow(env: &Env, escrow_id: &BytesN<32>) {
+        let key = DataKey::SrcEscrowDeposit(escrow_id.clone());
+        let Some(deposit) = env.storage().persistent().get::<_, i128>(&key) else {
+            return;
+        };
+        env.storage().persistent().remove(&key);
+        let exposure = Self::get_lop_deposit_exposure(env.clone());
+        env.storage().persistent().set(&DataKey::DepositExposure, &(exposure - deposit));
     }
 
-
-    /// Emergency function to rescue XLM stuck in contract
-    pub fn rescue_xlm(env: Env, caller: Address, to: Address) {
-        caller.require_auth();
-        
-        // Check if caller is owner
-        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
-        if caller != owner {
-            panic!("Only owner can rescue");
+    // Reject a part count that differs from the one recorded by the order's first fill
+    fn check_total_parts(env: &Env, order_hash: &BytesN<32>, total_parts: u32) {
+        let recorded: Option<u32> = env.storage()
+            .persistent()
+            .get(&DataKey::OrderTotalParts(order_hash.clone()));
+        if let Some(recorded) = recorded {
+            if recorded != total_parts {
+                panic!("Total parts mismatch");
+            }
         }
-
-        // In Stellar, we can't directly send native balance like EVM
-        // This would need to be implemented based on the specific token contract
-        log!(&env, "XLM rescue requested: to={}", to);
     }
-}
\ No newline at end of file
+}
+
+mod test;
@@ -0,0 +1,1188 @@
+#![cfg(test)]
+
+use super::*;
+use soroban_sdk::{
+    contract, contractimpl, symbol_short,
+    testutils::{Address as _, Events, Ledger},
+    token::{StellarAssetClient, TokenClient},
+    vec, Env, FromVal, String, Symbol,
+};
+
+// Mock factory that derives the escrow id from the buyer's (or destination creator's) address and cancels
+// unless marked withdrawn. Once given a token it also escrows source principals and refunds them on cancel
+#[contract]
+pub struct MockEscrowFactory;
+
+#[contractimpl]
+impl MockEscrowFactory {
+    pub fn set_token(env: Env, token: Address) {
+        env.storage().instance().set(&symbol_short!("token"), &token);
+    }
+
+    pub fn create_src_escrow_partial(
+        env: Env,
+        _creator: Address,
+        _hashed_secret: BytesN<32>,
+        _recipient: Address,
+        buyer: Address,
+        _token: Address,
+        token_amount: i128,
+        _windows: TimeWindows,
+        _part_index: u64,
+        _total_parts: u32,
+        _require_dual_auth: bool,
+    ) -> BytesN<32> {
+        let escrow_id = escrow_id_of(&env, &buyer);
+        if let Some(token) = env.storage().instance().get::<_, Address>(&symbol_short!("token")) {
+            TokenClient::new(&env, &token).transfer(&buyer, env.current_contract_address(), &token_amount);
+            env.storage().instance().set(&(symbol_short!("held"), escrow_id.clone()), &(buyer, token_amount));
+        }
+        escrow_id
+    }
+
+    pub fn create_dst_escrow(
+        env: Env,
+        creator: Address,
+        _hashed_secret: BytesN<32>,
+        _recipient: Address,
+        _token_amount: i128,
+        _withdrawal_start: u64,
+        _public_withdrawal_start: u64,
+        _cancellation_start: u64,
+        _public_cancellation_start: u64,
+        _require_dual_auth: bool,
+    ) -> BytesN<32> {
+        escrow_id_of(&env, &creator)
+    }
+
+    pub fn get_native_token(env: Env) -> Address {
+        env.storage().instance().get(&symbol_short!("token")).unwrap_or(env.current_contract_address())
+    }
+
+    pub fn cancel_src_escrow(env: Env, _caller: Address, escrow_id: BytesN<32>) {
+        // Escrows flagged as withdrawn can no longer be cancelled
+        if env.storage().instance().has(&escrow_id) {
+            panic!("Already withdrawn");
+        }
+        env.storage().instance().set(&(symbol_short!("cancelled"), escrow_id.clone()), &true);
+        if let Some(token) = env.storage().instance().get::<_, Address>(&symbol_short!("token")) {
+            let (buyer, amount): (Address, i128) = env.storage().instance().get(&(symbol_short!("held"), escrow_id)).unwrap();
+            TokenClient::new(&env, &token).transfer(&env.current_contract_address(), &buyer, &amount);
+        }
+    }
+
+    pub fn public_cancel_src_escrow(env: Env, caller: Address, escrow_id: BytesN<32>) {
+        env.storage().instance().set(&symbol_short!("keeper"), &caller);
+        Self::cancel_src_escrow(env, caller, escrow_id);
+    }
+
+    pub fn last_keeper(env: Env) -> Option<Address> {
+        env.storage().instance().get(&symbol_short!("keeper"))
+    }
+
+    pub fn get_src_deposit_held(env: Env, escrow_id: BytesN<32>) -> i128 {
+        let settled = env.storage().instance().has(&escrow_id)
+            || env.storage().instance().has(&(symbol_short!("cancelled"), escrow_id));
+        if settled { 0 } else { MOCK_DEPOSIT }
+    }
+
+    pub fn mark_withdrawn(env: Env, escrow_id: BytesN<32>) {
+        env.storage().instance().set(&escrow_id, &true);
+    }
+}
+
+// Mock maker hook that records its last notification, or panics when told to fail
+#[contract]
+pub struct MockFillHook;
+
+#[contractimpl]
+impl MockFillHook {
+    pub fn set_fail(env: Env, fail: bool) {
+        env.storage().instance().set(&symbol_short!("fail"), &fail);
+    }
+
+    pub fn on_order_filled(env: Env, order_hash: BytesN<32>, part_index: u64, escrow_id: BytesN<32>) {
+        let fail: bool = env.storage().instance().get(&symbol_short!("fail")).unwrap_or(false);
+        if fail {
+            panic!("Hook failed");
+        }
+        env.storage().instance().set(&symbol_short!("last"), &(order_hash, part_index, escrow_id));
+    }
+
+    pub fn last_fill(env: Env) -> Option<(BytesN<32>, u64, BytesN<32>)> {
+        env.storage().instance().get(&symbol_short!("last"))
+    }
+}
+
+// Security deposit the mock factory reports for each active source escrow
+const MOCK_DEPOSIT: i128 = 500;
+
+// Escrow id the mock factory hands out for escrows funded by an account
+fn escrow_id_of(env: &Env, funder: &Address) -> BytesN<32> {
+    env.crypto().sha256(&funder.clone().to_xdr(env)).into()
+}
+
+struct Setup<'a> {
+    env: Env,
+    owner: Address,
+    recipient: Address,
+    factory: MockEscrowFactoryClient<'a>,
+    lop: SimpleLimitOrderProtocolClient<'a>,
+}
+
+fn setup<'a>() -> Setup<'a> {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let owner = Address::generate(&env);
+    let factory_id = env.register(MockEscrowFactory, ());
+    let lop_id = env.register(SimpleLimitOrderProtocol, ());
+
+    let lop = SimpleLimitOrderProtocolClient::new(&env, &lop_id);
+    lop.initialize(&factory_id, &owner, &None);
+
+    let factory = MockEscrowFactoryClient::new(&env, &factory_id);
+    let recipient = Address::generate(&env);
+    Setup { env, owner, recipient, factory, lop }
+}
+
+fn set_time(env: &Env, timestamp: u64) {
+    env.ledger().with_mut(|l| l.timestamp = timestamp);
+}
+
+// Hash of a test order (1_000 per part, fixed secret and recipient, nonce 0)
+fn order_hash(s: &Setup, maker: &Address, total_parts: u32) -> BytesN<32> {
+    s.lop.compute_order_hash(
+        maker,
+        &s.recipient,
+        &1_000,
+        &BytesN::from_array(&s.env, &[2u8; 32]),
+        &total_parts,
+        &0,
+    )
+}
+
+fn fill(s: &Setup, maker: &Address, part_index: u64, total_parts: u32) -> BytesN<32> {
+    fill_as(s, &Address::generate(&s.env), maker, part_index, total_parts)
+}
+
+fn fill_as(s: &Setup, taker: &Address, maker: &Address, part_index: u64, total_parts: u32) -> BytesN<32> {
+    s.lop.fill_order(
+        taker,
+        maker,
+        &s.recipient,
+        &1_000,
+        &BytesN::from_array(&s.env, &[2u8; 32]),
+        &1_000,
+        &2_000,
+        &part_index,
+        &total_parts,
+        &0,
+    )
+}
+
+#[test]
+fn test_order_stats() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 4);
+    s.lop.approve(&maker, &10_000);
+
+    let stats = s.lop.get_order_stats(&order_hash);
+    assert_eq!(stats.total_parts_filled, 0);
+    assert_eq!(stats.first_fill_time, 0);
+
+    set_time(&s.env, 100);
+    fill(&s, &maker, 0, 4);
+    set_time(&s.env, 250);
+    fill(&s, &maker, 2, 4);
+    set_time(&s.env, 400);
+    fill(&s, &maker, 3, 4);
+
+    s.lop.cancel_order(&maker, &order_hash, &2);
+
+    let stats = s.lop.get_order_stats(&order_hash);
+    assert_eq!(stats.total_parts_filled, 3);
+    assert_eq!(stats.total_parts_cancelled, 1);
+    assert_eq!(stats.first_fill_time, 100);
+    assert_eq!(stats.last_fill_time, 400);
+}
+
+#[test]
+fn test_cancel_order_parts() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 4);
+    s.lop.approve(&maker, &10_000);
+
+    fill(&s, &maker, 0, 4);
+    fill(&s, &maker, 1, 4);
+    fill(&s, &maker, 2, 4);
+    s.lop.cancel_order(&maker, &order_hash, &1);
+
+    // Part 1 already cancelled, part 3 never filled
+    let results = s.lop.cancel_order_parts(&maker, &order_hash, &vec![&s.env, 0, 1, 2, 3]);
+    assert_eq!(results, vec![&s.env, true, false, true, false]);
+
+    let parts = s.lop.get_order(&order_hash);
+    for part in parts.iter() {
+        assert!(!part.is_active);
+    }
+    assert_eq!(s.lop.get_order_stats(&order_hash).total_parts_cancelled, 3);
+}
+
+#[test]
+fn test_cancel_order_parts_skips_withdrawn_escrow() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 2);
+    s.lop.approve(&maker, &10_000);
+
+    let escrow = fill(&s, &maker, 0, 2);
+    s.factory.mark_withdrawn(&escrow);
+
+    let results = s.lop.cancel_order_parts(&maker, &order_hash, &vec![&s.env, 0]);
+    assert_eq!(results, vec![&s.env, false]);
+    assert!(s.lop.get_order_part(&order_hash, &0).is_active);
+}
+
+#[test]
+#[should_panic(expected = "Only maker can cancel")]
+fn test_cancel_order_parts_maker_only() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 2);
+    s.lop.approve(&maker, &10_000);
+
+    fill(&s, &maker, 0, 2);
+    s.lop.cancel_order_parts(&Address::generate(&s.env), &order_hash, &vec![&s.env, 0]);
+}
+
+#[test]
+fn test_reserved_part_only_fillable_by_reserver() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 4);
+    let resolver_a = Address::generate(&s.env);
+    let resolver_b = Address::generate(&s.env);
+    s.lop.approve(&maker, &10_000);
+
+    set_time(&s.env, 100);
+    s.lop.reserve_part(&resolver_a, &order_hash, &1, &60);
+    assert_eq!(s.lop.get_part_reservation(&order_hash, &1), Some(resolver_a.clone()));
+
+    // Another resolver can neither fill nor re-reserve the part
+    let result = s.lop.try_fill_order(
+        &resolver_b,
+        &maker,
+        &s.recipient,
+        &1_000,
+        &BytesN::from_array(&s.env, &[2u8; 32]),
+        &1_000,
+        &2_000,
+        &1,
+        &4,
+        &0,
+    );
+    assert!(result.is_err());
+    assert!(s.lop.try_reserve_part(&resolver_b, &order_hash, &1, &60).is_err());
+
+    // Unreserved parts stay open to everyone
+    fill_as(&s, &resolver_b, &maker, 0, 4);
+
+    // The reserver fills, which releases the reservation
+    fill_as(&s, &resolver_a, &maker, 1, 4);
+    assert!(!s.lop.is_part_available(&order_hash, &1));
+    assert_eq!(s.lop.get_part_reservation(&order_hash, &1), None);
+}
+
+#[test]
+fn test_reservation_expires() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 2);
+    let resolver_a = Address::generate(&s.env);
+    let resolver_b = Address::generate(&s.env);
+    s.lop.approve(&maker, &10_000);
+
+    set_time(&s.env, 100);
+    s.lop.reserve_part(&resolver_a, &order_hash, &0, &60);
+
+    set_time(&s.env, 159);
+    assert!(s.lop.get_part_reservation(&order_hash, &0).is_some());
+
+    set_time(&s.env, 160);
+    assert_eq!(s.lop.get_part_reservation(&order_hash, &0), None);
+    fill_as(&s, &resolver_b, &maker, 0, 2);
+}
+
+#[test]
+fn test_reservation_ttl_is_capped() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 2);
+    let resolver = Address::generate(&s.env);
+
+    set_time(&s.env, 100);
+    assert!(s.lop.try_reserve_part(&resolver, &order_hash, &0, &3_601).is_err());
+    // A TTL that would overflow the expiry timestamp is rejected rather than wrapping
+    assert!(s.lop.try_reserve_part(&resolver, &order_hash, &0, &u64::MAX).is_err());
+
+    s.lop.reserve_part(&resolver, &order_hash, &0, &3_600);
+    set_time(&s.env, 3_699);
+    assert_eq!(s.lop.get_part_reservation(&order_hash, &0), Some(resolver.clone()));
+    set_time(&s.env, 3_700);
+    assert_eq!(s.lop.get_part_reservation(&order_hash, &0), None);
+}
+
+#[test]
+fn test_reservations_per_reserver_are_capped() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 10);
+    let resolver = Address::generate(&s.env);
+    s.lop.approve(&maker, &10_000);
+
+    set_time(&s.env, 100);
+    for part in 0..5 {
+        s.lop.reserve_part(&resolver, &order_hash, &part, &60);
+    }
+    // Renewing a part already held does not count against the cap
+    s.lop.reserve_part(&resolver, &order_hash, &4, &120);
+    assert!(s.lop.try_reserve_part(&resolver, &order_hash, &5, &60).is_err());
+
+    // Another taker is unaffected
+    s.lop.reserve_part(&Address::generate(&s.env), &order_hash, &5, &60);
+
+    // Filling a reserved part frees a slot
+    fill_as(&s, &resolver, &maker, 0, 10);
+    s.lop.reserve_part(&resolver, &order_hash, &6, &60);
+    assert!(s.lop.try_reserve_part(&resolver, &order_hash, &7, &60).is_err());
+
+    // So does a reservation expiring
+    set_time(&s.env, 160);
+    s.lop.reserve_part(&resolver, &order_hash, &7, &60);
+}
+
+#[test]
+#[should_panic(expected = "Part already filled")]
+fn test_reserve_filled_part_reverts() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 2);
+    s.lop.approve(&maker, &10_000);
+
+    fill(&s, &maker, 0, 2);
+    s.lop.reserve_part(&Address::generate(&s.env), &order_hash, &0, &60);
+}
+
+fn part_range(env: &Env, count: u32) -> Vec<u64> {
+    let mut parts = Vec::new(env);
+    for i in 0..count as u64 {
+        parts.push_back(i);
+    }
+    parts
+}
+
+#[test]
+fn test_cancel_order_parts_at_max_batch_size() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 2);
+    s.lop.approve(&maker, &10_000);
+
+    fill(&s, &maker, 0, 2);
+    let results = s.lop.cancel_order_parts(&maker, &order_hash, &part_range(&s.env, MAX_BATCH_SIZE));
+    assert_eq!(results.len(), MAX_BATCH_SIZE);
+    assert!(results.get(0).unwrap());
+}
+
+#[test]
+#[should_panic(expected = "Batch too large")]
+fn test_cancel_order_parts_over_max_batch_size_reverts() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 2);
+    s.lop.approve(&maker, &10_000);
+
+    fill(&s, &maker, 0, 2);
+    s.lop.cancel_order_parts(&maker, &order_hash, &part_range(&s.env, MAX_BATCH_SIZE + 1));
+}
+
+#[test]
+fn test_compute_order_hash_matches_client() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let hashed_secret = BytesN::from_array(&s.env, &[2u8; 32]);
+
+    // Client-side packing, mirroring the documented layout
+    let mut packed = Bytes::new(&s.env);
+    packed.append(&maker.clone().to_xdr(&s.env));
+    packed.append(&s.recipient.clone().to_xdr(&s.env));
+    packed.extend_from_array(&5_000i128.to_be_bytes());
+    packed.extend_from_array(&hashed_secret.to_array());
+    packed.extend_from_array(&4u32.to_be_bytes());
+    packed.extend_from_array(&7u64.to_be_bytes());
+    let expected: BytesN<32> = s.env.crypto().sha256(&packed).into();
+
+    assert_eq!(s.lop.compute_order_hash(&maker, &s.recipient, &5_000, &hashed_secret, &4, &7), expected);
+    // Any field change yields a different hash
+    assert_ne!(s.lop.compute_order_hash(&maker, &s.recipient, &5_000, &hashed_secret, &4, &8), expected);
+}
+
+#[test]
+fn test_fill_records_under_canonical_order_hash() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    s.lop.approve(&maker, &10_000);
+
+    let escrow = fill(&s, &maker, 1, 2);
+    let part = s.lop.get_order_part(&order_hash(&s, &maker, 2), &1);
+    assert_eq!(part.escrow_id, escrow);
+
+    // A hash for other order fields has no fills
+    assert_eq!(s.lop.get_order(&order_hash(&s, &maker, 4)).len(), 0);
+}
+
+#[test]
+fn test_fill_hook_notified() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let hook = MockFillHookClient::new(&s.env, &s.env.register(MockFillHook, ()));
+    s.lop.approve(&maker, &10_000);
+    s.lop.set_fill_hook(&maker, &Some(hook.address.clone()));
+
+    let escrow = fill(&s, &maker, 1, 2);
+    assert_eq!(hook.last_fill(), Some((order_hash(&s, &maker, 2), 1, escrow)));
+}
+
+#[test]
+fn test_failing_fill_hook_does_not_revert_fill() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let hook = MockFillHookClient::new(&s.env, &s.env.register(MockFillHook, ()));
+    hook.set_fail(&true);
+    s.lop.approve(&maker, &10_000);
+    s.lop.set_fill_hook(&maker, &Some(hook.address.clone()));
+
+    fill(&s, &maker, 0, 2);
+    assert!(!s.lop.is_part_available(&order_hash(&s, &maker, 2), &0));
+    assert_eq!(hook.last_fill(), None);
+}
+
+#[test]
+fn test_get_order_progress() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 5);
+    s.lop.approve(&maker, &10_000);
+
+    fill(&s, &maker, 1, 5);
+    fill(&s, &maker, 3, 5);
+
+    let progress = s.lop.get_order_progress(&order_hash, &5);
+    assert_eq!(progress.filled_count, 2);
+    assert_eq!(progress.remaining_count, 3);
+    assert_eq!(progress.filled_indices, vec![&s.env, 1, 3]);
+    assert_eq!(progress.remaining_indices, vec![&s.env, 0, 2, 4]);
+}
+
+#[test]
+#[should_panic(expected = "Too many parts")]
+fn test_get_order_progress_bounded() {
+    let s = setup();
+    let order_hash = order_hash(&s, &Address::generate(&s.env), 2);
+    s.lop.get_order_progress(&order_hash, &(MAX_PROGRESS_PARTS + 1));
+}
+
+#[test]
+fn test_total_parts_fixed_by_first_fill() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 4);
+    s.lop.approve(&maker, &10_000);
+
+    fill(&s, &maker, 0, 4);
+    fill(&s, &maker, 2, 4);
+    assert_eq!(s.lop.get_order_total_parts(&order_hash), Some(4));
+    assert_eq!(s.lop.get_remaining_segments(&order_hash, &4), 2);
+
+    // A different part count is a different order, leaving the first untouched
+    fill(&s, &maker, 0, 8);
+    let order_hash_8 = self::order_hash(&s, &maker, 8);
+    assert_ne!(order_hash, order_hash_8);
+    assert_eq!(s.lop.get_order_total_parts(&order_hash_8), Some(8));
+    assert_eq!(s.lop.get_order_total_parts(&order_hash), Some(4));
+}
+
+#[test]
+#[should_panic(expected = "Total parts mismatch")]
+fn test_total_parts_mismatch_reverts() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 4);
+    s.lop.approve(&maker, &10_000);
+
+    fill(&s, &maker, 0, 4);
+    s.lop.get_available_part_indices(&order_hash, &8);
+}
+
+// Latest Approval event published by the LOP, as (owner, spender, new allowance)
+fn last_approval(s: &Setup) -> (Address, Address, i128) {
+    let (_, topics, data) = s.env.events().all().iter()
+        .filter(|(contract, topics, _)| {
+            *contract == s.lop.address
+                && Symbol::from_val(&s.env, &topics.get(0).unwrap()) == symbol_short!("Approval")
+        })
+        .last()
+        .unwrap();
+    (
+        Address::from_val(&s.env, &topics.get(2).unwrap()),
+        Address::from_val(&s.env, &topics.get(3).unwrap()),
+        i128::from_val(&s.env, &data),
+    )
+}
+
+#[test]
+fn test_approval_events_track_allowance() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+
+    s.lop.approve(&maker, &5_000);
+    assert_eq!(last_approval(&s), (maker.clone(), s.lop.address.clone(), 5_000));
+    // Same event shape as the factory's, with the allowance's token as the last topic
+    let (_, topics, _) = s.env.events().all().iter().last().unwrap();
+    assert_eq!(Address::from_val(&s.env, &topics.get(4).unwrap()), s.factory.get_native_token());
+
+    // Each fill draws the allowance down and reports the remainder
+    fill(&s, &maker, 0, 2);
+    assert_eq!(last_approval(&s), (maker.clone(), s.lop.address.clone(), 4_000));
+}
+
+fn from_hex<const N: usize>(env: &Env, hex: &str) -> BytesN<N> {
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+    }
+    BytesN::from_array(env, &bytes)
+}
+
+// Domain separator from the EIP-712 specification's Mail example
+const EIP712_DOMAIN: &str = "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f";
+// Address of the private key keccak256("cow") used by the EIP-712 specification
+const COW_SIGNER: &str = "cd2a3d9f938e13cd947ec05abc7fe734df8dd826";
+
+fn evm_order(env: &Env) -> EvmOrder {
+    let mut salt = [0u8; 32];
+    salt[31] = 1;
+    EvmOrder {
+        salt: BytesN::from_array(env, &salt),
+        maker: from_hex(env, COW_SIGNER),
+        receiver: BytesN::from_array(env, &[0x11; 20]),
+        maker_asset: BytesN::from_array(env, &[0x22; 20]),
+        taker_asset: BytesN::from_array(env, &[0x33; 20]),
+        making_amount: 4_000,
+        taking_amount: 1_000,
+        maker_traits: BytesN::from_array(env, &[0; 32]),
+    }
+}
+
+// Stellar recipient and hash lock that signed_evm_order's salt commits to
+const SIGNED_RECIPIENT: &str = "GAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAWHF";
+const SIGNED_HASH_LOCK: [u8; 32] = [2u8; 32];
+
+fn signed_recipient(env: &Env) -> Address {
+    Address::from_string(&String::from_str(env, SIGNED_RECIPIENT))
+}
+
+// evm_order with the fill extension for SIGNED_RECIPIENT and SIGNED_HASH_LOCK in the low 160 bits of its salt
+fn signed_evm_order(s: &Setup) -> EvmOrder {
+    let extension_hash = s.lop
+        .compute_fill_extension_hash(&BytesN::from_array(&s.env, &SIGNED_HASH_LOCK), &signed_recipient(&s.env))
+        .to_array();
+    let mut salt = [0u8; 32];
+    salt[12..].copy_from_slice(&extension_hash[12..]);
+    EvmOrder { salt: BytesN::from_array(&s.env, &salt), ..evm_order(&s.env) }
+}
+
+// signed_evm_order signed by the "cow" key as eth_signTypedData_v4 would, as r || s || v
+fn evm_order_signature(env: &Env) -> BytesN<65> {
+    from_hex(env, "cee8472f557f354ed13bcf8f1812e728632083403feaab5d630a8ab60d29444714bb5023e55950d3d7ee5c5652845f620ba2646723dddbcf8c49d709faf2b7241b")
+}
+
+#[test]
+fn test_eip712_spec_vector() {
+    let env = Env::default();
+    let struct_hash: BytesN<32> = from_hex(&env, "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e");
+    let digest = SimpleLimitOrderProtocol::hash_typed_data(&env, &from_hex(&env, EIP712_DOMAIN), &struct_hash.to_array());
+    let expected: BytesN<32> = from_hex(&env, "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2");
+    assert_eq!(digest.to_array(), expected.to_array());
+
+    // The specification's signature over that digest recovers the "cow" address
+    let signature = from_hex(&env, "4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b915621c");
+    assert_eq!(SimpleLimitOrderProtocol::recover_evm_signer(&env, &digest, &signature), from_hex(&env, COW_SIGNER));
+}
+
+#[test]
+fn test_compute_typed_order_digest() {
+    let s = setup();
+    let digest = s.lop.compute_typed_order_digest(&from_hex(&s.env, EIP712_DOMAIN), &evm_order(&s.env));
+    assert_eq!(digest, from_hex(&s.env, "2b7413306cfcb75bc6c17d719056c3bdb2a4b674d3e456bc835818e1019e7a89"));
+}
+
+#[test]
+fn test_fill_order_signed() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    s.lop.set_domain_separator(&s.owner, &from_hex(&s.env, EIP712_DOMAIN));
+    s.lop.register_evm_signer(&maker, &Some(from_hex(&s.env, COW_SIGNER)));
+    s.lop.approve(&maker, &4_000);
+
+    s.lop.fill_order_signed(
+        &Address::generate(&s.env),
+        &maker,
+        &signed_recipient(&s.env),
+        &signed_evm_order(&s),
+        &evm_order_signature(&s.env),
+        &BytesN::from_array(&s.env, &SIGNED_HASH_LOCK),
+        &1_000,
+        &2_000,
+        &1,
+        &4,
+    );
+
+    let digest = s.lop.compute_typed_order_digest(&from_hex(&s.env, EIP712_DOMAIN), &signed_evm_order(&s));
+    assert!(!s.lop.is_part_available(&digest, &1));
+    assert_eq!(s.lop.allowance(&maker, &s.lop.address), 3_000);
+}
+
+#[test]
+#[should_panic(expected = "Invalid signature")]
+fn test_fill_order_signed_rejects_tampered_order() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    s.lop.set_domain_separator(&s.owner, &from_hex(&s.env, EIP712_DOMAIN));
+    s.lop.register_evm_signer(&maker, &Some(from_hex(&s.env, COW_SIGNER)));
+    s.lop.approve(&maker, &8_000);
+
+    let mut order = signed_evm_order(&s);
+    order.making_amount = 8_000;
+    s.lop.fill_order_signed(
+        &Address::generate(&s.env),
+        &maker,
+        &signed_recipient(&s.env),
+        &order,
+        &evm_order_signature(&s.env),
+        &BytesN::from_array(&s.env, &SIGNED_HASH_LOCK),
+        &1_000,
+        &2_000,
+        &0,
+        &4,
+    );
+}
+
+#[test]
+fn test_fill_order_signed_rejects_unsigned_fill_parameters() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let taker = Address::generate(&s.env);
+    s.lop.set_domain_separator(&s.owner, &from_hex(&s.env, EIP712_DOMAIN));
+    s.lop.register_evm_signer(&maker, &Some(from_hex(&s.env, COW_SIGNER)));
+    s.lop.approve(&maker, &4_000);
+
+    // A taker can't redirect the escrow to itself or lock it under a secret of its own choosing
+    let fill_with = |recipient: &Address, hash_lock: [u8; 32]| {
+        s.lop.try_fill_order_signed(
+            &taker,
+            &maker,
+            recipient,
+            &signed_evm_order(&s),
+            &evm_order_signature(&s.env),
+            &BytesN::from_array(&s.env, &hash_lock),
+            &1_000,
+            &2_000,
+            &0,
+            &4,
+        )
+    };
+    assert!(fill_with(&taker, SIGNED_HASH_LOCK).is_err());
+    assert!(fill_with(&signed_recipient(&s.env), [9u8; 32]).is_err());
+    assert_eq!(s.lop.allowance(&maker, &s.lop.address), 4_000);
+
+    fill_with(&signed_recipient(&s.env), SIGNED_HASH_LOCK).unwrap().unwrap();
+    assert_eq!(s.lop.allowance(&maker, &s.lop.address), 3_000);
+}
+
+#[test]
+fn test_can_complete_order() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 4);
+    s.lop.approve(&maker, &10_000);
+    assert!(s.lop.can_complete_order(&order_hash, &4));
+
+    fill(&s, &maker, 0, 4);
+    fill(&s, &maker, 2, 4);
+    assert!(s.lop.can_complete_order(&order_hash, &4));
+
+    // A cancelled part can't be refilled, so the order can never complete
+    s.lop.cancel_order(&maker, &order_hash, &2);
+    assert!(!s.lop.is_part_available(&order_hash, &2));
+    assert!(!s.lop.can_complete_order(&order_hash, &4));
+}
+
+#[test]
+fn test_reactivate_part() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 4);
+    s.lop.approve(&maker, &10_000);
+    fill(&s, &maker, 0, 4);
+    fill(&s, &maker, 2, 4);
+
+    s.env.ledger().with_mut(|li| li.timestamp += 100);
+    let first_fill_time = s.lop.get_order_stats(&order_hash).first_fill_time;
+    s.lop.cancel_order(&maker, &order_hash, &2);
+    s.lop.reactivate_part(&maker, &order_hash, &2);
+    assert!(s.lop.is_part_available(&order_hash, &2));
+
+    // The cancelled part no longer counts as filled or cancelled
+    let stats = s.lop.get_order_stats(&order_hash);
+    assert_eq!(stats.total_parts_filled, 1);
+    assert_eq!(stats.total_parts_cancelled, 0);
+    assert_eq!(s.lop.get_order_vwap(&order_hash), 1_000);
+    assert_eq!(s.lop.get_remaining_segments(&order_hash, &4), 3);
+    assert!(s.lop.can_complete_order(&order_hash, &4));
+
+    // The part refills under a fresh active record
+    fill(&s, &maker, 2, 4);
+    assert!(s.lop.get_order_part(&order_hash, &2).is_active);
+    assert_eq!(s.lop.get_order(&order_hash).len(), 2);
+    assert_eq!(s.lop.get_user_filled_orders(&maker).len(), 1);
+    let stats = s.lop.get_order_stats(&order_hash);
+    assert_eq!(stats.total_parts_filled, 2);
+    assert_eq!(stats.total_parts_cancelled, 0);
+    assert_eq!(stats.first_fill_time, first_fill_time);
+}
+
+#[test]
+fn test_reactivate_part_requires_cancelled_part() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 4);
+    s.lop.approve(&maker, &10_000);
+    let escrow = fill(&s, &maker, 1, 4);
+    fill(&s, &maker, 3, 4);
+
+    // Only the maker may reactivate a cancelled part
+    s.lop.cancel_order(&maker, &order_hash, &3);
+    assert!(s.lop.try_reactivate_part(&Address::generate(&s.env), &order_hash, &3).is_err());
+    assert!(!s.lop.is_part_available(&order_hash, &3));
+
+    // An active part, and one whose escrow was withdrawn so it could not be cancelled
+    assert!(s.lop.try_reactivate_part(&maker, &order_hash, &1).is_err());
+    s.factory.mark_withdrawn(&escrow);
+    assert!(s.lop.try_cancel_order(&maker, &order_hash, &1).is_err());
+    assert!(s.lop.try_reactivate_part(&maker, &order_hash, &1).is_err());
+}
+
+#[test]
+fn test_get_order_vwap() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 4);
+    s.lop.approve(&maker, &10_000);
+    assert_eq!(s.lop.get_order_vwap(&order_hash), 0);
+
+    // Parts of one order share the amount committed by its hash
+    fill(&s, &maker, 0, 4);
+    fill(&s, &maker, 1, 4);
+    assert_eq!(s.lop.get_order_part(&order_hash, &0).amount, 1_000);
+    assert_eq!(s.lop.get_order_vwap(&order_hash), 1_000);
+
+    // Re-record the parts at different amounts and add a cancelled one to check the weighting
+    let mut parts = s.lop.get_order(&order_hash);
+    let mut part = parts.get(1).unwrap();
+    part.amount = 3_000;
+    parts.set(1, part.clone());
+    part.part_index = 2;
+    part.amount = 50_000;
+    part.is_active = false;
+    parts.push_back(part);
+    s.env.as_contract(&s.lop.address, || {
+        s.env.storage().persistent().set(&DataKey::FilledOrders(order_hash.clone()), &parts);
+    });
+
+    // (1_000 * 1_000 + 3_000 * 3_000) / (1_000 + 3_000)
+    assert_eq!(s.lop.get_order_vwap(&order_hash), 2_500);
+}
+
+#[test]
+fn test_max_fills() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    s.lop.approve(&maker, &10_000);
+    let hashed_secret = BytesN::from_array(&s.env, &[2u8; 32]);
+    s.lop.set_max_fills(&maker, &s.recipient, &1_000, &hashed_secret, &4, &0, &Some(2));
+    assert_eq!(s.lop.get_max_fills(&order_hash(&s, &maker, 4)), Some(2));
+
+    fill(&s, &maker, 0, 4);
+    fill(&s, &maker, 3, 4);
+    let result = s.lop.try_fill_order(
+        &Address::generate(&s.env),
+        &maker,
+        &s.recipient,
+        &1_000,
+        &hashed_secret,
+        &1_000,
+        &2_000,
+        &1,
+        &4,
+        &0,
+    );
+    assert!(result.is_err());
+    assert_eq!(s.lop.get_order(&order_hash(&s, &maker, 4)).len(), 2);
+
+    // Lifting the cap lets the remaining parts fill
+    s.lop.set_max_fills(&maker, &s.recipient, &1_000, &hashed_secret, &4, &0, &None);
+    fill(&s, &maker, 1, 4);
+}
+
+#[test]
+fn test_get_consumed() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let spender = s.lop.address.clone();
+    s.lop.approve(&maker, &10_000);
+
+    fill(&s, &maker, 0, 4);
+    fill(&s, &maker, 2, 4);
+    assert_eq!(s.lop.get_consumed(&maker, &spender), 2_000);
+    assert_eq!(s.lop.get_consumed(&maker, &spender) + s.lop.allowance(&maker, &spender), 10_000);
+
+    // A fresh approval is a new grant
+    s.lop.approve(&maker, &5_000);
+    assert_eq!(s.lop.get_consumed(&maker, &spender), 0);
+}
+
+#[test]
+fn test_fill_with_maker_as_recipient() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    s.lop.approve(&maker, &10_000);
+
+    let hashed_secret = BytesN::from_array(&s.env, &[2u8; 32]);
+    let taker = Address::generate(&s.env);
+    s.lop.fill_order(&taker, &maker, &maker, &1_000, &hashed_secret, &1_000, &2_000, &0, &2, &0);
+
+    // The maker's allowance is drawn once for the part, and the order is recorded under the maker
+    assert_eq!(s.lop.allowance(&maker, &s.lop.address), 9_000);
+    let order_hash = s.lop.compute_order_hash(&maker, &maker, &1_000, &hashed_secret, &2, &0);
+    let part = s.lop.get_order_part(&order_hash, &0);
+    assert_eq!((part.maker, part.recipient), (maker.clone(), maker.clone()));
+    assert_eq!(s.lop.get_user_filled_orders(&maker).len(), 1);
+}
+
+// Namespace topic of the LOP's most recent Approval event
+fn last_approval_namespace(env: &Env, lop: &Address) -> Symbol {
+    let (_, topics, _) = env.events().all().iter()
+        .filter(|(contract, _, _)| contract == lop)
+        .last()
+        .unwrap();
+    Symbol::from_val(env, &topics.get(1).unwrap())
+}
+
+#[test]
+fn test_event_namespace() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+
+    s.lop.approve(&maker, &1_000);
+    assert_eq!(last_approval_namespace(&s.env, &s.lop.address), Symbol::new(&s.env, "fusion"));
+
+    let other = SimpleLimitOrderProtocolClient::new(&s.env, &s.env.register(SimpleLimitOrderProtocol, ()));
+    other.initialize(&s.factory.address, &s.owner, &Some(symbol_short!("testnet2")));
+    other.approve(&maker, &1_000);
+    assert_eq!(last_approval_namespace(&s.env, &other.address), symbol_short!("testnet2"));
+    assert_eq!(other.get_event_namespace(), symbol_short!("testnet2"));
+}
+
+#[test]
+#[should_panic(expected = "Already initialized")]
+fn test_initialize_twice_reverts() {
+    let s = setup();
+    s.lop.initialize(&Address::generate(&s.env), &Address::generate(&s.env), &None);
+}
+
+// Post an order of `amount` from maker to recipient under the shared test hashlock
+fn post(s: &Setup, maker: &Address, recipient: &Address, amount: i128, cancellation_start: u64) -> BytesN<32> {
+    s.lop.post_order(
+        maker,
+        recipient,
+        &amount,
+        &BytesN::from_array(&s.env, &[2u8; 32]),
+        &1_000,
+        &2_000,
+        &cancellation_start,
+        &0,
+    )
+}
+
+#[test]
+fn test_match_orders() {
+    let s = setup();
+    let alice = Address::generate(&s.env);
+    let bob = Address::generate(&s.env);
+    s.lop.approve(&alice, &1_000);
+
+    let src_hash = post(&s, &alice, &bob, 1_000, 50_000);
+    let dst_hash = post(&s, &bob, &alice, 1_000, 50_000);
+    let (src_escrow, dst_escrow) = s.lop.match_orders(&src_hash, &dst_hash);
+    assert_eq!((src_escrow, dst_escrow), (escrow_id_of(&s.env, &alice), escrow_id_of(&s.env, &bob)));
+
+    // The source leg is recorded as a fill and both orders leave the book
+    let part = s.lop.get_order_part(&src_hash, &0);
+    assert_eq!((part.maker, part.recipient, part.amount), (alice.clone(), bob, 1_000));
+    assert_eq!(s.lop.allowance(&alice, &s.lop.address), 0);
+    assert_eq!(s.lop.get_posted_order(&src_hash), None);
+    assert_eq!(s.lop.get_posted_order(&dst_hash), None);
+}
+
+#[test]
+fn test_match_orders_rejects_incompatible_orders() {
+    let s = setup();
+    let alice = Address::generate(&s.env);
+    let bob = Address::generate(&s.env);
+    s.lop.approve(&alice, &1_000);
+
+    let src_hash = post(&s, &alice, &bob, 1_000, 50_000);
+    let short_dst = post(&s, &bob, &alice, 900, 50_000);
+    assert!(s.lop.try_match_orders(&src_hash, &short_dst).is_err());
+
+    // The destination leg must become cancellable before the source leg
+    let late_dst = post(&s, &bob, &alice, 1_000, 1_000 + 86_400);
+    assert!(s.lop.try_match_orders(&src_hash, &late_dst).is_err());
+    assert!(s.lop.get_posted_order(&src_hash).is_some());
+}
+
+#[test]
+#[should_panic(expected = "Time window overflow")]
+fn test_match_orders_rejects_overflowing_windows() {
+    let s = setup();
+    let alice = Address::generate(&s.env);
+    let bob = Address::generate(&s.env);
+    s.lop.approve(&alice, &1_000);
+
+    let src_hash = post(&s, &alice, &bob, 1_000, 50_000);
+    let dst_hash = post(&s, &bob, &alice, 1_000, u64::MAX - 1);
+    s.lop.match_orders(&src_hash, &dst_hash);
+}
+
+#[test]
+fn test_lop_deposit_exposure() {
+    let s = setup();
+    assert_eq!(s.lop.get_lop_deposit_exposure(), 0);
+
+    let makers = [Address::generate(&s.env), Address::generate(&s.env), Address::generate(&s.env)];
+    for maker in makers.iter() {
+        s.lop.approve(maker, &1_000);
+        fill(&s, maker, 0, 1);
+    }
+    assert_eq!(s.lop.get_lop_deposit_exposure(), 3 * MOCK_DEPOSIT);
+
+    // Cancelling through the LOP releases the deposit at once
+    s.lop.cancel_order(&makers[1], &order_hash(&s, &makers[1], 1), &0);
+    assert_eq!(s.lop.get_lop_deposit_exposure(), 2 * MOCK_DEPOSIT);
+
+    // An escrow withdrawn on the factory counts until it is released, and reading the exposure changes nothing
+    let withdrawn = escrow_id_of(&s.env, &makers[0]);
+    s.factory.mark_withdrawn(&withdrawn);
+    assert_eq!(s.lop.get_lop_deposit_exposure(), 2 * MOCK_DEPOSIT);
+    s.lop.release_settled_escrow(&withdrawn);
+    assert_eq!(s.lop.get_lop_deposit_exposure(), MOCK_DEPOSIT);
+
+    // Releasing twice, or releasing an escrow still holding its deposit, reverts
+    assert!(s.lop.try_release_settled_escrow(&withdrawn).is_err());
+    assert!(s.lop.try_release_settled_escrow(&escrow_id_of(&s.env, &makers[2])).is_err());
+    assert_eq!(s.lop.get_lop_deposit_exposure(), MOCK_DEPOSIT);
+}
+
+#[test]
+fn test_cancel_order_parts_releases_deposit_exposure() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 4);
+    s.lop.approve(&maker, &10_000);
+
+    fill(&s, &maker, 0, 4);
+    assert_eq!(s.lop.get_lop_deposit_exposure(), MOCK_DEPOSIT);
+
+    // The batch path settles each part the same way cancel_order does
+    let results = s.lop.cancel_order_parts(&maker, &order_hash, &vec![&s.env, 0, 1]);
+    assert_eq!(results, vec![&s.env, true, false]);
+    assert_eq!(s.lop.get_lop_deposit_exposure(), 0);
+    assert_eq!(s.lop.allowance(&maker, &s.lop.address), 10_000);
+}
+
+#[test]
+fn test_pause_new_orders_keeps_existing_orders_fillable() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let alice = Address::generate(&s.env);
+    let bob = Address::generate(&s.env);
+    s.lop.approve(&maker, &10_000);
+    s.lop.approve(&alice, &1_000);
+
+    fill(&s, &maker, 0, 2);
+    let src_hash = post(&s, &alice, &bob, 1_000, 50_000);
+    let dst_hash = post(&s, &bob, &alice, 1_000, 50_000);
+    s.lop.pause_new_orders(&s.owner);
+    assert!(s.lop.is_new_orders_paused());
+
+    // Parts of the partly filled order and the posted orders still go through
+    fill(&s, &maker, 1, 2);
+    s.lop.match_orders(&src_hash, &dst_hash);
+
+    // New posts and first fills of unseen orders revert
+    let other = Address::generate(&s.env);
+    s.lop.approve(&other, &10_000);
+    assert!(s.lop.try_post_order(
+        &other,
+        &s.recipient,
+        &1_000,
+        &BytesN::from_array(&s.env, &[2u8; 32]),
+        &1_000,
+        &2_000,
+        &50_000,
+        &0,
+    ).is_err());
+    assert!(s.lop.try_fill_order(
+        &Address::generate(&s.env),
+        &other,
+        &s.recipient,
+        &1_000,
+        &BytesN::from_array(&s.env, &[2u8; 32]),
+        &1_000,
+        &2_000,
+        &0,
+        &2,
+        &0,
+    ).is_err());
+
+    s.lop.resume_new_orders(&s.owner);
+    fill(&s, &other, 0, 2);
+}
+
+#[test]
+#[should_panic(expected = "Only owner can pause")]
+fn test_pause_new_orders_owner_only() {
+    let s = setup();
+    s.lop.pause_new_orders(&Address::generate(&s.env));
+}
+
+#[test]
+fn test_taker_filled_orders() {
+    let s = setup();
+    let resolver = Address::generate(&s.env);
+    let maker_a = Address::generate(&s.env);
+    let maker_b = Address::generate(&s.env);
+    s.lop.approve(&maker_a, &10_000);
+    s.lop.approve(&maker_b, &10_000);
+
+    fill_as(&s, &resolver, &maker_a, 0, 4);
+    fill_as(&s, &resolver, &maker_a, 2, 4);
+    fill_as(&s, &resolver, &maker_b, 1, 2);
+    fill(&s, &maker_b, 0, 2);
+
+    assert_eq!(
+        s.lop.get_taker_filled_orders(&resolver, &0, &10),
+        vec![&s.env, order_hash(&s, &maker_a, 4), order_hash(&s, &maker_b, 2)]
+    );
+    assert_eq!(s.lop.get_taker_filled_orders(&resolver, &1, &1), vec![&s.env, order_hash(&s, &maker_b, 2)]);
+    assert_eq!(s.lop.get_taker_filled_count(&resolver), 2);
+    assert!(s.lop.has_taker_filled(&resolver, &order_hash(&s, &maker_a, 4)));
+    assert!(!s.lop.has_taker_filled(&maker_a, &order_hash(&s, &maker_a, 4)));
+    assert_eq!(s.lop.get_taker_filled_orders(&maker_a, &0, &10).len(), 0);
+}
+
+#[test]
+fn test_cancel_order_restores_balance_and_allowance() {
+    let s = setup();
+    // The maker's token transfer is authorized below the LOP call it was made from
+    s.env.mock_all_auths_allowing_non_root_auth();
+    let maker = Address::generate(&s.env);
+    let token = s.env.register_stellar_asset_contract_v2(Address::generate(&s.env)).address();
+    let token_client = TokenClient::new(&s.env, &token);
+    s.factory.set_token(&token);
+    StellarAssetClient::new(&s.env, &token).mint(&maker, &1_000);
+    s.lop.approve(&maker, &3_000);
+
+    fill(&s, &maker, 0, 2);
+    assert_eq!(token_client.balance(&maker), 0);
+    assert_eq!(s.lop.allowance(&maker, &s.lop.address), 2_000);
+    assert_eq!(s.lop.get_consumed(&maker, &s.lop.address), 1_000);
+
+    s.lop.cancel_order(&maker, &order_hash(&s, &maker, 2), &0);
+    assert_eq!(token_client.balance(&maker), 1_000);
+    assert_eq!(s.lop.allowance(&maker, &s.lop.address), 3_000);
+    assert_eq!(s.lop.get_consumed(&maker, &s.lop.address), 0);
+}
+
+#[test]
+fn test_cancel_after_revoke_keeps_allowance_revoked() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 4);
+    s.lop.approve(&maker, &10_000);
+    fill(&s, &maker, 0, 4);
+    fill(&s, &maker, 1, 4);
+
+    // Revoking replaces the grant the fills drew from, so cancelling must not re-open it
+    s.lop.approve(&maker, &0);
+    s.lop.cancel_order(&maker, &order_hash, &0);
+    assert_eq!(s.lop.allowance(&maker, &s.lop.address), 0);
+
+    // The same holds for a fresh approval: it isn't topped up by fills made under the old grant
+    s.lop.approve(&maker, &500);
+    s.lop.cancel_order_parts(&maker, &order_hash, &vec![&s.env, 1]);
+    assert_eq!(s.lop.allowance(&maker, &s.lop.address), 500);
+    assert_eq!(s.lop.get_consumed(&maker, &s.lop.address), 0);
+}
+
+#[test]
+fn test_cancel_order_as_taker() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let taker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 2);
+    s.lop.approve(&maker, &2_000);
+    fill_as(&s, &taker, &maker, 0, 2);
+
+    // Neither the maker nor another taker can use the taker path
+    assert!(s.lop.try_cancel_order_as_taker(&maker, &order_hash, &0).is_err());
+    assert!(s.lop.try_cancel_order_as_taker(&Address::generate(&s.env), &order_hash, &0).is_err());
+
+    // The taker cancels through the factory's public path, with the LOP collecting the keeper share
+    s.lop.cancel_order_as_taker(&taker, &order_hash, &0);
+    assert!(!s.lop.get_order_part(&order_hash, &0).is_active);
+    assert_eq!(s.factory.last_keeper(), Some(s.lop.address.clone()));
+    assert_eq!(s.lop.allowance(&maker, &s.lop.address), 2_000);
+    assert_eq!(s.lop.get_order_stats(&order_hash).total_parts_cancelled, 1);
+}
+
+#[test]
+fn test_cancel_order_parts_restores_allowance_for_cancelled_parts_only() {
+    let s = setup();
+    let maker = Address::generate(&s.env);
+    let order_hash = order_hash(&s, &maker, 4);
+    s.lop.approve(&maker, &10_000);
+
+    fill(&s, &maker, 0, 4);
+    fill(&s, &maker, 1, 4);
+    assert_eq!(s.lop.allowance(&maker, &s.lop.address), 8_000);
+
+    // Part 2 was never filled, so only part 1 is cancelled and credited back
+    s.lop.cancel_order_parts(&maker, &order_hash, &vec![&s.env, 1, 2]);
+    assert_eq!(s.lop.allowance(&maker, &s.lop.address), 9_000);
+    assert_eq!(s.lop.get_consumed(&maker, &s.lop.address), 1_000);
+}
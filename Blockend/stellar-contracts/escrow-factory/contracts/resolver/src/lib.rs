@@ -1,6 +1,7 @@
 #![no_std]
+#![allow(clippy::too_many_arguments)]
 use soroban_sdk::{
-    contract, contractimpl, contracttype, contractclient, Address, Bytes, BytesN, Env, Vec, log,
+    contract, contractimpl, contracttype, contractclient, token, Address, Bytes, BytesN, Env, Vec, log,
 };
 
 // LimitOrderProtocol client interface
@@ -8,7 +9,7 @@ use soroban_sdk::{
 pub trait LimitOrderProtocolTrait {
     fn fill_order(
         env: Env,
-        order_hash: BytesN<32>,
+        taker: Address,
         maker: Address,
         recipient: Address,
         token_amount: i128,
@@ -17,15 +18,28 @@ pub trait LimitOrderProtocolTrait {
         public_withdrawal_start: u64,
         part_index: u64,
         total_parts: u32,
-    ) -> Address;
+        nonce: u64,
+    ) -> BytesN<32>;
+    
+    fn compute_order_hash(
+        env: Env,
+        maker: Address,
+        recipient: Address,
+        token_amount: i128,
+        hashed_secret: BytesN<32>,
+        total_parts: u32,
+        nonce: u64,
+    ) -> BytesN<32>;
     
     fn cancel_order(env: Env, caller: Address, order_hash: BytesN<32>, part_index: u64);
+    fn cancel_order_as_taker(env: Env, caller: Address, order_hash: BytesN<32>, part_index: u64);
     fn get_order(env: Env, order_hash: BytesN<32>) -> Vec<FilledOrder>;
     fn get_order_part(env: Env, order_hash: BytesN<32>, part_index: u64) -> FilledOrder;
     fn get_remaining_segments(env: Env, order_hash: BytesN<32>, total_parts: u32) -> u64;
     fn is_part_available(env: Env, order_hash: BytesN<32>, part_index: u64) -> bool;
     fn get_available_part_indices(env: Env, order_hash: BytesN<32>, total_parts: u32) -> Vec<u64>;
     fn get_user_filled_orders(env: Env, user: Address) -> Vec<BytesN<32>>;
+    fn approve(env: Env, caller: Address, amount: i128);
 }
 
 // EscrowFactory client interface
@@ -36,35 +50,51 @@ pub trait EscrowFactoryTrait {
         creator: Address,
         hashed_secret: BytesN<32>,
         recipient: Address,
+        token: Address,
         token_amount: i128,
-        withdrawal_start: u64,
-        public_withdrawal_start: u64,
-        cancellation_start: u64,
+        windows: TimeWindows,
         part_index: u64,
         total_parts: u32,
-    ) -> Address;
+        part_proof: Option<PartProof>,
+        require_dual_auth: bool,
+    ) -> BytesN<32>;
     
-    fn get_user_escrows(env: Env, user: Address) -> Vec<Address>;
-    fn get_src_escrow(env: Env, escrow_address: Address) -> SourceEscrowData;
-    fn withdraw_src_escrow(env: Env, caller: Address, escrow_address: Address, secret: Bytes);
+    fn get_user_escrows(env: Env, user: Address) -> Vec<BytesN<32>>;
+    fn get_src_escrow(env: Env, escrow_id: BytesN<32>) -> SourceEscrowData;
+    fn withdraw_src_escrow(env: Env, caller: Address, escrow_id: BytesN<32>, secret: Bytes);
     fn withdraw_src_escrow_with_proof(
         env: Env, 
         caller: Address, 
-        escrow_address: Address, 
+        escrow_id: BytesN<32>, 
         secret: Bytes, 
         merkle_proof: Vec<BytesN<32>>
     );
-    fn get_dst_escrow(env: Env, escrow_address: Address) -> DestinationEscrowData;
-    fn withdraw_dst_escrow(env: Env, caller: Address, escrow_address: Address, secret: Bytes);
+    fn get_dst_escrow(env: Env, escrow_id: BytesN<32>) -> DestinationEscrowData;
+    fn cancel_dst_escrow(env: Env, caller: Address, escrow_id: BytesN<32>);
+    fn withdraw_dst_escrow(env: Env, caller: Address, escrow_id: BytesN<32>, secret: Bytes);
     fn withdraw_dst_escrow_with_proof(
         env: Env, 
         caller: Address, 
-        escrow_address: Address, 
+        escrow_id: BytesN<32>, 
         secret: Bytes, 
         merkle_proof: Vec<BytesN<32>>
     );
 }
 
+// Price oracle client interface (price of one source unit in destination units, scaled by PRICE_SCALE)
+#[contractclient(name = "PriceOracleClient")]
+pub trait PriceOracleTrait {
+    fn get_price(env: Env) -> i128;
+}
+
+// Hash algorithm of an escrow (matching the EscrowFactory)
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HashAlgo {
+    Sha256,
+    Keccak256,
+}
+
 // Source escrow data structure (matching the EscrowFactory)
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -72,6 +102,7 @@ pub struct SourceEscrowData {
     pub creator: Address,
     pub recipient: Address,
     pub hashed_secret: BytesN<32>,
+    pub hash_algo: HashAlgo,
     pub token: Address,
     pub amount: i128,
     pub security_deposit: i128,
@@ -84,6 +115,15 @@ pub struct SourceEscrowData {
     pub part_index: u64,
     pub total_parts: u32,
     pub is_partial_fill: bool,
+    pub created_at: u64,
+    pub settlement_callback: Option<Address>,
+    pub allowance_consumed: i128,
+    pub gas_deposit: i128,
+    pub rescue_recipient: Option<Address>,
+    pub proposed_cancellation_start: Option<u64>,
+    pub require_dual_auth: bool,
+    pub funded_via_allowance: bool,
+    pub frozen: bool,
 }
 
 // Destination escrow data structure (matching the EscrowFactory)
@@ -93,9 +133,11 @@ pub struct DestinationEscrowData {
     pub creator: Address,
     pub recipient: Address,
     pub hashed_secret: BytesN<32>,
+    pub hash_algo: HashAlgo,
     pub token: Address,
     pub amount: i128,
     pub security_deposit: i128,
+    pub exclusive_withdrawal_start: u64,
     pub withdrawal_start: u64,
     pub public_withdrawal_start: u64,
     pub cancellation_start: u64,
@@ -105,24 +147,113 @@ pub struct DestinationEscrowData {
     pub part_index: u64,
     pub total_parts: u32,
     pub is_partial_fill: bool,
+    pub created_at: u64,
+    pub settlement_callback: Option<Address>,
+    pub recipient_is_contract: bool,
+    pub require_dual_auth: bool,
+    pub auto_refund_deadline: u64,
+    pub frozen: bool,
+}
+
+// Creation-time proof of a partial destination part (matching the EscrowFactory)
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PartProof {
+    pub secret_hash: BytesN<32>,
+    pub merkle_proof: Vec<BytesN<32>>,
+}
+
+// Escrow time windows (matching the EscrowFactory)
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TimeWindows {
+    pub withdrawal_start: u64,
+    pub public_withdrawal_start: u64,
+    pub cancellation_start: u64,
+    pub public_cancellation_start: u64,
+}
+
+// One swap to execute, with the same fields as execute_cross_chain_swap
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SwapRequest {
+    pub maker: Address,
+    pub recipient: Address,
+    pub token_amount: i128,
+    pub hashed_secret: BytesN<32>,
+    pub withdrawal_start: u64,
+    pub part_index: u64,
+    pub total_parts: u32,
+    pub nonce: u64,
+    pub idempotency_key: BytesN<32>,
+}
+
+// Destination leg of a tracked swap
+// An enum rather than Option, which this SDK cannot convert for BytesN fields
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DstEscrow {
+    Pending,
+    Created(BytesN<32>),
 }
+
+// Swap initiated by this resolver (tracked for operator dashboards)
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SwapInfo {
+    pub order_hash: BytesN<32>,
+    pub part_index: u64,
+    pub hashed_secret: BytesN<32>,
+    pub src_escrow: BytesN<32>,
+    pub dst_escrow: DstEscrow,
+    pub secret_revealed: bool,
+}
+
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct FilledOrder {
     pub order_hash: BytesN<32>,
     pub maker: Address,
+    pub taker: Address,
     pub recipient: Address,
-    pub escrow_address: Address,
+    pub escrow_id: BytesN<32>,
     pub part_index: u64,
     pub total_parts: u32,
     pub is_active: bool,
+    pub amount: i128,
+    pub grant_epoch: u64,
 }
 
+// Consecutive swap failures after which execution halts until the owner resets the breaker
+const MAX_FAILURES: u32 = 3;
+
+// Operator roles, combinable as a bitmask; the owner implicitly holds all of them
+pub const ROLE_EXECUTE: u32 = 1; // execute swaps and create destination escrows
+pub const ROLE_COMPLETE: u32 = 2; // complete swaps by withdrawing from source escrows
+pub const ROLE_CANCEL: u32 = 4; // cancel order parts
+const ALL_ROLES: u32 = ROLE_EXECUTE | ROLE_COMPLETE | ROLE_CANCEL;
+
+// Largest batch accepted by execute_cross_chain_swaps_batch; each swap is a fill plus an escrow creation
+const MAX_BATCH_SWAPS: u32 = 10;
+
+// Fixed-point scale of oracle prices (7 decimals, matching Stellar amounts)
+const PRICE_SCALE: i128 = 10_000_000;
+
 #[contracttype]
 pub enum DataKey {
     LimitOrderProtocol, // LOP contract address
     EscrowFactory, // factory contract address
     Owner, // contract owner
+    FailureCount, // consecutive failed swap executions
+    SwapCount, // u32 - swaps initiated by this resolver
+    Swap(u32), // position -> SwapInfo, in initiation order
+    SwapBySrc(BytesN<32>), // source escrow -> position of its swap
+    SwapByDst(BytesN<32>), // destination escrow -> position of its swap
+    PendingSwap(BytesN<32>, u64), // (hashlock, part) -> position of the swap awaiting its destination leg
+    PendingOwner, // proposed owner awaiting acceptance
+    PriceOracle, // optional oracle used to quote destination amounts
+    ExecutedKey(BytesN<32>), // idempotency key -> escrow created by that execution
+    Roles(Address), // operator -> role bitmask
 }
 
 #[contract]
@@ -137,16 +268,21 @@ impl SimpleResolver {
         escrow_factory: Address,
         owner: Address
     ) {
+        Self::require_not_self(&env, &lop_address);
+        Self::require_not_self(&env, &escrow_factory);
+
         env.storage().instance().set(&DataKey::LimitOrderProtocol, &lop_address);
         env.storage().instance().set(&DataKey::EscrowFactory, &escrow_factory);
         env.storage().instance().set(&DataKey::Owner, &owner);
     }
 
     /// Execute a cross-chain swap by filling an order and creating escrow
+    /// Returns None if the fill failed; the failure is counted towards the circuit breaker
+    /// Repeating a call with an already-used idempotency key returns the original escrow without re-executing
+    /// The order is identified by the LOP's canonical hash of its fields and nonce
     pub fn execute_cross_chain_swap(
         env: Env,
         caller: Address,
-        order_hash: BytesN<32>,
         maker: Address,
         recipient: Address,
         token_amount: i128,
@@ -154,54 +290,204 @@ impl SimpleResolver {
         withdrawal_start: u64,
         part_index: u64,
         total_parts: u32,
-    ) -> Address {
-        // Only owner can execute
-        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
-        if caller != owner {
-            panic!("Only owner can execute");
+        nonce: u64,
+        idempotency_key: BytesN<32>,
+    ) -> Option<BytesN<32>> {
+        Self::require_role(&env, &caller, ROLE_EXECUTE);
+        caller.require_auth();
+
+        Self::execute_swap(
+            &env,
+            &SwapRequest {
+                maker,
+                recipient,
+                token_amount,
+                hashed_secret,
+                withdrawal_start,
+                part_index,
+                total_parts,
+                nonce,
+                idempotency_key,
+            },
+        )
+    }
+
+    /// Execute several swaps in one transaction, returning the source escrow of each in order
+    /// All or nothing: if any fill fails the whole batch reverts, including its failure count
+    pub fn execute_cross_chain_swaps_batch(env: Env, caller: Address, swaps: Vec<SwapRequest>) -> Vec<BytesN<32>> {
+        Self::require_role(&env, &caller, ROLE_EXECUTE);
+        caller.require_auth();
+
+        if swaps.len() > MAX_BATCH_SWAPS {
+            panic!("Batch too large");
         }
+
+        let mut escrows = Vec::new(&env);
+        for request in swaps.iter() {
+            match Self::execute_swap(&env, &request) {
+                Some(escrow_id) => escrows.push_back(escrow_id),
+                None => panic!("Swap failed"),
+            }
+        }
+        escrows
+    }
+
+    /// Set the maker's LOP allowance and fill their order in one transaction, returning the source escrow
+    /// The maker authorizes the approval inline, so no separate approve transaction is needed
+    /// All or nothing: if the fill fails the approval is rolled back too
+    pub fn prepare_and_fill(
+        env: Env,
+        caller: Address,
+        maker: Address,
+        approve_amount: i128,
+        order_params: SwapRequest,
+    ) -> BytesN<32> {
+        Self::require_role(&env, &caller, ROLE_EXECUTE);
         caller.require_auth();
+        maker.require_auth();
+
+        if order_params.maker != maker {
+            panic!("Maker mismatch");
+        }
 
-        // Get LOP address
         let lop_address: Address = env.storage()
             .instance()
             .get(&DataKey::LimitOrderProtocol)
             .unwrap();
+        LimitOrderProtocolTraitClient::new(&env, &lop_address).approve(&maker, &approve_amount);
 
-        // Fill the order through LOP (this creates the escrow)
-        let lop_client = LimitOrderProtocolTraitClient::new(&env, &lop_address);
-        let escrow_address = lop_client.fill_order(
-            &order_hash,
-            &maker,
-            &recipient,
-            &token_amount,
-            &hashed_secret,
-            &withdrawal_start,
-            &(withdrawal_start + 1800), // public_withdrawal_start (30 min later)
-            &part_index,
-            &total_parts,
-        );
+        Self::execute_swap(&env, &order_params).unwrap_or_else(|| panic!("Fill failed"))
+    }
+
+    /// Grant roles (a bitmask of ROLE_* values) to an operator, adding to any it already holds
+    pub fn grant_role(env: Env, caller: Address, operator: Address, role: u32) {
+        // Only owner can grant
+        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
+        if caller != owner {
+            panic!("Only owner can grant");
+        }
+        caller.require_auth();
+        if role == 0 || role & !ALL_ROLES != 0 {
+            panic!("Invalid role");
+        }
+
+        let roles = Self::get_roles(env.clone(), operator.clone()) | role;
+        env.storage().persistent().set(&DataKey::Roles(operator.clone()), &roles);
 
-        log!(&env, "CrossChainSwapInitiated: orderHash={}, escrowAddress={}, hashedSecret={}, partIndex={}", 
-             order_hash, escrow_address, hashed_secret, part_index);
+        log!(&env, "RoleGranted: operator={}, role={}, roles={}", operator, role, roles);
+    }
+
+    /// Revoke roles (a bitmask of ROLE_* values) from an operator
+    pub fn revoke_role(env: Env, caller: Address, operator: Address, role: u32) {
+        // Only owner can revoke
+        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
+        if caller != owner {
+            panic!("Only owner can revoke");
+        }
+        caller.require_auth();
 
-        escrow_address
+        let roles = Self::get_roles(env.clone(), operator.clone()) & !role;
+        env.storage().persistent().set(&DataKey::Roles(operator.clone()), &roles);
+
+        log!(&env, "RoleRevoked: operator={}, role={}, roles={}", operator, role, roles);
+    }
+
+    /// Get the role bitmask explicitly granted to an operator
+    pub fn get_roles(env: Env, operator: Address) -> u32 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Roles(operator))
+            .unwrap_or(0)
+    }
+
+    /// Check if an account holds every role in the bitmask (the owner holds all roles)
+    pub fn has_role(env: Env, account: Address, role: u32) -> bool {
+        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
+        account == owner || Self::get_roles(env, account) & role == role
+    }
+
+    /// Reset the circuit breaker after the cause of repeated failures has been fixed
+    pub fn reset_breaker(env: Env, caller: Address) {
+        // Only owner can reset
+        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
+        if caller != owner {
+            panic!("Only owner can reset");
+        }
+        caller.require_auth();
+
+        env.storage().instance().set(&DataKey::FailureCount, &0u32);
+
+        log!(&env, "CircuitBreakerReset: owner={}", caller);
+    }
+
+    /// Propose a new owner (first step of a two-step ownership transfer)
+    pub fn transfer_resolver_ownership(env: Env, caller: Address, new_owner: Address) {
+        // Only owner can transfer
+        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
+        if caller != owner {
+            panic!("Only owner can transfer");
+        }
+        caller.require_auth();
+
+        env.storage().instance().set(&DataKey::PendingOwner, &new_owner);
+
+        log!(&env, "OwnershipTransferStarted: owner={}, pendingOwner={}", caller, new_owner);
+    }
+
+    /// Accept a pending ownership transfer (second step, called by the proposed owner)
+    /// Circuit breaker state carries over so a tripped breaker stays tripped for the new owner
+    pub fn accept_resolver_ownership(env: Env, new_owner: Address) {
+        let pending_owner: Address = env.storage()
+            .instance()
+            .get(&DataKey::PendingOwner)
+            .unwrap_or_else(|| panic!("No pending owner"));
+        if new_owner != pending_owner {
+            panic!("Only pending owner can accept");
+        }
+        new_owner.require_auth();
+
+        let previous_owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
+        env.storage().instance().set(&DataKey::Owner, &new_owner);
+        env.storage().instance().remove(&DataKey::PendingOwner);
+
+        log!(&env, "OwnershipTransferred: previousOwner={}, newOwner={}", previous_owner, new_owner);
+    }
+
+    /// Get the resolver owner
+    pub fn get_owner(env: Env) -> Address {
+        env.storage().instance().get(&DataKey::Owner).unwrap()
+    }
+
+    /// Get the pending owner, if a transfer is in progress
+    pub fn get_pending_owner(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::PendingOwner)
+    }
+
+    /// Get the number of consecutive failed swap executions
+    pub fn get_failure_count(env: Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::FailureCount)
+            .unwrap_or(0)
+    }
+
+    /// Check if the circuit breaker is tripped
+    pub fn is_breaker_tripped(env: Env) -> bool {
+        Self::get_failure_count(env) >= MAX_FAILURES
     }
 
     /// Complete a cross-chain swap by withdrawing from escrow
+    /// The resolver contract withdraws as itself and forwards the proceeds to the owner, so a ROLE_COMPLETE
+    /// operator never holds the funds; before public_withdrawal_start the escrow's recipient must be the resolver
     pub fn complete_cross_chain_swap(
         env: Env,
         caller: Address,
-        escrow_address: Address,
+        escrow_id: BytesN<32>,
         secret: Bytes,
         part_index: u64,
         merkle_proof: Vec<BytesN<32>>,
     ) {
-        // Only owner can complete
-        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
-        if caller != owner {
-            panic!("Only owner can complete");
-        }
+        Self::require_role(&env, &caller, ROLE_COMPLETE);
         caller.require_auth();
 
         // Get factory address
@@ -212,29 +498,61 @@ impl SimpleResolver {
 
         // Get the escrow contract and check if it's a partial fill
         let factory_client = EscrowFactoryTraitClient::new(&env, &factory_address);
-        let escrow_data = factory_client.get_src_escrow(&escrow_address);
+        let escrow_data = factory_client.get_src_escrow(&escrow_id);
         
+        let resolver = env.current_contract_address();
+        let token_client = token::Client::new(&env, &escrow_data.token);
+        let balance_before = token_client.balance(&resolver);
+
         // Check if this is a partial fill escrow
         if escrow_data.is_partial_fill {
             // For partial fills, use withdrawWithProof with merkle proof
-            if merkle_proof.len() == 0 {
+            if merkle_proof.is_empty() {
                 panic!("Merkle proof required for partial fills");
             }
-            factory_client.withdraw_src_escrow_with_proof(&caller, &escrow_address, &secret, &merkle_proof);
+            factory_client.withdraw_src_escrow_with_proof(&resolver, &escrow_id, &secret, &merkle_proof);
         } else {
             // For complete fills, use regular withdraw
-            factory_client.withdraw_src_escrow(&caller, &escrow_address, &secret);
+            factory_client.withdraw_src_escrow(&resolver, &escrow_id, &secret);
         }
 
-        log!(&env, "CrossChainSwapCompleted: orderHash={}, escrowAddress={}, secret={}, partIndex={}", 
-             BytesN::from_array(&env, &[0u8; 32]), escrow_address, secret, part_index);
+        // Principal, deposit and any gas reimbursement all went to the resolver; pass them on to the owner
+        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
+        token_client.transfer(&resolver, &owner, &(token_client.balance(&resolver) - balance_before));
+
+        // Mark the tracked swap as having its secret revealed
+        if let Some((index, mut swap)) = Self::find_swap(&env, DataKey::SwapBySrc(escrow_id.clone())) {
+            if swap.part_index == part_index && !swap.secret_revealed {
+                swap.secret_revealed = true;
+                env.storage().persistent().set(&DataKey::Swap(index), &swap);
+            }
+        }
+
+        log!(&env, "CrossChainSwapCompleted: orderHash={}, escrowId={}, secret={}, partIndex={}", 
+             BytesN::from_array(&env, &[0u8; 32]), escrow_id, secret, part_index);
+    }
+
+    /// Revert unless both legs of a swap share a hashlock, so one secret unlocks both atomically
+    /// Partial fills compare merkle roots, which both legs store as their hashed_secret
+    pub fn assert_matching_hashlock(env: Env, src_escrow: BytesN<32>, dst_escrow: BytesN<32>) {
+        let factory_address: Address = env.storage()
+            .instance()
+            .get(&DataKey::EscrowFactory)
+            .unwrap();
+        let factory_client = EscrowFactoryTraitClient::new(&env, &factory_address);
+
+        let src = factory_client.get_src_escrow(&src_escrow);
+        let dst = factory_client.get_dst_escrow(&dst_escrow);
+        if src.hashed_secret != dst.hashed_secret {
+            panic!("Hashlock mismatch");
+        }
     }
 
     /// Withdraw from source escrow after finality lock passes
     pub fn withdraw_from_source_escrow(
         env: Env,
         caller: Address,
-        escrow_address: Address,
+        escrow_id: BytesN<32>,
         secret: Bytes,
         part_index: u64,
         merkle_proof: Vec<BytesN<32>>,
@@ -247,29 +565,29 @@ impl SimpleResolver {
 
         // Get the escrow contract and check if it's a partial fill
         let factory_client = EscrowFactoryTraitClient::new(&env, &factory_address);
-        let escrow_data = factory_client.get_src_escrow(&escrow_address);
+        let escrow_data = factory_client.get_src_escrow(&escrow_id);
         
         // Check if this is a partial fill escrow
         if escrow_data.is_partial_fill {
             // For partial fills, use withdrawWithProof with merkle proof
-            if merkle_proof.len() == 0 {
+            if merkle_proof.is_empty() {
                 panic!("Merkle proof required for partial fills");
             }
-            factory_client.withdraw_src_escrow_with_proof(&caller, &escrow_address, &secret, &merkle_proof);
+            factory_client.withdraw_src_escrow_with_proof(&caller, &escrow_id, &secret, &merkle_proof);
         } else {
             // For complete fills, use regular withdraw
-            factory_client.withdraw_src_escrow(&caller, &escrow_address, &secret);
+            factory_client.withdraw_src_escrow(&caller, &escrow_id, &secret);
         }
 
-        log!(&env, "SourceEscrowWithdrawn: escrowAddress={}, secret={}, partIndex={}", 
-             escrow_address, secret, part_index);
+        log!(&env, "SourceEscrowWithdrawn: escrowId={}, secret={}, partIndex={}", 
+             escrow_id, secret, part_index);
     }
 
     /// Withdraw from destination escrow after finality lock passes
     pub fn withdraw_from_destination_escrow(
         env: Env,
         caller: Address,
-        escrow_address: Address,
+        escrow_id: BytesN<32>,
         secret: Bytes,
         part_index: u64,
         merkle_proof: Vec<BytesN<32>>,
@@ -282,42 +600,88 @@ impl SimpleResolver {
 
         // Get the escrow contract and check if it's a partial fill
         let factory_client = EscrowFactoryTraitClient::new(&env, &factory_address);
-        let escrow_data = factory_client.get_dst_escrow(&escrow_address);
+        let escrow_data = factory_client.get_dst_escrow(&escrow_id);
         
         // Check if this is a partial fill escrow
         if escrow_data.is_partial_fill {
             // For partial fills, use withdrawWithProof with merkle proof
-            if merkle_proof.len() == 0 {
+            if merkle_proof.is_empty() {
                 panic!("Merkle proof required for partial fills");
             }
-            factory_client.withdraw_dst_escrow_with_proof(&caller, &escrow_address, &secret, &merkle_proof);
+            factory_client.withdraw_dst_escrow_with_proof(&caller, &escrow_id, &secret, &merkle_proof);
         } else {
             // For complete fills, use regular withdraw
-            factory_client.withdraw_dst_escrow(&caller, &escrow_address, &secret);
+            factory_client.withdraw_dst_escrow(&caller, &escrow_id, &secret);
         }
 
-        log!(&env, "DestinationEscrowWithdrawn: escrowAddress={}, secret={}, partIndex={}", 
-             escrow_address, secret, part_index);
+        log!(&env, "DestinationEscrowWithdrawn: escrowId={}, secret={}, partIndex={}", 
+             escrow_id, secret, part_index);
     }
 
-    /// Create destination escrow on target chain
+    /// Roll an expiring destination escrow into a fresh one with new windows (owner only)
+    /// The old escrow is cancelled, refunding the owner, and re-created with the same hashlock, recipient and amount
+    /// Only escrows the owner created can be rolled: the factory lets just the creator cancel a destination escrow,
+    /// so one an operator created through create_destination_escrow must be cancelled by that operator
+    pub fn roll_escrow(env: Env, caller: Address, old_escrow: BytesN<32>, new_windows: TimeWindows) -> BytesN<32> {
+        // Only owner can roll
+        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
+        if caller != owner {
+            panic!("Only owner can roll");
+        }
+        caller.require_auth();
+
+        let factory_address: Address = env.storage()
+            .instance()
+            .get(&DataKey::EscrowFactory)
+            .unwrap();
+        let factory_client = EscrowFactoryTraitClient::new(&env, &factory_address);
+
+        let old = factory_client.get_dst_escrow(&old_escrow);
+        if old.creator != owner {
+            panic!("Escrow not created by owner");
+        }
+        factory_client.cancel_dst_escrow(&caller, &old_escrow);
+
+        let new_escrow = factory_client.create_dst_escrow_partial(
+            &caller, // creator (resolver)
+            &old.hashed_secret,
+            &old.recipient,
+            &old.token,
+            &old.amount,
+            &new_windows,
+            &old.part_index,
+            &old.total_parts,
+            &None,
+            &old.require_dual_auth,
+        );
+
+        // Point the tracked swap at the new destination leg
+        if let Some((index, mut swap)) = Self::find_swap(&env, DataKey::SwapByDst(old_escrow.clone())) {
+            swap.dst_escrow = DstEscrow::Created(new_escrow.clone());
+            env.storage().persistent().set(&DataKey::Swap(index), &swap);
+            env.storage().persistent().remove(&DataKey::SwapByDst(old_escrow.clone()));
+            env.storage().persistent().set(&DataKey::SwapByDst(new_escrow.clone()), &index);
+        }
+
+        log!(&env, "EscrowRolled: oldEscrow={}, newEscrow={}, withdrawalStart={}", 
+             old_escrow, new_escrow, new_windows.withdrawal_start);
+
+        new_escrow
+    }
+
+    /// Create destination escrow on target chain, holding `amount` of `token`
     pub fn create_destination_escrow(
         env: Env,
         caller: Address,
         hashed_secret: BytesN<32>,
         recipient: Address,
+        token: Address,
         amount: i128,
-        withdrawal_start: u64,
-        public_withdrawal_start: u64,
-        cancellation_start: u64,
+        windows: TimeWindows,
         part_index: u64,
         total_parts: u32,
-    ) -> Address {
-        // Only owner can create
-        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
-        if caller != owner {
-            panic!("Only owner can create");
-        }
+    ) -> BytesN<32> {
+        Self::require_role(&env, &caller, ROLE_EXECUTE);
         caller.require_auth();
 
         // Get factory address
@@ -332,22 +696,31 @@ impl SimpleResolver {
             &caller, // creator (resolver)
             &hashed_secret,
             &recipient,
+            &token,
             &amount,
-            &withdrawal_start,
-            &public_withdrawal_start,
-            &cancellation_start,
+            &windows,
             &part_index,
             &total_parts,
+            &None,
+            &false,
         );
 
-        // Get the created escrow address
+        // Get the created escrow id
         let user_escrows = factory_client.get_user_escrows(&caller);
-        let escrow_address = user_escrows.get(user_escrows.len() - 1).unwrap();
+        let escrow_id = user_escrows.get(user_escrows.len() - 1).unwrap();
+
+        // Link the destination leg to the tracked swap sharing its hashlock and part
+        if let Some((index, mut swap)) = Self::find_swap(&env, DataKey::PendingSwap(hashed_secret.clone(), part_index)) {
+            swap.dst_escrow = DstEscrow::Created(escrow_id.clone());
+            env.storage().persistent().set(&DataKey::Swap(index), &swap);
+            env.storage().persistent().remove(&DataKey::PendingSwap(hashed_secret.clone(), part_index));
+            env.storage().persistent().set(&DataKey::SwapByDst(escrow_id.clone()), &index);
+        }
 
-        log!(&env, "DestinationEscrowCreated: creator={}, recipient={}, escrowAddress={}, amount={}, partIndex={}", 
-             caller, recipient, escrow_address, amount, part_index);
+        log!(&env, "DestinationEscrowCreated: creator={}, recipient={}, escrowId={}, amount={}, partIndex={}", 
+             caller, recipient, escrow_id, amount, part_index);
 
-        escrow_address
+        escrow_id
     }
 
     /// Cancel a specific order part
@@ -357,11 +730,7 @@ impl SimpleResolver {
         order_hash: BytesN<32>, 
         part_index: u64
     ) {
-        // Only owner can cancel
-        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
-        if caller != owner {
-            panic!("Only owner can cancel");
-        }
+        Self::require_role(&env, &caller, ROLE_CANCEL);
         caller.require_auth();
 
         // Get LOP address
@@ -378,6 +747,100 @@ impl SimpleResolver {
              order_hash, caller, part_index);
     }
 
+    /// Cancel both legs of a swap in one transaction: the source part via the LOP and the destination escrow
+    /// via the factory. If either cancellation fails the whole call reverts, so neither leg is left cancelled alone
+    /// The resolver filled the part, so it cancels the source leg as the taker, which the LOP only allows
+    /// once the source escrow's public cancellation window has opened
+    /// Both escrows must be the legs of one swap tracked by this resolver
+    pub fn cancel_swap(
+        env: Env,
+        caller: Address,
+        order_hash: BytesN<32>,
+        part_index: u64,
+        src_escrow: BytesN<32>,
+        dst_escrow: BytesN<32>,
+    ) {
+        Self::require_role(&env, &caller, ROLE_CANCEL);
+        caller.require_auth();
+
+        // Both legs must belong to the same tracked swap, so an unrelated destination escrow can't be cancelled
+        let (_, swap) = Self::find_swap(&env, DataKey::SwapBySrc(src_escrow.clone()))
+            .unwrap_or_else(|| panic!("Swap not found"));
+        if swap.order_hash != order_hash
+            || swap.part_index != part_index
+            || swap.dst_escrow != DstEscrow::Created(dst_escrow.clone())
+        {
+            panic!("Escrow mismatch");
+        }
+
+        let lop_address: Address = env.storage()
+            .instance()
+            .get(&DataKey::LimitOrderProtocol)
+            .unwrap();
+        let lop_client = LimitOrderProtocolTraitClient::new(&env, &lop_address);
+        if lop_client.get_order_part(&order_hash, &part_index).escrow_id != src_escrow {
+            panic!("Escrow mismatch");
+        }
+        lop_client.cancel_order_as_taker(&env.current_contract_address(), &order_hash, &part_index);
+
+        let factory_address: Address = env.storage()
+            .instance()
+            .get(&DataKey::EscrowFactory)
+            .unwrap();
+        let factory_client = EscrowFactoryTraitClient::new(&env, &factory_address);
+        factory_client.cancel_dst_escrow(&caller, &dst_escrow);
+
+        log!(&env, "SwapCancelled: orderHash={}, partIndex={}, srcEscrow={}, dstEscrow={}", 
+             order_hash, part_index, src_escrow, dst_escrow);
+    }
+
+    /// Get swaps initiated by this resolver, paginated by position
+    pub fn get_active_swaps(env: Env, start: u32, limit: u32) -> Vec<SwapInfo> {
+        let count: u32 = env.storage().persistent().get(&DataKey::SwapCount).unwrap_or(0);
+        let mut page = Vec::new(&env);
+
+        let end = start.saturating_add(limit).min(count);
+        for i in start..end {
+            page.push_back(env.storage().persistent().get(&DataKey::Swap(i)).unwrap());
+        }
+
+        page
+    }
+
+    /// Set the price oracle used by quote_dst_amount
+    pub fn set_price_oracle(env: Env, caller: Address, oracle: Address) {
+        // Only owner can set the oracle
+        let owner: Address = env.storage().instance().get(&DataKey::Owner).unwrap();
+        if caller != owner {
+            panic!("Only owner can set oracle");
+        }
+        caller.require_auth();
+        Self::require_not_self(&env, &oracle);
+
+        env.storage().instance().set(&DataKey::PriceOracle, &oracle);
+
+        log!(&env, "PriceOracleUpdated: oracle={}", oracle);
+    }
+
+    /// Quote the destination amount for a source amount using the configured oracle price
+    pub fn quote_dst_amount(env: Env, src_amount: i128) -> i128 {
+        if src_amount <= 0 {
+            panic!("Invalid amount");
+        }
+
+        let oracle: Address = env.storage()
+            .instance()
+            .get(&DataKey::PriceOracle)
+            .unwrap_or_else(|| panic!("Price oracle not set"));
+
+        let price = PriceOracleClient::new(&env, &oracle).get_price();
+        if price <= 0 {
+            panic!("Invalid price");
+        }
+
+        src_amount.checked_mul(price).expect("Quote overflow") / PRICE_SCALE
+    }
+
     /// Get all filled order parts
     pub fn get_order(env: Env, order_hash: BytesN<32>) -> Vec<FilledOrder> {
         let lop_address: Address = env.storage()
@@ -458,4 +921,111 @@ impl SimpleResolver {
         // This would need to be implemented based on the specific token contract
         log!(&env, "XLM rescue requested: to={}", to);
     }
-}
\ No newline at end of file
+
+    // Fill one swap through the LOP and track it; shared by single and batch execution
+    fn execute_swap(env: &Env, request: &SwapRequest) -> Option<BytesN<32>> {
+        // Get LOP address
+        let lop_address: Address = env.storage()
+            .instance()
+            .get(&DataKey::LimitOrderProtocol)
+            .unwrap();
+        let lop_client = LimitOrderProtocolTraitClient::new(env, &lop_address);
+        let order_hash = lop_client.compute_order_hash(
+            &request.maker,
+            &request.recipient,
+            &request.token_amount,
+            &request.hashed_secret,
+            &request.total_parts,
+            &request.nonce,
+        );
+
+        // A retried execution returns the escrow created the first time
+        if let Some(escrow_id) = env.storage()
+            .persistent()
+            .get::<_, BytesN<32>>(&DataKey::ExecutedKey(request.idempotency_key.clone()))
+        {
+            log!(env, "CrossChainSwapAlreadyExecuted: orderHash={}, escrowId={}", order_hash, escrow_id);
+            return Some(escrow_id);
+        }
+
+        // Refuse to run while the circuit breaker is tripped
+        let failure_count = Self::get_failure_count(env.clone());
+        if failure_count >= MAX_FAILURES {
+            panic!("Circuit breaker tripped");
+        }
+
+        // Fill the order through LOP (this creates the escrow)
+        // A failed fill is caught so it can be counted instead of reverting the whole call
+        let result = lop_client.try_fill_order(
+            &env.current_contract_address(), // taker (this resolver)
+            &request.maker,
+            &request.recipient,
+            &request.token_amount,
+            &request.hashed_secret,
+            &request.withdrawal_start,
+            &(request.withdrawal_start + 1800), // public_withdrawal_start (30 min later)
+            &request.part_index,
+            &request.total_parts,
+            &request.nonce,
+        );
+
+        let escrow_id = match result {
+            Ok(Ok(escrow_id)) => escrow_id,
+            _ => {
+                env.storage().instance().set(&DataKey::FailureCount, &(failure_count + 1));
+                log!(env, "CrossChainSwapFailed: orderHash={}, partIndex={}, failureCount={}", 
+                     order_hash, request.part_index, failure_count + 1);
+                return None;
+            }
+        };
+
+        // Successful execution resets the consecutive failure count
+        env.storage().instance().set(&DataKey::FailureCount, &0u32);
+
+        // Remember the result so retries with the same key are no-ops
+        env.storage().persistent().set(&DataKey::ExecutedKey(request.idempotency_key.clone()), &escrow_id);
+
+        // Track the swap for operator dashboards, indexed by each leg so later updates touch only this entry
+        let index: u32 = env.storage().persistent().get(&DataKey::SwapCount).unwrap_or(0);
+        env.storage().persistent().set(&DataKey::Swap(index), &SwapInfo {
+            order_hash: order_hash.clone(),
+            part_index: request.part_index,
+            hashed_secret: request.hashed_secret.clone(),
+            src_escrow: escrow_id.clone(),
+            dst_escrow: DstEscrow::Pending,
+            secret_revealed: false,
+        });
+        env.storage().persistent().set(&DataKey::SwapCount, &(index + 1));
+        env.storage().persistent().set(&DataKey::SwapBySrc(escrow_id.clone()), &index);
+        env.storage().persistent().set(&DataKey::PendingSwap(request.hashed_secret.clone(), request.part_index), &index);
+
+        log!(env, "CrossChainSwapInitiated: orderHash={}, escrowId={}, hashedSecret={}, partIndex={}", 
+             order_hash, escrow_id, request.hashed_secret, request.part_index);
+
+        Some(escrow_id)
+    }
+
+    fn require_role(env: &Env, caller: &Address, role: u32) {
+        if !Self::has_role(env.clone(), caller.clone(), role) {
+            panic!("Missing role");
+        }
+    }
+
+    // Reject a configured contract address pointing back at the resolver itself
+    fn require_not_self(env: &Env, address: &Address) {
+        if *address == env.current_contract_address() {
+            panic!("Self-reference");
+        }
+    }
+
+    // Look up a tracked swap through one of its index keys, returning its position and info
+    fn find_swap(env: &Env, key: DataKey) -> Option<(u32, SwapInfo)> {
+        let index: u32 = env.storage().persistent().get(&key)?;
+        Some((index, env.storage().persistent().get(&DataKey::Swap(index)).unwrap()))
+    }
+}
+
+#[cfg(any(test, feature = "testutils"))]
+pub mod testutils;
+
+mod test;
@@ -0,0 +1,1012 @@
+#![cfg(test)]
+
+use super::*;
+use soroban_sdk::{
+    contract, contractimpl, symbol_short,
+    testutils::{Address as _, Ledger},
+    token::{StellarAssetClient, TokenClient},
+    vec, Env,
+};
+
+// Mock LOP that fills and cancels orders using a fixed escrow id, or panics when told to fail
+// Order hashes are simply the nonce repeated, so tests can pick them directly
+#[contract]
+pub struct MockLimitOrderProtocol;
+
+#[contractimpl]
+impl MockLimitOrderProtocol {
+    pub fn set_fail(env: Env, fail: bool) {
+        env.storage().instance().set(&symbol_short!("fail"), &fail);
+    }
+
+    pub fn compute_order_hash(
+        env: Env,
+        _maker: Address,
+        _recipient: Address,
+        _token_amount: i128,
+        _hashed_secret: BytesN<32>,
+        _total_parts: u32,
+        nonce: u64,
+    ) -> BytesN<32> {
+        BytesN::from_array(&env, &[nonce as u8; 32])
+    }
+
+    pub fn fill_order(
+        env: Env,
+        _taker: Address,
+        maker: Address,
+        _recipient: Address,
+        token_amount: i128,
+        _hashed_secret: BytesN<32>,
+        _withdrawal_start: u64,
+        _public_withdrawal_start: u64,
+        _part_index: u64,
+        _total_parts: u32,
+        _nonce: u64,
+    ) -> BytesN<32> {
+        let fail: bool = env.storage().instance().get(&symbol_short!("fail")).unwrap_or(false);
+        if fail || token_amount <= 0 {
+            panic!("Fill failed");
+        }
+        // Makers who approved through the mock draw down their allowance like the real LOP
+        if let Some(allowance) = env.storage().instance().get::<_, i128>(&maker) {
+            if allowance < token_amount {
+                panic!("Insufficient allowance");
+            }
+            env.storage().instance().set(&maker, &(allowance - token_amount));
+        }
+        src_escrow_id(&env)
+    }
+
+    pub fn approve(env: Env, caller: Address, amount: i128) {
+        env.storage().instance().set(&caller, &amount);
+    }
+
+    pub fn allowance(env: Env, owner: Address) -> i128 {
+        env.storage().instance().get(&owner).unwrap_or(0)
+    }
+
+    pub fn get_order_part(env: Env, order_hash: BytesN<32>, part_index: u64) -> FilledOrder {
+        FilledOrder {
+            order_hash,
+            maker: env.current_contract_address(),
+            taker: env.current_contract_address(),
+            recipient: env.current_contract_address(),
+            escrow_id: src_escrow_id(&env),
+            part_index,
+            total_parts: 1,
+            is_active: true,
+            amount: 1_000,
+            grant_epoch: 0,
+        }
+    }
+
+    pub fn cancel_order(env: Env, _caller: Address, _order_hash: BytesN<32>, _part_index: u64) {
+        let fail: bool = env.storage().instance().get(&symbol_short!("fail")).unwrap_or(false);
+        if fail {
+            panic!("Cancel failed");
+        }
+        env.storage().instance().set(&symbol_short!("cancels"), &(Self::cancelled_parts(env.clone()) + 1));
+    }
+
+    pub fn cancel_order_as_taker(env: Env, caller: Address, order_hash: BytesN<32>, part_index: u64) {
+        Self::cancel_order(env, caller, order_hash, part_index);
+    }
+
+    pub fn cancelled_parts(env: Env) -> u32 {
+        env.storage().instance().get(&symbol_short!("cancels")).unwrap_or(0)
+    }
+}
+
+// Mock factory that reports every source escrow as a full fill and accepts any withdrawal, paying the
+// escrowed amount to the caller. Destination escrows get sequential ids and are listed as their creator's
+// escrows, as the real factory does
+#[contract]
+pub struct MockEscrowFactory;
+
+#[contractimpl]
+impl MockEscrowFactory {
+    pub fn set_token(env: Env, token: Address) {
+        env.storage().instance().set(&symbol_short!("token"), &token);
+    }
+
+    pub fn get_src_escrow(env: Env, _escrow_id: BytesN<32>) -> SourceEscrowData {
+        SourceEscrowData {
+            creator: env.current_contract_address(),
+            recipient: env.current_contract_address(),
+            hashed_secret: BytesN::from_array(&env, &[2u8; 32]),
+            hash_algo: HashAlgo::Sha256,
+            token: env.storage().instance().get(&symbol_short!("token")).unwrap(),
+            amount: 1_000,
+            security_deposit: 0,
+            withdrawal_start: 0,
+            public_withdrawal_start: 0,
+            cancellation_start: 0,
+            public_cancellation_start: 0,
+            funds_withdrawn: false,
+            cancelled: false,
+            part_index: 0,
+            total_parts: 1,
+            is_partial_fill: false,
+            created_at: 0,
+            settlement_callback: None,
+            allowance_consumed: 0,
+            gas_deposit: 0,
+            rescue_recipient: None,
+            proposed_cancellation_start: None,
+            require_dual_auth: false,
+            funded_via_allowance: true,
+            frozen: false,
+        }
+    }
+
+    pub fn withdraw_src_escrow(env: Env, caller: Address, escrow_id: BytesN<32>, _secret: Bytes) {
+        let escrow_data = Self::get_src_escrow(env.clone(), escrow_id);
+        TokenClient::new(&env, &escrow_data.token).transfer(&env.current_contract_address(), &caller, &escrow_data.amount);
+    }
+
+    pub fn create_dst_escrow_partial(
+        env: Env,
+        creator: Address,
+        hashed_secret: BytesN<32>,
+        recipient: Address,
+        token: Address,
+        token_amount: i128,
+        windows: TimeWindows,
+        part_index: u64,
+        total_parts: u32,
+        _part_proof: Option<PartProof>,
+        require_dual_auth: bool,
+    ) -> BytesN<32> {
+        let mut escrows: Vec<BytesN<32>> = env.storage().instance().get(&symbol_short!("escrows")).unwrap_or(Vec::new(&env));
+        let escrow_id = BytesN::from_array(&env, &[escrows.len() as u8 + 100; 32]);
+        escrows.push_back(escrow_id.clone());
+        env.storage().instance().set(&symbol_short!("escrows"), &escrows);
+
+        let escrow_data = DestinationEscrowData {
+            creator,
+            recipient,
+            hashed_secret,
+            hash_algo: HashAlgo::Sha256,
+            token,
+            amount: token_amount,
+            security_deposit: 0,
+            exclusive_withdrawal_start: 0,
+            withdrawal_start: windows.withdrawal_start,
+            public_withdrawal_start: windows.public_withdrawal_start,
+            cancellation_start: windows.cancellation_start,
+            public_cancellation_start: windows.public_cancellation_start,
+            funds_withdrawn: false,
+            cancelled: false,
+            part_index,
+            total_parts,
+            is_partial_fill: total_parts > 1,
+            created_at: 0,
+            settlement_callback: None,
+            recipient_is_contract: false,
+            require_dual_auth,
+            auto_refund_deadline: 0,
+            frozen: false,
+        };
+        env.storage().instance().set(&escrow_id, &escrow_data);
+        escrow_id
+    }
+
+    pub fn get_user_escrows(env: Env, _user: Address) -> Vec<BytesN<32>> {
+        env.storage().instance().get(&symbol_short!("escrows")).unwrap_or(Vec::new(&env))
+    }
+
+    pub fn get_dst_escrow(env: Env, escrow_id: BytesN<32>) -> DestinationEscrowData {
+        env.storage().instance().get(&escrow_id).unwrap()
+    }
+
+    pub fn cancel_dst_escrow(env: Env, _caller: Address, escrow_id: BytesN<32>) {
+        let mut escrow_data = Self::get_dst_escrow(env.clone(), escrow_id.clone());
+        escrow_data.cancelled = true;
+        env.storage().instance().set(&escrow_id, &escrow_data);
+        env.storage().instance().set(&symbol_short!("cancels"), &(Self::cancel_count(env.clone()) + 1));
+    }
+
+    // Source escrow creation and public cancellation as the real limit order protocol calls them
+    pub fn create_src_escrow_partial(
+        env: Env,
+        _creator: Address,
+        _hashed_secret: BytesN<32>,
+        _recipient: Address,
+        _buyer: Address,
+        _token: Address,
+        _token_amount: i128,
+        _windows: TimeWindows,
+        _part_index: u64,
+        _total_parts: u32,
+        _require_dual_auth: bool,
+    ) -> BytesN<32> {
+        src_escrow_id(&env)
+    }
+
+    pub fn get_native_token(env: Env) -> Address {
+        env.storage().instance().get(&symbol_short!("token")).unwrap()
+    }
+
+    pub fn get_src_deposit_held(env: Env, escrow_id: BytesN<32>) -> i128 {
+        if env.storage().instance().has(&(symbol_short!("src_cncl"), escrow_id)) { 0 } else { 1 }
+    }
+
+    pub fn public_cancel_src_escrow(env: Env, caller: Address, escrow_id: BytesN<32>) {
+        env.storage().instance().set(&(symbol_short!("src_cncl"), escrow_id), &caller);
+    }
+
+    pub fn src_cancelled_by(env: Env, escrow_id: BytesN<32>) -> Option<Address> {
+        env.storage().instance().get(&(symbol_short!("src_cncl"), escrow_id))
+    }
+
+    pub fn cancel_count(env: Env) -> u32 {
+        env.storage().instance().get(&symbol_short!("cancels")).unwrap_or(0)
+    }
+}
+
+// Mock oracle returning a settable price
+#[contract]
+pub struct MockPriceOracle;
+
+#[contractimpl]
+impl MockPriceOracle {
+    pub fn set_price(env: Env, price: i128) {
+        env.storage().instance().set(&symbol_short!("price"), &price);
+    }
+
+    pub fn get_price(env: Env) -> i128 {
+        env.storage().instance().get(&symbol_short!("price")).unwrap()
+    }
+}
+
+// Windows of the destination escrows the tests create
+fn dst_windows() -> TimeWindows {
+    TimeWindows {
+        withdrawal_start: 100,
+        public_withdrawal_start: 200,
+        cancellation_start: 300,
+        public_cancellation_start: 400,
+    }
+}
+
+// Source escrow id the mock LOP hands out for every fill
+fn src_escrow_id(env: &Env) -> BytesN<32> {
+    BytesN::from_array(env, &[9u8; 32])
+}
+
+struct Setup<'a> {
+    env: Env,
+    owner: Address,
+    factory: MockEscrowFactoryClient<'a>,
+    lop: MockLimitOrderProtocolClient<'a>,
+    resolver: SimpleResolverClient<'a>,
+    token: Address,
+}
+
+fn setup<'a>() -> Setup<'a> {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let owner = Address::generate(&env);
+    let factory_id = env.register(MockEscrowFactory, ());
+    let lop_id = env.register(MockLimitOrderProtocol, ());
+    let resolver_id = env.register(SimpleResolver, ());
+
+    let lop = MockLimitOrderProtocolClient::new(&env, &lop_id);
+    let resolver = SimpleResolverClient::new(&env, &resolver_id);
+    resolver.initialize(&lop_id, &factory_id, &owner);
+
+    // Fund the mock factory so source withdrawals pay out
+    let factory = MockEscrowFactoryClient::new(&env, &factory_id);
+    let token = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+    StellarAssetClient::new(&env, &token).mint(&factory_id, &1_000_000);
+    factory.set_token(&token);
+    Setup { env, owner, factory, lop, resolver, token }
+}
+
+fn execute(s: &Setup, part_index: u64) -> Option<BytesN<32>> {
+    execute_order(s, &BytesN::from_array(&s.env, &[1u8; 32]), part_index)
+}
+
+fn execute_order(s: &Setup, order_hash: &BytesN<32>, part_index: u64) -> Option<BytesN<32>> {
+    execute_with_key(s, order_hash, part_index, &swap_key(&s.env, order_hash, part_index))
+}
+
+// Derive an idempotency key for a swap from its order hash and part index
+fn swap_key(env: &Env, order_hash: &BytesN<32>, part_index: u64) -> BytesN<32> {
+    let mut packed = Bytes::from_array(env, &order_hash.to_array());
+    packed.extend_from_array(&part_index.to_be_bytes());
+    env.crypto().sha256(&packed).into()
+}
+
+fn execute_with_key(s: &Setup, order_hash: &BytesN<32>, part_index: u64, key: &BytesN<32>) -> Option<BytesN<32>> {
+    let maker = Address::generate(&s.env);
+    let recipient = Address::generate(&s.env);
+    s.resolver.execute_cross_chain_swap(
+        &s.owner,
+        &maker,
+        &recipient,
+        &1_000,
+        &BytesN::from_array(&s.env, &[2u8; 32]),
+        &100,
+        &part_index,
+        &1,
+        &(order_hash.get(0).unwrap() as u64), // nonce the mock LOP hashes to order_hash
+        key,
+    )
+}
+
+#[test]
+fn test_breaker_trips_after_consecutive_failures() {
+    let s = setup();
+
+    s.lop.set_fail(&true);
+    for i in 0..MAX_FAILURES {
+        assert_eq!(execute(&s, 0), None);
+        assert_eq!(s.resolver.get_failure_count(), i + 1);
+    }
+    assert!(s.resolver.is_breaker_tripped());
+
+    // Execution is refused while tripped, even if the LOP has recovered
+    s.lop.set_fail(&false);
+    let result = s.resolver.try_execute_cross_chain_swap(
+        &s.owner,
+        &Address::generate(&s.env),
+        &Address::generate(&s.env),
+        &1_000,
+        &BytesN::from_array(&s.env, &[2u8; 32]),
+        &100,
+        &0,
+        &1,
+        &1,
+        &BytesN::from_array(&s.env, &[3u8; 32]),
+    );
+    assert!(result.is_err());
+
+    // Owner reset re-enables execution
+    s.resolver.reset_breaker(&s.owner);
+    assert!(!s.resolver.is_breaker_tripped());
+    assert_eq!(execute(&s, 0), Some(src_escrow_id(&s.env)));
+}
+
+#[test]
+fn test_success_resets_failure_count() {
+    let s = setup();
+
+    s.lop.set_fail(&true);
+    execute(&s, 0);
+    execute(&s, 0);
+    assert_eq!(s.resolver.get_failure_count(), 2);
+
+    s.lop.set_fail(&false);
+    assert!(execute(&s, 0).is_some());
+    assert_eq!(s.resolver.get_failure_count(), 0);
+}
+
+#[test]
+#[should_panic(expected = "Only owner can reset")]
+fn test_reset_breaker_owner_only() {
+    let s = setup();
+    s.resolver.reset_breaker(&Address::generate(&s.env));
+}
+
+#[test]
+fn test_get_active_swaps() {
+    let s = setup();
+    let order_a = BytesN::from_array(&s.env, &[0xaa; 32]);
+    let order_b = BytesN::from_array(&s.env, &[0xbb; 32]);
+
+    let escrow_a = execute_order(&s, &order_a, 0).unwrap();
+    let escrow_b = execute_order(&s, &order_b, 3).unwrap();
+
+    let swaps = s.resolver.get_active_swaps(&0, &10);
+    assert_eq!(swaps.len(), 2);
+
+    let first = swaps.get(0).unwrap();
+    assert_eq!(first.order_hash, order_a);
+    assert_eq!(first.part_index, 0);
+    assert_eq!(first.src_escrow, escrow_a);
+    assert_eq!(first.dst_escrow, DstEscrow::Pending);
+    assert!(!first.secret_revealed);
+
+    let second = swaps.get(1).unwrap();
+    assert_eq!(second.order_hash, order_b);
+    assert_eq!(second.part_index, 3);
+    assert_eq!(second.src_escrow, escrow_b);
+
+    // Pagination
+    let page = s.resolver.get_active_swaps(&1, &1);
+    assert_eq!(page.len(), 1);
+    assert_eq!(page.get(0).unwrap().order_hash, order_b);
+    assert_eq!(s.resolver.get_active_swaps(&2, &5).len(), 0);
+}
+
+#[test]
+fn test_completing_swap_marks_secret_revealed() {
+    let s = setup();
+    let escrow = execute(&s, 0).unwrap();
+
+    s.resolver.complete_cross_chain_swap(
+        &s.owner,
+        &escrow,
+        &Bytes::from_slice(&s.env, b"secret"),
+        &0,
+        &Vec::new(&s.env),
+    );
+
+    let swap = s.resolver.get_active_swaps(&0, &1).get(0).unwrap();
+    assert!(swap.secret_revealed);
+}
+
+#[test]
+fn test_two_step_ownership_transfer() {
+    let s = setup();
+    let new_owner = Address::generate(&s.env);
+
+    s.resolver.transfer_resolver_ownership(&s.owner, &new_owner);
+    assert_eq!(s.resolver.get_owner(), s.owner);
+    assert_eq!(s.resolver.get_pending_owner(), Some(new_owner.clone()));
+
+    s.resolver.accept_resolver_ownership(&new_owner);
+    assert_eq!(s.resolver.get_owner(), new_owner);
+    assert_eq!(s.resolver.get_pending_owner(), None);
+
+    // Old owner lost its powers, new owner has them
+    assert!(s.resolver.try_reset_breaker(&s.owner).is_err());
+    s.resolver.reset_breaker(&new_owner);
+}
+
+#[test]
+#[should_panic(expected = "Only pending owner can accept")]
+fn test_accept_ownership_by_non_pending_reverts() {
+    let s = setup();
+    s.resolver.transfer_resolver_ownership(&s.owner, &Address::generate(&s.env));
+    s.resolver.accept_resolver_ownership(&Address::generate(&s.env));
+}
+
+#[test]
+fn test_breaker_state_survives_ownership_transfer() {
+    let s = setup();
+    let new_owner = Address::generate(&s.env);
+
+    s.lop.set_fail(&true);
+    for _ in 0..MAX_FAILURES {
+        execute(&s, 0);
+    }
+
+    s.resolver.transfer_resolver_ownership(&s.owner, &new_owner);
+    s.resolver.accept_resolver_ownership(&new_owner);
+    assert!(s.resolver.is_breaker_tripped());
+}
+
+#[test]
+fn test_quote_dst_amount_scales_with_price() {
+    let s = setup();
+    let oracle_id = s.env.register(MockPriceOracle, ());
+    let oracle = MockPriceOracleClient::new(&s.env, &oracle_id);
+    s.resolver.set_price_oracle(&s.owner, &oracle_id);
+
+    // 1.0
+    oracle.set_price(&PRICE_SCALE);
+    assert_eq!(s.resolver.quote_dst_amount(&5_000_000), 5_000_000);
+
+    // 2.5
+    oracle.set_price(&(PRICE_SCALE * 5 / 2));
+    assert_eq!(s.resolver.quote_dst_amount(&5_000_000), 12_500_000);
+
+    // 0.1
+    oracle.set_price(&(PRICE_SCALE / 10));
+    assert_eq!(s.resolver.quote_dst_amount(&5_000_000), 500_000);
+}
+
+#[test]
+#[should_panic(expected = "Price oracle not set")]
+fn test_quote_without_oracle_reverts() {
+    let s = setup();
+    s.resolver.quote_dst_amount(&1_000);
+}
+
+#[test]
+fn test_quote_dst_amount_rejects_bad_amounts() {
+    let s = setup();
+    let oracle_id = s.env.register(MockPriceOracle, ());
+    let oracle = MockPriceOracleClient::new(&s.env, &oracle_id);
+    s.resolver.set_price_oracle(&s.owner, &oracle_id);
+    oracle.set_price(&PRICE_SCALE);
+
+    assert!(s.resolver.try_quote_dst_amount(&0).is_err());
+    assert!(s.resolver.try_quote_dst_amount(&-5_000_000).is_err());
+
+    // An amount whose product with the price overflows reverts instead of wrapping
+    assert!(s.resolver.try_quote_dst_amount(&i128::MAX).is_err());
+}
+
+#[test]
+fn test_idempotency_key_prevents_duplicate_execution() {
+    let s = setup();
+    let order_hash = BytesN::from_array(&s.env, &[1u8; 32]);
+    let key = BytesN::from_array(&s.env, &[9u8; 32]);
+
+    let first = execute_with_key(&s, &order_hash, 0, &key);
+    assert!(first.is_some());
+
+    // Retry returns the same escrow and does not execute again
+    let second = execute_with_key(&s, &order_hash, 0, &key);
+    assert_eq!(second, first);
+    assert_eq!(s.resolver.get_active_swaps(&0, &10).len(), 1);
+}
+
+#[test]
+fn test_different_idempotency_keys_both_execute() {
+    let s = setup();
+    let order_hash = BytesN::from_array(&s.env, &[1u8; 32]);
+
+    assert!(execute_with_key(&s, &order_hash, 0, &BytesN::from_array(&s.env, &[1u8; 32])).is_some());
+    assert!(execute_with_key(&s, &order_hash, 1, &BytesN::from_array(&s.env, &[2u8; 32])).is_some());
+    assert_eq!(s.resolver.get_active_swaps(&0, &10).len(), 2);
+}
+
+#[test]
+fn test_failed_execution_does_not_consume_key() {
+    let s = setup();
+    let order_hash = BytesN::from_array(&s.env, &[1u8; 32]);
+    let key = BytesN::from_array(&s.env, &[9u8; 32]);
+
+    s.lop.set_fail(&true);
+    assert_eq!(execute_with_key(&s, &order_hash, 0, &key), None);
+
+    s.lop.set_fail(&false);
+    assert!(execute_with_key(&s, &order_hash, 0, &key).is_some());
+}
+
+#[test]
+fn test_roll_escrow_renews_windows_with_same_hashlock() {
+    let s = setup();
+    let recipient = Address::generate(&s.env);
+    let hashed_secret = BytesN::from_array(&s.env, &[2u8; 32]);
+
+    let old = s.resolver.create_destination_escrow(
+        &s.owner,
+        &hashed_secret,
+        &recipient,
+        &s.token,
+        &1_000,
+        &dst_windows(),
+        &0,
+        &1,
+    );
+
+    let new_windows = TimeWindows {
+        withdrawal_start: 1_100,
+        public_withdrawal_start: 1_200,
+        cancellation_start: 1_300,
+        public_cancellation_start: 1_400,
+    };
+    let new = s.resolver.roll_escrow(&s.owner, &old, &new_windows);
+    assert_eq!(s.factory.cancel_count(), 1);
+    assert_ne!(new, old);
+    assert!(s.factory.get_dst_escrow(&old).cancelled);
+
+    let rolled = s.factory.get_dst_escrow(&new);
+    assert_eq!(rolled.hashed_secret, hashed_secret);
+    assert_eq!(rolled.recipient, recipient);
+    assert_eq!(rolled.token, s.token);
+    assert_eq!(rolled.amount, 1_000);
+    assert_eq!(rolled.withdrawal_start, 1_100);
+    assert_eq!(rolled.public_withdrawal_start, 1_200);
+    assert_eq!(rolled.cancellation_start, 1_300);
+    assert_eq!(rolled.public_cancellation_start, 1_400);
+    assert!(!rolled.cancelled);
+}
+
+#[test]
+#[should_panic(expected = "Only owner can roll")]
+fn test_roll_escrow_owner_only() {
+    let s = setup();
+    let windows = TimeWindows {
+        withdrawal_start: 1_100,
+        public_withdrawal_start: 1_200,
+        cancellation_start: 1_300,
+        public_cancellation_start: 1_400,
+    };
+    s.resolver.roll_escrow(&Address::generate(&s.env), &BytesN::from_array(&s.env, &[100u8; 32]), &windows);
+}
+
+#[test]
+fn test_roll_escrow_requires_owner_created_escrow() {
+    let s = setup();
+    let operator = Address::generate(&s.env);
+    s.resolver.grant_role(&s.owner, &operator, &ROLE_EXECUTE);
+    let escrow = s.resolver.create_destination_escrow(
+        &operator,
+        &BytesN::from_array(&s.env, &[2u8; 32]),
+        &Address::generate(&s.env),
+        &s.token,
+        &1_000,
+        &dst_windows(),
+        &0,
+        &1,
+    );
+
+    // Only the operator that created the escrow can cancel it, so the owner can't roll it
+    let new_windows = TimeWindows {
+        withdrawal_start: 1_100,
+        public_withdrawal_start: 1_200,
+        cancellation_start: 1_300,
+        public_cancellation_start: 1_400,
+    };
+    assert!(s.resolver.try_roll_escrow(&s.owner, &escrow, &new_windows).is_err());
+    assert!(!s.factory.get_dst_escrow(&escrow).cancelled);
+    assert_eq!(s.factory.cancel_count(), 0);
+}
+
+#[test]
+fn test_complete_role_operator_can_complete() {
+    let s = setup();
+    let operator = Address::generate(&s.env);
+    s.resolver.grant_role(&s.owner, &operator, &ROLE_COMPLETE);
+    assert!(s.resolver.has_role(&operator, &ROLE_COMPLETE));
+    assert!(!s.resolver.has_role(&operator, &ROLE_EXECUTE));
+    assert!(s.resolver.has_role(&s.owner, &(ROLE_EXECUTE | ROLE_COMPLETE | ROLE_CANCEL)));
+
+    let escrow = execute(&s, 0).unwrap();
+    s.resolver.complete_cross_chain_swap(
+        &operator,
+        &escrow,
+        &Bytes::from_slice(&s.env, b"secret"),
+        &0,
+        &Vec::new(&s.env),
+    );
+    assert!(s.resolver.get_active_swaps(&0, &10).get(0).unwrap().secret_revealed);
+
+    // The operator only triggers the withdrawal; the proceeds go to the owner
+    let token = TokenClient::new(&s.env, &s.factory.get_src_escrow(&escrow).token);
+    assert_eq!(token.balance(&operator), 0);
+    assert_eq!(token.balance(&s.resolver.address), 0);
+    assert_eq!(token.balance(&s.owner), 1_000);
+}
+
+#[test]
+#[should_panic(expected = "Missing role")]
+fn test_complete_role_operator_cannot_execute() {
+    let s = setup();
+    let operator = Address::generate(&s.env);
+    s.resolver.grant_role(&s.owner, &operator, &ROLE_COMPLETE);
+
+    s.resolver.execute_cross_chain_swap(
+        &operator,
+        &Address::generate(&s.env),
+        &Address::generate(&s.env),
+        &1_000,
+        &BytesN::from_array(&s.env, &[2u8; 32]),
+        &100,
+        &0,
+        &1,
+        &1,
+        &swap_key(&s.env, &BytesN::from_array(&s.env, &[1u8; 32]), 0),
+    );
+}
+
+#[test]
+fn test_cancel_swap_cancels_both_legs() {
+    let s = setup();
+    let order_hash = BytesN::from_array(&s.env, &[1u8; 32]);
+    let src = execute(&s, 0).unwrap();
+    let dst = s.resolver.create_destination_escrow(
+        &s.owner,
+        &BytesN::from_array(&s.env, &[2u8; 32]),
+        &Address::generate(&s.env),
+        &s.token,
+        &1_000,
+        &dst_windows(),
+        &0,
+        &1,
+    );
+
+    s.resolver.cancel_swap(&s.owner, &order_hash, &0, &src, &dst);
+    assert_eq!(s.lop.cancelled_parts(), 1);
+    assert!(s.factory.get_dst_escrow(&dst).cancelled);
+}
+
+#[test]
+fn test_cancel_swap_reverts_both_legs_on_failure() {
+    let s = setup();
+    let order_hash = BytesN::from_array(&s.env, &[1u8; 32]);
+    let src = execute(&s, 0).unwrap();
+
+    // The destination leg does not exist, so the source cancellation is rolled back too
+    let missing_dst = BytesN::from_array(&s.env, &[1u8; 32]);
+    assert!(s.resolver.try_cancel_swap(&s.owner, &order_hash, &0, &src, &missing_dst).is_err());
+    assert_eq!(s.lop.cancelled_parts(), 0);
+
+    // A failing source cancellation leaves the destination escrow untouched
+    let dst = s.resolver.create_destination_escrow(
+        &s.owner,
+        &BytesN::from_array(&s.env, &[2u8; 32]),
+        &Address::generate(&s.env),
+        &s.token,
+        &1_000,
+        &dst_windows(),
+        &0,
+        &1,
+    );
+    s.lop.set_fail(&true);
+    assert!(s.resolver.try_cancel_swap(&s.owner, &order_hash, &0, &src, &dst).is_err());
+    assert!(!s.factory.get_dst_escrow(&dst).cancelled);
+    assert_eq!(s.factory.cancel_count(), 0);
+
+    // A source escrow that is not the order part's escrow is rejected
+    s.lop.set_fail(&false);
+    let wrong_src = BytesN::from_array(&s.env, &[1u8; 32]);
+    assert!(s.resolver.try_cancel_swap(&s.owner, &order_hash, &0, &wrong_src, &dst).is_err());
+}
+
+#[test]
+fn test_cancel_swap_rejects_unrelated_dst_escrow() {
+    let s = setup();
+    let order_hash = BytesN::from_array(&s.env, &[1u8; 32]);
+    let src = execute(&s, 0).unwrap();
+    let dst = s.resolver.create_destination_escrow(
+        &s.owner,
+        &BytesN::from_array(&s.env, &[2u8; 32]),
+        &Address::generate(&s.env),
+        &s.token,
+        &1_000,
+        &dst_windows(),
+        &0,
+        &1,
+    );
+
+    // A destination escrow with another hashlock is not this swap's leg
+    let other_dst = s.resolver.create_destination_escrow(
+        &s.owner,
+        &BytesN::from_array(&s.env, &[9u8; 32]),
+        &Address::generate(&s.env),
+        &s.token,
+        &1_000,
+        &dst_windows(),
+        &0,
+        &1,
+    );
+    assert!(s.resolver.try_cancel_swap(&s.owner, &order_hash, &0, &src, &other_dst).is_err());
+    assert!(!s.factory.get_dst_escrow(&other_dst).cancelled);
+    assert_eq!(s.lop.cancelled_parts(), 0);
+
+    s.resolver.cancel_swap(&s.owner, &order_hash, &0, &src, &dst);
+    assert!(s.factory.get_dst_escrow(&dst).cancelled);
+}
+
+#[test]
+fn test_cancel_swap_through_real_lop() {
+    let s = setup();
+    let lop = limit_order_protocol::SimpleLimitOrderProtocolClient::new(
+        &s.env,
+        &s.env.register(limit_order_protocol::SimpleLimitOrderProtocol, ()),
+    );
+    lop.initialize(&s.factory.address, &s.owner, &None);
+    let resolver = SimpleResolverClient::new(&s.env, &s.env.register(SimpleResolver, ()));
+    resolver.initialize(&lop.address, &s.factory.address, &s.owner);
+
+    let request = swap_request(&s.env, 0, 1_000);
+    lop.approve(&request.maker, &1_000);
+    let src = resolver.execute_cross_chain_swap(
+        &s.owner,
+        &request.maker,
+        &request.recipient,
+        &request.token_amount,
+        &request.hashed_secret,
+        &request.withdrawal_start,
+        &request.part_index,
+        &request.total_parts,
+        &request.nonce,
+        &request.idempotency_key,
+    ).unwrap();
+    let dst = resolver.create_destination_escrow(
+        &s.owner,
+        &request.hashed_secret,
+        &Address::generate(&s.env),
+        &s.token,
+        &1_000,
+        &dst_windows(),
+        &0,
+        &1,
+    );
+    let order_hash = lop.compute_order_hash(
+        &request.maker,
+        &request.recipient,
+        &request.token_amount,
+        &request.hashed_secret,
+        &request.total_parts,
+        &request.nonce,
+    );
+
+    // The real LOP only lets its maker cancel through cancel_order, so the resolver cancels as the taker
+    resolver.cancel_swap(&s.owner, &order_hash, &0, &src, &dst);
+    assert!(!lop.get_order_part(&order_hash, &0).is_active);
+    assert_eq!(s.factory.src_cancelled_by(&src), Some(lop.address.clone()));
+    assert!(s.factory.get_dst_escrow(&dst).cancelled);
+    assert_eq!(lop.allowance(&request.maker, &lop.address), 1_000);
+}
+
+fn swap_request(env: &Env, part_index: u64, token_amount: i128) -> SwapRequest {
+    let order_hash = BytesN::from_array(env, &[1u8; 32]);
+    SwapRequest {
+        maker: Address::generate(env),
+        recipient: Address::generate(env),
+        token_amount,
+        hashed_secret: BytesN::from_array(env, &[2u8; 32]),
+        withdrawal_start: 100,
+        part_index,
+        total_parts: 3,
+        nonce: 1,
+        idempotency_key: swap_key(env, &order_hash, part_index),
+    }
+}
+
+#[test]
+fn test_execute_swaps_batch() {
+    let s = setup();
+    let swaps = vec![&s.env, swap_request(&s.env, 0, 1_000), swap_request(&s.env, 1, 1_000), swap_request(&s.env, 2, 1_000)];
+
+    let escrows = s.resolver.execute_cross_chain_swaps_batch(&s.owner, &swaps);
+    assert_eq!(escrows.len(), 3);
+    let active = s.resolver.get_active_swaps(&0, &10);
+    assert_eq!(active.len(), 3);
+    for i in 0..3 {
+        assert_eq!(active.get(i).unwrap().part_index, i as u64);
+        assert_eq!(active.get(i).unwrap().src_escrow, escrows.get(i).unwrap());
+    }
+}
+
+#[test]
+fn test_execute_swaps_batch_reverts_on_failure() {
+    let s = setup();
+    let swaps = vec![&s.env, swap_request(&s.env, 0, 1_000), swap_request(&s.env, 1, 0), swap_request(&s.env, 2, 1_000)];
+
+    // The second fill fails, so the first is rolled back and nothing is tracked or counted
+    assert!(s.resolver.try_execute_cross_chain_swaps_batch(&s.owner, &swaps).is_err());
+    assert_eq!(s.resolver.get_active_swaps(&0, &10).len(), 0);
+    assert_eq!(s.resolver.get_failure_count(), 0);
+
+    let mut too_many = Vec::new(&s.env);
+    for i in 0..=MAX_BATCH_SWAPS {
+        too_many.push_back(swap_request(&s.env, i as u64, 1_000));
+    }
+    assert!(s.resolver.try_execute_cross_chain_swaps_batch(&s.owner, &too_many).is_err());
+}
+
+fn create_dst_leg(s: &Setup, hashed_secret: &BytesN<32>) -> BytesN<32> {
+    s.resolver.create_destination_escrow(
+        &s.owner,
+        hashed_secret,
+        &Address::generate(&s.env),
+        &s.token,
+        &1_000,
+        &dst_windows(),
+        &0,
+        &1,
+    )
+}
+
+#[test]
+fn test_assert_matching_hashlock() {
+    let s = setup();
+    let src = execute(&s, 0).unwrap();
+
+    // The mock source escrow is locked with [2; 32]
+    let dst = create_dst_leg(&s, &BytesN::from_array(&s.env, &[2u8; 32]));
+    s.resolver.assert_matching_hashlock(&src, &dst);
+}
+
+#[test]
+#[should_panic(expected = "Hashlock mismatch")]
+fn test_assert_matching_hashlock_mismatch() {
+    let s = setup();
+    let src = execute(&s, 0).unwrap();
+    let dst = create_dst_leg(&s, &BytesN::from_array(&s.env, &[3u8; 32]));
+    s.resolver.assert_matching_hashlock(&src, &dst);
+}
+
+#[test]
+fn test_simulate_full_swap_matches_manual_run() {
+    let s = setup();
+    let token = s.env.register_stellar_asset_contract_v2(Address::generate(&s.env)).address();
+    let maker = Address::generate(&s.env);
+    StellarAssetClient::new(&s.env, &token).mint(&maker, &5_000);
+    let secret = Bytes::from_slice(&s.env, b"secret");
+
+    // Part 0 through the clients one by one
+    let request = SwapRequest { maker: maker.clone(), ..swap_request(&s.env, 0, 1_000) };
+    let src_escrow = s.resolver.execute_cross_chain_swap(
+        &s.owner,
+        &request.maker,
+        &request.recipient,
+        &request.token_amount,
+        &request.hashed_secret,
+        &request.withdrawal_start,
+        &request.part_index,
+        &request.total_parts,
+        &request.nonce,
+        &request.idempotency_key,
+    ).unwrap();
+    s.env.ledger().with_mut(|l| l.timestamp = 500);
+    s.resolver.complete_cross_chain_swap(&s.owner, &src_escrow, &secret, &request.part_index, &Vec::new(&s.env));
+    let manual_swap = s.resolver.get_active_swaps(&0, &1).get(0).unwrap();
+    let manual_escrow = s.factory.get_src_escrow(&src_escrow);
+    let maker_balance = TokenClient::new(&s.env, &token).balance(&maker);
+
+    // Part 1 of the same order through the simulation
+    let simulated = testutils::simulate_full_swap(
+        &s.env,
+        &testutils::SwapSimParams {
+            resolver: s.resolver.address.clone(),
+            caller: s.owner.clone(),
+            request: SwapRequest { maker: maker.clone(), ..swap_request(&s.env, 1, 1_000) },
+            withdraw_at: 500,
+            secret,
+            merkle_proof: Vec::new(&s.env),
+            token,
+            accounts: vec![&s.env, maker],
+        },
+    );
+
+    assert_eq!(simulated.src_escrow, src_escrow);
+    assert_eq!(simulated.escrow, manual_escrow);
+    assert_eq!(simulated.swap, SwapInfo { part_index: 1, ..manual_swap });
+    assert!(simulated.swap.secret_revealed);
+    assert_eq!(simulated.balances, vec![&s.env, maker_balance]);
+}
+
+#[test]
+fn test_prepare_and_fill_from_zero_allowance() {
+    let s = setup();
+    let request = swap_request(&s.env, 0, 1_000);
+    assert_eq!(s.lop.allowance(&request.maker), 0);
+
+    let escrow = s.resolver.prepare_and_fill(&s.owner, &request.maker, &3_000, &request);
+    assert_eq!(escrow, src_escrow_id(&s.env));
+    assert_eq!(s.lop.allowance(&request.maker), 2_000);
+    let active = s.resolver.get_active_swaps(&0, &10);
+    assert_eq!(active.len(), 1);
+    assert_eq!(active.get(0).unwrap().src_escrow, escrow);
+}
+
+#[test]
+fn test_prepare_and_fill_rolls_back_approval_on_failed_fill() {
+    let s = setup();
+    let request = swap_request(&s.env, 0, 1_000);
+
+    // An approval below the order amount makes the fill fail, undoing the approval with it
+    assert!(s.resolver.try_prepare_and_fill(&s.owner, &request.maker, &500, &request).is_err());
+    assert_eq!(s.lop.allowance(&request.maker), 0);
+    assert_eq!(s.resolver.get_active_swaps(&0, &10).len(), 0);
+
+    let other_maker = Address::generate(&s.env);
+    assert!(s.resolver.try_prepare_and_fill(&s.owner, &other_maker, &3_000, &request).is_err());
+}
+
+#[test]
+#[should_panic(expected = "Self-reference")]
+fn test_initialize_rejects_self_as_lop() {
+    let env = Env::default();
+    let resolver_id = env.register(SimpleResolver, ());
+    let factory_id = env.register(MockEscrowFactory, ());
+    SimpleResolverClient::new(&env, &resolver_id).initialize(&resolver_id, &factory_id, &Address::generate(&env));
+}
+
+#[test]
+#[should_panic(expected = "Self-reference")]
+fn test_initialize_rejects_self_as_factory() {
+    let env = Env::default();
+    let resolver_id = env.register(SimpleResolver, ());
+    let lop_id = env.register(MockLimitOrderProtocol, ());
+    SimpleResolverClient::new(&env, &resolver_id).initialize(&lop_id, &resolver_id, &Address::generate(&env));
+}
+
+#[test]
+#[should_panic(expected = "Self-reference")]
+fn test_set_price_oracle_rejects_self() {
+    let s = setup();
+    s.resolver.set_price_oracle(&s.owner, &s.resolver.address);
+}
@@ -0,0 +1,75 @@
+// Test-only helpers for driving the resolver through a whole swap (enabled by the `testutils` feature)
+use soroban_sdk::{testutils::Ledger, token, Address, Bytes, BytesN, Env, Vec};
+
+use crate::{DataKey, EscrowFactoryTraitClient, SimpleResolverClient, SourceEscrowData, SwapInfo, SwapRequest};
+
+// Inputs of simulate_full_swap
+pub struct SwapSimParams {
+    pub resolver: Address,
+    pub caller: Address, // needs ROLE_EXECUTE and ROLE_COMPLETE
+    pub request: SwapRequest,
+    pub withdraw_at: u64, // ledger timestamp the source escrow is withdrawn at
+    pub secret: Bytes,
+    pub merkle_proof: Vec<BytesN<32>>, // empty for full fills
+    pub token: Address, // token whose balances are reported
+    pub accounts: Vec<Address>,
+}
+
+// Outcome of simulate_full_swap
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SwapSimResult {
+    pub src_escrow: BytesN<32>,
+    pub escrow: SourceEscrowData, // source escrow as the factory reports it after withdrawal
+    pub swap: SwapInfo, // the resolver's record of the swap
+    pub balances: Vec<i128>, // token balance of each of params.accounts, in order
+}
+
+// Execute a swap, advance the ledger to withdraw_at and complete it with the secret
+// Panics if any step fails, like the equivalent sequence of client calls
+pub fn simulate_full_swap(env: &Env, params: &SwapSimParams) -> SwapSimResult {
+    let resolver = SimpleResolverClient::new(env, &params.resolver);
+    let request = &params.request;
+
+    let src_escrow = resolver
+        .execute_cross_chain_swap(
+            &params.caller,
+            &request.maker,
+            &request.recipient,
+            &request.token_amount,
+            &request.hashed_secret,
+            &request.withdrawal_start,
+            &request.part_index,
+            &request.total_parts,
+            &request.nonce,
+            &request.idempotency_key,
+        )
+        .unwrap_or_else(|| panic!("Swap failed"));
+
+    env.ledger().with_mut(|l| l.timestamp = params.withdraw_at);
+    resolver.complete_cross_chain_swap(
+        &params.caller,
+        &src_escrow,
+        &params.secret,
+        &request.part_index,
+        &params.merkle_proof,
+    );
+
+    let factory: Address = env.as_contract(&params.resolver, || {
+        env.storage().instance().get(&DataKey::EscrowFactory).unwrap()
+    });
+    let escrow = EscrowFactoryTraitClient::new(env, &factory).get_src_escrow(&src_escrow);
+    let swap = resolver
+        .get_active_swaps(&0, &u32::MAX)
+        .iter()
+        .filter(|swap| swap.src_escrow == src_escrow && swap.part_index == request.part_index)
+        .last()
+        .unwrap();
+
+    let token_client = token::Client::new(env, &params.token);
+    let mut balances = Vec::new(env);
+    for account in params.accounts.iter() {
+        balances.push_back(token_client.balance(&account));
+    }
+
+    SwapSimResult { src_escrow, escrow, swap, balances }
+}
@@ -156,6 +156,31 @@ export class ResolverContractManager {
     }
   }
 
+  // Encode time windows as the factory's TimeWindows struct
+  private timeWindowsToScVal(timeWindows: {
+    withdrawalStart: number
+    publicWithdrawalStart: number
+    cancellationStart: number
+    publicCancellationStart: number
+  }): xdr.ScVal {
+    return nativeToScVal(
+      {
+        withdrawal_start: timeWindows.withdrawalStart,
+        public_withdrawal_start: timeWindows.publicWithdrawalStart,
+        cancellation_start: timeWindows.cancellationStart,
+        public_cancellation_start: timeWindows.publicCancellationStart
+      },
+      {
+        type: {
+          withdrawal_start: ["symbol", "u64"],
+          public_withdrawal_start: ["symbol", "u64"],
+          cancellation_start: ["symbol", "u64"],
+          public_cancellation_start: ["symbol", "u64"]
+        }
+      }
+    )
+  }
+
   // Prepare resolver tokens and approvals for destination chain
   async prepareResolver(
     params: ResolverPreparationParams,
@@ -488,7 +513,8 @@ export class ResolverContractManager {
           nativeToScVal(timeWindows.withdrawalStart, { type: "u64" }), // withdrawal_start
           nativeToScVal(timeWindows.publicWithdrawalStart, { type: "u64" }), // public_withdrawal_start
           nativeToScVal(timeWindows.cancellationStart, { type: "u64" }), // cancellation_start
-          nativeToScVal(timeWindows.publicCancellationStart, { type: "u64" }) // public_cancellation_start
+          nativeToScVal(timeWindows.publicCancellationStart, { type: "u64" }), // public_cancellation_start
+          nativeToScVal(false) // require_dual_auth
         ];
       } else {
         // Convert hashed secret from hex string to bytes (same as working test)
@@ -505,12 +531,12 @@ export class ResolverContractManager {
           nativeToScVal(hashedSecretBytes, { type: "bytes" }), // hashed_secret
           new Address(stellarWallet.publicKey).toScVal(), // recipient
           new Address(params.buyerAddress).toScVal(), // buyer
+          new Address(chainsConfig['stellar-testnet'].tokens.XLM.address).toScVal(), // token
           nativeToScVal(amountInStroops, { type: "i128" }), // token_amount
-          nativeToScVal(timeWindows.withdrawalStart, { type: "u64" }), // withdrawal_start
-          nativeToScVal(timeWindows.publicWithdrawalStart, { type: "u64" }), // public_withdrawal_start
-          nativeToScVal(timeWindows.cancellationStart, { type: "u64" }), // cancellation_start
+          this.timeWindowsToScVal(timeWindows), // windows
           nativeToScVal(actualPartIndex, { type: "u64" }), // part_index
-          nativeToScVal(actualTotalParts, { type: "u32" }) // total_parts
+          nativeToScVal(actualTotalParts, { type: "u32" }), // total_parts
+          nativeToScVal(false) // require_dual_auth
         ];
       }
       
@@ -562,7 +588,7 @@ export class ResolverContractManager {
       
       return {
         success: true,
-        escrowAddress: contractId, // In Stellar, the contract address is the escrow address
+        escrowAddress: await this.waitForStellarEscrowId(server, response.hash), // 0x-prefixed escrow id
         transactionHash: response.hash,
         message: 'Source escrow created successfully',
         details: {
@@ -743,6 +769,7 @@ export class ResolverContractManager {
       console.log(`  withdrawal_start: ${timeWindows.withdrawalStart}`)
       console.log(`  public_withdrawal_start: ${timeWindows.publicWithdrawalStart}`)
       console.log(`  cancellation_start: ${timeWindows.cancellationStart}`)
+      console.log(`  public_cancellation_start: ${timeWindows.publicCancellationStart}`)
       console.log(`  part_index: ${actualPartIndex}`)
       console.log(`  total_parts: ${actualTotalParts}`)
       
@@ -767,7 +794,9 @@ export class ResolverContractManager {
           nativeToScVal(amountInStroops, { type: "i128" }), // token_amount
           nativeToScVal(timeWindows.withdrawalStart, { type: "u64" }), // withdrawal_start
           nativeToScVal(timeWindows.publicWithdrawalStart, { type: "u64" }), // public_withdrawal_start
-          nativeToScVal(timeWindows.cancellationStart, { type: "u64" }) // cancellation_start
+          nativeToScVal(timeWindows.cancellationStart, { type: "u64" }), // cancellation_start
+          nativeToScVal(timeWindows.publicCancellationStart, { type: "u64" }), // public_cancellation_start
+          nativeToScVal(false) // require_dual_auth
         ];
       } else {
         // create_dst_escrow_partial
@@ -775,12 +804,13 @@ export class ResolverContractManager {
           new Address(stellarWallet.publicKey).toScVal(), // creator
           nativeToScVal(Buffer.from(hashedSecret.slice(2), 'hex'), { type: "bytes" }), // hashed_secret
           new Address(buyerAddress).toScVal(), // recipient
+          new Address(chainsConfig['stellar-testnet'].tokens.XLM.address).toScVal(), // token
           nativeToScVal(amountInStroops, { type: "i128" }), // token_amount
-          nativeToScVal(timeWindows.withdrawalStart, { type: "u64" }), // withdrawal_start
-          nativeToScVal(timeWindows.publicWithdrawalStart, { type: "u64" }), // public_withdrawal_start
-          nativeToScVal(timeWindows.cancellationStart, { type: "u64" }), // cancellation_start
+          this.timeWindowsToScVal(timeWindows), // windows
           nativeToScVal(actualPartIndex, { type: "u64" }), // part_index
-          nativeToScVal(actualTotalParts, { type: "u32" }) // total_parts
+          nativeToScVal(actualTotalParts, { type: "u32" }), // total_parts
+          xdr.ScVal.scvVoid(), // part_proof (none)
+          nativeToScVal(false) // require_dual_auth
         ];
       }
       
@@ -832,7 +862,7 @@ export class ResolverContractManager {
       
       return {
         success: true,
-        escrowAddress: contractId, // In Stellar, the contract address is the escrow address
+        escrowAddress: await this.waitForStellarEscrowId(server, response.hash), // 0x-prefixed escrow id
         transactionHash: response.hash,
         message: 'Destination escrow created successfully',
         details: {
@@ -1091,7 +1121,7 @@ export class ResolverContractManager {
       
       console.log("🔍 Debug - Parameters being passed:")
       console.log(`  caller: ${stellarWallet.publicKey}`)
-      console.log(`  escrow_address: ${params.escrowAddress}`)
+      console.log(`  escrow_id: ${params.escrowAddress}`)
       console.log(`  secret: ${params.secret}`)
       console.log(`  is_source: ${isSource}`)
       console.log(`  is_partial_fill: ${params.isPartialFill}`)
@@ -1118,7 +1148,7 @@ export class ResolverContractManager {
         // Single fill withdrawal
         args = [
           new Address(stellarWallet.publicKey).toScVal(), // caller
-          new Address(params.escrowAddress).toScVal(), // escrow_address
+          nativeToScVal(Buffer.from(params.escrowAddress.slice(2), 'hex'), { type: "bytes" }), // escrow_id
           nativeToScVal(Buffer.from(params.secret.slice(2), 'hex'), { type: "bytes" }) // secret
         ];
         console.log('🔍 Single fill withdrawal arguments prepared');
@@ -1136,7 +1166,7 @@ export class ResolverContractManager {
         
         args = [
           new Address(stellarWallet.publicKey).toScVal(), // caller
-          new Address(params.escrowAddress).toScVal(), // escrow_address
+          nativeToScVal(Buffer.from(params.escrowAddress.slice(2), 'hex'), { type: "bytes" }), // escrow_id
           nativeToScVal(Buffer.from(params.secret.slice(2), 'hex'), { type: "bytes" }), // secret
           merkleProofVec // merkle_proof as Vec<BytesN<32>>
         ];
@@ -1780,6 +1810,21 @@ export class ResolverContractManager {
     }
   }
 
+  // Wait for a Stellar escrow creation to land and return the escrow id it returned, 0x-prefixed
+  private async waitForStellarEscrowId(server: rpc.Server, transactionHash: string): Promise<string> {
+    for (let attempt = 0; attempt < 30; attempt++) {
+      const result = await server.getTransaction(transactionHash)
+      if (result.status === rpc.Api.GetTransactionStatus.SUCCESS) {
+        return '0x' + Buffer.from(result.returnValue!.bytes()).toString('hex')
+      }
+      if (result.status === rpc.Api.GetTransactionStatus.FAILED) {
+        throw new Error(`Escrow creation failed: ${transactionHash}`)
+      }
+      await new Promise(resolve => setTimeout(resolve, 1000))
+    }
+    throw new Error(`Escrow creation not confirmed: ${transactionHash}`)
+  }
+
   // Prepare destination tokens (matches dynamic-swap.ts exactly)
   private async prepareDestinationTokens(chainId: string, signer: ethers.Wallet | ethers.Signer, amount: ethers.BigNumber) {
     const config = CHAIN_CONFIGS[chainId as keyof typeof CHAIN_CONFIGS]